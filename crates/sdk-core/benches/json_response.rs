@@ -0,0 +1,75 @@
+//! Measures sending large query responses, comparing pre-serialized responses, which are sent
+//! without copying, with values, which are serialized as they are sent.
+//!
+//! Run with `cargo bench -p ndc-sdk-core --bench json_response`.
+
+use std::time::{Duration, Instant};
+
+use axum::body::{Bytes, HttpBody as _};
+use axum::response::IntoResponse as _;
+use ndc_models::{QueryResponse, RowFieldValue, RowSet};
+use ndc_sdk_core::json_response::JsonResponse;
+
+const ROWS: usize = 200_000;
+const ITERATIONS: u32 = 20;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let response = query_response();
+    let serialized = Bytes::from(serde_json::to_vec(&response).expect("serialization failed"));
+    println!(
+        "sending a response of {ROWS} rows, {} bytes",
+        serialized.len()
+    );
+
+    let elapsed = measure(|| JsonResponse::Serialized(serialized.clone())).await;
+    report("serialized", elapsed, serialized.len());
+
+    let elapsed = measure(|| JsonResponse::Value(response.clone())).await;
+    report("value", elapsed, serialized.len());
+}
+
+/// The mean time to turn the response into an HTTP response and read its body.
+async fn measure(response: impl Fn() -> JsonResponse<QueryResponse>) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let response = response();
+        let start = Instant::now();
+        let mut body = response.into_response().into_body();
+        let mut length = 0;
+        while let Some(chunk) = body.data().await {
+            length += chunk.expect("reading the body failed").len();
+        }
+        elapsed += start.elapsed();
+        assert!(length > 0);
+    }
+    elapsed / ITERATIONS
+}
+
+fn report(name: &str, elapsed: Duration, bytes: usize) {
+    #[allow(clippy::cast_precision_loss)]
+    let throughput = bytes as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("{name:>12}: {elapsed:>12.3?} per response, {throughput:>10.1} MB/s");
+}
+
+fn query_response() -> QueryResponse {
+    let rows = (0..ROWS)
+        .map(|id| {
+            [
+                ("id".into(), RowFieldValue(id.into())),
+                (
+                    "title".into(),
+                    RowFieldValue(format!("Article {id}").into()),
+                ),
+                ("author_id".into(), RowFieldValue((id % 100).into())),
+            ]
+            .into_iter()
+            .collect()
+        })
+        .collect();
+    QueryResponse(vec![RowSet {
+        aggregates: None,
+        rows: Some(rows),
+        groups: None,
+    }])
+}
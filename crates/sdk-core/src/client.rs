@@ -0,0 +1,438 @@
+//! A typed client for calling NDC connectors over HTTP, for connectors which federate to other
+//! connectors, and for testing connectors which are running elsewhere.
+//!
+//! Requests carry the trace context and baggage of the request being handled, so that the
+//! connector being called continues the same trace. They are added by the header injector, which
+//! the SDK registers with [`set_header_injector`] when it sets up tracing.
+//!
+//! ```ignore
+//! let client = NdcClient::new("http://localhost:8081".parse()?)
+//!     .with_service_token("secret")
+//!     .with_retries(2, Duration::from_millis(100));
+//! let response = client.query(&request).await?;
+//! ```
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{HeaderMap, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::connector::ErrorResponse;
+use crate::json_request::JsonRequest;
+use crate::json_response::JsonResponse;
+
+/// The longest a client waits before retrying a request, however many times it has failed.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+static HEADER_INJECTOR: OnceLock<fn(&mut HeaderMap)> = OnceLock::new();
+
+/// Add headers to every request made by an [`NdcClient`], such as the trace context and baggage
+/// of the request being handled. Only the first injector which is set is used.
+pub fn set_header_injector(injector: fn(&mut HeaderMap)) {
+    let _ = HEADER_INJECTOR.set(injector);
+}
+
+/// The ways a request made by an [`NdcClient`] can fail.
+#[derive(Debug, thiserror::Error)]
+pub enum NdcClientError {
+    #[error("invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("unable to serialize the request: {0}")]
+    Serialize(serde_json::Error),
+    #[error("unable to deserialize the response: {0}")]
+    Deserialize(serde_json::Error),
+    #[error("the connector responded with {status}: {}", error.message)]
+    Response {
+        status: StatusCode,
+        error: ndc_models::ErrorResponse,
+    },
+}
+
+/// Errors from the called connector keep its status code and details, and other failures are
+/// reported as `502 Bad Gateway`.
+impl From<NdcClientError> for ErrorResponse {
+    fn from(value: NdcClientError) -> Self {
+        match value {
+            NdcClientError::Response { status, error } => {
+                ErrorResponse::new(status, error.message, error.details)
+            }
+            err => ErrorResponse::new(
+                StatusCode::BAD_GATEWAY,
+                err.to_string(),
+                serde_json::Value::Null,
+            ),
+        }
+    }
+}
+
+/// A client for a connector served at a base URL.
+#[derive(Clone, Debug)]
+pub struct NdcClient {
+    client: reqwest::Client,
+    base_url: url::Url,
+    service_token: Option<String>,
+    spec_version: Option<semver::Version>,
+    retries: u32,
+    retry_interval: Duration,
+}
+
+impl NdcClient {
+    /// Create a client for the connector at the base URL, below which the endpoints are resolved,
+    /// so that a connector at `http://host/orders` is queried at `http://host/orders/query`.
+    pub fn new(mut base_url: url::Url) -> Self {
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            service_token: None,
+            spec_version: None,
+            retries: 0,
+            retry_interval: Duration::from_millis(100),
+        }
+    }
+
+    /// Send requests with this HTTP client, for example to configure timeouts or TLS.
+    #[must_use]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Send the service token as a bearer `Authorization` header with every request.
+    #[must_use]
+    pub fn with_service_token(mut self, service_token: impl Into<String>) -> Self {
+        self.service_token = Some(service_token.into());
+        self
+    }
+
+    /// Ask the connector for this ndc-spec version, in the `X-Hasura-NDC-Version` header.
+    #[must_use]
+    pub fn with_spec_version(mut self, spec_version: semver::Version) -> Self {
+        self.spec_version = Some(spec_version);
+        self
+    }
+
+    /// Retry failed requests up to this many times, waiting for the interval before the first
+    /// retry, and doubling it after each one, up to 5 seconds.
+    ///
+    /// Requests are retried if the connector could not be reached, or responded with
+    /// `429 Too Many Requests`, `502 Bad Gateway`, `503 Service Unavailable` or
+    /// `504 Gateway Timeout`. Mutations are only retried if the connector could not be reached,
+    /// as they may not be safe to repeat.
+    ///
+    /// If the connector responds with a `Retry-After` header in seconds, the request is retried
+    /// after that delay instead, or not at all if it is longer than 5 seconds.
+    #[must_use]
+    pub fn with_retries(mut self, retries: u32, retry_interval: Duration) -> Self {
+        self.retries = retries;
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// The base URL of the connector.
+    pub fn base_url(&self) -> &url::Url {
+        &self.base_url
+    }
+
+    pub async fn capabilities(&self) -> Result<ndc_models::CapabilitiesResponse, NdcClientError> {
+        self.send(reqwest::Method::GET, "capabilities", None, true)
+            .await
+    }
+
+    pub async fn schema(&self) -> Result<ndc_models::SchemaResponse, NdcClientError> {
+        self.send(reqwest::Method::GET, "schema", None, true).await
+    }
+
+    pub async fn query(
+        &self,
+        request: &ndc_models::QueryRequest,
+    ) -> Result<ndc_models::QueryResponse, NdcClientError> {
+        self.post("query", request, true).await
+    }
+
+    pub async fn query_explain(
+        &self,
+        request: &ndc_models::QueryRequest,
+    ) -> Result<ndc_models::ExplainResponse, NdcClientError> {
+        self.post("query/explain", request, true).await
+    }
+
+    pub async fn mutation(
+        &self,
+        request: &ndc_models::MutationRequest,
+    ) -> Result<ndc_models::MutationResponse, NdcClientError> {
+        self.post("mutation", request, false).await
+    }
+
+    /// Send a query request as it was received, without reserializing it, and return the
+    /// response as it was received, without deserializing it.
+    pub async fn query_raw(
+        &self,
+        request: JsonRequest<ndc_models::QueryRequest>,
+    ) -> Result<JsonResponse<ndc_models::QueryResponse>, NdcClientError> {
+        let body = request.into_bytes().map_err(NdcClientError::Serialize)?;
+        self.send_bytes(reqwest::Method::POST, "query", Some(body), true)
+            .await
+            .map(JsonResponse::Serialized)
+    }
+
+    /// Send a mutation request as it was received, without reserializing it, and return the
+    /// response as it was received, without deserializing it.
+    pub async fn mutation_raw(
+        &self,
+        request: JsonRequest<ndc_models::MutationRequest>,
+    ) -> Result<JsonResponse<ndc_models::MutationResponse>, NdcClientError> {
+        let body = request.into_bytes().map_err(NdcClientError::Serialize)?;
+        self.send_bytes(reqwest::Method::POST, "mutation", Some(body), false)
+            .await
+            .map(JsonResponse::Serialized)
+    }
+
+    pub async fn mutation_explain(
+        &self,
+        request: &ndc_models::MutationRequest,
+    ) -> Result<ndc_models::ExplainResponse, NdcClientError> {
+        self.post("mutation/explain", request, true).await
+    }
+
+    async fn post<A: Serialize, B: DeserializeOwned>(
+        &self,
+        path: &str,
+        request: &A,
+        idempotent: bool,
+    ) -> Result<B, NdcClientError> {
+        let body = serde_json::to_vec(request).map_err(NdcClientError::Serialize)?;
+        self.send(
+            reqwest::Method::POST,
+            path,
+            Some(Bytes::from(body)),
+            idempotent,
+        )
+        .await
+    }
+
+    /// Send a request, retrying as configured, and deserialize a successful response.
+    async fn send<A: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Bytes>,
+        idempotent: bool,
+    ) -> Result<A, NdcClientError> {
+        let bytes = self.send_bytes(method, path, body, idempotent).await?;
+        serde_json::from_slice(&bytes).map_err(NdcClientError::Deserialize)
+    }
+
+    /// Send a request, retrying as configured, and return the body of a successful response.
+    async fn send_bytes(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Bytes>,
+        idempotent: bool,
+    ) -> Result<Bytes, NdcClientError> {
+        let url = self.base_url.join(path)?;
+        let mut attempt = 0;
+        let mut backoff = self.retry_interval.min(MAX_BACKOFF);
+        loop {
+            match self
+                .send_once(method.clone(), url.clone(), body.clone())
+                .await
+            {
+                Err(failure) if attempt < self.retries && retryable(&failure.error, idempotent) => {
+                    let delay = match failure.retry_after {
+                        Some(retry_after) if retry_after > MAX_BACKOFF => return Err(failure.error),
+                        Some(retry_after) => retry_after,
+                        None => backoff,
+                    };
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                result => return result.map_err(|failure| failure.error),
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        method: reqwest::Method,
+        url: url::Url,
+        body: Option<Bytes>,
+    ) -> Result<Bytes, Failure> {
+        let mut headers = HeaderMap::new();
+        if let Some(inject) = HEADER_INJECTOR.get() {
+            inject(&mut headers);
+        }
+        let mut request = self.client.request(method, url).headers(headers);
+        if let Some(service_token) = &self.service_token {
+            request = request.bearer_auth(service_token);
+        }
+        if let Some(spec_version) = &self.spec_version {
+            request = request.header(ndc_models::VERSION_HEADER_NAME, spec_version.to_string());
+        }
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+        }
+        let response = request.send().await.map_err(NdcClientError::Request)?;
+        let status = response.status();
+        let retry_after = retry_after(response.headers());
+        let bytes = response.bytes().await.map_err(NdcClientError::Request)?;
+        if !status.is_success() {
+            let error =
+                serde_json::from_slice(&bytes).unwrap_or_else(|_| ndc_models::ErrorResponse {
+                    message: String::from_utf8_lossy(&bytes).into_owned(),
+                    details: serde_json::Value::Null,
+                });
+            return Err(Failure {
+                error: NdcClientError::Response { status, error },
+                retry_after,
+            });
+        }
+        Ok(bytes)
+    }
+}
+
+/// A failed attempt to send a request, with how long the connector asked to wait before retrying.
+struct Failure {
+    error: NdcClientError,
+    retry_after: Option<Duration>,
+}
+
+impl From<NdcClientError> for Failure {
+    fn from(error: NdcClientError) -> Self {
+        Self {
+            error,
+            retry_after: None,
+        }
+    }
+}
+
+/// The delay in a `Retry-After` header, if it is given in seconds rather than as a date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Whether a failed request may succeed if it is sent again.
+fn retryable(err: &NdcClientError, idempotent: bool) -> bool {
+    match err {
+        NdcClientError::Request(err) => err.is_connect() || (idempotent && err.is_timeout()),
+        NdcClientError::Response { status, .. } => {
+            idempotent
+                && matches!(
+                    *status,
+                    StatusCode::TOO_MANY_REQUESTS
+                        | StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                )
+        }
+        NdcClientError::Url(_) | NdcClientError::Serialize(_) | NdcClientError::Deserialize(_) => {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::http::header;
+    use axum::response::IntoResponse;
+    use axum::{routing, Json, Router};
+
+    use super::*;
+    use crate::testing::TestClient;
+
+    #[tokio::test]
+    async fn retries_after_the_delay_in_retry_after() -> anyhow::Result<()> {
+        let (client, requests) = unavailable_once("0")?;
+        // without the header, the client would wait for its 5 second backoff
+        let response =
+            tokio::time::timeout(Duration::from_secs(2), client.query(&request()?)).await??;
+        assert_eq!(response.0.len(), 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_when_retry_after_is_too_long() -> anyhow::Result<()> {
+        let (client, requests) = unavailable_once("60")?;
+        let err = client.query(&request()?).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NdcClientError::Response {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                ..
+            }
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn returns_raw_responses_without_deserializing_them() -> anyhow::Result<()> {
+        let (client, _) = unavailable_once("0")?;
+        let request = JsonRequest::from_bytes(Bytes::from(serde_json::to_vec(&request()?)?));
+        let response = client.query_raw(request).await?;
+        assert_eq!(
+            response.as_serialized(),
+            Some(&Bytes::from(r#"[{"rows":[{"id":1}]}]"#))
+        );
+        Ok(())
+    }
+
+    /// A client for a connector which responds to the first query with `503 Service Unavailable`
+    /// and the `Retry-After` header, and to the rest successfully, along with the number of
+    /// queries it has received.
+    fn unavailable_once(
+        retry_after: &'static str,
+    ) -> anyhow::Result<(NdcClient, Arc<AtomicUsize>)> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/query",
+            routing::post(move || async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        [(header::RETRY_AFTER, retry_after)],
+                        Json(serde_json::json!({ "message": "busy", "details": null })),
+                    )
+                        .into_response();
+                }
+                Json(serde_json::json!([{ "rows": [{ "id": 1 }] }])).into_response()
+            }),
+        );
+        let address = TestClient::new(app)?.address();
+        let client = NdcClient::new(format!("http://{address}/").parse()?)
+            .with_retries(1, Duration::from_secs(60));
+        Ok((client, requests))
+    }
+
+    fn request() -> serde_json::Result<ndc_models::QueryRequest> {
+        serde_json::from_value(serde_json::json!({
+            "collection": "articles",
+            "query": {},
+            "arguments": {},
+            "collection_relationships": {},
+        }))
+    }
+}
@@ -0,0 +1,50 @@
+//! An abstraction over the passage of time, so that time-dependent components, such as
+//! [`Throttle`](crate::throttle::Throttle) and the initialization backoff of
+//! [`ServerState`](crate::state::ServerState), can be tested deterministically.
+//!
+//! Components use the [`SystemClock`] by default. Tests can substitute the manual clock in the
+//! `testing` module, which only moves when it is advanced.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+
+/// A source of the current time, and of timers.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// A future which completes once the duration has passed.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The real time, with timers provided by Tokio.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Wait for the future to complete, for at most the duration as measured by the clock.
+///
+/// This returns `None` if the duration passes first, in which case the future is dropped.
+pub async fn timeout<F: Future>(
+    clock: &dyn Clock,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    tokio::select! {
+        biased;
+        output = future => Some(output),
+        () = clock.sleep(duration) => None,
+    }
+}
@@ -0,0 +1,48 @@
+//! Helpers for connectors parsing their configuration.
+//!
+//! None of these are applied automatically; connectors opt in by calling them from
+//! [`ConnectorSetup::parse_configuration`](crate::connector::ConnectorSetup::parse_configuration).
+
+use std::path::{Path, PathBuf};
+
+use crate::connector::ParseError;
+
+pub mod environment;
+mod fs;
+pub mod secrets;
+#[cfg(feature = "yaml")]
+pub mod yaml;
+
+/// The configuration path provided by the user, which may be a directory or a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigurationPath {
+    /// A directory containing one or more configuration files.
+    Directory(PathBuf),
+    /// A single configuration file.
+    File(PathBuf),
+}
+
+impl ConfigurationPath {
+    /// Inspect the configuration path, failing if it does not exist.
+    pub async fn resolve(path: &Path) -> Result<Self, ParseError> {
+        match fs::metadata(path).await {
+            Ok(metadata) if metadata.is_dir() => Ok(Self::Directory(path.to_owned())),
+            Ok(_) => Ok(Self::File(path.to_owned())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(ParseError::CouldNotFindConfiguration(path.to_owned()))
+            }
+            Err(err) => Err(ParseError::IoError(err)),
+        }
+    }
+
+    /// The path to the main configuration file.
+    ///
+    /// For a directory, this is the file with the given name inside it. For a single file, it is
+    /// the file itself.
+    pub fn main_file(&self, file_name: &str) -> PathBuf {
+        match self {
+            Self::Directory(directory) => directory.join(file_name),
+            Self::File(file) => file.clone(),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::Path;
+
+use crate::connector::{InvalidNode, InvalidNodes, KeyOrIndex, ParseError};
+
+/// Expand `${VARIABLE}` placeholders in every string in a JSON value, using the process
+/// environment.
+///
+/// See [`substitute_variables`] for the supported syntax.
+pub fn substitute_environment_variables(
+    file_path: &Path,
+    value: &mut serde_json::Value,
+) -> Result<(), ParseError> {
+    substitute_variables(file_path, value, |name| std::env::var(name).ok())
+}
+
+/// Expand `${VARIABLE}` placeholders in every string in a JSON value.
+///
+/// The following forms are supported:
+///
+/// - `${NAME}` is replaced by the value of `NAME`, which must be set.
+/// - `${NAME:-default}` is replaced by the value of `NAME`, or by `default` if `NAME` is unset
+///   or empty.
+/// - `$${` is replaced by a literal `${`.
+///
+/// Object keys are left untouched. Every failing placeholder is reported as an [`InvalidNode`]
+/// pointing at the string that contains it, so that all problems can be fixed at once.
+pub fn substitute_variables(
+    file_path: &Path,
+    value: &mut serde_json::Value,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<(), ParseError> {
+    let mut errors = vec![];
+    substitute_in_node(file_path, value, &lookup, &mut vec![], &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ParseError::ValidateError(InvalidNodes(errors)))
+    }
+}
+
+fn substitute_in_node(
+    file_path: &Path,
+    value: &mut serde_json::Value,
+    lookup: &impl Fn(&str) -> Option<String>,
+    node_path: &mut Vec<KeyOrIndex>,
+    errors: &mut Vec<InvalidNode>,
+) {
+    match value {
+        serde_json::Value::String(string) => match expand(string, lookup) {
+            Ok(expanded) => *string = expanded,
+            Err(message) => errors.push(InvalidNode {
+                file_path: file_path.to_owned(),
+                node_path: node_path.clone(),
+                message,
+            }),
+        },
+        serde_json::Value::Array(elements) => {
+            for (index, element) in elements.iter_mut().enumerate() {
+                node_path.push(KeyOrIndex::Index(u32::try_from(index).unwrap_or(u32::MAX)));
+                substitute_in_node(file_path, element, lookup, node_path, errors);
+                node_path.pop();
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                node_path.push(KeyOrIndex::Key(key.clone()));
+                substitute_in_node(file_path, field, lookup, node_path, errors);
+                node_path.pop();
+            }
+        }
+        serde_json::Value::Null | serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {}
+    }
+}
+
+fn expand(input: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let after_dollar = &rest[start + 1..];
+        if let Some(after_escape) = after_dollar.strip_prefix("${") {
+            output.push_str("${");
+            rest = after_escape;
+            continue;
+        }
+        let Some(placeholder) = after_dollar.strip_prefix('{') else {
+            output.push('$');
+            rest = after_dollar;
+            continue;
+        };
+        let Some(end) = placeholder.find('}') else {
+            return Err("unterminated environment variable placeholder".to_string());
+        };
+        let (name, default) = match placeholder[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&placeholder[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid environment variable name: {name:?}"));
+        }
+        match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => output.push_str(default),
+            (Some(value), _) => output.push_str(&value),
+            (None, Some(default)) => output.push_str(default),
+            (None, None) => return Err(format!("environment variable {name} is not set")),
+        }
+        rest = &placeholder[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::substitute_variables;
+    use crate::connector::ParseError;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("db.example.com".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn substitutes_nested_strings() {
+        let mut value = json!({
+            "connection": { "uri": "postgres://${HOST}:${PORT:-5432}/db" },
+            "schemas": ["${EMPTY:-public}", "$${NOT_A_VARIABLE}", "costs $5"],
+            "retries": 3,
+        });
+        substitute_variables(Path::new("config.json"), &mut value, lookup).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "connection": { "uri": "postgres://db.example.com:5432/db" },
+                "schemas": ["public", "${NOT_A_VARIABLE}", "costs $5"],
+                "retries": 3,
+            })
+        );
+    }
+
+    #[test]
+    fn reports_every_missing_variable() {
+        let mut value = json!({
+            "uri": "${MISSING_URI}",
+            "tokens": ["ok", "${MISSING_TOKEN}"],
+        });
+        let Err(ParseError::ValidateError(errors)) =
+            substitute_variables(Path::new("config.json"), &mut value, lookup)
+        else {
+            panic!("expected a validation error");
+        };
+        let mut errors = errors.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                r#"config.json, at .["tokens"].[1]: environment variable MISSING_TOKEN is not set"#,
+                r#"config.json, at .["uri"]: environment variable MISSING_URI is not set"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! Reading configuration files, asynchronously on native targets.
+//!
+//! Without the `native` feature, as on `wasm32-wasi`, Tokio cannot read files in the background,
+//! so files are read synchronously. Configuration is read before serving any requests, so this
+//! does not hold up other work.
+
+use std::fs::Metadata;
+#[cfg(not(feature = "native"))]
+use std::future::Future;
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "native")]
+pub(crate) async fn metadata(path: &Path) -> io::Result<Metadata> {
+    tokio::fs::metadata(path).await
+}
+
+#[cfg(not(feature = "native"))]
+pub(crate) fn metadata(path: &Path) -> impl Future<Output = io::Result<Metadata>> {
+    std::future::ready(std::fs::metadata(path))
+}
+
+#[cfg(feature = "native")]
+pub(crate) async fn read_to_string(path: &Path) -> io::Result<String> {
+    tokio::fs::read_to_string(path).await
+}
+
+#[cfg(not(feature = "native"))]
+pub(crate) fn read_to_string(path: &Path) -> impl Future<Output = io::Result<String>> {
+    std::future::ready(std::fs::read_to_string(path))
+}
@@ -0,0 +1,299 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use crate::connector::{InvalidNode, InvalidNodes, KeyOrIndex, ParseError};
+
+/// Resolves references to secrets in configuration files.
+///
+/// A reference is a JSON object with a single key, such as `{"fromEnv": "DATABASE_URL"}`. The
+/// key selects the resolver, and the value is passed to [`SecretsResolver::resolve`].
+///
+/// The SDK provides [`EnvironmentResolver`] and [`FileResolver`]; connectors can implement this
+/// trait to fetch secrets from other backends, such as a vault.
+#[async_trait]
+pub trait SecretsResolver: Send + Sync {
+    /// The key which identifies references handled by this resolver, e.g. `fromEnv`.
+    fn key(&self) -> &str;
+
+    /// Resolve a reference to its secret value.
+    ///
+    /// On failure, this should return a message describing the problem, which will be reported
+    /// against the location of the reference in the configuration file.
+    async fn resolve(&self, reference: &serde_json::Value) -> Result<String, String>;
+}
+
+/// Resolves `{"fromEnv": "NAME"}` references from the process environment.
+#[derive(Debug, Clone, Default)]
+pub struct EnvironmentResolver;
+
+#[async_trait]
+impl SecretsResolver for EnvironmentResolver {
+    fn key(&self) -> &'static str {
+        "fromEnv"
+    }
+
+    async fn resolve(&self, reference: &serde_json::Value) -> Result<String, String> {
+        let name = reference
+            .as_str()
+            .ok_or("expected the name of an environment variable")?;
+        std::env::var(name).map_err(|err| format!("environment variable {name}: {err}"))
+    }
+}
+
+/// Resolves `{"fromFile": "path"}` references by reading the file.
+///
+/// Relative paths are resolved against the base directory, which is usually the configuration
+/// directory. Trailing newlines are removed, as they are rarely intended to be part of the
+/// secret.
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    base_directory: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(base_directory: impl Into<PathBuf>) -> Self {
+        Self {
+            base_directory: base_directory.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretsResolver for FileResolver {
+    fn key(&self) -> &'static str {
+        "fromFile"
+    }
+
+    async fn resolve(&self, reference: &serde_json::Value) -> Result<String, String> {
+        let path = reference.as_str().ok_or("expected a file path")?;
+        let path = self.base_directory.join(path);
+        let contents = super::fs::read_to_string(&path)
+            .await
+            .map_err(|err| format!("could not read {}: {err}", path.display()))?;
+        Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Replace every secret reference in a JSON value with the string it resolves to.
+///
+/// Objects whose only key matches one of the resolvers are treated as references; all other
+/// values are left untouched. Every reference which fails to resolve is reported as an
+/// [`InvalidNode`].
+pub async fn resolve_secrets(
+    file_path: &Path,
+    value: &mut serde_json::Value,
+    resolvers: &[&dyn SecretsResolver],
+) -> Result<(), ParseError> {
+    let mut references = vec![];
+    find_references(value, resolvers, &mut vec![], &mut references);
+
+    let mut errors = vec![];
+    for (node_path, resolver) in references {
+        let Some(node) = node_at_path(value, &node_path) else {
+            continue;
+        };
+        let reference = &node[resolver.key()];
+        match resolver.resolve(reference).await {
+            Ok(secret) => *node = serde_json::Value::String(secret),
+            Err(message) => errors.push(InvalidNode {
+                file_path: file_path.to_owned(),
+                node_path,
+                message,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ParseError::ValidateError(InvalidNodes(errors)))
+    }
+}
+
+fn find_references<'a>(
+    value: &serde_json::Value,
+    resolvers: &[&'a dyn SecretsResolver],
+    node_path: &mut Vec<KeyOrIndex>,
+    references: &mut Vec<(Vec<KeyOrIndex>, &'a dyn SecretsResolver)>,
+) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            if fields.len() == 1 {
+                if let Some(resolver) = resolvers
+                    .iter()
+                    .find(|resolver| fields.contains_key(resolver.key()))
+                {
+                    references.push((node_path.clone(), *resolver));
+                    return;
+                }
+            }
+            for (key, field) in fields {
+                node_path.push(KeyOrIndex::Key(key.clone()));
+                find_references(field, resolvers, node_path, references);
+                node_path.pop();
+            }
+        }
+        serde_json::Value::Array(elements) => {
+            for (index, element) in elements.iter().enumerate() {
+                node_path.push(KeyOrIndex::Index(u32::try_from(index).unwrap_or(u32::MAX)));
+                find_references(element, resolvers, node_path, references);
+                node_path.pop();
+            }
+        }
+        serde_json::Value::Null
+        | serde_json::Value::Bool(_)
+        | serde_json::Value::Number(_)
+        | serde_json::Value::String(_) => {}
+    }
+}
+
+fn node_at_path<'a>(
+    mut value: &'a mut serde_json::Value,
+    node_path: &[KeyOrIndex],
+) -> Option<&'a mut serde_json::Value> {
+    for segment in node_path {
+        value = match segment {
+            KeyOrIndex::Key(key) => value.get_mut(key)?,
+            KeyOrIndex::Index(index) => value.get_mut(usize::try_from(*index).ok()?)?,
+        };
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use serde_json::json;
+
+    use super::{resolve_secrets, EnvironmentResolver, FileResolver};
+    use crate::connector::ParseError;
+
+    fn secrets_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("ndc-sdk-secrets-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[tokio::test]
+    async fn substitutes_environment_variables() {
+        std::env::set_var("NDC_SDK_SECRETS_TEST_URI", "postgres://db.example.com");
+        let mut value = json!({ "uri": { "fromEnv": "NDC_SDK_SECRETS_TEST_URI" } });
+        resolve_secrets(
+            Path::new("config.json"),
+            &mut value,
+            &[&EnvironmentResolver],
+        )
+        .await
+        .unwrap();
+        assert_eq!(value, json!({ "uri": "postgres://db.example.com" }));
+    }
+
+    #[tokio::test]
+    async fn substitutes_file_contents_relative_to_the_base_directory() {
+        let directory = secrets_directory("file");
+        std::fs::write(directory.join("password"), "hunter2\n").unwrap();
+        let mut value = json!({ "password": { "fromFile": "password" } });
+        resolve_secrets(
+            Path::new("config.json"),
+            &mut value,
+            &[&FileResolver::new(&directory)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(value, json!({ "password": "hunter2" }));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn substitutes_nested_references() {
+        std::env::set_var("NDC_SDK_SECRETS_TEST_NESTED", "secret");
+        let directory = secrets_directory("nested");
+        std::fs::write(directory.join("token"), "token").unwrap();
+        let mut value = json!({
+            "connections": [
+                { "uri": { "fromEnv": "NDC_SDK_SECRETS_TEST_NESTED" } },
+                { "headers": { "authorization": { "fromFile": "token" } } },
+            ],
+            // objects with other keys are not references
+            "options": { "fromEnv": "NDC_SDK_SECRETS_TEST_NESTED", "other": true },
+        });
+        resolve_secrets(
+            Path::new("config.json"),
+            &mut value,
+            &[&EnvironmentResolver, &FileResolver::new(&directory)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "connections": [
+                    { "uri": "secret" },
+                    { "headers": { "authorization": "token" } },
+                ],
+                "options": { "fromEnv": "NDC_SDK_SECRETS_TEST_NESTED", "other": true },
+            })
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_a_missing_variable() {
+        let mut value = json!({ "uri": { "fromEnv": "NDC_SDK_SECRETS_TEST_MISSING" } });
+        let Err(ParseError::ValidateError(errors)) = resolve_secrets(
+            Path::new("config.json"),
+            &mut value,
+            &[&EnvironmentResolver],
+        )
+        .await
+        else {
+            panic!("expected a validation error");
+        };
+        let [error] = errors.0.as_slice() else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert_eq!(error.file_path, Path::new("config.json"));
+        assert_eq!(
+            serde_json::to_value(&error.node_path).unwrap(),
+            json!(["uri"])
+        );
+        assert_eq!(
+            error.message,
+            "environment variable NDC_SDK_SECRETS_TEST_MISSING: environment variable not found"
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_a_missing_file() {
+        let directory = secrets_directory("missing");
+        let mut value = json!({ "tokens": [{ "fromFile": "missing" }] });
+        let Err(ParseError::ValidateError(errors)) = resolve_secrets(
+            Path::new("config.json"),
+            &mut value,
+            &[&FileResolver::new(&directory)],
+        )
+        .await
+        else {
+            panic!("expected a validation error");
+        };
+        let [error] = errors.0.as_slice() else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert_eq!(
+            serde_json::to_value(&error.node_path).unwrap(),
+            json!(["tokens", 0])
+        );
+        assert!(
+            error.message.starts_with(&format!(
+                "could not read {}:",
+                directory.join("missing").display()
+            )),
+            "unexpected message: {}",
+            error.message
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+
+use crate::connector::{InvalidNode, InvalidNodes, LocatedError, ParseError};
+
+/// Read and parse a YAML configuration file.
+///
+/// A missing file is reported as [`ParseError::CouldNotFindConfiguration`], and syntax or type
+/// errors are reported with their location in the file.
+pub async fn read_yaml_file<T: DeserializeOwned>(file_path: &Path) -> Result<T, ParseError> {
+    let contents = read_file(file_path).await?;
+    parse_yaml(file_path, &contents)
+}
+
+/// Read a YAML configuration file as a JSON value.
+///
+/// This is useful for applying pre-processing steps, such as
+/// [`substitute_environment_variables`](super::environment::substitute_environment_variables),
+/// before deserializing the configuration.
+pub async fn read_yaml_file_as_json(file_path: &Path) -> Result<serde_json::Value, ParseError> {
+    read_yaml_file(file_path).await
+}
+
+/// Parse YAML configuration which was read from the given file.
+pub fn parse_yaml<T: DeserializeOwned>(file_path: &Path, contents: &str) -> Result<T, ParseError> {
+    serde_yaml::from_str(contents).map_err(|err| yaml_error(file_path, &err))
+}
+
+async fn read_file(file_path: &Path) -> Result<String, ParseError> {
+    match super::fs::read_to_string(file_path).await {
+        Ok(contents) => Ok(contents),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Err(ParseError::CouldNotFindConfiguration(file_path.to_owned()))
+        }
+        Err(err) => Err(ParseError::IoError(err)),
+    }
+}
+
+fn yaml_error(file_path: &Path, err: &serde_yaml::Error) -> ParseError {
+    match err.location() {
+        Some(location) => ParseError::ParseError(LocatedError {
+            file_path: file_path.to_owned(),
+            line: location.line(),
+            column: location.column(),
+            message: err.to_string(),
+        }),
+        // errors without a location, such as a missing document, apply to the whole file
+        None => ParseError::ValidateError(InvalidNodes(vec![InvalidNode {
+            file_path: file_path.to_owned(),
+            node_path: vec![],
+            message: err.to_string(),
+        }])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::parse_yaml;
+    use crate::connector::ParseError;
+
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Configuration {
+        uri: String,
+        pool_size: u32,
+    }
+
+    #[test]
+    fn reports_the_location_of_type_errors() {
+        let contents = "uri: postgres://localhost\npool_size: lots\n";
+        let Err(ParseError::ParseError(error)) =
+            parse_yaml::<Configuration>(Path::new("configuration.yaml"), contents)
+        else {
+            panic!("expected a parse error");
+        };
+        assert_eq!((error.line, error.column), (2, 12));
+    }
+}
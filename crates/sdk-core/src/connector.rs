@@ -1,9 +1,15 @@
+use crate::health::HealthReport;
+use crate::json_request::JsonRequest;
 use crate::json_response::JsonResponse;
+use crate::version::ConnectorVersion;
 use async_trait::async_trait;
 use ndc_models as models;
 use std::path::Path;
+use std::sync::Arc;
+pub mod dynamic;
 pub mod error;
 pub mod example;
+pub use dynamic::DynConnector;
 pub use error::*;
 
 /// Connectors using this library should implement this trait.
@@ -25,8 +31,8 @@ pub use error::*;
 /// for simplified configuration management.
 ///
 /// Configuration is specified by the connector implementation. It is provided
-/// as a path to a directory. Parsing this directory should result in a
-/// [`Connector::Configuration`].
+/// as a path to a directory, or to a single file. Parsing this path should
+/// result in a [`Connector::Configuration`].
 ///
 /// ## State
 ///
@@ -66,6 +72,57 @@ pub trait Connector: Send + 'static {
         Ok(())
     }
 
+    /// Check that the connector is alive.
+    ///
+    /// This is served by the `/health/live` endpoint. Unlike readiness, a liveness failure
+    /// indicates that the process cannot recover by itself and should be restarted, for example
+    /// because a connection pool is deadlocked. Temporary problems, such as an unavailable data
+    /// source, should not cause this check to fail.
+    ///
+    /// This check never initializes the connector state, so `state` is `None` if the state has not
+    /// been initialized yet.
+    ///
+    /// The default implementation always succeeds.
+    async fn get_health_liveness(
+        _configuration: &Self::Configuration,
+        _state: Option<&Self::State>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Report the health of each of the connector's components.
+    ///
+    /// This is served by the `/health` endpoint, and is returned in full when the
+    /// `verbose=true` query parameter is provided. If any component is unhealthy, the endpoint
+    /// responds with `503 Service Unavailable`.
+    ///
+    /// The same caveats apply as for [`Connector::get_health_readiness`]. The default
+    /// implementation calls that function, and reports no components.
+    async fn get_health_report(
+        configuration: &Self::Configuration,
+        state: &Self::State,
+    ) -> Result<HealthReport> {
+        Self::get_health_readiness(configuration, state).await?;
+        Ok(HealthReport::new())
+    }
+
+    /// Add connector-specific attributes, such as the database name, shard or tenant, to the
+    /// span of a request.
+    ///
+    /// This is called by the request handlers before the request is executed, with the span
+    /// created for the request. It is not called for query and mutation requests if the span is
+    /// disabled, as the request may then be forwarded without being deserialized. Fields which are not declared by the SDK cannot be recorded on
+    /// the span with [`tracing::Span::record`], so attributes should be added with
+    /// `tracing_opentelemetry::OpenTelemetrySpanExt::set_attribute`, or recorded on a child span.
+    ///
+    /// The default implementation does nothing.
+    fn enrich_span(
+        _configuration: &Self::Configuration,
+        _request: ConnectorRequest<'_>,
+        _span: &tracing::Span,
+    ) {
+    }
+
     /// Get the connector's capabilities.
     ///
     /// This function implements the [capabilities endpoint](https://hasura.github.io/ndc-spec/specification/capabilities.html)
@@ -131,6 +188,54 @@ pub trait Connector: Send + 'static {
         state: &Self::State,
         request: models::QueryRequest,
     ) -> Result<JsonResponse<models::QueryResponse>>;
+
+    /// Execute a mutation, given the request as it was received
+    ///
+    /// This is called by the mutation endpoint. By default, it calls [`Connector::mutation`] with
+    /// the deserialized request. Connectors which forward requests elsewhere can override it to
+    /// send the bytes of the request on without reserializing them.
+    async fn mutation_raw(
+        configuration: &Self::Configuration,
+        state: &Self::State,
+        request: JsonRequest<models::MutationRequest>,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        let request = request
+            .into_value()
+            .map_err(|err| MutationError::new_invalid_request(&err))?;
+        Self::mutation(configuration, state, request).await
+    }
+
+    /// Execute a query, given the request as it was received
+    ///
+    /// This is called by the query endpoint. By default, it calls [`Connector::query`] with the
+    /// deserialized request. Connectors which forward requests elsewhere can override it to send
+    /// the bytes of the request on without reserializing them.
+    async fn query_raw(
+        configuration: &Self::Configuration,
+        state: &Self::State,
+        request: JsonRequest<models::QueryRequest>,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        let request = request
+            .into_value()
+            .map_err(|err| QueryError::new_invalid_request(&err))?;
+        Self::query(configuration, state, request).await
+    }
+}
+
+/// A request to one of the connector's endpoints, passed to [`Connector::enrich_span`].
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum ConnectorRequest<'a> {
+    /// A request to the schema endpoint.
+    Schema,
+    /// A request to the query endpoint.
+    Query(&'a models::QueryRequest),
+    /// A request to the query/explain endpoint.
+    QueryExplain(&'a models::QueryRequest),
+    /// A request to the mutation endpoint.
+    Mutation(&'a models::MutationRequest),
+    /// A request to the mutation/explain endpoint.
+    MutationExplain(&'a models::MutationRequest),
 }
 
 /// Connectors are set up by values that implement this trait.
@@ -145,13 +250,53 @@ pub trait ConnectorSetup: Send + Sync + 'static {
     /// Validate the configuration provided by the user, returning a configuration error or a
     /// validated [`Configuration`].
     ///
+    /// The path is usually a directory, but may also be a single file, for connectors whose
+    /// entire configuration fits in one document. [`ConfigurationPath`] can be used to
+    /// distinguish the two.
+    ///
     /// The [`ParseError`] type is provided as a convenience to connector authors, to be used on
     /// error.
+    ///
+    /// [`ConfigurationPath`]: crate::configuration::ConfigurationPath
     async fn parse_configuration(
         &self,
-        configuration_dir: &Path,
+        configuration_path: &Path,
     ) -> Result<<Self::Connector as Connector>::Configuration>;
 
+    /// Describe the configuration accepted by [`ConnectorSetup::parse_configuration`] as a JSON
+    /// Schema, if possible.
+    ///
+    /// This is used by tooling to validate configuration files before they are deployed. The
+    /// schema should describe the files as they are written by the user, which is not necessarily
+    /// the same as the validated [`Connector::Configuration`] type.
+    ///
+    /// The default implementation returns `None`, meaning no schema is available.
+    #[cfg(feature = "schemars")]
+    fn configuration_schema(&self) -> Option<schemars::schema::RootSchema> {
+        None
+    }
+
+    /// The name and version of the connector, which is reported by the `version` command.
+    ///
+    /// This is usually implemented with the [`connector_version!`](crate::connector_version)
+    /// macro. The default implementation returns `None`, in which case only the SDK and
+    /// specification versions are reported.
+    fn connector_version(&self) -> Option<ConnectorVersion> {
+        None
+    }
+
+    /// Summarize a validated configuration, which is printed by `--print-config` to help users
+    /// check what the connector will actually use.
+    ///
+    /// The summary must not contain secrets, such as passwords in connection strings. The default
+    /// implementation returns `None`, meaning no summary is available.
+    fn configuration_summary(
+        &self,
+        _configuration: &<Self::Connector as Connector>::Configuration,
+    ) -> Option<serde_json::Value> {
+        None
+    }
+
     /// Initialize the connector's in-memory state.
     ///
     /// For example, any connection pools, prepared queries, or other managed resources would be
@@ -167,3 +312,40 @@ pub trait ConnectorSetup: Send + Sync + 'static {
         metrics: &mut prometheus::Registry,
     ) -> Result<<Self::Connector as Connector>::State>;
 }
+
+// A shared setup can be used to set up a connector repeatedly, for example when reloading.
+#[async_trait]
+impl<Setup: ConnectorSetup> ConnectorSetup for Arc<Setup> {
+    type Connector = Setup::Connector;
+
+    async fn parse_configuration(
+        &self,
+        configuration_path: &Path,
+    ) -> Result<<Self::Connector as Connector>::Configuration> {
+        (**self).parse_configuration(configuration_path).await
+    }
+
+    #[cfg(feature = "schemars")]
+    fn configuration_schema(&self) -> Option<schemars::schema::RootSchema> {
+        (**self).configuration_schema()
+    }
+
+    fn connector_version(&self) -> Option<ConnectorVersion> {
+        (**self).connector_version()
+    }
+
+    fn configuration_summary(
+        &self,
+        configuration: &<Self::Connector as Connector>::Configuration,
+    ) -> Option<serde_json::Value> {
+        (**self).configuration_summary(configuration)
+    }
+
+    async fn try_init_state(
+        &self,
+        configuration: &<Self::Connector as Connector>::Configuration,
+        metrics: &mut prometheus::Registry,
+    ) -> Result<<Self::Connector as Connector>::State> {
+        (**self).try_init_state(configuration, metrics).await
+    }
+}
@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use ndc_models as models;
+
+use super::{Connector, ConnectorRequest, Result};
+use crate::health::HealthReport;
+use crate::json_request::JsonRequest;
+use crate::json_response::JsonResponse;
+use crate::state::ServerState;
+
+/// An object-safe view of a connector, with its configuration and state.
+///
+/// [`Connector`] is not object-safe, because its methods are associated functions and it has
+/// associated types. This trait erases both, so that hosts can hold connectors of different types
+/// behind `Box<dyn DynConnector>` or `Arc<dyn DynConnector>`.
+///
+/// It is implemented by [`ServerState`], so the connector state is initialized lazily, on the
+/// first call that needs it.
+#[async_trait]
+pub trait DynConnector: Send + Sync {
+    /// See [`Connector::fetch_metrics`].
+    async fn fetch_metrics(&self) -> Result<()>;
+
+    /// See [`Connector::get_health_readiness`].
+    async fn get_health_readiness(&self) -> Result<()>;
+
+    /// See [`Connector::get_health_liveness`].
+    async fn get_health_liveness(&self) -> Result<()>;
+
+    /// See [`Connector::get_health_report`].
+    async fn get_health_report(&self) -> Result<HealthReport>;
+
+    /// See [`Connector::enrich_span`].
+    fn enrich_span(&self, request: ConnectorRequest<'_>, span: &tracing::Span);
+
+    /// See [`Connector::get_capabilities`].
+    async fn get_capabilities(&self) -> models::Capabilities;
+
+    /// See [`Connector::get_schema`].
+    async fn get_schema(&self) -> Result<JsonResponse<models::SchemaResponse>>;
+
+    /// See [`Connector::query_explain`].
+    async fn query_explain(
+        &self,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>>;
+
+    /// See [`Connector::mutation_explain`].
+    async fn mutation_explain(
+        &self,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>>;
+
+    /// See [`Connector::mutation`].
+    async fn mutation(
+        &self,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::MutationResponse>>;
+
+    /// See [`Connector::query`].
+    async fn query(
+        &self,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::QueryResponse>>;
+
+    /// See [`Connector::mutation_raw`].
+    async fn mutation_raw(
+        &self,
+        request: JsonRequest<models::MutationRequest>,
+    ) -> Result<JsonResponse<models::MutationResponse>>;
+
+    /// See [`Connector::query_raw`].
+    async fn query_raw(
+        &self,
+        request: JsonRequest<models::QueryRequest>,
+    ) -> Result<JsonResponse<models::QueryResponse>>;
+
+    /// The metrics registry used by this connector.
+    fn metrics(&self) -> &prometheus::Registry;
+}
+
+#[async_trait]
+impl<C: Connector> DynConnector for ServerState<C> {
+    async fn fetch_metrics(&self) -> Result<()> {
+        C::fetch_metrics(self.configuration(), self.state().await?)
+    }
+
+    async fn get_health_readiness(&self) -> Result<()> {
+        C::get_health_readiness(self.configuration(), self.state().await?).await
+    }
+
+    async fn get_health_liveness(&self) -> Result<()> {
+        C::get_health_liveness(self.configuration(), self.initialized_state()).await
+    }
+
+    async fn get_health_report(&self) -> Result<HealthReport> {
+        C::get_health_report(self.configuration(), self.state().await?).await
+    }
+
+    fn enrich_span(&self, request: ConnectorRequest<'_>, span: &tracing::Span) {
+        C::enrich_span(self.configuration(), request, span);
+    }
+
+    async fn get_capabilities(&self) -> models::Capabilities {
+        C::get_capabilities().await
+    }
+
+    async fn get_schema(&self) -> Result<JsonResponse<models::SchemaResponse>> {
+        C::get_schema(self.configuration()).await
+    }
+
+    async fn query_explain(
+        &self,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        C::query_explain(self.configuration(), self.state().await?, request).await
+    }
+
+    async fn mutation_explain(
+        &self,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        C::mutation_explain(self.configuration(), self.state().await?, request).await
+    }
+
+    async fn mutation(
+        &self,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        C::mutation(self.configuration(), self.state().await?, request).await
+    }
+
+    async fn query(
+        &self,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        C::query(self.configuration(), self.state().await?, request).await
+    }
+
+    async fn mutation_raw(
+        &self,
+        request: JsonRequest<models::MutationRequest>,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        C::mutation_raw(self.configuration(), self.state().await?, request).await
+    }
+
+    async fn query_raw(
+        &self,
+        request: JsonRequest<models::QueryRequest>,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        C::query_raw(self.configuration(), self.state().await?, request).await
+    }
+
+    fn metrics(&self) -> &prometheus::Registry {
+        ServerState::metrics(self)
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::Display;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[cfg(feature = "axum")]
 use axum::{
@@ -7,7 +8,7 @@ use axum::{
     Json,
 };
 use http::StatusCode;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use ndc_models as models;
 
@@ -17,13 +18,22 @@ pub type Result<T> = std::result::Result<T, ErrorResponse>;
 pub struct ErrorResponse {
     status_code: StatusCode,
     inner: ndc_models::ErrorResponse,
+    retry_after: Option<Duration>,
 }
 
+/// The key in [`ErrorResponse::details`] which holds the [`ErrorCode`], if any.
+pub const ERROR_CODE_DETAILS_KEY: &str = "errorCode";
+
+/// The key in [`ErrorResponse::details`] which marks an error as retryable, holding the number of
+/// seconds to wait before retrying.
+pub const RETRY_AFTER_DETAILS_KEY: &str = "retryAfterSeconds";
+
 impl ErrorResponse {
     pub fn new(status_code: StatusCode, message: String, details: serde_json::Value) -> Self {
         Self {
             status_code,
             inner: ndc_models::ErrorResponse { message, details },
+            retry_after: None,
         }
     }
 
@@ -42,6 +52,7 @@ impl ErrorResponse {
                 message: value.to_string(),
                 details: serde_json::Value::Null,
             },
+            retry_after: None,
         }
     }
 
@@ -52,6 +63,194 @@ impl ErrorResponse {
             ..self
         }
     }
+
+    /// Construct an error with a machine-readable code, using the code's default status code.
+    pub fn new_with_code(code: ErrorCode, message: String) -> Self {
+        Self::new(code.status_code(), message, serde_json::Value::Null).with_error_code(code)
+    }
+
+    /// Attach a machine-readable code to the error, under [`ERROR_CODE_DETAILS_KEY`] in the
+    /// details.
+    ///
+    /// This does not change the status code.
+    #[must_use]
+    pub fn with_error_code(mut self, code: ErrorCode) -> Self {
+        self.insert_detail(
+            ERROR_CODE_DETAILS_KEY,
+            serde_json::Value::from(code.to_string()),
+        );
+        self
+    }
+
+    /// The machine-readable code attached to the error, if any.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        self.inner
+            .details
+            .get(ERROR_CODE_DETAILS_KEY)
+            .and_then(|code| ErrorCode::deserialize(code).ok())
+    }
+
+    /// Attach the code implied by a [`QueryError`], [`MutationError`] or [`ParseError`] if the
+    /// details are an object or absent. Details of any other shape are sent exactly as the
+    /// connector provided them.
+    fn with_implied_error_code(self, code: ErrorCode) -> Self {
+        if self.inner.details.is_object() || self.inner.details.is_null() {
+            self.with_error_code(code)
+        } else {
+            self
+        }
+    }
+
+    /// Mark the error as transient, asking the client to retry after the given duration.
+    ///
+    /// This sets the `Retry-After` header on the response, and records the number of seconds in
+    /// the details under [`RETRY_AFTER_DETAILS_KEY`]. It is usually combined with a status code
+    /// such as `503 Service Unavailable` or `429 Too Many Requests`, for example when a
+    /// connection pool is exhausted.
+    #[must_use]
+    pub fn retryable(mut self, after: Duration) -> Self {
+        self.insert_detail(
+            RETRY_AFTER_DETAILS_KEY,
+            serde_json::Value::from(whole_seconds(after)),
+        );
+        self.retry_after = Some(after);
+        self
+    }
+
+    /// How long the client should wait before retrying, if the error is retryable.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Add a key to the details object, converting the details to an object if necessary.
+    ///
+    /// Existing details which are not an object are preserved under the `cause` key.
+    fn insert_detail(&mut self, key: &str, value: serde_json::Value) {
+        let details = &mut self.inner.details;
+        if !details.is_object() {
+            let previous = std::mem::take(details);
+            let mut object = serde_json::Map::new();
+            if !previous.is_null() {
+                object.insert("cause".to_string(), previous);
+            }
+            *details = serde_json::Value::Object(object);
+        }
+        if let serde_json::Value::Object(object) = details {
+            object.insert(key.to_string(), value);
+        }
+    }
+
+    /// The HTTP status code of the response.
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    /// The error message.
+    pub fn message(&self) -> &str {
+        &self.inner.message
+    }
+
+    /// Any additional structured information about the error.
+    pub fn details(&self) -> &serde_json::Value {
+        &self.inner.details
+    }
+
+    /// Split the error into its status code, message, and details.
+    ///
+    /// This is the inverse of [`ErrorResponse::new`].
+    pub fn into_parts(self) -> (StatusCode, String, serde_json::Value) {
+        (self.status_code, self.inner.message, self.inner.details)
+    }
+
+    /// A copy of the error which is safe to return to untrusted clients.
+    ///
+    /// The details are removed, except for the [`ErrorCode`] and retry information, which never
+    /// contain connector-specific data. If `max_message_length` is provided, the message is
+    /// truncated to that many characters.
+    #[must_use]
+    pub fn redacted(&self, max_message_length: Option<usize>) -> Self {
+        let mut message = self.inner.message.clone();
+        if let Some((index, _)) = max_message_length
+            .and_then(|max_message_length| message.char_indices().nth(max_message_length))
+        {
+            message.truncate(index);
+            message.push_str("...");
+        }
+        let mut redacted = Self {
+            status_code: self.status_code,
+            inner: ndc_models::ErrorResponse {
+                message,
+                details: serde_json::Value::Null,
+            },
+            retry_after: None,
+        };
+        if let Some(code) = self.error_code() {
+            redacted = redacted.with_error_code(code);
+        }
+        if let Some(retry_after) = self.retry_after {
+            redacted = redacted.retryable(retry_after);
+        }
+        redacted
+    }
+}
+
+/// Machine-readable error codes, which allow clients to classify errors without parsing messages.
+///
+/// Codes are included in [`ErrorResponse::details`] under [`ERROR_CODE_DETAILS_KEY`].
+/// [`QueryError`] and [`MutationError`] include the appropriate code automatically when their
+/// details are an object or absent; other details are left as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// The request was malformed.
+    InvalidRequest,
+    /// The request was well formed, but could not be followed.
+    UnprocessableContent,
+    /// The request relies on an unsupported feature.
+    UnsupportedOperation,
+    /// The request would result in a conflicting state.
+    Conflict,
+    /// The request would violate a constraint in the data source.
+    ConstraintViolation,
+    /// The caller is not allowed to perform the request.
+    PermissionDenied,
+    /// A data source could not be reached.
+    UpstreamUnavailable,
+    /// A data source did not respond in time.
+    UpstreamTimeout,
+    /// An unexpected error in the connector.
+    Internal,
+}
+
+impl ErrorCode {
+    /// The HTTP status code usually associated with this code.
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            Self::InvalidRequest => StatusCode::BAD_REQUEST,
+            Self::UnprocessableContent => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::ConstraintViolation | Self::PermissionDenied => StatusCode::FORBIDDEN,
+            Self::UpstreamUnavailable => StatusCode::BAD_GATEWAY,
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(code)) => f.write_str(&code),
+            _ => Err(std::fmt::Error),
+        }
+    }
+}
+
+/// Round a duration up to a whole number of seconds, as required by `Retry-After`.
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
 }
 
 impl std::fmt::Display for ErrorResponse {
@@ -72,6 +271,39 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for ErrorResponse {
                 message: value.to_string(),
                 details: serde_json::Value::Null,
             },
+            retry_after: None,
+        }
+    }
+}
+
+/// Converts an [`anyhow::Error`] into an internal error, including the chain of causes in the
+/// message.
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for ErrorResponse {
+    fn from(value: anyhow::Error) -> Self {
+        Self {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            inner: ndc_models::ErrorResponse {
+                message: format!("{value:#}"),
+                details: serde_json::Value::Null,
+            },
+            retry_after: None,
+        }
+    }
+}
+
+/// Converts an [`eyre::Report`] into an internal error, including the chain of causes in the
+/// message.
+#[cfg(feature = "eyre")]
+impl From<eyre::Report> for ErrorResponse {
+    fn from(value: eyre::Report) -> Self {
+        Self {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            inner: ndc_models::ErrorResponse {
+                message: format!("{value:#}"),
+                details: serde_json::Value::Null,
+            },
+            retry_after: None,
         }
     }
 }
@@ -81,6 +313,7 @@ impl From<ndc_models::ErrorResponse> for ErrorResponse {
         Self {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
             inner: value,
+            retry_after: None,
         }
     }
 }
@@ -100,6 +333,7 @@ impl From<String> for ErrorResponse {
                 message: value,
                 details: serde_json::Value::Null,
             },
+            retry_after: None,
         }
     }
 }
@@ -107,7 +341,16 @@ impl From<String> for ErrorResponse {
 #[cfg(feature = "axum")]
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> Response {
-        (self.status_code, Json(self.inner)).into_response()
+        let mut response = (self.status_code, Json(&self.inner)).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response.headers_mut().insert(
+                http::header::RETRY_AFTER,
+                http::HeaderValue::from(whole_seconds(retry_after)),
+            );
+        }
+        // keep the original error around, so that middleware can inspect it
+        response.extensions_mut().insert(self);
+        response
     }
 }
 
@@ -127,9 +370,26 @@ pub enum ParseError {
     IoError(#[from] std::io::Error),
 }
 
+/// Configuration errors are reported as `422 Unprocessable Content`, with the location of each
+/// problem in the details, so that tooling can point at the offending file, line, or key.
+///
+/// I/O errors are reported as internal errors.
 impl From<ParseError> for ErrorResponse {
     fn from(value: ParseError) -> Self {
-        Self::from_error(value)
+        let details = match &value {
+            ParseError::ParseError(error) => serde_json::json!({
+                "locatedError": error.to_json(),
+            }),
+            ParseError::ValidateError(InvalidNodes(nodes)) => serde_json::json!({
+                "invalidNodes": nodes.iter().map(InvalidNode::to_json).collect::<Vec<_>>(),
+            }),
+            ParseError::CouldNotFindConfiguration(file_path) => serde_json::json!({
+                "filePath": file_path.display().to_string(),
+            }),
+            ParseError::IoError(_) => return Self::from_error(value),
+        };
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, value.to_string(), details)
+            .with_implied_error_code(ErrorCode::UnprocessableContent)
     }
 }
 
@@ -142,6 +402,17 @@ pub struct LocatedError {
     pub message: String,
 }
 
+impl LocatedError {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "filePath": self.file_path.display().to_string(),
+            "line": self.line,
+            "column": self.column,
+            "message": self.message,
+        })
+    }
+}
+
 impl Display for LocatedError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -163,6 +434,16 @@ pub struct InvalidNode {
     pub message: String,
 }
 
+impl InvalidNode {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "filePath": self.file_path.display().to_string(),
+            "nodePath": self.node_path,
+            "message": self.message,
+        })
+    }
+}
+
 impl Display for InvalidNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}, at ", self.file_path.display())?;
@@ -212,6 +493,7 @@ impl Display for KeyOrIndex {
 ///
 /// See [`Connector::query`].
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum QueryError {
     /// The request was invalid or did not match the
     /// requirements of the specification. This indicates
@@ -228,6 +510,16 @@ pub enum QueryError {
     /// or just an unimplemented feature.
     #[error("unsupported operation: {}", .0.message)]
     UnsupportedOperation(models::ErrorResponse),
+    /// A data source which the connector depends on could
+    /// not be reached or returned an error. This does not
+    /// indicate an error with the client or the connector,
+    /// and the request may succeed if retried.
+    #[error("upstream unavailable: {}", .0.message)]
+    UpstreamUnavailable(models::ErrorResponse),
+    /// A data source which the connector depends on did
+    /// not respond in time.
+    #[error("upstream timeout: {}", .0.message)]
+    Timeout(models::ErrorResponse),
 }
 
 impl QueryError {
@@ -252,6 +544,31 @@ impl QueryError {
         })
     }
 
+    pub fn new_upstream_unavailable<T: ToString>(message: &T) -> Self {
+        Self::UpstreamUnavailable(models::ErrorResponse {
+            message: message.to_string(),
+            details: serde_json::Value::Null,
+        })
+    }
+
+    pub fn new_timeout<T: ToString>(message: &T) -> Self {
+        Self::Timeout(models::ErrorResponse {
+            message: message.to_string(),
+            details: serde_json::Value::Null,
+        })
+    }
+
+    /// The machine-readable code for this error.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Self::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Self::UnprocessableContent(_) => ErrorCode::UnprocessableContent,
+            Self::UnsupportedOperation(_) => ErrorCode::UnsupportedOperation,
+            Self::UpstreamUnavailable(_) => ErrorCode::UpstreamUnavailable,
+            Self::Timeout(_) => ErrorCode::UpstreamTimeout,
+        }
+    }
+
     #[must_use]
     pub fn with_details(self, details: serde_json::Value) -> Self {
         match self {
@@ -264,13 +581,20 @@ impl QueryError {
             Self::UnsupportedOperation(models::ErrorResponse { message, .. }) => {
                 Self::UnsupportedOperation(models::ErrorResponse { message, details })
             }
+            Self::UpstreamUnavailable(models::ErrorResponse { message, .. }) => {
+                Self::UpstreamUnavailable(models::ErrorResponse { message, details })
+            }
+            Self::Timeout(models::ErrorResponse { message, .. }) => {
+                Self::Timeout(models::ErrorResponse { message, details })
+            }
         }
     }
 }
 
 impl From<QueryError> for ErrorResponse {
     fn from(value: QueryError) -> Self {
-        match value {
+        let code = value.error_code();
+        let response = match value {
             QueryError::InvalidRequest(err) => {
                 ErrorResponse::from(err).with_status_code(StatusCode::BAD_REQUEST)
             }
@@ -280,7 +604,14 @@ impl From<QueryError> for ErrorResponse {
             QueryError::UnsupportedOperation(err) => {
                 ErrorResponse::from(err).with_status_code(StatusCode::NOT_IMPLEMENTED)
             }
-        }
+            QueryError::UpstreamUnavailable(err) => {
+                ErrorResponse::from(err).with_status_code(StatusCode::BAD_GATEWAY)
+            }
+            QueryError::Timeout(err) => {
+                ErrorResponse::from(err).with_status_code(StatusCode::GATEWAY_TIMEOUT)
+            }
+        };
+        response.with_implied_error_code(code)
     }
 }
 
@@ -288,6 +619,7 @@ impl From<QueryError> for ErrorResponse {
 ///
 /// See [`Connector::mutation`].
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum MutationError {
     /// The request was invalid or did not match the
     /// requirements of the specification. This indicates
@@ -312,6 +644,16 @@ pub enum MutationError {
     /// underlying data store.
     #[error("mutation violates constraint: {}", .0.message)]
     ConstraintNotMet(models::ErrorResponse),
+    /// A data source which the connector depends on could
+    /// not be reached or returned an error. This does not
+    /// indicate an error with the client or the connector,
+    /// and the request may succeed if retried.
+    #[error("upstream unavailable: {}", .0.message)]
+    UpstreamUnavailable(models::ErrorResponse),
+    /// A data source which the connector depends on did
+    /// not respond in time.
+    #[error("upstream timeout: {}", .0.message)]
+    Timeout(models::ErrorResponse),
 }
 
 impl MutationError {
@@ -350,6 +692,33 @@ impl MutationError {
         })
     }
 
+    pub fn new_upstream_unavailable<T: ToString>(message: &T) -> Self {
+        Self::UpstreamUnavailable(models::ErrorResponse {
+            message: message.to_string(),
+            details: serde_json::Value::Null,
+        })
+    }
+
+    pub fn new_timeout<T: ToString>(message: &T) -> Self {
+        Self::Timeout(models::ErrorResponse {
+            message: message.to_string(),
+            details: serde_json::Value::Null,
+        })
+    }
+
+    /// The machine-readable code for this error.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Self::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Self::UnprocessableContent(_) => ErrorCode::UnprocessableContent,
+            Self::UnsupportedOperation(_) => ErrorCode::UnsupportedOperation,
+            Self::Conflict(_) => ErrorCode::Conflict,
+            Self::ConstraintNotMet(_) => ErrorCode::ConstraintViolation,
+            Self::UpstreamUnavailable(_) => ErrorCode::UpstreamUnavailable,
+            Self::Timeout(_) => ErrorCode::UpstreamTimeout,
+        }
+    }
+
     #[must_use]
     pub fn with_details(self, details: serde_json::Value) -> Self {
         match self {
@@ -368,13 +737,20 @@ impl MutationError {
             Self::ConstraintNotMet(models::ErrorResponse { message, .. }) => {
                 Self::ConstraintNotMet(models::ErrorResponse { message, details })
             }
+            Self::UpstreamUnavailable(models::ErrorResponse { message, .. }) => {
+                Self::UpstreamUnavailable(models::ErrorResponse { message, details })
+            }
+            Self::Timeout(models::ErrorResponse { message, .. }) => {
+                Self::Timeout(models::ErrorResponse { message, details })
+            }
         }
     }
 }
 
 impl From<MutationError> for ErrorResponse {
     fn from(value: MutationError) -> Self {
-        match value {
+        let code = value.error_code();
+        let response = match value {
             MutationError::InvalidRequest(err) => {
                 ErrorResponse::from(err).with_status_code(StatusCode::BAD_REQUEST)
             }
@@ -390,6 +766,166 @@ impl From<MutationError> for ErrorResponse {
             MutationError::ConstraintNotMet(err) => {
                 ErrorResponse::from(err).with_status_code(StatusCode::FORBIDDEN)
             }
-        }
+            MutationError::UpstreamUnavailable(err) => {
+                ErrorResponse::from(err).with_status_code(StatusCode::BAD_GATEWAY)
+            }
+            MutationError::Timeout(err) => {
+                ErrorResponse::from(err).with_status_code(StatusCode::GATEWAY_TIMEOUT)
+            }
+        };
+        response.with_implied_error_code(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::StatusCode;
+    use serde_json::json;
+
+    use std::path::PathBuf;
+
+    use super::{
+        ErrorCode, ErrorResponse, InvalidNode, InvalidNodes, KeyOrIndex, LocatedError, ParseError,
+        QueryError,
+    };
+
+    #[test]
+    fn query_errors_include_their_error_code() {
+        let error = ErrorResponse::from(
+            QueryError::new_timeout(&"database did not respond")
+                .with_details(json!({ "timeoutSeconds": 30 })),
+        );
+        assert_eq!(error.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(error.error_code(), Some(ErrorCode::UpstreamTimeout));
+        assert_eq!(
+            error.details(),
+            &json!({ "timeoutSeconds": 30, "errorCode": "upstreamTimeout" })
+        );
+    }
+
+    #[test]
+    fn query_errors_without_details_include_their_error_code() {
+        let error = ErrorResponse::from(QueryError::new_invalid_request(&"unknown collection"));
+        assert_eq!(error.details(), &json!({ "errorCode": "invalidRequest" }));
+        assert_eq!(error.error_code(), Some(ErrorCode::InvalidRequest));
+    }
+
+    #[test]
+    fn query_errors_leave_details_which_are_not_objects_alone() {
+        let error = ErrorResponse::from(
+            QueryError::new_invalid_request(&"unknown collection").with_details(json!(["authors"])),
+        );
+        assert_eq!(error.details(), &json!(["authors"]));
+    }
+
+    #[test]
+    fn parse_errors_are_unprocessable_with_their_location() {
+        let error = ErrorResponse::from(ParseError::ParseError(LocatedError {
+            file_path: PathBuf::from("configuration.json"),
+            line: 3,
+            column: 14,
+            message: "expected a string".to_string(),
+        }));
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            error.details(),
+            &json!({
+                "locatedError": {
+                    "filePath": "configuration.json",
+                    "line": 3,
+                    "column": 14,
+                    "message": "expected a string",
+                },
+                "errorCode": "unprocessableContent",
+            })
+        );
+    }
+
+    #[test]
+    fn validation_errors_are_unprocessable_with_their_invalid_nodes() {
+        let error =
+            ErrorResponse::from(ParseError::ValidateError(InvalidNodes(vec![InvalidNode {
+                file_path: PathBuf::from("configuration.json"),
+                node_path: vec![KeyOrIndex::Key("tables".to_string()), KeyOrIndex::Index(0)],
+                message: "unknown table".to_string(),
+            }])));
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            error.details(),
+            &json!({
+                "invalidNodes": [{
+                    "filePath": "configuration.json",
+                    "nodePath": ["tables", 0],
+                    "message": "unknown table",
+                }],
+                "errorCode": "unprocessableContent",
+            })
+        );
+    }
+
+    #[test]
+    fn retryable_errors_preserve_existing_details() {
+        let error = ErrorResponse::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "connection pool exhausted".to_string(),
+            json!("pool size: 10"),
+        )
+        .retryable(Duration::from_millis(1500));
+        assert_eq!(error.retry_after(), Some(Duration::from_millis(1500)));
+        assert_eq!(
+            error.details(),
+            &json!({ "cause": "pool size: 10", "retryAfterSeconds": 2 })
+        );
+    }
+
+    #[test]
+    fn redacted_errors_keep_only_safe_details() {
+        let error = ErrorResponse::from(
+            QueryError::new_upstream_unavailable(&"could not connect to postgres://admin@db")
+                .with_details(json!({ "connectionString": "postgres://admin:secret@db" })),
+        )
+        .retryable(Duration::from_secs(5));
+        let redacted = error.redacted(Some(20));
+        assert_eq!(redacted.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(redacted.message(), "could not connect to...");
+        assert_eq!(
+            redacted.details(),
+            &json!({ "errorCode": "upstreamUnavailable", "retryAfterSeconds": 5 })
+        );
+        assert_eq!(redacted.retry_after(), Some(Duration::from_secs(5)));
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn converts_anyhow_errors_with_their_causes() {
+        let error = ErrorResponse::from(
+            anyhow::anyhow!("connection refused").context("could not connect to the database"),
+        );
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            error.message(),
+            "could not connect to the database: connection refused"
+        );
+        assert_eq!(error.details(), &serde_json::Value::Null);
+    }
+
+    #[cfg(feature = "eyre")]
+    #[test]
+    fn converts_eyre_reports_with_their_causes() {
+        use eyre::WrapErr as _;
+
+        let error = ErrorResponse::from(
+            Err::<(), _>(eyre::eyre!("connection refused"))
+                .wrap_err("could not connect to the database")
+                .unwrap_err(),
+        );
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            error.message(),
+            "could not connect to the database: connection refused"
+        );
+        assert_eq!(error.details(), &serde_json::Value::Null);
     }
 }
@@ -15,7 +15,7 @@ impl ConnectorSetup for Example {
 
     async fn parse_configuration(
         &self,
-        _configuration_dir: &Path,
+        _configuration_path: &Path,
     ) -> Result<<Self as Connector>::Configuration> {
         Ok(())
     }
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Serializer};
+
+/// A structured health report, describing the status of each of a connector's components.
+///
+/// The `/health` endpoint returns this report as JSON when the `verbose=true` query parameter is
+/// provided. Otherwise, only the status code reflects the overall status.
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    components: BTreeMap<String, ComponentHealth>,
+}
+
+impl HealthReport {
+    /// Create a healthy report with no components.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the status of a named component, such as a connection pool or a cache, replacing any
+    /// previous status of a component with the same name.
+    ///
+    /// If any component is unhealthy, the report as a whole is unhealthy.
+    #[must_use]
+    pub fn with_component(mut self, name: impl Into<String>, health: ComponentHealth) -> Self {
+        self.components.insert(name.into(), health);
+        self
+    }
+
+    /// The overall status, which is unhealthy if any component is unhealthy.
+    pub fn status(&self) -> HealthStatus {
+        if self
+            .components
+            .values()
+            .any(|health| health.status == HealthStatus::Unhealthy)
+        {
+            HealthStatus::Unhealthy
+        } else {
+            HealthStatus::Healthy
+        }
+    }
+
+    /// Whether every component is healthy.
+    pub fn is_healthy(&self) -> bool {
+        self.status() == HealthStatus::Healthy
+    }
+
+    /// The status of each component, by name.
+    pub fn components(&self) -> &BTreeMap<String, ComponentHealth> {
+        &self.components
+    }
+}
+
+/// The report is serialized with its overall status, along with the status of each component.
+impl Serialize for HealthReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            status: HealthStatus,
+            components: &'a BTreeMap<String, ComponentHealth>,
+        }
+
+        Report {
+            status: self.status(),
+            components: &self.components,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// The health of a single component.
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl ComponentHealth {
+    pub fn healthy() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            message: None,
+        }
+    }
+
+    pub fn unhealthy(message: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Unhealthy,
+            message: Some(message.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthStatus {
+    #[default]
+    Healthy,
+    Unhealthy,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn is_unhealthy_if_any_component_is_unhealthy() -> anyhow::Result<()> {
+        let report = HealthReport::new();
+        assert!(report.is_healthy());
+
+        let report = report.with_component("pool", ComponentHealth::healthy());
+        assert_eq!(report.status(), HealthStatus::Healthy);
+
+        let report = report.with_component("cache", ComponentHealth::unhealthy("unreachable"));
+        assert_eq!(report.status(), HealthStatus::Unhealthy);
+        assert!(!report.is_healthy());
+        assert_eq!(
+            serde_json::to_value(&report)?,
+            json!({
+                "status": "unhealthy",
+                "components": {
+                    "cache": { "status": "unhealthy", "message": "unreachable" },
+                    "pool": { "status": "healthy" },
+                },
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn is_healthy_once_an_unhealthy_component_is_replaced() {
+        let report = HealthReport::new()
+            .with_component("pool", ComponentHealth::unhealthy("connecting"))
+            .with_component("pool", ComponentHealth::healthy());
+        assert!(report.is_healthy());
+        assert_eq!(report.components().len(), 1);
+    }
+}
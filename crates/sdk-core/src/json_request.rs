@@ -0,0 +1,197 @@
+use std::sync::OnceLock;
+
+#[cfg(feature = "axum")]
+use async_trait::async_trait;
+#[cfg(feature = "axum")]
+use axum::{extract::FromRequest, http::Request};
+use bytes::Bytes;
+
+/// Represents a request value that was received as JSON.
+///
+/// This is the counterpart of [`JsonResponse`](crate::json_response::JsonResponse). It keeps the
+/// bytes of the request as they were received, and deserializes them into a value of type `A`
+/// only when the value is first needed, so that connectors which forward requests elsewhere can
+/// send the bytes on without deserializing and reserializing them.
+///
+/// A request may also be constructed from a value, in which case it is serialized if the bytes
+/// are needed.
+#[derive(Debug)]
+pub struct JsonRequest<A> {
+    bytes: Option<Bytes>,
+    value: OnceLock<A>,
+}
+
+impl<A> From<A> for JsonRequest<A> {
+    fn from(value: A) -> Self {
+        Self {
+            bytes: None,
+            value: OnceLock::from(value),
+        }
+    }
+}
+
+impl<A> JsonRequest<A> {
+    /// Wraps a serialized JSON bytestring that is _assumed_ to represent a value of type `A`. It
+    /// is not deserialized until the value is needed.
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        Self {
+            bytes: Some(bytes),
+            value: OnceLock::new(),
+        }
+    }
+
+    /// Wraps a serialized JSON bytestring along with the value which it has already been
+    /// deserialized into.
+    pub fn from_parts(bytes: Bytes, value: A) -> Self {
+        Self {
+            bytes: Some(bytes),
+            value: OnceLock::from(value),
+        }
+    }
+
+    /// The bytes of the request as they were received, if it was received as JSON rather than
+    /// constructed from a value.
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        self.bytes.as_ref()
+    }
+}
+
+impl<A: (for<'de> serde::Deserialize<'de>)> JsonRequest<A> {
+    /// The value of the request, deserializing the bytes the first time it is needed.
+    pub fn value(&self) -> Result<&A, serde_json::Error> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let bytes = self.bytes.as_deref().unwrap_or_default();
+        let value = serde_json::de::from_slice(bytes)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+
+    /// Unwraps the value, deserializing if necessary.
+    pub fn into_value(self) -> Result<A, serde_json::Error> {
+        match self.value.into_inner() {
+            Some(value) => Ok(value),
+            None => serde_json::de::from_slice(self.bytes.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+impl<A: serde::Serialize> JsonRequest<A> {
+    /// The request as serialized JSON, which is the bytes as they were received if there are
+    /// any, and the serialized value otherwise.
+    pub fn into_bytes(self) -> Result<Bytes, serde_json::Error> {
+        match (self.bytes, self.value.into_inner()) {
+            (Some(bytes), _) => Ok(bytes),
+            (None, Some(value)) => serde_json::to_vec(&value).map(Bytes::from),
+            (None, None) => Ok(Bytes::new()),
+        }
+    }
+}
+
+/// Extracts the request body without deserializing it.
+///
+/// The body is rejected, as it would be by the `Json` extractor, if the content type is not JSON
+/// or the body is not syntactically valid JSON. Whether it represents a value of type `A` is only
+/// checked when the value is needed.
+#[cfg(feature = "axum")]
+#[async_trait]
+impl<S, B, A> FromRequest<S, B> for JsonRequest<A>
+where
+    S: Send + Sync,
+    B: axum::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<axum::BoxError>,
+    A: Send,
+{
+    type Rejection = axum::extract::rejection::JsonRejection;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(raw) =
+            axum::Json::<Box<serde_json::value::RawValue>>::from_request(request, state).await?;
+        Ok(Self::from_bytes(Bytes::from(
+            Box::<str>::from(raw).into_boxed_bytes(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing, Router};
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::testing::TestClient;
+
+    #[test]
+    fn deserializes_bytes_when_the_value_is_needed() -> anyhow::Result<()> {
+        let request = JsonRequest::<Person>::from_bytes(Bytes::from(r#"{"name":"Ann","age":4}"#));
+        assert_eq!(request.value()?.name, "Ann");
+        assert_eq!(request.value()?.age, 4);
+        assert_eq!(request.into_value()?.name, "Ann");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_the_bytes_which_were_received() -> anyhow::Result<()> {
+        // the order of the fields and the whitespace are preserved
+        let bytes = Bytes::from(r#"{ "age": 4, "name": "Ann" }"#);
+        let request = JsonRequest::<Person>::from_bytes(bytes.clone());
+        request.value()?;
+        assert_eq!(request.as_bytes(), Some(&bytes));
+        assert_eq!(request.into_bytes()?, bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn serializes_values_into_bytes() -> anyhow::Result<()> {
+        let request = JsonRequest::from(Person {
+            name: "Ben".to_owned(),
+            age: 50,
+        });
+        assert!(request.as_bytes().is_none());
+        assert_eq!(request.into_bytes()?, r#"{"name":"Ben","age":50}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_to_deserialize_bytes_of_another_type() {
+        let request = JsonRequest::<Person>::from_bytes(Bytes::from(r#"{"name":"Cat"}"#));
+        assert!(request.value().is_err());
+        assert!(request.into_value().is_err());
+    }
+
+    #[tokio::test]
+    async fn extracts_the_body_without_deserializing_it() -> anyhow::Result<()> {
+        let app = Router::new().route(
+            "/",
+            routing::post(|request: JsonRequest<Person>| async move {
+                request.as_bytes().cloned().unwrap_or_default()
+            }),
+        );
+
+        let client = TestClient::new(app)?;
+        let response = client
+            .post("/")
+            .header("Content-Type", "application/json")
+            .body(r#"{"unexpected":true}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await?, r#"{"unexpected":true}"#);
+
+        let response = client
+            .post("/")
+            .header("Content-Type", "application/json")
+            .body(r#"{"name":"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    struct Person {
+        name: String,
+        age: u16,
+    }
+}
@@ -1,14 +1,24 @@
+use std::fmt;
+use std::io::{self, Read as _, Write as _};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
 #[cfg(feature = "axum")]
 use axum::response::IntoResponse;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt as _};
 #[cfg(feature = "axum")]
-use http::{header, HeaderValue};
+use http::header;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::connector::ErrorResponse;
 
 /// Represents a response value that will be serialized to JSON.
 ///
 /// The value may be of a type that implements `serde::Serialize`, or it may be
 /// a contiguous sequence of bytes, which are _assumed_ to be valid JSON.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum JsonResponse<A> {
     /// A value that can be serialized to JSON.
     Value(A),
@@ -16,6 +26,102 @@ pub enum JsonResponse<A> {
     /// type `A`. This is not guaranteed by the SDK; the connector is
     /// responsible for ensuring this.
     Serialized(Bytes),
+    /// A stream of chunks of serialized JSON which, concatenated, are assumed
+    /// to represent a value of type `A`, as with [`JsonResponse::Serialized`].
+    ///
+    /// The chunks are written to the response body as they are produced, so
+    /// the response is never buffered in full. If the stream fails, the
+    /// response is cut short, as the status code has already been sent.
+    Stream(JsonStream),
+    /// A serialized JSON bytestring, as for [`JsonResponse::Serialized`],
+    /// which has already been compressed, for example because the connector
+    /// caches compressed results.
+    ///
+    /// The bytes are sent as they are, with a `Content-Encoding` header. The
+    /// `serve` command decompresses them for clients which do not accept the
+    /// encoding.
+    Compressed {
+        bytes: Bytes,
+        encoding: ContentEncoding,
+    },
+    /// A response with additional headers, such as `Cache-Control`, which are
+    /// sent along with it. They replace any headers of the same name which
+    /// would otherwise be set.
+    WithHeaders {
+        headers: HeaderMap,
+        response: Box<JsonResponse<A>>,
+    },
+}
+
+/// A content coding applied to a [`JsonResponse::Compressed`] response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ContentEncoding {
+    /// The `gzip` coding.
+    Gzip,
+    /// The `deflate` coding, which is zlib-wrapped DEFLATE.
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// The name of the coding, as used in `Content-Encoding` headers.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Decompress bytes which were compressed with this coding.
+    pub fn decode(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        match self {
+            Self::Gzip => flate2::read::GzDecoder::new(bytes).read_to_end(&mut decoded)?,
+            Self::Deflate => flate2::read::ZlibDecoder::new(bytes).read_to_end(&mut decoded)?,
+        };
+        Ok(decoded)
+    }
+
+    /// Compress bytes with this coding.
+    pub fn encode(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Self::Deflate => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// A stream of chunks of serialized JSON, for [`JsonResponse::Stream`].
+pub struct JsonStream(Pin<Box<dyn Stream<Item = Result<Bytes, ErrorResponse>> + Send>>);
+
+impl JsonStream {
+    pub fn new(stream: impl Stream<Item = Result<Bytes, ErrorResponse>> + Send + 'static) -> Self {
+        Self(Box::pin(stream))
+    }
+}
+
+impl fmt::Debug for JsonStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JsonStream(..)")
+    }
+}
+
+impl Stream for JsonStream {
+    type Item = Result<Bytes, ErrorResponse>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
 }
 
 impl<A> From<A> for JsonResponse<A> {
@@ -24,9 +130,91 @@ impl<A> From<A> for JsonResponse<A> {
     }
 }
 
+impl<A> JsonResponse<A> {
+    /// Wraps a raw JSON value, such as a fragment of a response composed by
+    /// the connector, as a [`JsonResponse::Serialized`] bytestring without
+    /// copying it.
+    ///
+    /// This is not a `From` implementation, because it would overlap with
+    /// `From<A>` when `A` is itself a `Box<RawValue>`.
+    pub fn from_raw_value(value: Box<serde_json::value::RawValue>) -> Self {
+        Self::Serialized(Bytes::from(Box::<str>::from(value).into_boxed_bytes()))
+    }
+
+    /// Adds a header to the response.
+    #[must_use]
+    pub fn with_header(self, name: HeaderName, value: HeaderValue) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value);
+        self.with_headers(headers)
+    }
+
+    /// Adds headers to the response, replacing any added before with the same
+    /// names.
+    #[must_use]
+    pub fn with_headers(self, headers: HeaderMap) -> Self {
+        match self {
+            Self::WithHeaders {
+                headers: mut existing,
+                response,
+            } => {
+                existing.extend(headers);
+                Self::WithHeaders {
+                    headers: existing,
+                    response,
+                }
+            }
+            response => Self::WithHeaders {
+                headers,
+                response: Box::new(response),
+            },
+        }
+    }
+
+    /// The response without any additional headers.
+    pub fn body(&self) -> &Self {
+        match self {
+            Self::WithHeaders { response, .. } => response.body(),
+            response => response,
+        }
+    }
+
+    /// The serialized bytes, if this is a [`JsonResponse::Serialized`]
+    /// response.
+    pub fn as_serialized(&self) -> Option<&Bytes> {
+        match self {
+            Self::Serialized(bytes) => Some(bytes),
+            Self::WithHeaders { response, .. } => response.as_serialized(),
+            Self::Value(_) | Self::Stream(_) | Self::Compressed { .. } => None,
+        }
+    }
+
+    /// Collects a streamed response into a single [`JsonResponse::Serialized`]
+    /// bytestring. Other responses are returned unchanged.
+    pub async fn buffer(self) -> Result<Self, ErrorResponse> {
+        match self {
+            Self::Stream(mut stream) => {
+                let mut buffer = BytesMut::new();
+                while let Some(chunk) = stream.next().await {
+                    buffer.extend_from_slice(&chunk?);
+                }
+                Ok(Self::Serialized(buffer.freeze()))
+            }
+            Self::WithHeaders { headers, response } => Ok(Self::WithHeaders {
+                headers,
+                response: Box::new(Box::pin(response.buffer()).await?),
+            }),
+            response => Ok(response),
+        }
+    }
+}
+
 impl<A: (for<'de> serde::Deserialize<'de>)> JsonResponse<A> {
     /// Unwraps the value, deserializing if necessary.
     ///
+    /// Streamed responses must be collected with [`JsonResponse::buffer`]
+    /// first.
+    ///
     /// This is only intended for testing and compatibility. If it lives on a
     /// critical path, we recommend you avoid it.
     pub fn into_value<E: From<Box<dyn std::error::Error + Send + Sync>>>(self) -> Result<A, E> {
@@ -35,6 +223,92 @@ impl<A: (for<'de> serde::Deserialize<'de>)> JsonResponse<A> {
             Self::Serialized(bytes) => {
                 serde_json::de::from_slice(&bytes).map_err(|err| E::from(Box::new(err)))
             }
+            Self::Stream(_) => Err(E::from(
+                "a streamed response must be buffered before it is deserialized".into(),
+            )),
+            Self::Compressed { bytes, encoding } => {
+                let bytes = encoding
+                    .decode(&bytes)
+                    .map_err(|err| E::from(Box::new(err)))?;
+                serde_json::de::from_slice(&bytes).map_err(|err| E::from(Box::new(err)))
+            }
+            Self::WithHeaders { response, .. } => response.into_value(),
+        }
+    }
+
+    /// Checks that a serialized response represents a value of type `A`, by
+    /// deserializing it.
+    ///
+    /// Values are valid by construction, and streamed responses are not
+    /// checked, as they can only be read once; collect them with
+    /// [`JsonResponse::buffer`] first.
+    pub fn validate(&self) -> Result<(), serde_json::Error> {
+        match self {
+            Self::Serialized(bytes) => serde_json::de::from_slice::<A>(bytes).map(|_| ()),
+            Self::Compressed { bytes, encoding } => {
+                let bytes = encoding.decode(bytes).map_err(serde_json::Error::io)?;
+                serde_json::de::from_slice::<A>(&bytes).map(|_| ())
+            }
+            Self::WithHeaders { response, .. } => response.validate(),
+            Self::Value(_) | Self::Stream(_) => Ok(()),
+        }
+    }
+}
+
+impl<A: serde::Serialize + Send + 'static> JsonResponse<A> {
+    /// Serializes a value on tokio's blocking thread pool, producing a
+    /// [`JsonResponse::Serialized`] bytestring. Other responses are returned
+    /// unchanged.
+    ///
+    /// Serializing a very large value can take long enough to stall the async
+    /// worker thread it runs on, delaying every other request scheduled on
+    /// that thread. Connectors which know that a response is large can call
+    /// this to avoid that.
+    ///
+    /// Without the `native` feature, there is no blocking thread pool, so the
+    /// value is serialized in place.
+    pub async fn serialize_blocking(self) -> Result<Self, ErrorResponse> {
+        match self {
+            #[cfg(feature = "native")]
+            Self::Value(value) => {
+                let bytes = tokio::task::spawn_blocking(move || serde_json::to_vec(&value))
+                    .await
+                    .map_err(ErrorResponse::from_error)?
+                    .map_err(ErrorResponse::from_error)?;
+                Ok(Self::Serialized(Bytes::from(bytes)))
+            }
+            #[cfg(not(feature = "native"))]
+            Self::Value(value) => {
+                let bytes = serde_json::to_vec(&value).map_err(ErrorResponse::from_error)?;
+                Ok(Self::Serialized(Bytes::from(bytes)))
+            }
+            Self::WithHeaders { headers, response } => Ok(Self::WithHeaders {
+                headers,
+                response: Box::new(Box::pin(response.serialize_blocking()).await?),
+            }),
+            response => Ok(response),
+        }
+    }
+}
+
+impl<A: serde::Serialize> JsonResponse<A> {
+    /// The response as serialized JSON, serializing the value or
+    /// decompressing the bytes if necessary.
+    ///
+    /// Streamed responses must be collected with [`JsonResponse::buffer`]
+    /// first.
+    pub fn into_bytes(self) -> Result<Bytes, serde_json::Error> {
+        match self {
+            Self::Value(value) => serde_json::to_vec(&value).map(Bytes::from),
+            Self::Serialized(bytes) => Ok(bytes),
+            Self::Stream(_) => Err(serde::ser::Error::custom(
+                "a streamed response must be buffered before it is converted to bytes",
+            )),
+            Self::Compressed { bytes, encoding } => encoding
+                .decode(&bytes)
+                .map(Bytes::from)
+                .map_err(serde_json::Error::io),
+            Self::WithHeaders { response, .. } => response.into_bytes(),
         }
     }
 }
@@ -42,27 +316,47 @@ impl<A: (for<'de> serde::Deserialize<'de>)> JsonResponse<A> {
 #[cfg(feature = "axum")]
 impl<A: serde::Serialize> IntoResponse for JsonResponse<A> {
     fn into_response(self) -> axum::response::Response {
+        let content_type = [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
+        )];
         match self {
             Self::Value(value) => axum::Json(value).into_response(),
-            Self::Serialized(bytes) => (
-                [(
-                    header::CONTENT_TYPE,
-                    HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
-                )],
-                bytes,
-            )
-                .into_response(),
+            Self::Serialized(bytes) => (content_type, bytes).into_response(),
+            Self::Stream(stream) => {
+                (content_type, axum::body::StreamBody::new(stream)).into_response()
+            }
+            Self::Compressed { bytes, encoding } => {
+                let mut response = (
+                    content_type,
+                    [(
+                        header::CONTENT_ENCODING,
+                        HeaderValue::from_static(encoding.as_str()),
+                    )],
+                    bytes,
+                )
+                    .into_response();
+                // lets middleware tell these apart from responses compressed in transit
+                response.extensions_mut().insert(encoding);
+                response
+            }
+            Self::WithHeaders { headers, response } => {
+                let mut response = response.into_response();
+                response.headers_mut().extend(headers);
+                response
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use axum::body::HttpBody as _;
     use axum::{routing, Router};
     use reqwest::StatusCode;
 
-    use super::test_client::TestClient;
     use super::*;
+    use crate::testing::TestClient;
 
     #[tokio::test]
     async fn serializes_value_to_json() -> anyhow::Result<()> {
@@ -117,47 +411,231 @@ mod tests {
         Ok(())
     }
 
-    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-    struct Person {
-        name: String,
-        age: u16,
+    #[tokio::test]
+    async fn sends_serialized_bytes_without_copying() -> anyhow::Result<()> {
+        let bytes = Bytes::from(r#"{"name":"Bob Burger","age":7}"#);
+        let responses = [
+            JsonResponse::<Person>::Serialized(bytes.clone()),
+            JsonResponse::<Person>::Serialized(bytes.clone())
+                .with_header(header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+            JsonResponse::<Person>::Compressed {
+                bytes: bytes.clone(),
+                encoding: ContentEncoding::Gzip,
+            },
+        ];
+
+        for response in responses {
+            let mut body = response.into_response().into_body();
+            let chunk = body.data().await.expect("the body is empty")?;
+            // the body is the same allocation as the response, rather than a copy of it
+            assert_eq!(chunk.as_ptr(), bytes.as_ptr());
+            assert_eq!(chunk.len(), bytes.len());
+            assert!(body.data().await.is_none());
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-pub mod test_client {
-    use std::net::SocketAddr;
+    #[tokio::test]
+    async fn streams_json_chunks() -> anyhow::Result<()> {
+        let app = Router::new().route(
+            "/",
+            routing::get(|| async {
+                let chunks = [r#"{"name":"#, r#""Carol Cooper","#, r#""age":35}"#]
+                    .map(|chunk| Ok(Bytes::from(chunk)));
+                JsonResponse::Stream::<Person>(JsonStream::new(futures::stream::iter(chunks)))
+            }),
+        );
 
-    const LOCALHOST: std::net::IpAddr = std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST);
+        let client = TestClient::new(app)?;
+        let response = client.get("/").send().await?;
 
-    pub struct TestClient {
-        address: SocketAddr,
-        client: reqwest::Client,
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_all("Content-Type").iter().collect::<Vec<_>>(),
+            vec!["application/json"]
+        );
+
+        let body = response.text().await?;
+        assert_eq!(body, r#"{"name":"Carol Cooper","age":35}"#);
+        Ok(())
     }
 
-    impl TestClient {
-        pub fn new(router: axum::Router) -> anyhow::Result<Self> {
-            let listener = std::net::TcpListener::bind(std::net::SocketAddr::new(LOCALHOST, 0))?;
-            let address = listener.local_addr()?;
+    #[tokio::test]
+    async fn buffers_streamed_responses() -> anyhow::Result<()> {
+        let chunks = [r#"{"name":"Dan","#, r#""age":3}"#].map(|chunk| Ok(Bytes::from(chunk)));
+        let response =
+            JsonResponse::<Person>::Stream(JsonStream::new(futures::stream::iter(chunks)));
 
-            // we ignore the handle and let the test runner clean up the server
-            tokio::spawn(async move {
-                axum::Server::from_tcp(listener)
-                    .expect("server error")
-                    .serve(router.into_make_service())
-                    .await
-                    .expect("server error");
-            });
+        let person = response
+            .buffer()
+            .await?
+            .into_value::<Box<dyn std::error::Error + Send + Sync>>()
+            .map_err(|err| anyhow::anyhow!(err))?;
+        assert_eq!(person.name, "Dan");
+        assert_eq!(person.age, 3);
+        Ok(())
+    }
 
-            let client = reqwest::Client::builder()
-                .redirect(reqwest::redirect::Policy::none())
-                .build()?;
+    #[test]
+    fn validates_serialized_bytes_against_the_type() {
+        let valid = JsonResponse::<Person>::Serialized(Bytes::from(r#"{"name":"Eve","age":9}"#));
+        assert!(valid.validate().is_ok());
 
-            Ok(TestClient { address, client })
-        }
+        let missing_field = JsonResponse::<Person>::Serialized(Bytes::from(r#"{"name":"Eve"}"#));
+        assert!(missing_field.validate().is_err());
+
+        let malformed = JsonResponse::<Person>::Serialized(Bytes::from(r#"{"name":"Eve","#));
+        assert!(malformed.validate().is_err());
+    }
+
+    #[test]
+    fn wraps_raw_values_without_reserializing() -> anyhow::Result<()> {
+        let raw =
+            serde_json::value::RawValue::from_string(r#"{"name":"Fay","age":61}"#.to_owned())?;
+        let response = JsonResponse::<Person>::from_raw_value(raw);
 
-        pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
-            self.client.get(format!("http://{}{}", self.address, url))
+        assert_eq!(
+            response.as_serialized().map(|bytes| &bytes[..]),
+            Some(&br#"{"name":"Fay","age":61}"#[..])
+        );
+        assert_eq!(response.into_bytes()?, r#"{"name":"Fay","age":61}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn serializes_values_into_bytes() -> anyhow::Result<()> {
+        let response = JsonResponse::Value(Person {
+            name: "Gus".to_owned(),
+            age: 18,
+        });
+
+        assert!(response.as_serialized().is_none());
+        assert_eq!(response.into_bytes()?, r#"{"name":"Gus","age":18}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_compressed_bytes_with_their_encoding() -> anyhow::Result<()> {
+        let app = Router::new().route(
+            "/",
+            routing::get(|| async {
+                JsonResponse::<Person>::Compressed {
+                    bytes: Bytes::from(gzip(r#"{"name":"Hal","age":50}"#)),
+                    encoding: ContentEncoding::Gzip,
+                }
+            }),
+        );
+
+        let client = TestClient::new(app)?;
+        let response = client.get("/").send().await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_all("Content-Type").iter().collect::<Vec<_>>(),
+            vec!["application/json"]
+        );
+        assert_eq!(
+            headers
+                .get_all("Content-Encoding")
+                .iter()
+                .collect::<Vec<_>>(),
+            vec!["gzip"]
+        );
+
+        let body = response.bytes().await?;
+        assert_eq!(
+            ContentEncoding::Gzip.decode(&body)?,
+            br#"{"name":"Hal","age":50}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decompresses_compressed_responses_into_values() -> anyhow::Result<()> {
+        let response = JsonResponse::<Person>::Compressed {
+            bytes: Bytes::from(gzip(r#"{"name":"Ivy","age":27}"#)),
+            encoding: ContentEncoding::Gzip,
+        };
+
+        assert!(response.validate().is_ok());
+        let person = response
+            .into_value::<Box<dyn std::error::Error + Send + Sync>>()
+            .map_err(|err| anyhow::anyhow!(err))?;
+        assert_eq!(person.name, "Ivy");
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() -> anyhow::Result<()> {
+        let json = br#"{"name":"Lou","age":12}"#;
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate] {
+            let encoded = encoding.encode(json)?;
+            assert_ne!(encoded, json);
+            assert_eq!(encoding.decode(&encoded)?, json);
         }
+        Ok(())
+    }
+
+    fn gzip(json: &str) -> Vec<u8> {
+        use std::io::Write as _;
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(json.as_bytes()).expect("gzip failed");
+        encoder.finish().expect("gzip failed")
+    }
+
+    #[tokio::test]
+    async fn serializes_values_on_the_blocking_pool() -> anyhow::Result<()> {
+        let response = JsonResponse::Value(Person {
+            name: "Jo".to_owned(),
+            age: 70,
+        })
+        .serialize_blocking()
+        .await?;
+
+        assert_eq!(
+            response.as_serialized().map(|bytes| &bytes[..]),
+            Some(&br#"{"name":"Jo","age":70}"#[..])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_additional_headers() -> anyhow::Result<()> {
+        let app = Router::new().route(
+            "/",
+            routing::get(|| async {
+                JsonResponse::<Person>::Serialized(Bytes::from(r#"{"name":"Kim","age":5}"#))
+                    .with_header(
+                        header::CACHE_CONTROL,
+                        HeaderValue::from_static("max-age=60"),
+                    )
+                    .with_header(header::CONTENT_LANGUAGE, HeaderValue::from_static("en"))
+            }),
+        );
+
+        let client = TestClient::new(app)?;
+        let response = client.get("/").send().await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert_eq!(headers.get("Cache-Control").unwrap(), "max-age=60");
+        assert_eq!(headers.get("Content-Language").unwrap(), "en");
+        assert_eq!(headers.get("Content-Type").unwrap(), "application/json");
+
+        let body = response.text().await?;
+        assert_eq!(body, r#"{"name":"Kim","age":5}"#);
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    struct Person {
+        name: String,
+        age: u16,
     }
 }
@@ -1,4 +1,19 @@
+#[cfg(any(test, feature = "client"))]
+pub mod client;
+pub mod clock;
+pub mod configuration;
 pub mod connector;
+pub mod health;
+pub mod json_request;
 pub mod json_response;
+pub mod metrics_prefix;
+pub mod process_metrics;
+pub mod query_response_writer;
+pub mod runtime_metrics;
 pub mod schema;
+pub mod startup_metrics;
 pub mod state;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod throttle;
+pub mod version;
@@ -0,0 +1,83 @@
+//! A prefix for metric names, so that fleets running many connectors can namespace their series
+//! consistently.
+//!
+//! The prefix is set once, at startup, by `serve --metrics-prefix`. It is applied to every metric
+//! registered by the SDK. Connectors can apply it to their own metrics by creating their options
+//! with [`opts`] and [`histogram_opts`].
+
+use std::sync::OnceLock;
+
+use prometheus::{HistogramOpts, Opts};
+
+static PREFIX: OnceLock<String> = OnceLock::new();
+
+/// Set the prefix for the rest of the process.
+///
+/// This fails if the prefix is not a valid metric name, or if a prefix has already been set.
+pub fn set_metrics_prefix(prefix: impl Into<String>) -> Result<(), String> {
+    let prefix = prefix.into();
+    validate_metrics_prefix(&prefix)?;
+    PREFIX
+        .set(prefix)
+        .map_err(|prefix| format!("the metrics prefix has already been set to {prefix:?}"))
+}
+
+/// The prefix, if one has been set.
+pub fn metrics_prefix() -> Option<&'static str> {
+    PREFIX.get().map(String::as_str)
+}
+
+/// Check that the prefix is a valid metric name, so that prefixed names are valid too.
+pub fn validate_metrics_prefix(prefix: &str) -> Result<(), String> {
+    let mut chars = prefix.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == ':')
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_' || char == ':');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{prefix:?} is not a valid metric name: it must start with a letter, '_' or ':', and contain only letters, digits, '_' and ':'"
+        ))
+    }
+}
+
+/// Options for a counter or gauge, with the prefix applied.
+pub fn opts(name: impl Into<String>, help: impl Into<String>) -> Opts {
+    let opts = Opts::new(name, help);
+    match metrics_prefix() {
+        Some(prefix) => opts.namespace(prefix),
+        None => opts,
+    }
+}
+
+/// Options for a histogram, with the prefix applied.
+pub fn histogram_opts(name: impl Into<String>, help: impl Into<String>) -> HistogramOpts {
+    HistogramOpts::from(opts(name, help))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_metric_names() {
+        for prefix in [
+            "myconnector",
+            "my_connector",
+            "_private",
+            "team:connector",
+            "v2",
+        ] {
+            assert_eq!(validate_metrics_prefix(prefix), Ok(()), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_metric_names() {
+        for prefix in ["", "2fast", "my-connector", "my connector", "café"] {
+            assert!(validate_metrics_prefix(prefix).is_err(), "{prefix}");
+        }
+    }
+}
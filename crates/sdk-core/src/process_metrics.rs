@@ -0,0 +1,143 @@
+//! Metrics for the connector process, such as CPU time, memory and open file descriptors, using the
+//! standard `process_*` metric names.
+//!
+//! On Linux, these are always available, and are read from `/proc`. On other platforms, they
+//! require the `process-metrics` feature, and are read using `sysinfo`, which cannot report the
+//! limit on open file descriptors.
+
+use prometheus::Registry;
+
+/// Register the process metrics with the given registry, if they are available on this platform.
+#[cfg(target_os = "linux")]
+pub fn register(registry: &Registry) -> Result<(), prometheus::Error> {
+    use prometheus::process_collector::{pid_t, ProcessCollector};
+
+    let pid = pid_t::try_from(std::process::id())
+        .map_err(|err| prometheus::Error::Msg(format!("unable to get the process ID: {err}")))?;
+    let prefix = crate::metrics_prefix::metrics_prefix().unwrap_or_default();
+    registry.register(Box::new(ProcessCollector::new(pid, prefix)))
+}
+
+/// Register the process metrics with the given registry, if they are available on this platform.
+#[cfg(all(not(target_os = "linux"), feature = "process-metrics"))]
+pub fn register(registry: &Registry) -> Result<(), prometheus::Error> {
+    registry.register(Box::new(sysinfo_collector::SysinfoCollector::new()?))
+}
+
+/// Register the process metrics with the given registry, if they are available on this platform.
+#[cfg(all(not(target_os = "linux"), not(feature = "process-metrics")))]
+pub fn register(_registry: &Registry) -> Result<(), prometheus::Error> {
+    Ok(())
+}
+
+#[cfg(all(not(target_os = "linux"), feature = "process-metrics"))]
+mod sysinfo_collector {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use prometheus::core::{Collector, Desc};
+    use prometheus::proto::MetricFamily;
+    use prometheus::{Counter, Gauge, IntGauge};
+    use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+    use crate::metrics_prefix::opts;
+
+    pub(super) struct SysinfoCollector {
+        pid: Pid,
+        system: Mutex<System>,
+        cpu_seconds_total: Counter,
+        open_fds: IntGauge,
+        resident_memory_bytes: IntGauge,
+        virtual_memory_bytes: IntGauge,
+        start_time_seconds: Gauge,
+    }
+
+    impl SysinfoCollector {
+        pub fn new() -> Result<Self, prometheus::Error> {
+            Ok(Self {
+                pid: sysinfo::get_current_pid().map_err(|err| {
+                    prometheus::Error::Msg(format!("unable to get the process ID: {err}"))
+                })?,
+                system: Mutex::new(System::new()),
+                cpu_seconds_total: Counter::with_opts(opts(
+                    "process_cpu_seconds_total",
+                    "Total user and system CPU time spent in seconds.",
+                ))?,
+                open_fds: IntGauge::with_opts(opts(
+                    "process_open_fds",
+                    "Number of open file descriptors.",
+                ))?,
+                resident_memory_bytes: IntGauge::with_opts(opts(
+                    "process_resident_memory_bytes",
+                    "Resident memory size in bytes.",
+                ))?,
+                virtual_memory_bytes: IntGauge::with_opts(opts(
+                    "process_virtual_memory_bytes",
+                    "Virtual memory size in bytes.",
+                ))?,
+                start_time_seconds: Gauge::with_opts(opts(
+                    "process_start_time_seconds",
+                    "Start time of the process since unix epoch in seconds.",
+                ))?,
+            })
+        }
+
+        fn metrics(&self) -> [&dyn Collector; 5] {
+            [
+                &self.cpu_seconds_total,
+                &self.open_fds,
+                &self.resident_memory_bytes,
+                &self.virtual_memory_bytes,
+                &self.start_time_seconds,
+            ]
+        }
+
+        fn update(&self) {
+            let Ok(mut system) = self.system.lock() else {
+                return;
+            };
+            system.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[self.pid]),
+                true,
+                ProcessRefreshKind::nothing().with_cpu().with_memory(),
+            );
+            let Some(process) = system.process(self.pid) else {
+                return;
+            };
+
+            // the CPU time only increases, so the counter is advanced by the difference
+            let cpu_seconds = Duration::from_millis(process.accumulated_cpu_time()).as_secs_f64();
+            let increase = cpu_seconds - self.cpu_seconds_total.get();
+            if increase > 0.0 {
+                self.cpu_seconds_total.inc_by(increase);
+            }
+            if let Some(open_files) = process.open_files() {
+                self.open_fds
+                    .set(i64::try_from(open_files).unwrap_or(i64::MAX));
+            }
+            self.resident_memory_bytes
+                .set(i64::try_from(process.memory()).unwrap_or(i64::MAX));
+            self.virtual_memory_bytes
+                .set(i64::try_from(process.virtual_memory()).unwrap_or(i64::MAX));
+            self.start_time_seconds
+                .set(Duration::from_secs(process.start_time()).as_secs_f64());
+        }
+    }
+
+    impl Collector for SysinfoCollector {
+        fn desc(&self) -> Vec<&Desc> {
+            self.metrics()
+                .into_iter()
+                .flat_map(Collector::desc)
+                .collect()
+        }
+
+        fn collect(&self) -> Vec<MetricFamily> {
+            self.update();
+            self.metrics()
+                .into_iter()
+                .flat_map(Collector::collect)
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Writing query responses incrementally, so that connectors can produce large responses row by
+//! row without first collecting them into a [`QueryResponse`].
+//!
+//! A [`QueryResponseWriter`] either buffers the serialized response, producing a
+//! [`JsonResponse::Serialized`] bytestring, or sends it in chunks as a [`JsonResponse::Stream`].
+
+use std::future::Future;
+
+use bytes::{BufMut as _, Bytes, BytesMut};
+use futures::channel::mpsc;
+use futures::{SinkExt as _, StreamExt as _};
+use ndc_models::QueryResponse;
+use serde::Serialize;
+use tracing::Instrument as _;
+
+use crate::connector::ErrorResponse;
+use crate::json_response::{JsonResponse, JsonStream};
+
+/// Serializes a query response one row at a time.
+///
+/// ```ignore
+/// let mut writer = QueryResponseWriter::new();
+/// let mut row_set = writer.row_set();
+/// for row in rows {
+///     row_set.append_row(&row).await?;
+/// }
+/// row_set.finish(None::<&()>)?;
+/// Ok(writer.finish())
+/// ```
+#[derive(Debug)]
+pub struct QueryResponseWriter {
+    buffer: BytesMut,
+    row_sets: usize,
+    stream: Option<StreamSink>,
+}
+
+#[derive(Debug)]
+struct StreamSink {
+    sender: mpsc::Sender<Result<Bytes, ErrorResponse>>,
+    chunk_size: usize,
+}
+
+impl Default for QueryResponseWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QueryResponseWriter {
+    /// A writer which buffers the whole response, for [`QueryResponseWriter::finish`].
+    pub fn new() -> Self {
+        Self {
+            buffer: BytesMut::new(),
+            row_sets: 0,
+            stream: None,
+        }
+    }
+
+    /// Stream a response, written by `write` in a background task.
+    ///
+    /// The response is sent in chunks of roughly `chunk_size` bytes as rows are appended, and
+    /// appending waits while the client is not keeping up. If `write` fails, or the client goes
+    /// away, appending fails and the response is cut short.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn stream<F, Fut>(chunk_size: usize, write: F) -> JsonResponse<QueryResponse>
+    where
+        F: FnOnce(Self) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Self, ErrorResponse>> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(1);
+        let writer = Self {
+            stream: Some(StreamSink {
+                sender: sender.clone(),
+                chunk_size,
+            }),
+            ..Self::new()
+        };
+        let task = tokio::spawn(
+            async move {
+                let mut sender = sender;
+                let last_chunk = match write(writer).await {
+                    Ok(mut writer) => {
+                        writer.stream = None;
+                        Ok(writer.finish_bytes())
+                    }
+                    Err(err) => Err(err),
+                };
+                // if this fails, the client has gone away, so there is nobody to tell
+                let _ = sender.send(last_chunk).await;
+            }
+            .in_current_span(),
+        );
+        // the receiver only ends once the task has dropped its senders, so the task has finished
+        // by the time it is awaited; this reports a panic rather than ending the response early
+        let outcome = futures::stream::once(task).filter_map(|result| async move {
+            result.err().map(|err| Err(ErrorResponse::from_error(err)))
+        });
+        JsonResponse::Stream(JsonStream::new(receiver.chain(outcome)))
+    }
+
+    /// Start writing the next row set, which has a `rows` array even if no rows are appended.
+    ///
+    /// Dropping the [`RowSetWriter`] finishes the row set without aggregates.
+    pub fn row_set(&mut self) -> RowSetWriter<'_> {
+        self.start_row_set();
+        self.buffer.put_slice(br#""rows":["#);
+        RowSetWriter {
+            writer: self,
+            rows: 0,
+            finished: false,
+        }
+    }
+
+    /// Write a row set with aggregates but no rows.
+    pub fn aggregates_row_set(&mut self, aggregates: &impl Serialize) -> Result<(), ErrorResponse> {
+        let aggregates = serde_json::to_vec(aggregates).map_err(ErrorResponse::from_error)?;
+        self.start_row_set();
+        self.buffer.put_slice(br#""aggregates":"#);
+        self.buffer.put_slice(&aggregates);
+        self.buffer.put_u8(b'}');
+        Ok(())
+    }
+
+    /// Finish a buffered response.
+    pub fn finish(self) -> JsonResponse<QueryResponse> {
+        JsonResponse::Serialized(self.finish_bytes())
+    }
+
+    fn finish_bytes(mut self) -> Bytes {
+        if self.row_sets == 0 {
+            self.buffer.put_u8(b'[');
+        }
+        self.buffer.put_u8(b']');
+        self.buffer.freeze()
+    }
+
+    fn start_row_set(&mut self) {
+        self.buffer
+            .put_u8(if self.row_sets == 0 { b'[' } else { b',' });
+        self.buffer.put_u8(b'{');
+        self.row_sets += 1;
+    }
+
+    /// Send the buffered bytes if streaming and enough have been written.
+    async fn flush(&mut self) -> Result<(), ErrorResponse> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+        if self.buffer.len() < stream.chunk_size {
+            return Ok(());
+        }
+        let chunk = self.buffer.split().freeze();
+        stream.sender.send(Ok(chunk)).await.map_err(|_| {
+            ErrorResponse::new_internal_with_details(serde_json::json!({
+                "message": "The response body was dropped while it was being written",
+            }))
+        })
+    }
+}
+
+/// Appends rows to a row set of a [`QueryResponseWriter`].
+#[derive(Debug)]
+pub struct RowSetWriter<'a> {
+    writer: &'a mut QueryResponseWriter,
+    rows: usize,
+    finished: bool,
+}
+
+impl RowSetWriter<'_> {
+    /// Append a row, which should serialize to an object of row field values.
+    pub async fn append_row(&mut self, row: &impl Serialize) -> Result<(), ErrorResponse> {
+        let row = serde_json::to_vec(row).map_err(ErrorResponse::from_error)?;
+        if self.rows > 0 {
+            self.writer.buffer.put_u8(b',');
+        }
+        self.writer.buffer.put_slice(&row);
+        self.rows += 1;
+        self.writer.flush().await
+    }
+
+    /// Finish the row set, with the given aggregates, if any.
+    pub fn finish(mut self, aggregates: Option<&impl Serialize>) -> Result<(), ErrorResponse> {
+        let aggregates = aggregates
+            .map(serde_json::to_vec)
+            .transpose()
+            .map_err(ErrorResponse::from_error)?;
+        self.close(aggregates.as_deref());
+        Ok(())
+    }
+
+    fn close(&mut self, aggregates: Option<&[u8]>) {
+        self.writer.buffer.put_u8(b']');
+        if let Some(aggregates) = aggregates {
+            self.writer.buffer.put_slice(br#","aggregates":"#);
+            self.writer.buffer.put_slice(aggregates);
+        }
+        self.writer.buffer.put_u8(b'}');
+        self.finished = true;
+    }
+}
+
+impl Drop for RowSetWriter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.close(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_row_sets() -> anyhow::Result<()> {
+        let mut writer = QueryResponseWriter::new();
+        let mut row_set = writer.row_set();
+        row_set.append_row(&json!({ "id": 1 })).await?;
+        row_set.append_row(&json!({ "id": 2 })).await?;
+        row_set.finish(Some(&json!({ "count": 2 })))?;
+        drop(writer.row_set());
+        writer.aggregates_row_set(&json!({ "count": 0 }))?;
+
+        let QueryResponse(row_sets) = writer.finish().into_value::<ErrorResponse>()?;
+        let row_sets = row_sets
+            .iter()
+            .map(|row_set| {
+                Ok((
+                    serde_json::to_value(&row_set.rows)?,
+                    serde_json::to_value(&row_set.aggregates)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        assert_eq!(
+            row_sets,
+            vec![
+                (json!([{ "id": 1 }, { "id": 2 }]), json!({ "count": 2 })),
+                (json!([]), json!(null)),
+                (json!(null), json!({ "count": 0 })),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_an_empty_response() -> anyhow::Result<()> {
+        let response = QueryResponseWriter::new().finish();
+        assert_eq!(
+            response.as_serialized().map(|bytes| &bytes[..]),
+            Some(&b"[]"[..])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn streams_rows_in_chunks() -> anyhow::Result<()> {
+        let response = QueryResponseWriter::stream(16, |mut writer| async move {
+            let mut row_set = writer.row_set();
+            for id in 0..10 {
+                row_set.append_row(&json!({ "id": id })).await?;
+            }
+            row_set.finish(None::<&()>)?;
+            Ok(writer)
+        });
+        let JsonResponse::Stream(stream) = response else {
+            panic!("expected a streamed response");
+        };
+        let chunks = stream.collect::<Vec<_>>().await;
+        assert!(chunks.len() > 1);
+
+        let mut body = Vec::new();
+        for chunk in chunks {
+            body.extend_from_slice(&chunk?);
+        }
+        let rows = (0..10).map(|id| json!({ "id": id })).collect::<Vec<_>>();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body)?,
+            json!([{ "rows": rows }])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cuts_streams_short_on_failure() {
+        let response = QueryResponseWriter::stream(1024, |mut writer| async move {
+            writer.row_set().append_row(&json!({ "id": 1 })).await?;
+            Err(ErrorResponse::from(
+                "the upstream database went away".to_owned(),
+            ))
+        });
+        let JsonResponse::Stream(stream) = response else {
+            panic!("expected a streamed response");
+        };
+        let chunks = stream.collect::<Vec<_>>().await;
+        assert!(matches!(chunks.last(), Some(Err(_))));
+    }
+}
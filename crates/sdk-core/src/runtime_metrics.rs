@@ -0,0 +1,159 @@
+//! Metrics for the tokio runtime, so that operators can tell when a connector is limited by its
+//! async runtime rather than by the services it calls.
+//!
+//! The blocking thread pool metrics are only available when the connector is built with
+//! `RUSTFLAGS="--cfg tokio_unstable"`.
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{Counter, IntGauge, Registry};
+use tokio::runtime::Handle;
+
+use crate::metrics_prefix::opts;
+
+/// A collector which reads the runtime metrics whenever the registry is gathered.
+#[derive(Clone, Debug)]
+pub struct RuntimeMetrics {
+    handle: Handle,
+    workers: IntGauge,
+    alive_tasks: IntGauge,
+    global_queue_depth: IntGauge,
+    busy_seconds_total: Counter,
+    #[cfg(tokio_unstable)]
+    blocking_threads: IntGauge,
+    #[cfg(tokio_unstable)]
+    idle_blocking_threads: IntGauge,
+    #[cfg(tokio_unstable)]
+    blocking_queue_depth: IntGauge,
+}
+
+impl RuntimeMetrics {
+    /// Create the metrics for the current runtime, and register them with the given registry.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let handle = Handle::try_current()
+            .map_err(|err| prometheus::Error::Msg(format!("no tokio runtime: {err}")))?;
+        let metrics = Self {
+            handle,
+            workers: IntGauge::with_opts(opts(
+                "ndc_sdk_tokio_workers",
+                "Number of worker threads used by the runtime",
+            ))?,
+            alive_tasks: IntGauge::with_opts(opts(
+                "ndc_sdk_tokio_alive_tasks",
+                "Number of tasks which have been spawned and have not completed",
+            ))?,
+            global_queue_depth: IntGauge::with_opts(opts(
+                "ndc_sdk_tokio_global_queue_depth",
+                "Number of tasks waiting in the runtime's global queue",
+            ))?,
+            busy_seconds_total: Counter::with_opts(opts(
+                "ndc_sdk_tokio_workers_busy_seconds_total",
+                "Total time worker threads have spent busy, summed over all workers",
+            ))?,
+            #[cfg(tokio_unstable)]
+            blocking_threads: IntGauge::with_opts(opts(
+                "ndc_sdk_tokio_blocking_threads",
+                "Number of threads in the blocking thread pool",
+            ))?,
+            #[cfg(tokio_unstable)]
+            idle_blocking_threads: IntGauge::with_opts(opts(
+                "ndc_sdk_tokio_idle_blocking_threads",
+                "Number of idle threads in the blocking thread pool",
+            ))?,
+            #[cfg(tokio_unstable)]
+            blocking_queue_depth: IntGauge::with_opts(opts(
+                "ndc_sdk_tokio_blocking_queue_depth",
+                "Number of tasks waiting for a thread in the blocking thread pool",
+            ))?,
+        };
+        registry.register(Box::new(metrics.clone()))?;
+        Ok(metrics)
+    }
+
+    fn metrics(&self) -> Vec<&dyn Collector> {
+        vec![
+            &self.workers,
+            &self.alive_tasks,
+            &self.global_queue_depth,
+            &self.busy_seconds_total,
+            #[cfg(tokio_unstable)]
+            &self.blocking_threads,
+            #[cfg(tokio_unstable)]
+            &self.idle_blocking_threads,
+            #[cfg(tokio_unstable)]
+            &self.blocking_queue_depth,
+        ]
+    }
+
+    /// Read the current values from the runtime.
+    fn update(&self) {
+        let runtime = self.handle.metrics();
+        self.workers.set(gauge_value(runtime.num_workers()));
+        self.alive_tasks.set(gauge_value(runtime.num_alive_tasks()));
+        self.global_queue_depth
+            .set(gauge_value(runtime.global_queue_depth()));
+
+        // the busy time only increases, so the counter is advanced by the difference
+        let busy_seconds: f64 = (0..runtime.num_workers())
+            .map(|worker| runtime.worker_total_busy_duration(worker).as_secs_f64())
+            .sum();
+        let increase = busy_seconds - self.busy_seconds_total.get();
+        if increase > 0.0 {
+            self.busy_seconds_total.inc_by(increase);
+        }
+
+        #[cfg(tokio_unstable)]
+        {
+            self.blocking_threads
+                .set(gauge_value(runtime.num_blocking_threads()));
+            self.idle_blocking_threads
+                .set(gauge_value(runtime.num_idle_blocking_threads()));
+            self.blocking_queue_depth
+                .set(gauge_value(runtime.blocking_queue_depth()));
+        }
+    }
+}
+
+fn gauge_value(value: usize) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+impl Collector for RuntimeMetrics {
+    fn desc(&self) -> Vec<&Desc> {
+        self.metrics()
+            .into_iter()
+            .flat_map(Collector::desc)
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.update();
+        self.metrics()
+            .into_iter()
+            .flat_map(Collector::collect)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn reports_the_runtime_metrics() {
+        let registry = Registry::new();
+        RuntimeMetrics::register(&registry).unwrap();
+
+        let families = registry.gather();
+        let workers = families
+            .iter()
+            .find(|family| family.get_name() == "ndc_sdk_tokio_workers")
+            .unwrap();
+        assert!((workers.get_metric()[0].get_gauge().get_value() - 2.0).abs() < f64::EPSILON);
+        assert!(families
+            .iter()
+            .any(|family| family.get_name() == "ndc_sdk_tokio_workers_busy_seconds_total"));
+    }
+}
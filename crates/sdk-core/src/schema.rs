@@ -18,18 +18,19 @@ pub async fn get_capabilities<C: Connector>() -> JsonResponse<ndc_models::Capabi
 /// Prints a JSON object to the writer containing the ndc schema and capabilities of the connector
 pub async fn print_schema_and_capabilities<Setup, W: Write>(
     setup: Setup,
-    config_directory: &Path,
+    configuration_path: &Path,
     writer: W,
 ) -> Result<()>
 where
     Setup: ConnectorSetup,
     Setup::Connector: Connector + 'static,
-    <Setup::Connector as Connector>::Configuration: Clone,
-    <Setup::Connector as Connector>::State: Clone,
 {
-    let server_state = init_server_state(setup, config_directory).await?;
+    let server_state = init_server_state(setup, configuration_path).await?;
 
-    let schema = Setup::Connector::get_schema(server_state.configuration()).await?;
+    let schema = Setup::Connector::get_schema(server_state.configuration())
+        .await?
+        .buffer()
+        .await?;
     let capabilities = get_capabilities::<Setup::Connector>().await;
 
     print_json_schema_and_capabilities(writer, schema, capabilities)?;
@@ -37,6 +38,16 @@ where
     Ok(())
 }
 
+/// Prints the capabilities of the connector to the writer as a JSON object.
+///
+/// Capabilities do not depend on configuration, so this does not need to parse any.
+pub async fn print_capabilities<C: Connector, W: Write>(mut writer: W) -> Result<()> {
+    let capabilities = get_capabilities::<C>().await;
+    write_json_response(&mut writer, capabilities)?;
+    writeln!(writer).map_err(crate::connector::ErrorResponse::from_error)?;
+    Ok(())
+}
+
 /// This foulness manually writes out a JSON object with schema and capabilities properties.
 /// We do it like this to avoid having to deserialize and reserialize any
 /// JsonResponse::Serialized values.
@@ -61,6 +72,13 @@ fn write_json_response<W: Write, A: serde::Serialize>(
     match json {
         JsonResponse::Value(value) => Ok(serde_json::to_writer(writer, &value)?),
         JsonResponse::Serialized(bytes) => Ok(writer.write_all(&bytes)?),
+        JsonResponse::Stream(_) => {
+            Err("a streamed response must be buffered before it is written".into())
+        }
+        JsonResponse::Compressed { bytes, encoding } => {
+            Ok(writer.write_all(&encoding.decode(&bytes)?)?)
+        }
+        JsonResponse::WithHeaders { response, .. } => write_json_response(writer, *response),
     }
 }
 
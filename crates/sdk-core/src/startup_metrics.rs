@@ -0,0 +1,47 @@
+//! Timings for the phases of connector startup, so that slow startups, such as those spent
+//! introspecting a huge schema, are visible on dashboards.
+//!
+//! Each phase also runs in a span of the same name, so the same timings are visible in traces.
+
+use std::time::Duration;
+
+use prometheus::{GaugeVec, Registry};
+
+use crate::metrics_prefix::opts;
+
+/// Parsing the configuration, in [`ConnectorSetup::parse_configuration`](crate::connector::ConnectorSetup::parse_configuration).
+pub const PARSE_CONFIGURATION: &str = "parse_configuration";
+/// Initializing the connector state, in [`ConnectorSetup::try_init_state`](crate::connector::ConnectorSetup::try_init_state).
+pub const INIT_STATE: &str = "init_state";
+/// Constructing the HTTP router.
+pub const CREATE_ROUTER: &str = "create_router";
+
+/// The duration of the most recent run of each startup phase.
+#[derive(Clone, Debug)]
+pub struct StartupMetrics {
+    phase_duration_seconds: GaugeVec,
+}
+
+impl StartupMetrics {
+    /// Create the metrics, and register them with the given registry.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let phase_duration_seconds = GaugeVec::new(
+            opts(
+                "ndc_sdk_startup_phase_duration_seconds",
+                "Duration of the most recent run of each phase of connector startup, in seconds",
+            ),
+            &["phase"],
+        )?;
+        registry.register(Box::new(phase_duration_seconds.clone()))?;
+        Ok(Self {
+            phase_duration_seconds,
+        })
+    }
+
+    /// Record how long a phase took.
+    pub fn record(&self, phase: &str, duration: Duration) {
+        self.phase_duration_seconds
+            .with_label_values(&[phase])
+            .set(duration.as_secs_f64());
+    }
+}
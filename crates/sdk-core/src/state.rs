@@ -1,38 +1,63 @@
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use prometheus::Registry;
 use tokio::sync::OnceCell;
+use tracing::Instrument as _;
 
+use crate::clock::{Clock, SystemClock};
 use crate::connector::error::*;
 use crate::connector::{Connector, ConnectorSetup};
+use crate::process_metrics;
+use crate::runtime_metrics::RuntimeMetrics;
+use crate::startup_metrics::{StartupMetrics, INIT_STATE, PARSE_CONFIGURATION};
 
 /// Everything we need to keep in memory.
+///
+/// This is cloned for every request, so everything in it is shared.
 pub struct ServerState<C: Connector> {
-    configuration: C::Configuration,
+    configuration: Arc<C::Configuration>,
     state: Arc<ConnectorState<C>>,
     metrics: prometheus::Registry,
+    startup_metrics: Option<StartupMetrics>,
+    clock: Arc<dyn Clock>,
+    init_backoff: Option<InitBackoff>,
 }
 
 /// The connector state, which may or may not be initialized.
 struct ConnectorState<C: Connector> {
     cell: OnceCell<C::State>,
     init_state: Box<dyn ConnectorSetup<Connector = C>>,
+    /// The last initialization failure, while backing off.
+    last_failure: Mutex<Option<InitFailure>>,
 }
 
-// Server state must be cloneable even if the underlying connector is not.
-// We only require `Connector::Configuration` to be cloneable.
-//
-// Server state is always stored in an `Arc`, so is therefore cloneable.
-impl<C: Connector> Clone for ServerState<C>
-where
-    C::Configuration: Clone,
-{
+/// How long to wait between attempts to initialize the state, doubling after each failure.
+#[derive(Clone, Copy, Debug)]
+struct InitBackoff {
+    initial: Duration,
+    max: Duration,
+}
+
+#[derive(Debug)]
+struct InitFailure {
+    error: ErrorResponse,
+    failed_at: Instant,
+    backoff: Duration,
+}
+
+// Server state must be cloneable even if the underlying connector, its configuration and its
+// state are not. Cloning is cheap, as they are shared through `Arc`s rather than copied.
+impl<C: Connector> Clone for ServerState<C> {
     fn clone(&self) -> Self {
         Self {
             configuration: self.configuration.clone(),
             state: self.state.clone(),
             metrics: self.metrics.clone(),
+            startup_metrics: self.startup_metrics.clone(),
+            clock: self.clock.clone(),
+            init_backoff: self.init_backoff,
         }
     }
 }
@@ -44,16 +69,50 @@ impl<C: Connector> ServerState<C> {
         init_state: impl ConnectorSetup<Connector = C> + 'static,
         metrics: prometheus::Registry,
     ) -> Self {
+        let startup_metrics = StartupMetrics::register(&metrics)
+            .map_err(|err| {
+                tracing::warn!(
+                    meta.signal_type = "log",
+                    event.domain = "ndc",
+                    event.name = "Unable to register startup metrics",
+                    name = "Unable to register startup metrics",
+                    body = %err,
+                );
+            })
+            .ok();
         Self {
-            configuration,
+            configuration: Arc::new(configuration),
             state: Arc::new(ConnectorState {
                 cell: OnceCell::new(),
                 init_state: Box::new(init_state),
+                last_failure: Mutex::new(None),
             }),
             metrics,
+            startup_metrics,
+            clock: Arc::new(SystemClock),
+            init_backoff: None,
         }
     }
 
+    /// Measure time with the given clock, rather than the system clock, so that tests can control
+    /// the passage of time.
+    #[must_use]
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Back off after failing to initialize the state, rather than retrying on every call.
+    ///
+    /// After a failure, calls fail with the same error, marked as retryable, until the backoff
+    /// has passed. The backoff starts at `initial`, and doubles after each consecutive failure, up
+    /// to `max`.
+    #[must_use]
+    pub fn with_init_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.init_backoff = Some(InitBackoff { initial, max });
+        self
+    }
+
     /// The server configuration.
     pub fn configuration(&self) -> &C::Configuration {
         &self.configuration
@@ -63,31 +122,217 @@ impl<C: Connector> ServerState<C> {
     ///
     /// If the state has not yet been initialized, this initializes it.
     ///
-    /// On initialization failure, this function will also fail, and subsequent calls will retry.
+    /// On initialization failure, this function will also fail, and subsequent calls will retry,
+    /// once any backoff set with [`ServerState::with_init_backoff`] has passed.
     pub async fn state(&self) -> Result<&C::State> {
+        if let Some(state) = self.state.cell.get() {
+            return Ok(state);
+        }
+        self.check_init_backoff()?;
         self.state
             .cell
             .get_or_try_init(|| async {
-                self.state
+                // another caller may have failed while this one waited to initialize the state
+                self.check_init_backoff()?;
+                let started = self.clock.now();
+                let state = self
+                    .state
                     .init_state
                     .try_init_state(&self.configuration, &mut self.metrics.clone())
-                    .await
+                    .instrument(tracing::info_span!(INIT_STATE))
+                    .await;
+                let finished = self.clock.now();
+                self.record_startup_phase(INIT_STATE, finished.saturating_duration_since(started));
+                if let Err(err) = &state {
+                    self.record_init_failure(err, finished);
+                }
+                state
             })
             .await
     }
 
+    /// Fail with the last initialization error if we are still backing off after it.
+    fn check_init_backoff(&self) -> Result<()> {
+        let last_failure = self.lock_last_failure();
+        let Some(failure) = last_failure.as_ref() else {
+            return Ok(());
+        };
+        let elapsed = self
+            .clock
+            .now()
+            .saturating_duration_since(failure.failed_at);
+        match failure.backoff.checked_sub(elapsed) {
+            Some(remaining) if !remaining.is_zero() => {
+                Err(failure.error.clone().retryable(remaining))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_init_failure(&self, error: &ErrorResponse, failed_at: Instant) {
+        let Some(init_backoff) = self.init_backoff else {
+            return;
+        };
+        let mut last_failure = self.lock_last_failure();
+        let backoff = match last_failure.as_ref() {
+            Some(failure) => (failure.backoff * 2).min(init_backoff.max),
+            None => init_backoff.initial.min(init_backoff.max),
+        };
+        *last_failure = Some(InitFailure {
+            error: error.clone(),
+            failed_at,
+            backoff,
+        });
+    }
+
+    fn lock_last_failure(&self) -> std::sync::MutexGuard<'_, Option<InitFailure>> {
+        self.state
+            .last_failure
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// The transient server state, if it has already been initialized.
+    ///
+    /// Unlike [`ServerState::state`], this never attempts initialization.
+    pub fn initialized_state(&self) -> Option<&C::State> {
+        self.state.cell.get()
+    }
+
     /// The server metrics.
     pub fn metrics(&self) -> &prometheus::Registry {
         &self.metrics
     }
+
+    /// Record how long a phase of startup took, in the `ndc_sdk_startup_phase_duration_seconds`
+    /// metric. The phases run by the SDK are listed in [`crate::startup_metrics`].
+    pub fn record_startup_phase(&self, phase: &str, duration: Duration) {
+        if let Some(startup_metrics) = &self.startup_metrics {
+            startup_metrics.record(phase, duration);
+        }
+    }
 }
 
 /// Initialize the server state from the configuration file.
 pub async fn init_server_state<Setup: ConnectorSetup>(
     setup: Setup,
-    config_directory: &Path,
+    configuration_path: &Path,
 ) -> Result<ServerState<Setup::Connector>> {
     let metrics = Registry::new();
-    let configuration = setup.parse_configuration(config_directory).await?;
-    Ok(ServerState::new(configuration, setup, metrics))
+    let registered = [
+        RuntimeMetrics::register(&metrics).map(|_| ()),
+        process_metrics::register(&metrics),
+    ];
+    for err in registered.into_iter().filter_map(std::result::Result::err) {
+        tracing::warn!(
+            meta.signal_type = "log",
+            event.domain = "ndc",
+            event.name = "Unable to register built-in metrics",
+            name = "Unable to register built-in metrics",
+            body = %err,
+        );
+    }
+    let started = Instant::now();
+    let configuration = setup
+        .parse_configuration(configuration_path)
+        .instrument(tracing::info_span!(PARSE_CONFIGURATION))
+        .await?;
+    let server_state = ServerState::new(configuration, setup, metrics);
+    server_state.record_startup_phase(PARSE_CONFIGURATION, started.elapsed());
+    Ok(server_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::testing::{ManualClock, MockConnector};
+
+    /// Fails to initialize the state, counting the attempts.
+    struct FailingSetup {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ConnectorSetup for FailingSetup {
+        type Connector = MockConnector;
+
+        async fn parse_configuration(&self, _configuration_path: &Path) -> Result<MockConnector> {
+            Ok(MockConnector::new())
+        }
+
+        async fn try_init_state(
+            &self,
+            _configuration: &MockConnector,
+            _metrics: &mut prometheus::Registry,
+        ) -> Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Err(ErrorResponse::from(
+                "the database is unavailable".to_owned(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_failing_to_initialize_the_state() {
+        let clock = ManualClock::new();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let state = ServerState::new(
+            MockConnector::new(),
+            FailingSetup {
+                attempts: attempts.clone(),
+            },
+            Registry::new(),
+        )
+        .with_clock(clock.clone())
+        .with_init_backoff(Duration::from_secs(1), Duration::from_secs(3));
+
+        assert!(state.state().await.is_err());
+        let error = state.state().await.unwrap_err();
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(1)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // the backoff doubles after each failure, up to the maximum
+        for backoff in [2, 3, 3] {
+            clock.advance(Duration::from_secs(1));
+            assert!(state.state().await.is_err());
+            clock.advance(Duration::from_secs(backoff - 1));
+            let error = state.state().await.unwrap_err();
+            assert_eq!(error.retry_after(), Some(Duration::from_secs(1)));
+        }
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn retries_on_every_call_without_a_backoff() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let state = ServerState::new(
+            MockConnector::new(),
+            FailingSetup {
+                attempts: attempts.clone(),
+            },
+            Registry::new(),
+        );
+
+        assert!(state.state().await.is_err());
+        assert!(state.state().await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shares_the_configuration_between_clones() {
+        let state = ServerState::new(
+            MockConnector::new(),
+            FailingSetup {
+                attempts: Arc::new(AtomicUsize::new(0)),
+            },
+            Registry::new(),
+        );
+
+        let clone = state.clone();
+        assert!(std::ptr::eq(state.configuration(), clone.configuration()));
+    }
 }
@@ -0,0 +1,219 @@
+//! Testing connectors over HTTP, against a router served on an ephemeral port.
+
+use std::net::SocketAddr;
+
+use http::StatusCode;
+
+use crate::client::{NdcClient, NdcClientError};
+use crate::connector::ErrorResponse;
+
+mod capabilities_matrix;
+mod golden;
+mod manual_clock;
+mod mock_connector;
+#[cfg(feature = "proptest")]
+mod query_requests;
+mod query_response_diff;
+pub use capabilities_matrix::{
+    capabilities_matrix, for_each_capabilities, AggregatesOnlyCapabilities, FullCapabilities,
+    QueryOnlyCapabilities, RelationalCapabilities,
+};
+pub use golden::{
+    assert_golden, assert_golden_capabilities, assert_golden_schema, canonical_json,
+    UPDATE_GOLDEN_FILES,
+};
+pub use manual_clock::ManualClock;
+pub use mock_connector::{CapabilitiesFixture, MinimalCapabilities, MockConnector};
+#[cfg(feature = "proptest")]
+pub use query_requests::query_requests;
+pub use query_response_diff::{assert_query_response_eq, QueryResponseComparison};
+
+const LOCALHOST: std::net::IpAddr = std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST);
+
+/// A client for a router, which is served in the background for as long as the test runs.
+#[derive(Debug)]
+pub struct TestClient {
+    address: SocketAddr,
+    client: reqwest::Client,
+    service_token: Option<String>,
+    /// Makes the typed requests, as a connector calling this one would.
+    ndc_client: NdcClient,
+}
+
+/// The ways a request made by a [`TestClient`] can fail.
+#[derive(Debug, thiserror::Error)]
+pub enum TestClientError {
+    #[error("unable to set up the connector: {0}")]
+    Setup(#[from] ErrorResponse),
+    #[error("unable to serve the router: {0}")]
+    Serve(#[from] std::io::Error),
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("the connector responded with {status}: {}", error.message)]
+    Response {
+        status: StatusCode,
+        error: ndc_models::ErrorResponse,
+    },
+    #[error(transparent)]
+    Client(NdcClientError),
+}
+
+impl From<NdcClientError> for TestClientError {
+    fn from(value: NdcClientError) -> Self {
+        match value {
+            NdcClientError::Request(err) => Self::Request(err),
+            NdcClientError::Response { status, error } => Self::Response { status, error },
+            err => Self::Client(err),
+        }
+    }
+}
+
+impl TestClient {
+    /// Serve the router on an ephemeral port, and create a client for it.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn new(router: axum::Router) -> Result<Self, TestClientError> {
+        let listener = std::net::TcpListener::bind(std::net::SocketAddr::new(LOCALHOST, 0))?;
+        let address = listener.local_addr()?;
+
+        // we ignore the handle and let the test runner clean up the server
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener)
+                .expect("server error")
+                .serve(router.into_make_service())
+                .await
+                .expect("server error");
+        });
+
+        Self::connect(address)
+    }
+
+    /// Create a client for a connector which is already being served at the given address.
+    pub fn connect(address: SocketAddr) -> Result<Self, TestClientError> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let base_url = url::Url::parse(&format!("http://{address}/"))
+            .map_err(|err| TestClientError::Client(err.into()))?;
+        let ndc_client = NdcClient::new(base_url).with_client(client.clone());
+
+        Ok(TestClient {
+            address,
+            client,
+            service_token: None,
+            ndc_client,
+        })
+    }
+
+    /// Send the service token as a bearer `Authorization` header with every request.
+    #[must_use]
+    pub fn with_service_token(mut self, service_token: impl Into<String>) -> Self {
+        let service_token = service_token.into();
+        self.ndc_client = self.ndc_client.with_service_token(service_token.clone());
+        self.service_token = Some(service_token);
+        self
+    }
+
+    /// The address on which the router is served.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.client.get(format!("http://{}{}", self.address, url)))
+    }
+
+    pub fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.client.post(format!("http://{}{}", self.address, url)))
+    }
+
+    pub async fn capabilities(&self) -> Result<ndc_models::CapabilitiesResponse, TestClientError> {
+        Ok(self.ndc_client.capabilities().await?)
+    }
+
+    pub async fn schema(&self) -> Result<ndc_models::SchemaResponse, TestClientError> {
+        Ok(self.ndc_client.schema().await?)
+    }
+
+    pub async fn query(
+        &self,
+        request: &ndc_models::QueryRequest,
+    ) -> Result<ndc_models::QueryResponse, TestClientError> {
+        Ok(self.ndc_client.query(request).await?)
+    }
+
+    pub async fn query_explain(
+        &self,
+        request: &ndc_models::QueryRequest,
+    ) -> Result<ndc_models::ExplainResponse, TestClientError> {
+        Ok(self.ndc_client.query_explain(request).await?)
+    }
+
+    pub async fn mutation(
+        &self,
+        request: &ndc_models::MutationRequest,
+    ) -> Result<ndc_models::MutationResponse, TestClientError> {
+        Ok(self.ndc_client.mutation(request).await?)
+    }
+
+    pub async fn mutation_explain(
+        &self,
+        request: &ndc_models::MutationRequest,
+    ) -> Result<ndc_models::ExplainResponse, TestClientError> {
+        Ok(self.ndc_client.mutation_explain(request).await?)
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.service_token {
+            Some(service_token) => request.bearer_auth(service_token),
+            None => request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing, Json, Router};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn deserializes_successful_responses() -> anyhow::Result<()> {
+        let app = Router::new().route(
+            "/query",
+            routing::post(|| async { Json(serde_json::json!([{ "rows": [{ "id": 1 }] }])) }),
+        );
+        let client = TestClient::new(app)?;
+
+        let request = serde_json::from_value(serde_json::json!({
+            "collection": "articles",
+            "query": {},
+            "arguments": {},
+            "collection_relationships": {},
+        }))?;
+        let ndc_models::QueryResponse(row_sets) = client.query(&request).await?;
+        assert_eq!(row_sets.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_error_responses() -> anyhow::Result<()> {
+        let app = Router::new().route(
+            "/schema",
+            routing::get(|| async {
+                ErrorResponse::new_internal_with_details(serde_json::json!({}))
+            }),
+        );
+        let client = TestClient::new(app)?.with_service_token("secret");
+
+        let error = client.schema().await.unwrap_err();
+        assert!(matches!(
+            error,
+            TestClientError::Response {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..
+            }
+        ));
+        Ok(())
+    }
+}
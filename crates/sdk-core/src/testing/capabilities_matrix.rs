@@ -0,0 +1,212 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+use futures::FutureExt as _;
+use ndc_models as models;
+use serde::Deserialize as _;
+use serde_json::json;
+
+use super::mock_connector::{CapabilitiesFixture, MinimalCapabilities};
+
+/// Queries with variables, aggregates, nested fields and unrelated `exists` predicates, but no
+/// relationships.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueryOnlyCapabilities;
+
+impl CapabilitiesFixture for QueryOnlyCapabilities {
+    fn capabilities() -> models::Capabilities {
+        fixture(&json!({
+            "query": {
+                "aggregates": {},
+                "variables": {},
+                "explain": {},
+                "nested_fields": { "filter_by": {}, "order_by": {}, "aggregates": {} },
+                "exists": { "unrelated": {}, "nested_collections": {} },
+            },
+            "mutation": {},
+        }))
+    }
+}
+
+/// Relationships, including comparisons and ordering across them, with no other optional
+/// capabilities.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RelationalCapabilities;
+
+impl CapabilitiesFixture for RelationalCapabilities {
+    fn capabilities() -> models::Capabilities {
+        fixture(&json!({
+            "query": { "nested_fields": {}, "exists": {} },
+            "mutation": {},
+            "relationships": { "relation_comparisons": {}, "order_by_aggregate": {} },
+        }))
+    }
+}
+
+/// Aggregates, including grouping, with no other optional capabilities.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AggregatesOnlyCapabilities;
+
+impl CapabilitiesFixture for AggregatesOnlyCapabilities {
+    fn capabilities() -> models::Capabilities {
+        fixture(&json!({
+            "query": {
+                "aggregates": {
+                    "filter_by": {},
+                    "group_by": { "filter": {}, "order": {}, "paginate": {} },
+                },
+                "nested_fields": {},
+                "exists": {},
+            },
+            "mutation": {},
+        }))
+    }
+}
+
+/// Every optional capability.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FullCapabilities;
+
+impl CapabilitiesFixture for FullCapabilities {
+    fn capabilities() -> models::Capabilities {
+        fixture(&json!({
+            "query": {
+                "aggregates": {
+                    "filter_by": {},
+                    "group_by": { "filter": {}, "order": {}, "paginate": {} },
+                },
+                "variables": {},
+                "explain": {},
+                "nested_fields": {
+                    "filter_by": { "nested_arrays": { "contains": {}, "is_empty": {} } },
+                    "order_by": {},
+                    "aggregates": {},
+                    "nested_collections": {},
+                },
+                "exists": {
+                    "named_scopes": {},
+                    "unrelated": {},
+                    "nested_collections": {},
+                    "nested_scalar_collections": {},
+                },
+            },
+            "mutation": { "transactional": {}, "explain": {} },
+            "relationships": {
+                "relation_comparisons": {},
+                "order_by_aggregate": {},
+                "nested": { "array": {}, "filtering": {}, "ordering": {} },
+            },
+        }))
+    }
+}
+
+fn fixture(capabilities: &serde_json::Value) -> models::Capabilities {
+    models::Capabilities::deserialize(capabilities).expect("invalid capabilities fixture")
+}
+
+/// Each of the capabilities fixtures, by name, from the fewest capabilities to the most.
+pub fn capabilities_matrix() -> Vec<(&'static str, models::Capabilities)> {
+    vec![
+        ("minimal", MinimalCapabilities::capabilities()),
+        ("query-only", QueryOnlyCapabilities::capabilities()),
+        ("relational", RelationalCapabilities::capabilities()),
+        (
+            "aggregates-only",
+            AggregatesOnlyCapabilities::capabilities(),
+        ),
+        ("full", FullCapabilities::capabilities()),
+    ]
+}
+
+/// Run a test under each of the capabilities in [`capabilities_matrix`], to catch code paths
+/// which rely on capabilities that were not declared.
+///
+/// Every fixture is tested, even if an earlier one fails. The test fails afterwards, naming each
+/// fixture under which it returned an error or panicked.
+///
+/// ```ignore
+/// for_each_capabilities(|capabilities| async move {
+///     let client = client(MySetup::with_capabilities(capabilities), "tests/configuration").await?;
+///     client.query(&request).await?;
+///     Ok::<_, TestClientError>(())
+/// })
+/// .await;
+/// ```
+pub async fn for_each_capabilities<F, Fut, E>(mut test: F)
+where
+    F: FnMut(models::Capabilities) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let mut failures = vec![];
+    for (name, capabilities) in capabilities_matrix() {
+        match AssertUnwindSafe(test(capabilities)).catch_unwind().await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => failures.push(format!("  {name}: {err}")),
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(ToString::to_string)
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "panicked".to_string());
+                failures.push(format!("  {name}: {message}"));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "the test failed with these capabilities:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_are_distinct() {
+        let matrix = capabilities_matrix()
+            .into_iter()
+            .map(|(name, capabilities)| (name, serde_json::to_value(capabilities).unwrap()))
+            .collect::<Vec<_>>();
+        for (index, (name, capabilities)) in matrix.iter().enumerate() {
+            for (other_name, other) in &matrix[index + 1..] {
+                assert_ne!(capabilities, other, "{name} and {other_name} are the same");
+            }
+        }
+        let full = serde_json::to_value(FullCapabilities::capabilities()).unwrap();
+        assert!(!full["relationships"]["nested"]["array"].is_null());
+    }
+
+    #[tokio::test]
+    async fn names_the_fixtures_under_which_the_test_fails() {
+        let result = AssertUnwindSafe(for_each_capabilities(|capabilities| async move {
+            if capabilities.relationships.is_some() {
+                return Err("relationships are not supported");
+            }
+            assert!(
+                capabilities.query.aggregates.is_none(),
+                "unexpected aggregates"
+            );
+            Ok(())
+        }))
+        .catch_unwind()
+        .await;
+
+        let panic = result.unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(!message.contains("minimal"), "{message}");
+        assert!(
+            message.contains("query-only: unexpected aggregates"),
+            "{message}"
+        );
+        assert!(
+            message.contains("relational: relationships are not supported"),
+            "{message}"
+        );
+        assert!(message.contains("aggregates-only"), "{message}");
+        assert!(message.contains("full"), "{message}");
+    }
+}
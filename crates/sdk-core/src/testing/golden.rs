@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::connector::{Connector, ConnectorSetup, ErrorResponse, Result};
+use crate::schema::get_capabilities;
+
+/// Set this environment variable to write the actual values to the golden files, rather than
+/// comparing them.
+pub const UPDATE_GOLDEN_FILES: &str = "UPDATE_GOLDEN_FILES";
+
+/// Serialize a value as pretty-printed JSON with sorted object keys, so that it only changes when
+/// the value does.
+pub fn canonical_json(value: &impl Serialize) -> std::result::Result<String, serde_json::Error> {
+    let mut json = serde_json::to_string_pretty(&sort_keys(serde_json::to_value(value)?))?;
+    json.push('\n');
+    Ok(json)
+}
+
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries = object.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            entries
+                .into_iter()
+                .map(|(key, value)| (key, sort_keys(value)))
+                .collect()
+        }
+        serde_json::Value::Array(values) => values.into_iter().map(sort_keys).collect(),
+        value => value,
+    }
+}
+
+/// Compare the actual contents of a file with the golden file at the given path, panicking with a
+/// diff if they differ.
+///
+/// If the `UPDATE_GOLDEN_FILES` environment variable is set, the golden file is written instead.
+pub fn assert_golden(golden_path: impl AsRef<Path>, actual: &str) {
+    let golden_path = golden_path.as_ref();
+    if std::env::var_os(UPDATE_GOLDEN_FILES).is_some() {
+        if let Some(parent) = golden_path.parent() {
+            std::fs::create_dir_all(parent).expect("unable to create the golden file directory");
+        }
+        std::fs::write(golden_path, actual).expect("unable to write the golden file");
+        return;
+    }
+    let expected = std::fs::read_to_string(golden_path).unwrap_or_else(|err| {
+        panic!(
+            "unable to read the golden file {}: {err}\nSet {UPDATE_GOLDEN_FILES}=1 to create it.",
+            golden_path.display()
+        )
+    });
+    if expected != actual {
+        let diff = similar::TextDiff::from_lines(expected.as_str(), actual)
+            .unified_diff()
+            .context_radius(3)
+            .header("golden", "actual")
+            .to_string();
+        panic!(
+            "{} does not match:\n{diff}\nSet {UPDATE_GOLDEN_FILES}=1 to update it.",
+            golden_path.display()
+        );
+    }
+}
+
+/// Compare the connector's schema, for the configuration at the given path, with a golden file.
+pub async fn assert_golden_schema<Setup: ConnectorSetup>(
+    setup: &Setup,
+    configuration_path: &Path,
+    golden_path: impl AsRef<Path>,
+) -> Result<()> {
+    let configuration = setup.parse_configuration(configuration_path).await?;
+    let schema = Setup::Connector::get_schema(&configuration)
+        .await?
+        .buffer()
+        .await?
+        .into_value::<ErrorResponse>()?;
+    assert_golden(
+        golden_path,
+        &canonical_json(&schema).map_err(ErrorResponse::from_error)?,
+    );
+    Ok(())
+}
+
+/// Compare the connector's capabilities, including the specification version, with a golden
+/// file.
+pub async fn assert_golden_capabilities<C: Connector>(golden_path: impl AsRef<Path>) -> Result<()> {
+    let capabilities = get_capabilities::<C>()
+        .await
+        .into_value::<ErrorResponse>()?;
+    assert_golden(
+        golden_path,
+        &canonical_json(&capabilities).map_err(ErrorResponse::from_error)?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_object_keys_recursively() -> anyhow::Result<()> {
+        let value = serde_json::json!({ "b": [{ "d": 1, "c": 2 }], "a": null });
+        assert_eq!(
+            canonical_json(&value)?,
+            "{\n  \"a\": null,\n  \"b\": [\n    {\n      \"c\": 2,\n      \"d\": 1\n    }\n  ]\n}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "-  \"name\": \"before\"")]
+    fn panics_with_a_diff() {
+        let golden =
+            std::env::temp_dir().join(format!("ndc-sdk-golden-{}.json", std::process::id()));
+        std::fs::write(&golden, "{\n  \"name\": \"before\"\n}\n").unwrap();
+        let actual = canonical_json(&serde_json::json!({ "name": "after" })).unwrap();
+        let result = std::panic::catch_unwind(|| assert_golden(&golden, &actual));
+        std::fs::remove_file(&golden).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use tokio::sync::oneshot;
+
+use crate::clock::Clock;
+
+/// A clock which only moves when it is advanced, for testing time-dependent behavior without
+/// waiting, or pausing the Tokio runtime.
+///
+/// Clones share the same time, so a test can keep one while the component under test uses
+/// another.
+///
+/// ```ignore
+/// let clock = ManualClock::new();
+/// let throttle = Throttle::new(Duration::from_secs(30)).with_clock(clock.clone());
+/// // ...
+/// clock.advance(Duration::from_secs(30));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    time: Arc<Mutex<ManualTime>>,
+}
+
+#[derive(Debug)]
+struct ManualTime {
+    now: Instant,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl ManualClock {
+    /// Create a clock, starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            time: Arc::new(Mutex::new(ManualTime {
+                now: Instant::now(),
+                sleepers: vec![],
+            })),
+        }
+    }
+
+    /// Move the clock forward, waking the sleepers whose duration has now passed.
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.lock();
+        time.now += duration;
+        let now = time.now;
+        let (woken, sleeping) = std::mem::take(&mut time.sleepers)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        time.sleepers = sleeping;
+        drop(time);
+        for (_, sleeper) in woken {
+            let _ = sleeper.send(());
+        }
+    }
+
+    /// The number of sleepers waiting for the clock to be advanced, so that a test can wait for
+    /// a task to start sleeping before advancing it.
+    pub fn sleepers(&self) -> usize {
+        let mut time = self.lock();
+        time.sleepers.retain(|(_, sleeper)| !sleeper.is_closed());
+        time.sleepers.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ManualTime> {
+        self.time
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.lock().now
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        if duration.is_zero() {
+            return Box::pin(std::future::ready(()));
+        }
+        let (sender, receiver) = oneshot::channel();
+        let mut time = self.lock();
+        let deadline = time.now + duration;
+        time.sleepers.push((deadline, sender));
+        Box::pin(async move {
+            // the sender is only dropped without sending if the clock is, in which case time
+            // will never pass, so the sleeper never wakes
+            if receiver.await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::timeout;
+
+    #[tokio::test]
+    async fn wakes_sleepers_when_advanced_past_their_deadline() {
+        let clock = ManualClock::new();
+        let started = clock.now();
+        let sleeper = tokio::spawn(clock.sleep(Duration::from_secs(10)));
+
+        clock.advance(Duration::from_secs(5));
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+        assert_eq!(clock.sleepers(), 1);
+
+        clock.advance(Duration::from_secs(5));
+        sleeper.await.unwrap();
+        assert_eq!(clock.now() - started, Duration::from_secs(10));
+        assert_eq!(clock.sleepers(), 0);
+    }
+
+    #[tokio::test]
+    async fn times_out_when_advanced() {
+        let clock = ManualClock::new();
+        let waiting = tokio::spawn({
+            let clock = clock.clone();
+            async move { timeout(&clock, Duration::from_secs(1), std::future::pending::<()>()).await }
+        });
+        while clock.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(waiting.await.unwrap(), None);
+        assert_eq!(
+            timeout(&clock, Duration::from_secs(1), async { 1 }).await,
+            Some(1)
+        );
+    }
+}
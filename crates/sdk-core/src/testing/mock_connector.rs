@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ndc_models as models;
+
+use crate::connector::{Connector, ConnectorSetup, ErrorCode, ErrorResponse, Result};
+use crate::json_response::JsonResponse;
+
+/// Capabilities which are fixed for a type, as [`Connector::get_capabilities`] does not have
+/// access to the connector's configuration.
+pub trait CapabilitiesFixture: Send + Sync + 'static {
+    fn capabilities() -> models::Capabilities;
+}
+
+/// No optional capabilities at all.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MinimalCapabilities;
+
+impl CapabilitiesFixture for MinimalCapabilities {
+    fn capabilities() -> models::Capabilities {
+        models::Capabilities {
+            relationships: None,
+            query: models::QueryCapabilities {
+                variables: None,
+                aggregates: None,
+                explain: None,
+                nested_fields: models::NestedFieldCapabilities {
+                    filter_by: None,
+                    order_by: None,
+                    aggregates: None,
+                    nested_collections: None,
+                },
+                exists: models::ExistsCapabilities {
+                    nested_collections: None,
+                    unrelated: None,
+                    named_scopes: None,
+                    nested_scalar_collections: None,
+                },
+            },
+            mutation: models::MutationCapabilities {
+                transactional: None,
+                explain: None,
+            },
+        }
+    }
+}
+
+type Handler<Request, Response> = Arc<dyn Fn(Request) -> Result<Response> + Send + Sync>;
+
+/// A connector whose responses are configured by each test, to simulate specific behaviors of a
+/// real connector, including errors and latency.
+///
+/// The mock is its own [`ConnectorSetup`], and its configuration is the mock itself, so it does
+/// not read anything from the configuration directory. Its capabilities are given by the type
+/// parameter. Endpoints which have not been configured respond with an `unsupportedOperation`
+/// error.
+///
+/// ```ignore
+/// let mock = MockConnector::<MinimalCapabilities>::new()
+///     .with_query_response(QueryResponse(vec![row_set]))
+///     .with_latency(Duration::from_millis(100));
+/// let client = ndc_sdk::testing::client(mock, ".").await?;
+/// ```
+pub struct MockConnector<Capabilities = MinimalCapabilities> {
+    schema: models::SchemaResponse,
+    query: Option<Handler<models::QueryRequest, models::QueryResponse>>,
+    query_explain: Option<Handler<models::QueryRequest, models::ExplainResponse>>,
+    mutation: Option<Handler<models::MutationRequest, models::MutationResponse>>,
+    mutation_explain: Option<Handler<models::MutationRequest, models::ExplainResponse>>,
+    latency: Duration,
+    capabilities: PhantomData<fn() -> Capabilities>,
+}
+
+impl<Capabilities> Clone for MockConnector<Capabilities> {
+    fn clone(&self) -> Self {
+        Self {
+            schema: self.schema.clone(),
+            query: self.query.clone(),
+            query_explain: self.query_explain.clone(),
+            mutation: self.mutation.clone(),
+            mutation_explain: self.mutation_explain.clone(),
+            latency: self.latency,
+            capabilities: PhantomData,
+        }
+    }
+}
+
+impl<Capabilities> std::fmt::Debug for MockConnector<Capabilities> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockConnector")
+            .field("schema", &self.schema)
+            .field("latency", &self.latency)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Capabilities> Default for MockConnector<Capabilities> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Capabilities> MockConnector<Capabilities> {
+    /// A mock with an empty schema, which does not support any queries or mutations.
+    pub fn new() -> Self {
+        Self {
+            schema: models::SchemaResponse {
+                collections: vec![],
+                functions: vec![],
+                procedures: vec![],
+                object_types: BTreeMap::new(),
+                scalar_types: BTreeMap::new(),
+                capabilities: None,
+            },
+            query: None,
+            query_explain: None,
+            mutation: None,
+            mutation_explain: None,
+            latency: Duration::ZERO,
+            capabilities: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn with_schema(mut self, schema: models::SchemaResponse) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Respond to every query with the same response.
+    #[must_use]
+    pub fn with_query_response(self, response: models::QueryResponse) -> Self {
+        self.with_query(move |_| Ok(response.clone()))
+    }
+
+    /// Respond to queries, or fail, according to the request.
+    #[must_use]
+    pub fn with_query(
+        mut self,
+        handler: impl Fn(models::QueryRequest) -> Result<models::QueryResponse> + Send + Sync + 'static,
+    ) -> Self {
+        self.query = Some(Arc::new(handler));
+        self
+    }
+
+    #[must_use]
+    pub fn with_query_explain(
+        mut self,
+        handler: impl Fn(models::QueryRequest) -> Result<models::ExplainResponse>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.query_explain = Some(Arc::new(handler));
+        self
+    }
+
+    /// Respond to every mutation with the same response.
+    #[must_use]
+    pub fn with_mutation_response(self, response: models::MutationResponse) -> Self {
+        self.with_mutation(move |_| Ok(response.clone()))
+    }
+
+    /// Respond to mutations, or fail, according to the request.
+    #[must_use]
+    pub fn with_mutation(
+        mut self,
+        handler: impl Fn(models::MutationRequest) -> Result<models::MutationResponse>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.mutation = Some(Arc::new(handler));
+        self
+    }
+
+    #[must_use]
+    pub fn with_mutation_explain(
+        mut self,
+        handler: impl Fn(models::MutationRequest) -> Result<models::ExplainResponse>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.mutation_explain = Some(Arc::new(handler));
+        self
+    }
+
+    /// Wait this long before every response, including errors.
+    #[must_use]
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+}
+
+async fn respond<Request, Response>(
+    handler: Option<&Handler<Request, Response>>,
+    latency: Duration,
+    endpoint: &str,
+    request: Request,
+) -> Result<JsonResponse<Response>> {
+    tokio::time::sleep(latency).await;
+    match handler {
+        Some(handler) => handler(request).map(JsonResponse::Value),
+        None => Err(ErrorResponse::new_with_code(
+            ErrorCode::UnsupportedOperation,
+            format!("the mock connector has no {endpoint} response"),
+        )),
+    }
+}
+
+#[async_trait]
+impl<Capabilities: CapabilitiesFixture> ConnectorSetup for MockConnector<Capabilities> {
+    type Connector = Self;
+
+    async fn parse_configuration(&self, _configuration_path: &Path) -> Result<Self> {
+        Ok(self.clone())
+    }
+
+    async fn try_init_state(
+        &self,
+        _configuration: &Self,
+        _metrics: &mut prometheus::Registry,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<Capabilities: CapabilitiesFixture> Connector for MockConnector<Capabilities> {
+    type Configuration = Self;
+    type State = ();
+
+    fn fetch_metrics(_configuration: &Self, _state: &()) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_capabilities() -> models::Capabilities {
+        Capabilities::capabilities()
+    }
+
+    async fn get_schema(configuration: &Self) -> Result<JsonResponse<models::SchemaResponse>> {
+        tokio::time::sleep(configuration.latency).await;
+        Ok(JsonResponse::Value(configuration.schema.clone()))
+    }
+
+    async fn query_explain(
+        configuration: &Self,
+        _state: &(),
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        respond(
+            configuration.query_explain.as_ref(),
+            configuration.latency,
+            "query/explain",
+            request,
+        )
+        .await
+    }
+
+    async fn mutation_explain(
+        configuration: &Self,
+        _state: &(),
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        respond(
+            configuration.mutation_explain.as_ref(),
+            configuration.latency,
+            "mutation/explain",
+            request,
+        )
+        .await
+    }
+
+    async fn mutation(
+        configuration: &Self,
+        _state: &(),
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        respond(
+            configuration.mutation.as_ref(),
+            configuration.latency,
+            "mutation",
+            request,
+        )
+        .await
+    }
+
+    async fn query(
+        configuration: &Self,
+        _state: &(),
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        respond(
+            configuration.query.as_ref(),
+            configuration.latency,
+            "query",
+            request,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_request(collection: &str) -> anyhow::Result<models::QueryRequest> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "collection": collection,
+            "query": {},
+            "arguments": {},
+            "collection_relationships": {},
+        }))?)
+    }
+
+    #[tokio::test]
+    async fn responds_with_canned_responses() -> anyhow::Result<()> {
+        let mock = MockConnector::<MinimalCapabilities>::new()
+            .with_query_response(models::QueryResponse(vec![]));
+
+        let response = MockConnector::query(&mock, &(), query_request("articles")?).await?;
+        assert_eq!(
+            response.into_value::<ErrorResponse>()?,
+            models::QueryResponse(vec![])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn responds_according_to_the_request() -> anyhow::Result<()> {
+        let mock = MockConnector::<MinimalCapabilities>::new().with_query(|request| {
+            if request.collection.as_str() == "articles" {
+                Ok(models::QueryResponse(vec![]))
+            } else {
+                Err(ErrorResponse::new_with_code(
+                    ErrorCode::InvalidRequest,
+                    "unknown collection".to_owned(),
+                ))
+            }
+        });
+
+        assert!(MockConnector::query(&mock, &(), query_request("articles")?)
+            .await
+            .is_ok());
+        let error = MockConnector::query(&mock, &(), query_request("authors")?)
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_code(), Some(ErrorCode::InvalidRequest));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_unconfigured_endpoints() -> anyhow::Result<()> {
+        let mock = MockConnector::<MinimalCapabilities>::new();
+
+        let error = MockConnector::query(&mock, &(), query_request("articles")?)
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_code(), Some(ErrorCode::UnsupportedOperation));
+        Ok(())
+    }
+}
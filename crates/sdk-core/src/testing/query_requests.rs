@@ -0,0 +1,722 @@
+//! Generating spec-valid query requests for a schema, to fuzz a connector's query translation with
+//! [`proptest`].
+//!
+//! The schema is read through its JSON representation, so that everything the generator does not
+//! understand, such as geospatial scalar types or predicate arguments, is skipped rather than
+//! producing invalid requests.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::{select, subsequence};
+use proptest::strategy::Union;
+use serde_json::{json, Map, Value};
+
+use ndc_models::{Capabilities, QueryRequest, SchemaResponse};
+
+/// How deeply object types are nested in generated values.
+const MAX_OBJECT_DEPTH: u32 = 2;
+/// How many elements are generated for arrays, including `in` comparisons.
+const MAX_ARRAY_LENGTH: usize = 3;
+/// How deeply `and`, `or` and `not` expressions are nested in predicates.
+const MAX_PREDICATE_DEPTH: u32 = 3;
+/// How many operands `and` and `or` expressions have.
+const MAX_PREDICATE_OPERANDS: u32 = 3;
+/// How many variable sets are generated, if the connector supports variables.
+const MAX_VARIABLE_SETS: usize = 3;
+
+/// Generate query requests for the collections of the schema, using only the given capabilities.
+///
+/// Requests select a non-empty subset of a collection's columns, and may have a limit, an offset,
+/// an ordering and a predicate built from the comparison operators of the columns' scalar types.
+/// Aggregates are only generated if the connector supports them, and likewise for variables.
+/// Collections with arguments whose values cannot be generated are skipped.
+///
+/// ```ignore
+/// proptest! {
+///     #[test]
+///     fn translates_any_query(request in query_requests(&schema, &capabilities)) {
+///         translate(&request).unwrap();
+///     }
+/// }
+/// ```
+///
+/// # Panics
+///
+/// If the schema has no collections for which requests can be generated.
+pub fn query_requests(
+    schema: &SchemaResponse,
+    capabilities: &Capabilities,
+) -> BoxedStrategy<QueryRequest> {
+    let schema = serde_json::to_value(schema).expect("unable to serialize the schema");
+    let capabilities =
+        serde_json::to_value(capabilities).expect("unable to serialize the capabilities");
+    let types = Types {
+        scalar_types: schema["scalar_types"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default(),
+        object_types: schema["object_types"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default(),
+    };
+    let features = Features {
+        aggregates: supports(&capabilities["query"], "aggregates"),
+        variables: supports(&capabilities["query"], "variables"),
+    };
+
+    let collections = schema["collections"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|collection| collection_requests(&types, collection, features))
+        .collect::<Vec<_>>();
+    assert!(
+        !collections.is_empty(),
+        "the schema has no collections for which query requests can be generated"
+    );
+
+    Union::new(collections)
+        .prop_map(|request| {
+            serde_json::from_value(request).expect("generated an invalid query request")
+        })
+        .boxed()
+}
+
+fn supports(capabilities: &Value, capability: &str) -> bool {
+    capabilities
+        .get(capability)
+        .is_some_and(|capability| !capability.is_null())
+}
+
+#[derive(Clone, Copy)]
+struct Features {
+    aggregates: bool,
+    variables: bool,
+}
+
+struct Types {
+    scalar_types: Map<String, Value>,
+    object_types: Map<String, Value>,
+}
+
+struct Column {
+    name: String,
+    scalar_type: Option<Value>,
+    values: Option<BoxedStrategy<Value>>,
+}
+
+/// A binary comparison whose value can be generated.
+struct Comparison {
+    column: String,
+    operator: String,
+    values: BoxedStrategy<Value>,
+}
+
+impl Comparison {
+    /// The variable which may be compared against instead of a literal value.
+    fn variable(&self) -> String {
+        format!("{}_{}", self.column, self.operator)
+    }
+}
+
+fn collection_requests(
+    types: &Types,
+    collection: &Value,
+    features: Features,
+) -> Option<BoxedStrategy<Value>> {
+    let name = collection["name"].as_str()?.to_owned();
+    let object_type = types.object_types.get(collection["type"].as_str()?)?;
+
+    let arguments = arguments(types, collection)?;
+    let columns = columns(types, object_type);
+    if columns.is_empty() {
+        return None;
+    }
+    let comparisons = comparisons(types, &columns);
+
+    let variables = if features.variables && !comparisons.is_empty() {
+        proptest::option::of(vec(variable_sets(&comparisons), 1..=MAX_VARIABLE_SETS)).boxed()
+    } else {
+        Just(None).boxed()
+    };
+    let literal_predicates = predicates(&columns, &comparisons, false);
+    let variable_predicates = predicates(&columns, &comparisons, true);
+    let variables_and_predicate = variables.prop_flat_map(move |variables| {
+        let predicates = if variables.is_some() {
+            variable_predicates.clone()
+        } else {
+            literal_predicates.clone()
+        };
+        (Just(variables), proptest::option::of(predicates))
+    });
+
+    let fields = fields(&columns);
+    let aggregates = if features.aggregates {
+        proptest::option::of(aggregates(&columns)).boxed()
+    } else {
+        Just(None).boxed()
+    };
+
+    Some(
+        (
+            arguments,
+            variables_and_predicate,
+            fields,
+            aggregates,
+            proptest::option::of(0..100u32),
+            proptest::option::of(0..100u32),
+            proptest::option::of(order_by(&columns)),
+        )
+            .prop_map(
+                move |(
+                    arguments,
+                    (variables, predicate),
+                    fields,
+                    aggregates,
+                    limit,
+                    offset,
+                    order_by,
+                )| {
+                    let query = [
+                        ("fields", Some(fields)),
+                        ("aggregates", aggregates),
+                        ("limit", limit.map(Value::from)),
+                        ("offset", offset.map(Value::from)),
+                        ("order_by", order_by),
+                        ("predicate", predicate),
+                    ];
+                    let mut request = json!({
+                        "collection": name,
+                        "query": object(query),
+                        "arguments": arguments,
+                        "collection_relationships": {},
+                    });
+                    if let Some(variables) = variables {
+                        request["variables"] = Value::Array(variables);
+                    }
+                    request
+                },
+            )
+            .boxed(),
+    )
+}
+
+/// Literal values for the arguments of a collection, if they can all be generated.
+fn arguments(types: &Types, collection: &Value) -> Option<impl Strategy<Value = Value>> {
+    let arguments = collection["arguments"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, argument)| {
+            let name = name.clone();
+            let values = types.values(&argument["type"], MAX_OBJECT_DEPTH)?;
+            Some(values.prop_map(move |value| {
+                (name.clone(), json!({ "type": "literal", "value": value }))
+            }))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(arguments.prop_map(|arguments| Value::Object(arguments.into_iter().collect())))
+}
+
+fn columns(types: &Types, object_type: &Value) -> Vec<Column> {
+    object_type["fields"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        // fields with arguments cannot be selected without them
+        .filter(|(_, field)| {
+            field["arguments"]
+                .as_object()
+                .into_iter()
+                .all(Map::is_empty)
+        })
+        .map(|(name, field)| Column {
+            name: name.clone(),
+            scalar_type: types.scalar_type(&field["type"]).cloned(),
+            values: types.values(&field["type"], MAX_OBJECT_DEPTH),
+        })
+        .collect()
+}
+
+fn comparisons(types: &Types, columns: &[Column]) -> Vec<Comparison> {
+    columns
+        .iter()
+        .filter_map(|column| Some((column, column.scalar_type.as_ref()?)))
+        .flat_map(|(column, scalar_type)| {
+            scalar_type["comparison_operators"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(move |(operator, definition)| {
+                    Some(Comparison {
+                        column: column.name.clone(),
+                        operator: operator.clone(),
+                        values: types.comparison_values(column, definition)?,
+                    })
+                })
+        })
+        .collect()
+}
+
+/// A value for the variable of every comparison.
+fn variable_sets(comparisons: &[Comparison]) -> impl Strategy<Value = Value> {
+    comparisons
+        .iter()
+        .map(|comparison| {
+            let variable = comparison.variable();
+            comparison
+                .values
+                .clone()
+                .prop_map(move |value| (variable.clone(), value))
+        })
+        .collect::<Vec<_>>()
+        .prop_map(|variables| Value::Object(variables.into_iter().collect()))
+}
+
+/// A non-empty selection of columns.
+fn fields(columns: &[Column]) -> impl Strategy<Value = Value> {
+    let columns = columns
+        .iter()
+        .map(|column| column.name.clone())
+        .collect::<Vec<_>>();
+    let count = columns.len();
+    subsequence(columns, 1..=count).prop_map(|columns| {
+        Value::Object(
+            columns
+                .into_iter()
+                .map(|column| {
+                    let field = json!({ "type": "column", "column": column, "arguments": {} });
+                    (column, field)
+                })
+                .collect(),
+        )
+    })
+}
+
+/// An object with the entries which are present.
+fn object<const N: usize>(entries: [(&str, Option<Value>); N]) -> Value {
+    Value::Object(
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_owned(), value?)))
+            .collect(),
+    )
+}
+
+fn column_target(column: &str) -> Value {
+    json!({ "type": "column", "name": column, "arguments": {} })
+}
+
+fn predicates(
+    columns: &[Column],
+    comparisons: &[Comparison],
+    variables: bool,
+) -> BoxedStrategy<Value> {
+    let is_null = columns.iter().map(|column| {
+        Just(json!({
+            "type": "unary_comparison_operator",
+            "column": column_target(&column.name),
+            "operator": "is_null",
+        }))
+        .boxed()
+    });
+    let comparisons = comparisons.iter().map(|comparison| {
+        let column = column_target(&comparison.column);
+        let operator = comparison.operator.clone();
+        let literals = comparison
+            .values
+            .clone()
+            .prop_map(|value| json!({ "type": "scalar", "value": value }));
+        let values = if variables {
+            prop_oneof![
+                literals,
+                Just(json!({ "type": "variable", "name": comparison.variable() })),
+            ]
+            .boxed()
+        } else {
+            literals.boxed()
+        };
+        values
+            .prop_map(move |value| {
+                json!({
+                    "type": "binary_comparison_operator",
+                    "column": column,
+                    "operator": operator,
+                    "value": value,
+                })
+            })
+            .boxed()
+    });
+
+    Union::new(is_null.chain(comparisons))
+        .prop_recursive(MAX_PREDICATE_DEPTH, 16, MAX_PREDICATE_OPERANDS, |inner| {
+            let operands = 0..=MAX_PREDICATE_OPERANDS as usize;
+            prop_oneof![
+                vec(inner.clone(), operands.clone())
+                    .prop_map(|expressions| json!({ "type": "and", "expressions": expressions })),
+                vec(inner.clone(), operands)
+                    .prop_map(|expressions| json!({ "type": "or", "expressions": expressions })),
+                inner.prop_map(|expression| json!({ "type": "not", "expression": expression })),
+            ]
+        })
+        .boxed()
+}
+
+/// Orderings by scalar columns.
+fn order_by(columns: &[Column]) -> BoxedStrategy<Value> {
+    let columns = columns
+        .iter()
+        .filter(|column| column.scalar_type.is_some())
+        .map(|column| column.name.clone())
+        .collect::<Vec<_>>();
+    let count = columns.len();
+    subsequence(columns, 0..=count)
+        .prop_flat_map(|columns| {
+            let directions = vec(select(&["asc", "desc"][..]), columns.len());
+            (Just(columns), directions)
+        })
+        .prop_map(|(columns, directions)| {
+            let elements = columns
+                .into_iter()
+                .zip(directions)
+                .map(|(column, direction)| {
+                    json!({
+                        "order_direction": direction,
+                        "target": {
+                            "type": "column",
+                            "name": column,
+                            "arguments": {},
+                            "path": [],
+                        },
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({ "elements": elements })
+        })
+        .boxed()
+}
+
+/// Counts, and the aggregate functions of scalar columns.
+fn aggregates(columns: &[Column]) -> BoxedStrategy<Value> {
+    let mut aggregates = vec![json!({ "type": "star_count" })];
+    for column in columns {
+        for distinct in [false, true] {
+            aggregates.push(json!({
+                "type": "column_count",
+                "column": column.name,
+                "arguments": {},
+                "distinct": distinct,
+            }));
+        }
+        let functions = column
+            .scalar_type
+            .as_ref()
+            .and_then(|scalar_type| scalar_type["aggregate_functions"].as_object());
+        for function in functions
+            .into_iter()
+            .flatten()
+            .map(|(function, _)| function)
+        {
+            aggregates.push(json!({
+                "type": "single_column",
+                "column": column.name,
+                "arguments": {},
+                "function": function,
+            }));
+        }
+    }
+    let count = aggregates.len();
+    subsequence(aggregates, 0..=count)
+        .prop_map(|aggregates| {
+            Value::Object(
+                aggregates
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, aggregate)| (format!("aggregate_{index}"), aggregate))
+                    .collect(),
+            )
+        })
+        .boxed()
+}
+
+impl Types {
+    /// The scalar type of a column, which may be nullable.
+    fn scalar_type(&self, r#type: &Value) -> Option<&Value> {
+        match r#type["type"].as_str()? {
+            "named" => self.scalar_types.get(r#type["name"].as_str()?),
+            "nullable" => self.scalar_type(&r#type["underlying_type"]),
+            _ => None,
+        }
+    }
+
+    /// Values of the type, if they can be generated.
+    fn values(&self, r#type: &Value, depth: u32) -> Option<BoxedStrategy<Value>> {
+        match r#type["type"].as_str()? {
+            "named" => {
+                let name = r#type["name"].as_str()?;
+                if let Some(scalar_type) = self.scalar_types.get(name) {
+                    representation_values(&scalar_type["representation"])
+                } else {
+                    self.object_values(self.object_types.get(name)?, depth)
+                }
+            }
+            "nullable" => {
+                let values = self.values(&r#type["underlying_type"], depth)?;
+                Some(prop_oneof![Just(Value::Null), values].boxed())
+            }
+            "array" => {
+                let elements = self.values(&r#type["element_type"], depth)?;
+                Some(
+                    vec(elements, 0..=MAX_ARRAY_LENGTH)
+                        .prop_map(Value::Array)
+                        .boxed(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn object_values(&self, object_type: &Value, depth: u32) -> Option<BoxedStrategy<Value>> {
+        let depth = depth.checked_sub(1)?;
+        let fields = object_type["fields"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(name, field)| {
+                let name = name.clone();
+                let values = self.values(&field["type"], depth)?;
+                Some(values.prop_map(move |value| (name.clone(), value)))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(
+            fields
+                .prop_map(|fields| Value::Object(fields.into_iter().collect()))
+                .boxed(),
+        )
+    }
+
+    /// Values to compare a column against with an operator, if they can be generated.
+    fn comparison_values(
+        &self,
+        column: &Column,
+        definition: &Value,
+    ) -> Option<BoxedStrategy<Value>> {
+        match definition["type"].as_str()? {
+            "equal"
+            | "less_than"
+            | "less_than_or_equal"
+            | "greater_than"
+            | "greater_than_or_equal" => column.values.clone(),
+            "in" => Some(
+                vec(column.values.clone()?, 0..=MAX_ARRAY_LENGTH)
+                    .prop_map(Value::Array)
+                    .boxed(),
+            ),
+            "contains"
+            | "contains_insensitive"
+            | "starts_with"
+            | "starts_with_insensitive"
+            | "ends_with"
+            | "ends_with_insensitive" => Some(any::<String>().prop_map(Value::from).boxed()),
+            "custom" => self.values(&definition["argument_type"], MAX_OBJECT_DEPTH),
+            _ => None,
+        }
+    }
+}
+
+/// Values of a scalar type representation, in their JSON encoding.
+fn representation_values(representation: &Value) -> Option<BoxedStrategy<Value>> {
+    let values = match representation["type"].as_str()? {
+        "boolean" => any::<bool>().prop_map(Value::from).boxed(),
+        "string" => any::<String>().prop_map(Value::from).boxed(),
+        "int8" => any::<i8>().prop_map(Value::from).boxed(),
+        "int16" => any::<i16>().prop_map(Value::from).boxed(),
+        "int32" => any::<i32>().prop_map(Value::from).boxed(),
+        // 64-bit and arbitrary precision numbers are encoded as strings
+        "int64" => any::<i64>()
+            .prop_map(|n| Value::from(n.to_string()))
+            .boxed(),
+        "biginteger" => any::<i128>()
+            .prop_map(|n| Value::from(n.to_string()))
+            .boxed(),
+        "bigdecimal" => (any::<i64>(), any::<u32>())
+            .prop_map(|(integer, fraction)| Value::from(format!("{integer}.{fraction}")))
+            .boxed(),
+        "float32" => (-1e6f32..1e6f32).prop_map(Value::from).boxed(),
+        "float64" => (-1e12f64..1e12f64).prop_map(Value::from).boxed(),
+        "uuid" => any::<u128>()
+            .prop_map(|n| {
+                let hex = format!("{n:032x}");
+                Value::from(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                ))
+            })
+            .boxed(),
+        "date" => dates().prop_map(Value::from).boxed(),
+        "timestamp" => timestamps().prop_map(Value::from).boxed(),
+        "timestamptz" => timestamps()
+            .prop_map(|timestamp| Value::from(format!("{timestamp}Z")))
+            .boxed(),
+        "bytes" => "([A-Za-z0-9+/]{4}){0,4}".prop_map(Value::from).boxed(),
+        "json" => prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i32>().prop_map(Value::from),
+            any::<String>().prop_map(Value::from),
+        ]
+        .boxed(),
+        "enum" => {
+            let one_of = representation["one_of"].as_array()?.clone();
+            if one_of.is_empty() {
+                return None;
+            }
+            select(one_of).boxed()
+        }
+        _ => return None,
+    };
+    Some(values)
+}
+
+fn dates() -> impl Strategy<Value = String> {
+    (1970..2100i32, 1..=12u32, 1..=28u32)
+        .prop_map(|(year, month, day)| format!("{year:04}-{month:02}-{day:02}"))
+}
+
+fn timestamps() -> impl Strategy<Value = String> {
+    (dates(), 0..24u32, 0..60u32, 0..60u32).prop_map(|(date, hour, minute, second)| {
+        format!("{date}T{hour:02}:{minute:02}:{second:02}")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::strategy::ValueTree as _;
+    use proptest::test_runner::TestRunner;
+
+    use super::*;
+    use crate::testing::{CapabilitiesFixture as _, MinimalCapabilities};
+
+    fn schema() -> anyhow::Result<SchemaResponse> {
+        Ok(serde_json::from_value(json!({
+            "scalar_types": {
+                "Int": {
+                    "representation": { "type": "int32" },
+                    "aggregate_functions": {
+                        "max": { "type": "max" },
+                    },
+                    "comparison_operators": {
+                        "_eq": { "type": "equal" },
+                        "_in": { "type": "in" },
+                        "_gt": {
+                            "type": "custom",
+                            "argument_type": { "type": "named", "name": "Int" },
+                        },
+                    },
+                    "extraction_functions": {},
+                },
+                "String": {
+                    "representation": { "type": "string" },
+                    "aggregate_functions": {},
+                    "comparison_operators": {
+                        "_like": { "type": "contains" },
+                    },
+                    "extraction_functions": {},
+                },
+                "Point": {
+                    "representation": { "type": "geometry" },
+                    "aggregate_functions": {},
+                    "comparison_operators": {},
+                    "extraction_functions": {},
+                },
+            },
+            "object_types": {
+                "article": {
+                    "fields": {
+                        "id": { "type": { "type": "named", "name": "Int" }, "arguments": {} },
+                        "title": {
+                            "type": {
+                                "type": "nullable",
+                                "underlying_type": { "type": "named", "name": "String" },
+                            },
+                            "arguments": {},
+                        },
+                        "location": { "type": { "type": "named", "name": "Point" }, "arguments": {} },
+                    },
+                    "foreign_keys": {},
+                },
+            },
+            "collections": [
+                {
+                    "name": "articles",
+                    "arguments": {},
+                    "type": "article",
+                    "uniqueness_constraints": {},
+                },
+                {
+                    "name": "articles_near",
+                    "arguments": {
+                        "location": { "type": { "type": "named", "name": "Point" } },
+                    },
+                    "type": "article",
+                    "uniqueness_constraints": {},
+                },
+            ],
+            "functions": [],
+            "procedures": [],
+        }))?)
+    }
+
+    fn generate(
+        schema: &SchemaResponse,
+        capabilities: &Capabilities,
+    ) -> anyhow::Result<Vec<QueryRequest>> {
+        let strategy = query_requests(schema, capabilities);
+        let mut runner = TestRunner::deterministic();
+        (0..256)
+            .map(|_| {
+                let tree = strategy
+                    .new_tree(&mut runner)
+                    .map_err(|reason| anyhow::anyhow!("{reason}"))?;
+                Ok(tree.current())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generates_requests_within_the_schema() -> anyhow::Result<()> {
+        let requests = generate(&schema()?, &MinimalCapabilities::capabilities())?;
+
+        for request in &requests {
+            // the collection with an argument of an unsupported type is skipped
+            assert_eq!(request.collection.as_str(), "articles");
+            assert!(request.variables.is_none());
+            assert!(request.query.aggregates.is_none());
+            let fields = request.query.fields.as_ref().expect("no fields");
+            assert!(!fields.is_empty());
+        }
+        assert!(requests
+            .iter()
+            .any(|request| request.query.predicate.is_some()));
+        Ok(())
+    }
+
+    #[test]
+    fn generates_aggregates_and_variables_if_supported() -> anyhow::Result<()> {
+        let mut capabilities = serde_json::to_value(MinimalCapabilities::capabilities())?;
+        capabilities["query"]["aggregates"] = json!({});
+        capabilities["query"]["variables"] = json!({});
+        let requests = generate(&schema()?, &serde_json::from_value(capabilities)?)?;
+
+        assert!(requests
+            .iter()
+            .any(|request| request.query.aggregates.is_some()));
+        assert!(requests.iter().any(|request| request.variables.is_some()));
+        Ok(())
+    }
+}
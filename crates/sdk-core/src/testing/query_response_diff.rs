@@ -0,0 +1,247 @@
+use ndc_models::{QueryRequest, QueryResponse};
+use serde_json::Value;
+
+use super::canonical_json;
+
+/// Compares query responses, ignoring differences which a connector is free to make, and
+/// describes the remaining differences as a diff.
+///
+/// Object keys are compared without regard to their order, integers are equal to floating-point
+/// numbers with the same value, and a missing `rows` or `aggregates` is equal to `null`.
+///
+/// ```ignore
+/// QueryResponseComparison::for_request(&request)
+///     .with_numeric_tolerance(1e-9)
+///     .assert_eq(&expected, &actual);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryResponseComparison {
+    ignore_row_order: bool,
+    numeric_tolerance: f64,
+}
+
+impl QueryResponseComparison {
+    /// Compare responses exactly, apart from the normalization above.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compare the responses to a request, ignoring the order of rows unless the request, or any
+    /// of its relationships, orders them.
+    pub fn for_request(request: &QueryRequest) -> Self {
+        let request = serde_json::to_value(request).expect("unable to serialize the request");
+        Self::new().ignore_row_order(!orders_rows(&request))
+    }
+
+    /// Compare the rows of each row set as multisets. Row sets themselves are always ordered, as
+    /// there is one for each variable set.
+    #[must_use]
+    pub fn ignore_row_order(mut self, ignore_row_order: bool) -> Self {
+        self.ignore_row_order = ignore_row_order;
+        self
+    }
+
+    /// Consider numbers equal if they differ by at most this much.
+    #[must_use]
+    pub fn with_numeric_tolerance(mut self, numeric_tolerance: f64) -> Self {
+        self.numeric_tolerance = numeric_tolerance;
+        self
+    }
+
+    /// A unified diff of the responses, serialized with sorted keys, or `None` if they are equal.
+    ///
+    /// When rows are compared without regard to their order, the actual rows are listed in the
+    /// order of the expected rows which they match, followed by those which match none.
+    pub fn diff(&self, expected: &QueryResponse, actual: &QueryResponse) -> Option<String> {
+        let expected = normalize(serde_json::to_value(expected).expect("unable to serialize"));
+        let actual = normalize(serde_json::to_value(actual).expect("unable to serialize"));
+        let actual = self.align(&expected, actual, false);
+        if actual == expected {
+            return None;
+        }
+        let expected = canonical_json(&expected).expect("unable to serialize");
+        let actual = canonical_json(&actual).expect("unable to serialize");
+        Some(
+            similar::TextDiff::from_lines(expected.as_str(), actual.as_str())
+                .unified_diff()
+                .context_radius(3)
+                .header("expected", "actual")
+                .to_string(),
+        )
+    }
+
+    /// Panic with a diff if the responses differ.
+    pub fn assert_eq(&self, expected: &QueryResponse, actual: &QueryResponse) {
+        if let Some(diff) = self.diff(expected, actual) {
+            panic!("the query responses differ:\n{diff}");
+        }
+    }
+
+    /// Rewrite the parts of the actual value which are equal to the expected value, as far as the
+    /// comparison is concerned, to be identical to it.
+    fn align(&self, expected: &Value, actual: Value, rows: bool) -> Value {
+        match (expected, actual) {
+            (Value::Number(expected), Value::Number(actual)) => {
+                match (expected.as_f64(), actual.as_f64()) {
+                    (Some(x), Some(y)) if (x - y).abs() <= self.numeric_tolerance => {
+                        Value::Number(expected.clone())
+                    }
+                    _ => Value::Number(actual),
+                }
+            }
+            (Value::Object(expected), Value::Object(actual)) => Value::Object(
+                actual
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let value = match expected.get(&key) {
+                            Some(expected) => self.align(expected, value, key == "rows"),
+                            None => value,
+                        };
+                        (key, value)
+                    })
+                    .collect(),
+            ),
+            (Value::Array(expected), Value::Array(actual)) if rows && self.ignore_row_order => {
+                Value::Array(self.align_unordered(expected, actual))
+            }
+            (Value::Array(expected), Value::Array(actual)) => Value::Array(
+                actual
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, value)| match expected.get(index) {
+                        Some(expected) => self.align(expected, value, false),
+                        None => value,
+                    })
+                    .collect(),
+            ),
+            (_, actual) => actual,
+        }
+    }
+
+    fn align_unordered(&self, expected: &[Value], actual: Vec<Value>) -> Vec<Value> {
+        let mut unmatched = actual.into_iter().map(Some).collect::<Vec<_>>();
+        let mut aligned = Vec::with_capacity(unmatched.len());
+        for expected in expected {
+            let matched = unmatched.iter_mut().find_map(|candidate| {
+                let value = self.align(expected, candidate.clone()?, false);
+                (value == *expected).then(|| {
+                    *candidate = None;
+                    value
+                })
+            });
+            aligned.extend(matched);
+        }
+        aligned.extend(unmatched.into_iter().flatten());
+        aligned
+    }
+}
+
+/// Compare query responses, ignoring the order of rows unless the request orders them, and panic
+/// with a diff if they differ.
+pub fn assert_query_response_eq(
+    request: &QueryRequest,
+    expected: &QueryResponse,
+    actual: &QueryResponse,
+) {
+    QueryResponseComparison::for_request(request).assert_eq(expected, actual);
+}
+
+fn orders_rows(request: &Value) -> bool {
+    match request {
+        Value::Object(object) => object
+            .iter()
+            .any(|(key, value)| (key == "order_by" && !value.is_null()) || orders_rows(value)),
+        Value::Array(values) => values.iter().any(orders_rows),
+        _ => false,
+    }
+}
+
+/// Remove the row set fields which are `null`, which is the same as omitting them.
+fn normalize(value: Value) -> Value {
+    match value {
+        Value::Object(object) => object
+            .into_iter()
+            .filter(|(key, value)| {
+                !(value.is_null() && matches!(key.as_str(), "rows" | "aggregates" | "groups"))
+            })
+            .map(|(key, value)| (key, normalize(value)))
+            .collect(),
+        Value::Array(values) => values.into_iter().map(normalize).collect(),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn response(value: Value) -> QueryResponse {
+        serde_json::from_value(value).expect("invalid response")
+    }
+
+    fn request(query: &Value) -> QueryRequest {
+        serde_json::from_value(json!({
+            "collection": "articles",
+            "query": query,
+            "arguments": {},
+            "collection_relationships": {},
+        }))
+        .expect("invalid request")
+    }
+
+    #[test]
+    fn ignores_row_order_unless_the_request_orders_rows() {
+        let expected = response(json!([{ "rows": [{ "id": 1 }, { "id": 2 }] }]));
+        let actual = response(json!([{ "rows": [{ "id": 2 }, { "id": 1 }] }]));
+
+        let unordered = request(&json!({}));
+        assert_eq!(
+            QueryResponseComparison::for_request(&unordered).diff(&expected, &actual),
+            None
+        );
+        let ordered = request(&json!({
+            "order_by": {
+                "elements": [{
+                    "order_direction": "asc",
+                    "target": { "type": "column", "name": "id", "arguments": {}, "path": [] },
+                }],
+            },
+        }));
+        assert!(QueryResponseComparison::for_request(&ordered)
+            .diff(&expected, &actual)
+            .is_some());
+    }
+
+    #[test]
+    fn tolerates_numeric_differences() {
+        let expected = response(json!([{ "rows": [{ "price": 1.0 }], "aggregates": null }]));
+        let actual = response(json!([{ "rows": [{ "price": 1.000_000_1 }] }]));
+
+        assert!(QueryResponseComparison::new()
+            .diff(&expected, &actual)
+            .is_some());
+        QueryResponseComparison::new()
+            .with_numeric_tolerance(1e-6)
+            .assert_eq(&expected, &actual);
+    }
+
+    #[test]
+    fn describes_differences_as_a_diff() {
+        let expected = response(json!([{ "rows": [{ "id": 1, "title": "The Next 700" }] }]));
+        let actual = response(json!([{ "rows": [{ "id": 1, "title": "The Next 800" }] }]));
+
+        let diff = QueryResponseComparison::new()
+            .diff(&expected, &actual)
+            .expect("no differences");
+        assert!(
+            diff.contains(r#"-        "title": "The Next 700""#),
+            "{diff}"
+        );
+        assert!(
+            diff.contains(r#"+        "title": "The Next 800""#),
+            "{diff}"
+        );
+    }
+}
@@ -0,0 +1,469 @@
+//! Rate limiting for expensive operations which are requested often, such as reading metrics from
+//! an upstream database whenever they are scraped.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use prometheus::{Gauge, GaugeVec, IntCounter, IntCounterVec, Registry};
+use tokio::sync::Mutex;
+use tracing::Instrument as _;
+
+use crate::clock::{self, Clock, SystemClock};
+use crate::metrics_prefix::opts;
+
+/// Runs an operation at most once per interval, sharing its result with every caller in between.
+///
+/// Callers which arrive while the operation is running wait for it, and receive its result rather
+/// than running it again. Callers which arrive within the interval after it started receive the
+/// same result immediately.
+///
+/// Cancelling a caller is safe. If the caller running the operation is cancelled, so is the
+/// operation, and the next caller in the queue runs it instead.
+#[derive(Debug)]
+pub struct Throttle<T> {
+    interval: Duration,
+    max_wait: Option<Duration>,
+    edge: Edge,
+    name: Option<String>,
+    metrics: Option<BoundMetrics>,
+    clock: Arc<dyn Clock>,
+    /// When the operation last started, and its result.
+    last: Mutex<Option<(Instant, T)>>,
+    /// A copy of the last result, which is readable while the operation runs again.
+    latest: std::sync::Mutex<Option<T>>,
+}
+
+/// When the operation runs, relative to the first caller which needs a new result.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Edge {
+    /// Run the operation immediately.
+    #[default]
+    Leading,
+    /// Wait for the interval first, so that the result reflects the end of the interval rather
+    /// than the start. Callers which arrive in the meantime wait for the same result.
+    Trailing,
+}
+
+/// Metrics for throttled operations, labelled with the name of each throttle, for
+/// [`Throttle::with_metrics`].
+#[derive(Clone, Debug)]
+pub struct ThrottleMetrics {
+    executions_total: IntCounterVec,
+    coalesced_total: IntCounterVec,
+    wait_seconds: GaugeVec,
+}
+
+impl ThrottleMetrics {
+    /// Create the metrics, and register them with the given registry.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let executions_total = IntCounterVec::new(
+            opts(
+                "ndc_sdk_throttle_executions_total",
+                "Total number of times each throttled operation has run",
+            ),
+            &["throttle"],
+        )?;
+        let coalesced_total = IntCounterVec::new(
+            opts(
+                "ndc_sdk_throttle_coalesced_total",
+                "Total number of callers of each throttled operation which received a result without running it",
+            ),
+            &["throttle"],
+        )?;
+        let wait_seconds = GaugeVec::new(
+            opts(
+                "ndc_sdk_throttle_wait_seconds",
+                "How long the most recent caller of each throttled operation waited for its result, in seconds",
+            ),
+            &["throttle"],
+        )?;
+        registry.register(Box::new(executions_total.clone()))?;
+        registry.register(Box::new(coalesced_total.clone()))?;
+        registry.register(Box::new(wait_seconds.clone()))?;
+        Ok(Self {
+            executions_total,
+            coalesced_total,
+            wait_seconds,
+        })
+    }
+}
+
+/// The metrics of a single throttle.
+#[derive(Debug)]
+struct BoundMetrics {
+    executions_total: IntCounter,
+    coalesced_total: IntCounter,
+    wait_seconds: Gauge,
+}
+
+/// The error returned by [`Throttle::try_next`] when the maximum wait has passed and there is no
+/// earlier result to fall back on.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("timed out after {max_wait:?} waiting for a throttled operation")]
+pub struct ThrottleTimeout {
+    pub max_wait: Duration,
+}
+
+impl<T: Clone> Throttle<T> {
+    /// Create a throttle which runs the operation at most once per `interval`.
+    ///
+    /// With an interval of zero, the operation runs for every caller which does not arrive while
+    /// it is already running.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            max_wait: None,
+            edge: Edge::Leading,
+            name: None,
+            metrics: None,
+            clock: Arc::new(SystemClock),
+            last: Mutex::new(None),
+            latest: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Bound how long [`Throttle::try_next`] waits for a result, including waiting for the
+    /// operation to run for another caller.
+    #[must_use]
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// The minimum time between runs of the operation.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Run the operation at the start or the end of the interval. It runs at the start by
+    /// default.
+    #[must_use]
+    pub fn with_edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// When the operation runs, relative to the first caller which needs a new result.
+    pub fn edge(&self) -> Edge {
+        self.edge
+    }
+
+    /// Name the throttle, for the span in which the operation runs.
+    #[must_use]
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Record how often the operation runs, and how long callers wait, in the given metrics,
+    /// labelled with the name of the throttle.
+    #[must_use]
+    pub fn with_metrics(mut self, name: &str, metrics: &ThrottleMetrics) -> Self {
+        self.metrics = Some(BoundMetrics {
+            executions_total: metrics.executions_total.with_label_values(&[name]),
+            coalesced_total: metrics.coalesced_total.with_label_values(&[name]),
+            wait_seconds: metrics.wait_seconds.with_label_values(&[name]),
+        });
+        self.with_name(name)
+    }
+
+    /// Measure time with the given clock, rather than the system clock, so that tests can control
+    /// the passage of time.
+    #[must_use]
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The longest time that [`Throttle::try_next`] waits for a result, if bounded.
+    pub fn max_wait(&self) -> Option<Duration> {
+        self.max_wait
+    }
+
+    /// The result of the operation, as for [`Throttle::next`], waiting no longer than the
+    /// maximum wait.
+    ///
+    /// If the maximum wait passes, this returns the last result, however old, or an error if the
+    /// operation has never completed. If this caller was running the operation, it is cancelled,
+    /// and the next caller runs it again.
+    pub async fn try_next<F, Fut>(&self, operation: F) -> Result<T, ThrottleTimeout>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let Some(max_wait) = self.max_wait else {
+            return Ok(self.next(operation).await);
+        };
+        match clock::timeout(&*self.clock, max_wait, self.next(operation)).await {
+            Some(value) => Ok(value),
+            None => self
+                .latest
+                .lock()
+                .ok()
+                .and_then(|latest| latest.clone())
+                .ok_or(ThrottleTimeout { max_wait }),
+        }
+    }
+
+    /// The result of the operation, running it if the last result is older than the interval.
+    ///
+    /// This waits as long as it takes for a result, regardless of the maximum wait.
+    pub async fn next<F, Fut>(&self, operation: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let arrived = self.clock.now();
+        // callers queue on the lock while the operation runs, and then find its result
+        let mut last = self.last.lock().await;
+        if let Some((started, value)) = last.as_ref() {
+            if self.elapsed_since(*started) < self.interval {
+                self.record(arrived, false);
+                return value.clone();
+            }
+        }
+        if self.edge == Edge::Trailing {
+            self.clock.sleep(self.interval).await;
+        }
+        let started = self.clock.now();
+        let span = tracing::info_span!("throttled operation", throttle = self.name.as_deref());
+        let value = operation().instrument(span).await;
+        *last = Some((started, value.clone()));
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = Some(value.clone());
+        }
+        self.record(arrived, true);
+        value
+    }
+
+    fn record(&self, arrived: Instant, executed: bool) {
+        if let Some(metrics) = &self.metrics {
+            if executed {
+                metrics.executions_total.inc();
+            } else {
+                metrics.coalesced_total.inc();
+            }
+            metrics
+                .wait_seconds
+                .set(self.elapsed_since(arrived).as_secs_f64());
+        }
+    }
+
+    fn elapsed_since(&self, instant: Instant) -> Duration {
+        self.clock.now().saturating_duration_since(instant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::testing::ManualClock;
+
+    async fn count(runs: &AtomicUsize) -> usize {
+        tokio::task::yield_now().await;
+        runs.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    #[tokio::test]
+    async fn reuses_the_result_within_the_interval() {
+        let throttle = Throttle::new(Duration::from_secs(30));
+        let runs = AtomicUsize::new(0);
+
+        assert_eq!(throttle.next(|| count(&runs)).await, 1);
+        assert_eq!(throttle.next(|| count(&runs)).await, 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn runs_again_after_the_interval() {
+        let throttle = Throttle::new(Duration::ZERO);
+        let runs = AtomicUsize::new(0);
+
+        assert_eq!(throttle.next(|| count(&runs)).await, 1);
+        assert_eq!(throttle.next(|| count(&runs)).await, 2);
+    }
+
+    #[tokio::test]
+    async fn runs_again_once_the_clock_passes_the_interval() {
+        let clock = ManualClock::new();
+        let throttle = Throttle::new(Duration::from_secs(30)).with_clock(clock.clone());
+        let runs = AtomicUsize::new(0);
+
+        assert_eq!(throttle.next(|| count(&runs)).await, 1);
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(throttle.next(|| count(&runs)).await, 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(throttle.next(|| count(&runs)).await, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn pools_concurrent_callers() {
+        let throttle = Arc::new(Throttle::new(Duration::from_secs(30)));
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let callers = (0..10)
+            .map(|_| {
+                let throttle = throttle.clone();
+                let runs = runs.clone();
+                tokio::spawn(async move { throttle.next(|| count(&runs)).await })
+            })
+            .collect::<Vec<_>>();
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 1);
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn delays_trailing_runs_until_the_end_of_the_interval() {
+        let interval = Duration::from_millis(50);
+        let throttle = Arc::new(Throttle::new(interval).with_edge(Edge::Trailing));
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let arrived = Instant::now();
+        let callers = (0..3)
+            .map(|_| {
+                let throttle = throttle.clone();
+                let runs = runs.clone();
+                tokio::spawn(async move { throttle.next(|| count(&runs)).await })
+            })
+            .collect::<Vec<_>>();
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 1);
+        }
+        assert!(arrived.elapsed() >= interval);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn counts_executions_and_coalesced_callers() -> anyhow::Result<()> {
+        let registry = Registry::new();
+        let metrics = ThrottleMetrics::register(&registry)?;
+        let throttle = Throttle::new(Duration::from_secs(30)).with_metrics("test", &metrics);
+        let runs = AtomicUsize::new(0);
+
+        for _ in 0..3 {
+            throttle.next(|| count(&runs)).await;
+        }
+        assert_eq!(
+            metrics.executions_total.with_label_values(&["test"]).get(),
+            1
+        );
+        assert_eq!(
+            metrics.coalesced_total.with_label_values(&["test"]).get(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn takes_over_when_the_running_caller_is_cancelled() {
+        let throttle = Arc::new(Throttle::new(Duration::from_secs(30)));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let running = Arc::new(tokio::sync::Notify::new());
+
+        let hung = tokio::spawn({
+            let throttle = throttle.clone();
+            let running = running.clone();
+            async move {
+                throttle
+                    .next(|| async move {
+                        running.notify_one();
+                        std::future::pending::<usize>().await
+                    })
+                    .await
+            }
+        });
+        running.notified().await;
+        let waiting = tokio::spawn({
+            let throttle = throttle.clone();
+            let runs = runs.clone();
+            async move { throttle.next(|| count(&runs)).await }
+        });
+        tokio::task::yield_now().await;
+        hung.abort();
+
+        assert_eq!(waiting.await.unwrap(), 1);
+        assert_eq!(throttle.next(|| count(&runs)).await, 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn is_unaffected_by_cancelled_waiters() {
+        let throttle = Arc::new(Throttle::new(Duration::from_secs(30)));
+        let started = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+
+        let running = tokio::spawn({
+            let throttle = throttle.clone();
+            let started = started.clone();
+            let release = release.clone();
+            async move {
+                throttle
+                    .next(|| async move {
+                        started.notify_one();
+                        release.notified().await;
+                        1
+                    })
+                    .await
+            }
+        });
+        started.notified().await;
+        let cancelled = tokio::spawn({
+            let throttle = throttle.clone();
+            async move { throttle.next(|| async { 2 }).await }
+        });
+        tokio::task::yield_now().await;
+        cancelled.abort();
+        release.notify_one();
+
+        assert_eq!(running.await.unwrap(), 1);
+        assert_eq!(throttle.next(|| async { 3 }).await, 1);
+    }
+
+    #[tokio::test]
+    async fn times_out_when_the_clock_passes_the_maximum_wait() {
+        let clock = ManualClock::new();
+        let throttle = Arc::new(
+            Throttle::<usize>::new(Duration::ZERO)
+                .with_max_wait(Duration::from_secs(10))
+                .with_clock(clock.clone()),
+        );
+
+        let waiting = tokio::spawn({
+            let throttle = throttle.clone();
+            async move { throttle.try_next(std::future::pending).await }
+        });
+        while clock.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_secs(10));
+        assert!(matches!(
+            waiting.await.unwrap(),
+            Err(ThrottleTimeout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn times_out_without_an_earlier_result() {
+        let throttle =
+            Throttle::<usize>::new(Duration::ZERO).with_max_wait(Duration::from_millis(10));
+
+        let result = throttle.try_next(std::future::pending).await;
+        assert!(matches!(result, Err(ThrottleTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn falls_back_on_the_last_result_after_the_maximum_wait() {
+        let throttle = Throttle::new(Duration::ZERO).with_max_wait(Duration::from_millis(10));
+        let runs = AtomicUsize::new(0);
+
+        assert_eq!(throttle.try_next(|| count(&runs)).await.unwrap(), 1);
+        assert_eq!(throttle.try_next(std::future::pending).await.unwrap(), 1);
+        // the hung operation was cancelled, so the next caller runs the operation again
+        assert_eq!(throttle.try_next(|| count(&runs)).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,87 @@
+//! Version and build information, so that operators can identify exactly what is deployed.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// The name and version of a connector, and details of its build.
+///
+/// This is usually constructed with the [`connector_version!`](crate::connector_version) macro,
+/// which reads the package name and version of the calling crate. The git SHA and build timestamp
+/// are read from the `GIT_SHA` and `BUILD_TIMESTAMP` environment variables at compile time, which
+/// can be set by a build script, for example:
+///
+/// ```ignore
+/// println!("cargo:rustc-env=GIT_SHA={sha}");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectorVersion {
+    pub name: &'static str,
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_sha: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_timestamp: Option<&'static str>,
+}
+
+/// Construct a [`ConnectorVersion`] for the calling crate.
+#[macro_export]
+macro_rules! connector_version {
+    () => {
+        $crate::version::ConnectorVersion {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: option_env!("GIT_SHA"),
+            build_timestamp: option_env!("BUILD_TIMESTAMP"),
+        }
+    };
+}
+
+/// Everything known about the version of a running connector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connector: Option<ConnectorVersion>,
+    pub sdk_version: &'static str,
+    pub ndc_spec_version: &'static str,
+}
+
+impl VersionInfo {
+    /// Combine the connector version, if known, with the SDK and specification versions.
+    pub fn new(connector: Option<ConnectorVersion>) -> Self {
+        Self {
+            connector,
+            sdk_version: env!("CARGO_PKG_VERSION"),
+            ndc_spec_version: ndc_models::VERSION,
+        }
+    }
+
+    /// A short version string, such as `1.2.3`, suitable for `--version`.
+    ///
+    /// This is the connector version if known, and the SDK version otherwise.
+    pub fn short(&self) -> &'static str {
+        match &self.connector {
+            Some(connector) => connector.version,
+            None => self.sdk_version,
+        }
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(connector) = &self.connector {
+            writeln!(f, "connector:        {}", connector.name)?;
+            writeln!(f, "version:          {}", connector.version)?;
+            if let Some(git_sha) = connector.git_sha {
+                writeln!(f, "git sha:          {git_sha}")?;
+            }
+            if let Some(build_timestamp) = connector.build_timestamp {
+                writeln!(f, "build timestamp:  {build_timestamp}")?;
+            }
+        }
+        writeln!(f, "ndc-sdk version:  {}", self.sdk_version)?;
+        write!(f, "ndc-spec version: {}", self.ndc_spec_version)
+    }
+}
@@ -11,9 +11,6 @@ use ndc_sdk::default_main::default_main;
 pub async fn main() -> ExitCode {
     match default_main::<Example>().await {
         Ok(()) => ExitCode::SUCCESS,
-        Err(err) => {
-            eprintln!("{err}");
-            ExitCode::FAILURE
-        }
+        Err(err) => err.report(),
     }
 }
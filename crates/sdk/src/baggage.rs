@@ -0,0 +1,71 @@
+//! [W3C Baggage](https://www.w3.org/TR/baggage/) propagation, so that identifiers such as a tenant
+//! or user ID can flow from the engine, through the connector, to the services it calls.
+//!
+//! Baggage sent with a request in the `baggage` header is available to the connector while the
+//! request is handled:
+//!
+//! ```ignore
+//! let tenant = ndc_sdk::baggage::get("tenant");
+//! ```
+//!
+//! To pass it on, call [`inject`] on the headers of outgoing requests. HTTP clients which are
+//! instrumented with OpenTelemetry and use the global propagator do this automatically.
+
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::baggage::{Baggage, BaggageExt as _, KeyValueMetadata};
+use opentelemetry::propagation::TextMapPropagator as _;
+use opentelemetry::trace::FutureExt as _;
+use opentelemetry_sdk::propagation::BaggagePropagator;
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+/// The baggage of the request being handled.
+pub fn current() -> Baggage {
+    entries(opentelemetry::Context::current().baggage())
+        .into_iter()
+        .collect()
+}
+
+/// The value of a baggage entry of the request being handled, if present.
+pub fn get(key: &str) -> Option<String> {
+    opentelemetry::Context::current()
+        .baggage()
+        .get(key.to_string())
+        .map(ToString::to_string)
+}
+
+/// Add the trace context and baggage of the request being handled to the headers of an outgoing
+/// request, using the global propagator.
+pub fn inject(headers: &mut HeaderMap) {
+    // the current span carries the trace context, but not the baggage if tracing is disabled
+    let span_context = tracing::Span::current().context();
+    let context = if span_context.baggage().is_empty() {
+        span_context.with_baggage(entries(opentelemetry::Context::current().baggage()))
+    } else {
+        span_context
+    };
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut opentelemetry_http::HeaderInjector(headers));
+    });
+}
+
+/// Make the baggage of an incoming request available while it is handled.
+///
+/// This is intended to be used with [`axum::middleware::from_fn`]. It does not depend on the
+/// global propagator, so that baggage is available even if tracing is not configured.
+pub(crate) async fn attach_baggage<B>(request: Request<B>, next: Next<B>) -> Response {
+    let context =
+        BaggagePropagator::new().extract(&opentelemetry_http::HeaderExtractor(request.headers()));
+    next.run(request).with_context(context).await
+}
+
+/// Copy the entries of some baggage, which cannot be cloned directly.
+pub(crate) fn entries(baggage: &Baggage) -> Vec<KeyValueMetadata> {
+    baggage
+        .iter()
+        .map(|(key, (value, metadata))| {
+            KeyValueMetadata::new(key.clone(), value.clone(), metadata.clone())
+        })
+        .collect()
+}
@@ -0,0 +1,128 @@
+//! Machine-readable output for benchmark reports, used by the `bench` and `bench-compare`
+//! commands.
+//!
+//! Benchmark reports are written as a JSON object with a key per benchmark. Each value contains
+//! the statistics reported by `ndc-test`, flattened so that nested fields are named with dots,
+//! such as `statistics.mean`.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+/// Flattened benchmark statistics, by benchmark name.
+pub(crate) type BenchReports = BTreeMap<String, BTreeMap<String, serde_json::Value>>;
+
+/// Flatten the reports returned by `ndc-test` into a map of fields per benchmark.
+pub(crate) fn flatten_reports<R: Serialize>(
+    reports: &BTreeMap<String, R>,
+) -> serde_json::Result<BenchReports> {
+    reports
+        .iter()
+        .map(|(name, report)| {
+            let mut fields = BTreeMap::new();
+            flatten_into(&mut fields, String::new(), serde_json::to_value(report)?);
+            Ok((name.clone(), fields))
+        })
+        .collect()
+}
+
+fn flatten_into(
+    fields: &mut BTreeMap<String, serde_json::Value>,
+    prefix: String,
+    value: serde_json::Value,
+) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_into(fields, key, value);
+            }
+        }
+        value => {
+            fields.insert(prefix, value);
+        }
+    }
+}
+
+/// Write the reports as CSV, with a row per benchmark and a column per field.
+pub(crate) fn write_csv<W: Write>(mut writer: W, reports: &BenchReports) -> io::Result<()> {
+    let mut columns = reports
+        .values()
+        .flat_map(BTreeMap::keys)
+        .collect::<Vec<_>>();
+    columns.sort();
+    columns.dedup();
+
+    write!(writer, "name")?;
+    for column in &columns {
+        write!(writer, ",{}", csv_field(column))?;
+    }
+    writeln!(writer)?;
+
+    for (name, fields) in reports {
+        write!(writer, "{}", csv_field(name))?;
+        for column in &columns {
+            match fields.get(*column) {
+                None | Some(serde_json::Value::Null) => write!(writer, ",")?,
+                Some(serde_json::Value::String(value)) => write!(writer, ",{}", csv_field(value))?,
+                Some(value) => write!(writer, ",{}", csv_field(&value.to_string()))?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if necessary.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A benchmark whose mean has changed between two reports.
+#[derive(Debug)]
+pub(crate) struct Comparison {
+    pub name: String,
+    pub old_mean: f64,
+    pub new_mean: f64,
+}
+
+impl Comparison {
+    /// The relative change in the mean, where positive values are slower.
+    pub fn change(&self) -> f64 {
+        (self.new_mean - self.old_mean) / self.old_mean
+    }
+}
+
+/// Compare the means of the benchmarks present in both reports.
+///
+/// Benchmarks which are only present in one of the reports, or which have no mean, are skipped.
+pub(crate) fn compare(old: &BenchReports, new: &BenchReports) -> Vec<Comparison> {
+    old.iter()
+        .filter_map(|(name, old_fields)| {
+            let old_mean = mean(old_fields)?;
+            let new_mean = mean(new.get(name)?)?;
+            Some(Comparison {
+                name: name.clone(),
+                old_mean,
+                new_mean,
+            })
+        })
+        .collect()
+}
+
+/// Find the mean in a flattened report, which is the field named `mean`, possibly nested.
+fn mean(fields: &BTreeMap<String, serde_json::Value>) -> Option<f64> {
+    fields
+        .iter()
+        .find(|(key, _)| key.rsplit('.').next() == Some("mean"))
+        .and_then(|(_, value)| value.as_f64())
+}
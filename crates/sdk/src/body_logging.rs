@@ -0,0 +1,146 @@
+//! Diagnosing a mismatch between the engine and a connector often requires seeing what was sent.
+//! When enabled, the bodies of `/query` and `/mutation` requests, and summaries of their
+//! responses, are logged at the `debug` level, with sensitive fields redacted and long bodies
+//! truncated.
+//!
+//! The events are logged with the `ndc_sdk::body_logging` target, so the log level must include
+//! it, for example `info,ndc_sdk::body_logging=debug`.
+
+use ndc_models::{MutationRequest, MutationResponse, QueryRequest, QueryResponse};
+use serde_json::json;
+
+use crate::field_redaction::{FieldRedaction, RedactionMode};
+use crate::json_response::JsonResponse;
+
+/// How request bodies are logged.
+#[derive(Clone, Debug)]
+pub struct BodyLogging {
+    /// Truncate logged bodies to this many characters.
+    pub max_length: usize,
+    /// The fields whose values are redacted from logged bodies.
+    pub redaction: FieldRedaction,
+}
+
+impl Default for BodyLogging {
+    fn default() -> Self {
+        Self {
+            max_length: 4096,
+            redaction: FieldRedaction::new(RedactionMode::Strip),
+        }
+    }
+}
+
+impl BodyLogging {
+    /// The request as sanitized, truncated JSON.
+    fn format(&self, request: &impl serde::Serialize) -> String {
+        let mut value = match serde_json::to_value(request) {
+            Ok(value) => value,
+            Err(err) => return format!("<unable to serialize the request: {err}>"),
+        };
+        self.redaction.redact_json(&mut value);
+        let mut body = value.to_string();
+        if let Some((index, _)) = body.char_indices().nth(self.max_length) {
+            body.truncate(index);
+            body.push_str("...");
+        }
+        body
+    }
+}
+
+pub(crate) fn log_query_request(logging: Option<&BodyLogging>, request: &QueryRequest) {
+    if let Some(logging) = enabled(logging) {
+        tracing::debug!(
+            meta.signal_type = "log",
+            event.domain = "ndc",
+            event.name = "Query request",
+            name = "Query request",
+            body = logging.format(request),
+        );
+    }
+}
+
+pub(crate) fn log_query_response(
+    logging: Option<&BodyLogging>,
+    response: &JsonResponse<QueryResponse>,
+) {
+    if enabled(logging).is_none() {
+        return;
+    }
+    let summary = match response {
+        JsonResponse::Value(QueryResponse(row_sets)) => json!({
+            "rowSets": row_sets.len(),
+            "rows": row_sets
+                .iter()
+                .map(|row_set| row_set.rows.as_ref().map(Vec::len))
+                .collect::<Vec<_>>(),
+            "hasAggregates": row_sets
+                .iter()
+                .map(|row_set| row_set.aggregates.is_some())
+                .collect::<Vec<_>>(),
+        }),
+        JsonResponse::Serialized(bytes) => json!({ "serializedBytes": bytes.len() }),
+        JsonResponse::Stream(_) => json!({ "streamed": true }),
+        JsonResponse::Compressed { bytes, encoding } => json!({
+            "compressedBytes": bytes.len(),
+            "encoding": encoding.as_str(),
+        }),
+        JsonResponse::WithHeaders { response, .. } => return log_query_response(logging, response),
+        _ => json!({}),
+    };
+    tracing::debug!(
+        meta.signal_type = "log",
+        event.domain = "ndc",
+        event.name = "Query response",
+        name = "Query response",
+        body = %summary,
+    );
+}
+
+pub(crate) fn log_mutation_request(logging: Option<&BodyLogging>, request: &MutationRequest) {
+    if let Some(logging) = enabled(logging) {
+        tracing::debug!(
+            meta.signal_type = "log",
+            event.domain = "ndc",
+            event.name = "Mutation request",
+            name = "Mutation request",
+            body = logging.format(request),
+        );
+    }
+}
+
+pub(crate) fn log_mutation_response(
+    logging: Option<&BodyLogging>,
+    response: &JsonResponse<MutationResponse>,
+) {
+    if enabled(logging).is_none() {
+        return;
+    }
+    let summary = match response {
+        JsonResponse::Value(response) => json!({
+            "operationResults": response.operation_results.len(),
+        }),
+        JsonResponse::Serialized(bytes) => json!({ "serializedBytes": bytes.len() }),
+        JsonResponse::Stream(_) => json!({ "streamed": true }),
+        JsonResponse::Compressed { bytes, encoding } => json!({
+            "compressedBytes": bytes.len(),
+            "encoding": encoding.as_str(),
+        }),
+        JsonResponse::WithHeaders { response, .. } => {
+            return log_mutation_response(logging, response)
+        }
+        _ => json!({}),
+    };
+    tracing::debug!(
+        meta.signal_type = "log",
+        event.domain = "ndc",
+        event.name = "Mutation response",
+        name = "Mutation response",
+        body = %summary,
+    );
+}
+
+/// The configuration, if body logging is enabled and the events would be logged, so that bodies
+/// are not serialized otherwise.
+pub(crate) fn enabled(logging: Option<&BodyLogging>) -> Option<&BodyLogging> {
+    logging.filter(|_| tracing::enabled!(tracing::Level::DEBUG))
+}
@@ -0,0 +1,259 @@
+//! Which of the capabilities a connector advertises were exercised by `test` or `replay`, so that
+//! authors notice capabilities which they claim but never validate.
+//!
+//! Requests are inspected through their JSON representation as they are sent to the connector,
+//! and each capability is counted if a request relies on it.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use ndc_test::connector::Connector;
+use ndc_test::error::Error;
+use serde_json::Value;
+
+/// A capability, by its path in the capabilities object, and how to tell whether a request relies
+/// on it.
+struct Capability {
+    path: &'static str,
+    kinds: &'static [RequestKind],
+    used_by: fn(&Value) -> bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RequestKind {
+    Query,
+    Mutation,
+}
+
+const CAPABILITIES: &[Capability] = &[
+    Capability {
+        path: "query.aggregates",
+        kinds: &[RequestKind::Query],
+        used_by: |request| any_object(request, &|object| non_empty(object.get("aggregates"))),
+    },
+    Capability {
+        path: "query.aggregates.group_by",
+        kinds: &[RequestKind::Query],
+        used_by: |request| {
+            any_object(request, &|object| {
+                object.get("groups").is_some_and(|groups| !groups.is_null())
+            })
+        },
+    },
+    Capability {
+        path: "query.variables",
+        kinds: &[RequestKind::Query],
+        used_by: |request| !request["variables"].is_null(),
+    },
+    // ndc-test does not send explain requests
+    Capability {
+        path: "query.explain",
+        kinds: &[],
+        used_by: |_| false,
+    },
+    Capability {
+        path: "query.nested_fields.filter_by",
+        kinds: &[RequestKind::Query],
+        used_by: |request| any_field_path_under(request, "column"),
+    },
+    Capability {
+        path: "query.nested_fields.order_by",
+        kinds: &[RequestKind::Query],
+        used_by: |request| any_field_path_under(request, "target"),
+    },
+    Capability {
+        path: "query.nested_fields.aggregates",
+        kinds: &[RequestKind::Query],
+        used_by: |request| {
+            any_object(request, &|object| {
+                matches!(
+                    object.get("type").and_then(Value::as_str),
+                    Some("column_count" | "single_column")
+                ) && non_empty(object.get("field_path"))
+            })
+        },
+    },
+    Capability {
+        path: "query.exists.unrelated",
+        kinds: &[RequestKind::Query],
+        used_by: |request| any_exists_in(request, "unrelated"),
+    },
+    Capability {
+        path: "query.exists.nested_collections",
+        kinds: &[RequestKind::Query],
+        used_by: |request| any_exists_in(request, "nested_collection"),
+    },
+    Capability {
+        path: "query.exists.nested_scalar_collections",
+        kinds: &[RequestKind::Query],
+        used_by: |request| any_exists_in(request, "nested_scalar_collection"),
+    },
+    Capability {
+        path: "relationships",
+        kinds: &[RequestKind::Query, RequestKind::Mutation],
+        used_by: |request| non_empty(request.get("collection_relationships")),
+    },
+    Capability {
+        path: "relationships.relation_comparisons",
+        kinds: &[RequestKind::Query, RequestKind::Mutation],
+        used_by: |request| any_exists_in(request, "related"),
+    },
+    Capability {
+        path: "relationships.order_by_aggregate",
+        kinds: &[RequestKind::Query, RequestKind::Mutation],
+        used_by: |request| {
+            any_object(request, &|object| {
+                object
+                    .get("target")
+                    .and_then(|target| target["type"].as_str())
+                    .is_some_and(|target| target.ends_with("aggregate"))
+            })
+        },
+    },
+    Capability {
+        path: "mutation.transactional",
+        kinds: &[RequestKind::Mutation],
+        used_by: |request| {
+            request["operations"]
+                .as_array()
+                .is_some_and(|ops| ops.len() > 1)
+        },
+    },
+    Capability {
+        path: "mutation.explain",
+        kinds: &[],
+        used_by: |_| false,
+    },
+];
+
+fn non_empty(value: Option<&Value>) -> bool {
+    match value {
+        Some(Value::Object(object)) => !object.is_empty(),
+        Some(Value::Array(values)) => !values.is_empty(),
+        _ => false,
+    }
+}
+
+/// Whether any object within the value, including the value itself, satisfies the predicate.
+fn any_object(value: &Value, predicate: &dyn Fn(&serde_json::Map<String, Value>) -> bool) -> bool {
+    match value {
+        Value::Object(object) => {
+            predicate(object) || object.values().any(|value| any_object(value, predicate))
+        }
+        Value::Array(values) => values.iter().any(|value| any_object(value, predicate)),
+        _ => false,
+    }
+}
+
+/// Whether a comparison or ordering target, under the given key, has a non-empty field path.
+fn any_field_path_under(request: &Value, key: &str) -> bool {
+    any_object(request, &|object| {
+        object
+            .get(key)
+            .is_some_and(|target| non_empty(target.get("field_path")))
+    })
+}
+
+fn any_exists_in(request: &Value, in_collection: &str) -> bool {
+    any_object(request, &|object| {
+        object
+            .get("in_collection")
+            .is_some_and(|target| target["type"].as_str() == Some(in_collection))
+    })
+}
+
+/// A connector which counts the capabilities used by the requests sent to it.
+pub(crate) struct CoverageConnector<C> {
+    connector: C,
+    requests: RefCell<BTreeMap<&'static str, usize>>,
+}
+
+impl<C: Connector> CoverageConnector<C> {
+    pub fn new(connector: C) -> Self {
+        Self {
+            connector,
+            requests: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn observe(&self, kind: RequestKind, request: &impl serde::Serialize) {
+        let Ok(request) = serde_json::to_value(request) else {
+            return;
+        };
+        let mut requests = self.requests.borrow_mut();
+        for capability in CAPABILITIES {
+            if capability.kinds.contains(&kind) && (capability.used_by)(&request) {
+                *requests.entry(capability.path).or_default() += 1;
+            }
+        }
+    }
+
+    /// Describe how many requests used each advertised capability, listing those which none used
+    /// last.
+    pub async fn report(&self) -> Result<String, Error> {
+        let capabilities = serde_json::to_value(self.connector.get_capabilities().await?)
+            .map_err(|err| Error::OtherError(err.into()))?;
+        let requests = self.requests.borrow();
+        let mut exercised = vec![];
+        let mut unexercised = vec![];
+        for capability in CAPABILITIES {
+            let advertised = capability
+                .path
+                .split('.')
+                .try_fold(&capabilities["capabilities"], |value, key| value.get(key))
+                .is_some_and(|value| !value.is_null());
+            if !advertised {
+                continue;
+            }
+            match requests.get(capability.path) {
+                Some(count) => exercised.push(format!("  {}: {count} requests", capability.path)),
+                None => unexercised.push(format!("  {}", capability.path)),
+            }
+        }
+
+        let mut report = String::from("Capability coverage:\n");
+        if exercised.is_empty() {
+            report.push_str("  no advertised capabilities were exercised\n");
+        }
+        for line in exercised {
+            report.push_str(&line);
+            report.push('\n');
+        }
+        if !unexercised.is_empty() {
+            report.push_str("Advertised capabilities which were never exercised:\n");
+            for line in unexercised {
+                report.push_str(&line);
+                report.push('\n');
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: Connector> Connector for CoverageConnector<C> {
+    async fn get_capabilities(&self) -> Result<ndc_models::CapabilitiesResponse, Error> {
+        self.connector.get_capabilities().await
+    }
+
+    async fn get_schema(&self) -> Result<ndc_models::SchemaResponse, Error> {
+        self.connector.get_schema().await
+    }
+
+    async fn query(
+        &self,
+        request: ndc_models::QueryRequest,
+    ) -> Result<ndc_models::QueryResponse, Error> {
+        self.observe(RequestKind::Query, &request);
+        self.connector.query(request).await
+    }
+
+    async fn mutation(
+        &self,
+        request: ndc_models::MutationRequest,
+    ) -> Result<ndc_models::MutationResponse, Error> {
+        self.observe(RequestKind::Mutation, &request);
+        self.connector.mutation(request).await
+    }
+}
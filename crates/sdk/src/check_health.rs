@@ -1,9 +1,20 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 #[derive(Debug, thiserror::Error)]
 pub enum HealthCheckError {
     #[error("URL parse error: {0}")]
     ParseError(url::ParseError),
     #[error("request error: {0}")]
     RequestError(reqwest::Error),
+    #[error("unable to read CA certificate: {0}")]
+    CertificateError(std::io::Error),
+    #[error("unix socket error: {0}")]
+    UnixSocketError(std::io::Error),
+    #[error("HTTP error: {0}")]
+    HttpError(hyper::Error),
+    #[error("timed out")]
+    Timeout,
     #[error("unsuccessful response with status code: {status}\nbody:\n{body}")]
     UnsuccessfulResponse {
         status: reqwest::StatusCode,
@@ -17,18 +28,131 @@ impl From<HealthCheckError> for crate::connector::error::ErrorResponse {
     }
 }
 
+/// Options for [`check_health_with_options`].
+#[derive(Clone, Debug)]
+pub struct HealthCheckOptions {
+    /// The host to connect to. Defaults to `localhost`.
+    pub host: Option<String>,
+    pub port: u16,
+    /// The maximum time to wait for each attempt.
+    pub timeout: Option<Duration>,
+    /// The number of times to retry after a failed attempt.
+    pub retries: u32,
+    /// The time to wait between attempts.
+    pub retry_interval: Duration,
+    /// Connect using HTTPS rather than HTTP.
+    pub tls: bool,
+    /// A PEM-encoded CA certificate to trust, in addition to the system roots.
+    pub ca_certificate: Option<PathBuf>,
+    /// Accept invalid TLS certificates, such as self-signed ones.
+    pub insecure: bool,
+    /// Sent as a bearer token, for deployments where the health endpoint is authenticated.
+    pub service_token_secret: Option<String>,
+    /// Connect to a Unix domain socket at this path, instead of a host and port.
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl HealthCheckOptions {
+    pub fn new(host: Option<String>, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            timeout: None,
+            retries: 0,
+            retry_interval: Duration::from_secs(1),
+            tls: false,
+            ca_certificate: None,
+            insecure: false,
+            service_token_secret: None,
+            unix_socket: None,
+        }
+    }
+}
+
 pub async fn check_health(host: Option<String>, port: u16) -> Result<(), HealthCheckError> {
+    check_health_with_options(&HealthCheckOptions::new(host, port)).await
+}
+
+/// Check the health of a connector, retrying on failure.
+///
+/// This returns the error from the last attempt if every attempt fails.
+pub async fn check_health_with_options(
+    options: &HealthCheckOptions,
+) -> Result<(), HealthCheckError> {
     let url = (|| -> Result<url::Url, url::ParseError> {
-        let mut url = reqwest::Url::parse("http://localhost/").unwrap(); // cannot fail
-        if let Some(host) = host {
-            url.set_host(Some(&host))?;
+        let base = if options.tls {
+            "https://localhost/"
+        } else {
+            "http://localhost/"
+        };
+        let mut url = reqwest::Url::parse(base).unwrap(); // cannot fail
+        if let Some(host) = &options.host {
+            url.set_host(Some(host))?;
         }
-        url.set_port(Some(port)).unwrap(); // canont fail for HTTP URLs
+        url.set_port(Some(options.port)).unwrap(); // canont fail for HTTP URLs
         url.set_path("/health");
         Ok(url)
     })()
     .map_err(HealthCheckError::ParseError)?;
-    let response = reqwest::get(url)
+
+    let mut client = reqwest::Client::builder();
+    if let Some(timeout) = options.timeout {
+        client = client.timeout(timeout);
+    }
+    if let Some(ca_certificate) = &options.ca_certificate {
+        let pem = std::fs::read(ca_certificate).map_err(HealthCheckError::CertificateError)?;
+        let certificate =
+            reqwest::Certificate::from_pem(&pem).map_err(HealthCheckError::RequestError)?;
+        client = client.add_root_certificate(certificate);
+    }
+    if options.insecure {
+        client = client.danger_accept_invalid_certs(true);
+    }
+    let client = client.build().map_err(HealthCheckError::RequestError)?;
+
+    let mut attempt = 0;
+    loop {
+        let result = match &options.unix_socket {
+            Some(unix_socket) => {
+                let check = check_health_unix(unix_socket, options.service_token_secret.as_deref());
+                match options.timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, check)
+                        .await
+                        .unwrap_or(Err(HealthCheckError::Timeout)),
+                    None => check.await,
+                }
+            }
+            None => {
+                check_health_once(
+                    &client,
+                    url.clone(),
+                    options.service_token_secret.as_deref(),
+                )
+                .await
+            }
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt >= options.retries => return Err(err),
+            Err(_) => {
+                attempt += 1;
+                tokio::time::sleep(options.retry_interval).await;
+            }
+        }
+    }
+}
+
+async fn check_health_once(
+    client: &reqwest::Client,
+    url: url::Url,
+    service_token_secret: Option<&str>,
+) -> Result<(), HealthCheckError> {
+    let mut request = client.get(url);
+    if let Some(service_token_secret) = service_token_secret {
+        request = request.bearer_auth(service_token_secret);
+    }
+    let response = request
+        .send()
         .await
         .map_err(HealthCheckError::RequestError)?;
     let status = response.status();
@@ -42,3 +166,55 @@ pub async fn check_health(host: Option<String>, port: u16) -> Result<(), HealthC
         Err(HealthCheckError::UnsuccessfulResponse { status, body })
     }
 }
+
+#[cfg(unix)]
+async fn check_health_unix(
+    unix_socket: &std::path::Path,
+    service_token_secret: Option<&str>,
+) -> Result<(), HealthCheckError> {
+    let stream = tokio::net::UnixStream::connect(unix_socket)
+        .await
+        .map_err(HealthCheckError::UnixSocketError)?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(HealthCheckError::HttpError)?;
+    tokio::spawn(connection);
+
+    let mut request = hyper::Request::get("/health").header(hyper::header::HOST, "localhost");
+    if let Some(service_token_secret) = service_token_secret {
+        request = request.header(
+            hyper::header::AUTHORIZATION,
+            format!("Bearer {service_token_secret}"),
+        );
+    }
+    let request = request
+        .body(hyper::Body::empty())
+        .expect("the request is valid");
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(HealthCheckError::HttpError)?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(HealthCheckError::HttpError)?;
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(HealthCheckError::UnsuccessfulResponse {
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
+
+#[cfg(not(unix))]
+async fn check_health_unix(
+    _unix_socket: &std::path::Path,
+    _service_token_secret: Option<&str>,
+) -> Result<(), HealthCheckError> {
+    Err(HealthCheckError::UnixSocketError(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    )))
+}
@@ -0,0 +1,117 @@
+//! A propagator for Datadog's trace context headers, so that connectors deployed alongside
+//! services instrumented by Datadog's tracers keep unified traces.
+//!
+//! Datadog trace IDs are 64 bits. The upper 64 bits of 128-bit trace IDs are carried in the
+//! `_dd.p.tid` tag of the `x-datadog-tags` header.
+
+use std::sync::OnceLock;
+
+use opentelemetry::propagation::text_map_propagator::FieldIter;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{
+    SpanContext, SpanId, TraceContextExt as _, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry::Context;
+
+const TRACE_ID_HEADER: &str = "x-datadog-trace-id";
+const PARENT_ID_HEADER: &str = "x-datadog-parent-id";
+const SAMPLING_PRIORITY_HEADER: &str = "x-datadog-sampling-priority";
+const TAGS_HEADER: &str = "x-datadog-tags";
+const TRACE_ID_UPPER_TAG: &str = "_dd.p.tid";
+
+static FIELDS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Propagates trace context with the `x-datadog-*` headers.
+#[derive(Clone, Debug, Default)]
+pub struct DatadogPropagator {
+    _private: (),
+}
+
+impl DatadogPropagator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TextMapPropagator for DatadogPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        let trace_id = u128::from_be_bytes(span_context.trace_id().to_bytes());
+        let lower = u64::try_from(trace_id & u128::from(u64::MAX)).unwrap_or_default();
+        let upper = u64::try_from(trace_id >> 64).unwrap_or_default();
+        let parent_id = u64::from_be_bytes(span_context.span_id().to_bytes());
+        let sampling_priority = if span_context.is_sampled() { "1" } else { "0" };
+
+        injector.set(TRACE_ID_HEADER, lower.to_string());
+        injector.set(PARENT_ID_HEADER, parent_id.to_string());
+        injector.set(SAMPLING_PRIORITY_HEADER, sampling_priority.to_string());
+        if upper != 0 {
+            injector.set(TAGS_HEADER, format!("{TRACE_ID_UPPER_TAG}={upper:016x}"));
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        match extract_span_context(extractor) {
+            Some(span_context) => cx.with_remote_span_context(span_context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(FIELDS.get_or_init(|| {
+            [
+                TRACE_ID_HEADER,
+                PARENT_ID_HEADER,
+                SAMPLING_PRIORITY_HEADER,
+                TAGS_HEADER,
+            ]
+            .map(ToOwned::to_owned)
+            .to_vec()
+        }))
+    }
+}
+
+fn extract_span_context(extractor: &dyn Extractor) -> Option<SpanContext> {
+    let lower = extractor.get(TRACE_ID_HEADER)?.trim().parse::<u64>().ok()?;
+    let parent_id = extractor
+        .get(PARENT_ID_HEADER)?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    let upper = extractor
+        .get(TAGS_HEADER)
+        .and_then(|tags| {
+            tags.split(',').find_map(|tag| {
+                tag.trim()
+                    .strip_prefix(TRACE_ID_UPPER_TAG)?
+                    .strip_prefix('=')
+            })
+        })
+        .and_then(|upper| u64::from_str_radix(upper, 16).ok())
+        .unwrap_or_default();
+    // without a sampling priority, the decision is deferred to us, so keep the trace
+    let sampling_priority = extractor
+        .get(SAMPLING_PRIORITY_HEADER)
+        .and_then(|priority| priority.trim().parse::<i32>().ok());
+    let sampled = match sampling_priority {
+        Some(priority) => priority > 0,
+        None => true,
+    };
+
+    let span_context = SpanContext::new(
+        TraceId::from_bytes(((u128::from(upper) << 64) | u128::from(lower)).to_be_bytes()),
+        SpanId::from_bytes(parent_id.to_be_bytes()),
+        if sampled {
+            TraceFlags::SAMPLED
+        } else {
+            TraceFlags::default()
+        },
+        true,
+        TraceState::default(),
+    );
+    span_context.is_valid().then_some(span_context)
+}
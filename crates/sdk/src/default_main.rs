@@ -1,17 +1,23 @@
+use std::io::Write as _;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{io, net};
 
+use async_trait::async_trait;
 use axum::{
     body::Body,
-    extract::State,
-    http::{HeaderValue, Request, StatusCode},
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::{from_fn, from_fn_with_state, map_response, map_response_with_state},
     response::IntoResponse as _,
-    routing::{get, post},
-    Json,
+    routing::{delete, get, post},
+    Extension, Json,
 };
 use axum_extra::extract::WithRejection;
-use clap::{Parser, Subcommand};
-use ndc_sdk_core::schema::{get_capabilities, print_schema_and_capabilities};
+use clap::{CommandFactory as _, FromArgMatches as _, Parser, Subcommand};
+use ndc_sdk_core::schema::{get_capabilities, print_capabilities, print_schema_and_capabilities};
+use serde::Deserialize;
 use serde_json::json;
 use tower_http::{
     limit::RequestBodyLimitLayer, trace::TraceLayer, validate_request::ValidateRequestHeaderLayer,
@@ -21,26 +27,119 @@ use ndc_models::{
     ExplainResponse, MutationRequest, MutationResponse, QueryRequest, QueryResponse, SchemaResponse,
 };
 
+use crate::baggage::attach_baggage;
+use crate::body_logging::{
+    self, log_mutation_request, log_mutation_response, log_query_request, log_query_response,
+    BodyLogging,
+};
 use crate::check_health;
-use crate::connector::{Connector, ConnectorSetup, ErrorResponse, Result};
-use crate::fetch_metrics::fetch_metrics;
-use crate::json_rejection::JsonRejection;
-use crate::json_response::JsonResponse;
+use crate::connector::{
+    Connector, ConnectorRequest, ConnectorSetup, ErrorResponse, MutationError, QueryError, Result,
+};
+use crate::error_metrics::{count_errors, ErrorMetrics};
+use crate::error_redaction::{redact_errors, ErrorRedaction};
+use crate::exit_code::{CommandError, ExitCode};
+use crate::fetch_metrics::{refresh_metrics, warn_unregistered, MetricsEncoder, MetricsFormat};
+use crate::field_redaction::{FieldRedaction, RedactionMode};
+use crate::json_rejection::{JsonBody, JsonRejection};
+use crate::json_response::{ContentEncoding, JsonResponse};
+use crate::log_file::LogFile;
+use crate::log_sampling::LogSampler;
+use crate::manifest::print_manifest;
+use crate::metrics_prefix;
+use crate::openmetrics;
+use crate::persisted_queries::{
+    delete_persisted_query, get_persisted_queries, post_persisted_query, PersistedQueries,
+    QueryBody,
+};
+use crate::precompressed::{accepts_encoding, decode_precompressed};
+use crate::query_cache::{QueryCache, QueryCacheOptions};
+use crate::request_limits::{limit_requests, RequestLimits};
+use crate::request_metrics::{observe_requests, HistogramBuckets, RequestMetrics};
+use crate::schema_publication::SchemaPublication;
+use crate::shutdown::{
+    fail_readiness_when_shutting_down, Readiness, ShutdownSignal, ShutdownSignals,
+};
+use crate::snapshot_recording::SnapshotRecorder;
+use crate::startup_metrics::CREATE_ROUTER;
 use crate::state::{init_server_state, ServerState};
-use crate::tracing::{init_tracing, make_span, on_response};
+#[cfg(feature = "ndc-test")]
+use crate::test_cases::TestCase;
+use crate::throttle::{Throttle, ThrottleMetrics};
+use crate::tracing::{
+    add_trace_response, make_span, make_span_except_probes, on_response, record_mutation_request,
+    record_query_request, record_query_response, row_count, LogFormat, OtlpBatchOptions,
+    OtlpExporterOptions, Propagator, TracingBuilder, TracingOptions,
+};
+use crate::version::{ConnectorVersion, VersionInfo};
+use crate::watch::{Change, Watcher};
 
 #[derive(Parser)]
-struct CliArgs {
+struct CliArgs<Custom: Subcommand> {
+    #[command(flatten)]
+    global: GlobalOptions,
     #[command(subcommand)]
-    command: Command,
+    command: Command<Custom>,
+}
+
+/// Options which apply to every command, including any [`CustomCommands`].
+#[derive(Clone, Debug, Default, clap::Args)]
+#[non_exhaustive]
+pub struct GlobalOptions {
+    /// A file of environment variables to load before parsing the command line, such as `.env`.
+    /// Variables which are already set are not overridden.
+    #[arg(long, global = true, value_name = "PATH", env = "HASURA_ENV_FILE")]
+    pub env_file: Option<PathBuf>,
+}
+
+/// Connector-specific subcommands, such as `introspect` or `migrate`, which are added to the CLI
+/// alongside the standard commands.
+///
+/// Implement this for a type deriving [`clap::Subcommand`], and pass it to
+/// [`default_main_with_commands`]:
+///
+/// ```ignore
+/// #[derive(Clone, clap::Subcommand)]
+/// enum MyCommands {
+///     Introspect { #[arg(long)] connection_uri: String },
+/// }
+///
+/// #[async_trait]
+/// impl CustomCommands<MySetup> for MyCommands {
+///     async fn run(self, setup: MySetup, global: GlobalOptions) -> Result<()> {
+///         match self {
+///             MyCommands::Introspect { connection_uri } => { /* ... */ }
+///         }
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait CustomCommands<Setup: ConnectorSetup>: Subcommand + Send {
+    /// Run the command which was selected on the command line.
+    async fn run(self, setup: Setup, global: GlobalOptions) -> Result<()>;
+}
+
+/// The absence of [`CustomCommands`], used by [`default_main`] and [`default_main_with`].
+#[derive(Clone, Copy, Debug, Subcommand)]
+pub enum NoCustomCommands {}
+
+#[async_trait]
+impl<Setup: ConnectorSetup> CustomCommands<Setup> for NoCustomCommands {
+    async fn run(self, _setup: Setup, _global: GlobalOptions) -> Result<()> {
+        match self {}
+    }
 }
 
 #[derive(Clone, Subcommand)]
-enum Command {
-    #[command()]
-    Serve(ServeCommand),
+enum Command<Custom: Subcommand> {
     #[command()]
+    Serve(Box<ServeCommand>),
+    /// Print the connector's schema and capabilities as JSON
+    #[command(visible_alias = "schema")]
     PrintSchemaAndCapabilities(PrintSchemaAndCapabilitiesCommand),
+    /// Print the connector's capabilities as JSON, without reading any configuration
+    #[command()]
+    Capabilities,
     #[command()]
     #[cfg(feature = "ndc-test")]
     Test(TestCommand),
@@ -50,16 +149,32 @@ enum Command {
     #[command()]
     #[cfg(feature = "ndc-test")]
     Bench(BenchCommand),
+    /// Compare two benchmark reports written by `bench --output json`
+    #[command()]
+    #[cfg(feature = "ndc-test")]
+    BenchCompare(BenchCompareCommand),
     #[command()]
     CheckHealth(CheckHealthCommand),
+    #[command()]
+    Validate(ValidateCommand),
+    /// Print the connector, SDK and specification versions
+    #[command()]
+    Version,
+    /// Print the connector's packaging metadata for the connector hub and DDN as JSON, including
+    /// its capabilities and the environment variables which it reads
+    #[command()]
+    Manifest,
+    #[command()]
+    #[cfg(feature = "schemars")]
+    PrintConfigurationSchema,
+    #[command(flatten)]
+    Custom(Custom),
 }
 
 #[derive(Clone, Parser)]
 struct ServeCommand {
-    #[arg(long, value_name = "DIRECTORY", env = "HASURA_CONFIGURATION_DIRECTORY")]
+    #[arg(long, value_name = "PATH", env = "HASURA_CONFIGURATION_DIRECTORY")]
     configuration: PathBuf,
-    #[arg(long, value_name = "ENDPOINT", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
-    otlp_endpoint: Option<String>,
     #[arg(
         long,
         value_name = "HOST IP",
@@ -77,44 +192,625 @@ struct ServeCommand {
     port: Port,
     #[arg(long, value_name = "TOKEN", env = "HASURA_SERVICE_TOKEN_SECRET")]
     service_token_secret: Option<String>,
+    #[arg(
+        long,
+        help = "Restart the server when the configuration or the executable changes, for development"
+    )]
+    watch: bool,
+    #[arg(
+        long,
+        env = "HASURA_REDACT_ERROR_DETAILS",
+        help = "Remove details from error responses, logging them instead"
+    )]
+    redact_error_details: bool,
+    #[arg(
+        long,
+        value_name = "LENGTH",
+        env = "HASURA_MAX_ERROR_MESSAGE_LENGTH",
+        requires = "redact_error_details",
+        help = "Truncate error messages to this many characters when redacting errors"
+    )]
+    max_error_message_length: Option<usize>,
+    #[arg(
+        long,
+        help = "Print the effective settings as JSON and exit, without starting the server"
+    )]
+    print_config: bool,
+    #[arg(
+        long,
+        value_name = "SIGNALS",
+        env = "HASURA_GRACEFUL_SHUTDOWN_SIGNALS",
+        value_enum,
+        value_delimiter = ',',
+        ignore_case = true,
+        default_values_t = ShutdownSignal::graceful_defaults(),
+        help = "Signals which stop the server once requests in progress have completed"
+    )]
+    graceful_shutdown_signals: Vec<ShutdownSignal>,
+    #[arg(
+        long,
+        value_name = "SIGNALS",
+        env = "HASURA_IMMEDIATE_SHUTDOWN_SIGNALS",
+        value_enum,
+        value_delimiter = ',',
+        ignore_case = true,
+        help = "Signals which stop the server immediately, without waiting for requests in progress"
+    )]
+    immediate_shutdown_signals: Vec<ShutdownSignal>,
+    #[arg(
+        long,
+        value_name = "ROWS",
+        env = "HASURA_BLOCKING_SERIALIZATION_ROWS",
+        default_value_t = 10_000,
+        help = "Serialize query responses with at least this many rows on the blocking thread pool, so that they do not stall other requests"
+    )]
+    blocking_serialization_rows: usize,
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "HASURA_RECORD_SNAPSHOTS_DIR",
+        help = "Record successful queries and mutations in this directory, as snapshots for the replay command"
+    )]
+    record_snapshots_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "ENTRIES",
+        env = "HASURA_MAX_PERSISTED_QUERIES",
+        help = "Allow up to this many query requests to be persisted, and invoked by their hash"
+    )]
+    max_persisted_queries: Option<std::num::NonZeroUsize>,
+    // these must come last, because the help headings apply to any arguments which follow
+    #[command(flatten)]
+    tracing: TracingArgs,
+    #[command(flatten)]
+    limits: HttpLimits,
+    #[command(flatten)]
+    metrics: MetricsArgs,
+    #[command(flatten)]
+    query_cache: QueryCacheArgs,
+    #[command(flatten)]
+    schema_registry: SchemaRegistryArgs,
+}
+
+// Options for logging, and for exporting traces.
+// This is not a doc comment, because clap would use it to describe the `serve` command.
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Logging and tracing")]
+struct TracingArgs {
+    #[arg(long, value_name = "ENDPOINT", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+    #[command(flatten)]
+    otlp: OtlpArgs,
+    #[command(flatten)]
+    otlp_batch: OtlpBatchArgs,
+    #[command(flatten)]
+    log_file: LogFileArgs,
+    #[arg(
+        long,
+        value_name = "PROPAGATORS",
+        env = "OTEL_PROPAGATORS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "tracecontext,b3multi,baggage",
+        help = "The formats used to propagate trace context to and from other services, separated by commas"
+    )]
+    propagators: Vec<Propagator>,
     #[arg(long, value_name = "NAME", env = "OTEL_SERVICE_NAME")]
     service_name: Option<String>,
-    #[arg(long, value_name = "MAX_REQUEST_SIZE", env = "HASURA_MAX_REQUEST_SIZE")]
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = "HASURA_DEPLOYMENT_ENVIRONMENT",
+        help = "The environment the connector is deployed to, such as `production`, reported with traces"
+    )]
+    deployment_environment: Option<String>,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        env = "HASURA_LOG_FORMAT",
+        value_enum,
+        default_value_t = LogFormat::Json
+    )]
+    log_format: LogFormat,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        env = "HASURA_LOG_LEVEL",
+        value_delimiter = ',',
+        help = "Log level or per-target directive, such as `debug` or `my_connector=debug`, overriding RUST_LOG"
+    )]
+    log_level: Vec<String>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        env = "HASURA_REDACT_SENSITIVE_FIELDS",
+        value_enum,
+        help = "Strip or hash the values of sensitive fields, such as SQL text, argument values and authorization headers, in logs and traces"
+    )]
+    redact_sensitive_fields: Option<RedactionMode>,
+    #[arg(
+        long,
+        value_name = "FIELDS",
+        env = "HASURA_SENSITIVE_FIELDS",
+        value_delimiter = ',',
+        requires = "redact_sensitive_fields",
+        help = "Additional fields to redact, separated by commas"
+    )]
+    sensitive_fields: Vec<String>,
+    #[arg(
+        long,
+        env = "HASURA_LOG_REQUEST_BODIES",
+        help = "Log the bodies of query and mutation requests, and summaries of their responses, at the debug level, with sensitive fields redacted"
+    )]
+    log_request_bodies: bool,
+    #[arg(
+        long,
+        env = "HASURA_SKIP_PROBE_SPANS",
+        help = "Do not create spans for health checks and metrics scrapes"
+    )]
+    skip_probe_spans: bool,
+    #[arg(
+        long,
+        value_name = "LENGTH",
+        env = "HASURA_MAX_LOGGED_BODY_LENGTH",
+        default_value_t = BodyLogging::default().max_length,
+        help = "Truncate logged request bodies to this many characters"
+    )]
+    max_logged_body_length: usize,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        env = "HASURA_MAX_ERROR_LOGS_PER_MINUTE",
+        help = "Log at most this many request failures and authorization errors of each kind per minute"
+    )]
+    max_error_logs_per_minute: Option<u32>,
+    #[arg(
+        long,
+        env = "HASURA_SKIP_TRACING_INIT",
+        help = "Do not initialize logging and tracing, because the application has already done so"
+    )]
+    skip_tracing_init: bool,
+}
+
+// How the OTLP exporter connects to the endpoint.
+#[derive(Clone, Debug, clap::Args)]
+struct OtlpArgs {
+    #[arg(
+        long = "otlp-header",
+        value_name = "KEY=VALUE",
+        value_parser = parse_header,
+        help = "A header to send with exported traces, such as an API key, in addition to those in OTEL_EXPORTER_OTLP_HEADERS"
+    )]
+    headers: Vec<(String, String)>,
+    #[arg(
+        long = "otlp-certificate",
+        value_name = "PATH",
+        env = "OTEL_EXPORTER_OTLP_CERTIFICATE",
+        help = "A PEM file of CA certificates to trust when exporting traces"
+    )]
+    certificate: Option<PathBuf>,
+    #[arg(
+        long = "otlp-client-certificate",
+        value_name = "PATH",
+        env = "OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE",
+        requires = "client_key",
+        help = "A PEM client certificate for exporting traces with mutual TLS, over gRPC"
+    )]
+    client_certificate: Option<PathBuf>,
+    #[arg(
+        long = "otlp-client-key",
+        value_name = "PATH",
+        env = "OTEL_EXPORTER_OTLP_CLIENT_KEY",
+        requires = "client_certificate",
+        help = "The PEM private key of the OTLP client certificate"
+    )]
+    client_key: Option<PathBuf>,
+    #[arg(
+        long = "otlp-insecure",
+        env = "OTEL_EXPORTER_OTLP_INSECURE",
+        help = "Export traces over gRPC without TLS"
+    )]
+    insecure: bool,
+}
+
+impl OtlpArgs {
+    fn exporter_options(&self) -> OtlpExporterOptions {
+        OtlpExporterOptions {
+            headers: self.headers.clone(),
+            certificate: self.certificate.clone(),
+            client_certificate: self.client_certificate.clone(),
+            client_key: self.client_key.clone(),
+            insecure: self.insecure,
+        }
+    }
+}
+
+// How spans are queued and exported. The OTEL_BSP_* durations are in milliseconds.
+#[derive(Clone, Debug, clap::Args)]
+struct OtlpBatchArgs {
+    #[arg(
+        long = "otlp-max-queue-size",
+        value_name = "SPANS",
+        env = "OTEL_BSP_MAX_QUEUE_SIZE",
+        help = "The maximum number of spans waiting to be exported, beyond which spans are dropped [default: 2048]"
+    )]
+    max_queue_size: Option<usize>,
+    #[arg(
+        long = "otlp-max-export-batch-size",
+        value_name = "SPANS",
+        env = "OTEL_BSP_MAX_EXPORT_BATCH_SIZE",
+        help = "The maximum number of spans exported at once [default: 512]"
+    )]
+    max_export_batch_size: Option<usize>,
+    #[arg(
+        long = "otlp-export-timeout",
+        value_name = "MILLISECONDS",
+        env = "OTEL_BSP_EXPORT_TIMEOUT",
+        value_parser = parse_milliseconds,
+        help = "The maximum time to export a batch of spans, including retries [default: 30000]"
+    )]
+    export_timeout: Option<Duration>,
+    #[arg(
+        long = "otlp-export-retries",
+        value_name = "COUNT",
+        env = "HASURA_OTLP_EXPORT_RETRIES",
+        default_value_t = 0,
+        help = "The number of times to retry a failed export, backing off exponentially"
+    )]
+    retries: u32,
+}
+
+impl OtlpBatchArgs {
+    fn batch_options(&self) -> OtlpBatchOptions {
+        OtlpBatchOptions {
+            max_queue_size: self.max_queue_size,
+            max_export_batch_size: self.max_export_batch_size,
+            export_timeout: self.export_timeout,
+            retries: self.retries,
+        }
+    }
+}
+
+// Where to write logs, in addition to stdout, and how to rotate them.
+#[derive(Clone, Debug, clap::Args)]
+struct LogFileArgs {
+    #[arg(
+        long = "log-file",
+        value_name = "PATH",
+        env = "HASURA_LOG_FILE",
+        help = "Also write logs to this file, as JSON"
+    )]
+    path: Option<PathBuf>,
+    #[arg(
+        long = "log-file-max-size",
+        value_name = "BYTES",
+        env = "HASURA_LOG_FILE_MAX_SIZE",
+        requires = "path",
+        help = "Rotate the log file once it grows larger than this"
+    )]
+    max_size: Option<u64>,
+    #[arg(
+        long = "log-file-rotation-interval",
+        value_name = "SECONDS",
+        env = "HASURA_LOG_FILE_ROTATION_INTERVAL",
+        value_parser = parse_positive_seconds,
+        requires = "path",
+        help = "Rotate the log file once it has been written to for this long, such as 86400 for daily"
+    )]
+    rotation_interval: Option<Duration>,
+    #[arg(
+        long = "log-file-max-files",
+        value_name = "COUNT",
+        env = "HASURA_LOG_FILE_MAX_FILES",
+        default_value_t = LogFile::new("").max_files,
+        help = "The number of rotated log files to keep"
+    )]
+    max_files: usize,
+}
+
+impl LogFileArgs {
+    fn log_file(&self) -> Option<LogFile> {
+        self.path.as_ref().map(|path| LogFile {
+            path: path.clone(),
+            max_size: self.max_size,
+            rotation_interval: self.rotation_interval,
+            max_files: self.max_files,
+        })
+    }
+}
+
+// Limits on the size, duration and concurrency of requests, and on how long to wait for them
+// during shutdown.
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "HTTP limits")]
+struct HttpLimits {
+    #[arg(
+        long,
+        value_name = "MAX_REQUEST_SIZE",
+        env = "HASURA_MAX_REQUEST_SIZE",
+        help = "The maximum size of a request body in bytes [default: 100MB]"
+    )]
     max_request_size: Option<usize>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "HASURA_REQUEST_TIMEOUT",
+        value_parser = parse_positive_seconds,
+        help = "Respond with 504 Gateway Timeout to requests which take longer than this"
+    )]
+    request_timeout: Option<Duration>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        env = "HASURA_MAX_CONCURRENT_REQUESTS",
+        help = "The maximum number of requests to handle at once, queueing any others"
+    )]
+    max_concurrent_requests: Option<std::num::NonZeroUsize>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "HASURA_SHUTDOWN_DRAIN_TIMEOUT",
+        value_parser = parse_seconds,
+        default_value = "30",
+        help = "On shutdown, the maximum time to wait for requests in progress to complete"
+    )]
+    shutdown_drain_timeout: Duration,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "HASURA_SHUTDOWN_DELAY",
+        value_parser = parse_seconds,
+        default_value = "0",
+        help = "On shutdown, fail readiness checks but keep serving requests for this long before draining, so that load balancers stop routing requests here first"
+    )]
+    shutdown_delay: Duration,
+}
+
+// Options for caching query responses.
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Query cache")]
+struct QueryCacheArgs {
+    #[arg(
+        long = "query-cache-ttl",
+        value_name = "SECONDS",
+        env = "HASURA_QUERY_CACHE_TTL",
+        value_parser = parse_seconds,
+        help = "Cache query responses for this long, which enables the query cache"
+    )]
+    ttl: Option<Duration>,
+    #[arg(
+        long = "query-cache-max-entries",
+        value_name = "ENTRIES",
+        env = "HASURA_QUERY_CACHE_MAX_ENTRIES",
+        default_value_t = 1000,
+        help = "The maximum number of cached query responses"
+    )]
+    max_entries: usize,
+    #[arg(
+        long = "query-cache-excluded-collections",
+        value_name = "COLLECTIONS",
+        env = "HASURA_QUERY_CACHE_EXCLUDED_COLLECTIONS",
+        value_delimiter = ',',
+        help = "Never cache queries against these collections, separated by commas"
+    )]
+    excluded_collections: Vec<String>,
+}
+
+impl QueryCacheArgs {
+    fn options(&self) -> Option<QueryCacheOptions> {
+        self.ttl.map(|ttl| QueryCacheOptions {
+            ttl,
+            max_entries: self.max_entries,
+            excluded_collections: self.excluded_collections.iter().cloned().collect(),
+        })
+    }
+}
+
+// Options for publishing the schema to a registry on startup.
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Schema publication")]
+struct SchemaRegistryArgs {
+    #[arg(
+        long = "schema-registry-url",
+        value_name = "URL",
+        env = "HASURA_SCHEMA_REGISTRY_URL",
+        help = "Publish the schema and capabilities to this URL on startup"
+    )]
+    url: Option<url::Url>,
+    #[arg(
+        long = "schema-registry-token",
+        value_name = "TOKEN",
+        env = "HASURA_SCHEMA_REGISTRY_TOKEN",
+        help = "Send this bearer token when publishing the schema"
+    )]
+    token: Option<String>,
+    #[arg(
+        long = "schema-registry-retries",
+        value_name = "RETRIES",
+        env = "HASURA_SCHEMA_REGISTRY_RETRIES",
+        default_value_t = 5,
+        help = "The number of times to retry publishing the schema after a failed attempt"
+    )]
+    publication_retries: u32,
+}
+
+impl SchemaRegistryArgs {
+    fn publication(&self) -> Option<SchemaPublication> {
+        self.url.clone().map(|url| SchemaPublication {
+            url,
+            token: self.token.clone(),
+            retries: self.publication_retries,
+        })
+    }
+}
+
+// Options for the built-in metrics.
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Metrics")]
+struct MetricsArgs {
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        env = "HASURA_METRICS_PREFIX",
+        value_parser = parse_metrics_prefix,
+        help = "A prefix for the names of all metrics registered by the SDK, such as `my_connector`"
+    )]
+    metrics_prefix: Option<String>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "HASURA_METRICS_REFRESH_INTERVAL",
+        value_parser = parse_seconds,
+        default_value = "0",
+        help = "Update the connector's metrics at most once per this interval, rather than on every scrape"
+    )]
+    metrics_refresh_interval: Duration,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "HASURA_REQUEST_DURATION_BUCKETS",
+        value_delimiter = ',',
+        value_parser = parse_bucket,
+        help = "Bucket boundaries for the request duration histogram, separated by commas [default: 0.005 to 10]"
+    )]
+    request_duration_buckets: Vec<f64>,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        env = "HASURA_BODY_SIZE_BUCKETS",
+        value_delimiter = ',',
+        value_parser = parse_bucket,
+        help = "Bucket boundaries for the request and response size histograms, separated by commas [default: 256 to 67108864]"
+    )]
+    body_size_buckets: Vec<f64>,
+}
+
+impl MetricsArgs {
+    /// The histogram buckets, using the defaults for any which were not provided.
+    fn histogram_buckets(&self) -> HistogramBuckets {
+        let defaults = HistogramBuckets::default();
+        HistogramBuckets {
+            request_duration_seconds: if self.request_duration_buckets.is_empty() {
+                defaults.request_duration_seconds
+            } else {
+                self.request_duration_buckets.clone()
+            },
+            body_size_bytes: if self.body_size_buckets.is_empty() {
+                defaults.body_size_bytes
+            } else {
+                self.body_size_buckets.clone()
+            },
+        }
+    }
 }
 
 #[derive(Clone, Parser)]
 struct PrintSchemaAndCapabilitiesCommand {
-    #[arg(long, value_name = "DIRECTORY", env = "HASURA_CONFIGURATION_DIRECTORY")]
+    #[arg(long, value_name = "PATH", env = "HASURA_CONFIGURATION_DIRECTORY")]
     configuration: PathBuf,
 }
 
+// The connector to run tests against: either an in-process connector, created from the
+// configuration, or a running connector.
+// This is not a doc comment, because clap would use it to describe the commands using it.
+#[derive(Clone, clap::Args)]
+struct TestTarget {
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "HASURA_CONFIGURATION_DIRECTORY",
+        required_unless_present = "url"
+    )]
+    configuration: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Test a running connector at this URL, instead of creating one from the configuration"
+    )]
+    url: Option<url::Url>,
+    #[arg(long, value_name = "TOKEN", env = "HASURA_SERVICE_TOKEN_SECRET")]
+    service_token_secret: Option<String>,
+}
+
 #[derive(Clone, Parser)]
 struct TestCommand {
     #[arg(long, value_name = "SEED", env = "SEED")]
     seed: Option<String>,
-    #[arg(long, value_name = "DIRECTORY", env = "HASURA_CONFIGURATION_DIRECTORY")]
-    configuration: PathBuf,
+    #[command(flatten)]
+    target: TestTarget,
     #[arg(long, value_name = "DIRECTORY", env = "HASURA_SNAPSHOTS_DIR")]
     snapshots_dir: Option<PathBuf>,
     #[arg(long, help = "Turn off validations for query responses")]
     no_validate_responses: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        env = "HASURA_LOG_LEVEL",
+        value_delimiter = ',',
+        help = "Log level or per-target directive, such as `debug` or `my_connector=debug`, overriding RUST_LOG"
+    )]
+    log_level: Vec<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write the results of every test to this file as JSON"
+    )]
+    report_json: Option<PathBuf>,
+    #[arg(
+        long = "spec-version",
+        value_name = "VERSION",
+        help = "Run the tests once for each of these ndc-spec versions, negotiated with the X-Hasura-NDC-Version header, and report which versions pass"
+    )]
+    spec_versions: Vec<semver::Version>,
 }
 
 #[derive(Clone, Parser)]
 struct ReplayCommand {
-    #[arg(long, value_name = "DIRECTORY", env = "HASURA_CONFIGURATION_DIRECTORY")]
-    configuration: PathBuf,
+    #[command(flatten)]
+    target: TestTarget,
     #[arg(long, value_name = "DIRECTORY", env = "HASURA_SNAPSHOTS_DIR")]
     snapshots_dir: PathBuf,
     #[arg(long, help = "Turn off validations for query responses")]
     no_validate_responses: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        env = "HASURA_LOG_LEVEL",
+        value_delimiter = ',',
+        help = "Log level or per-target directive, such as `debug` or `my_connector=debug`, overriding RUST_LOG"
+    )]
+    log_level: Vec<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write the results of every test to this file as JSON"
+    )]
+    report_json: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "1",
+        help = "The number of snapshots to replay concurrently"
+    )]
+    jobs: std::num::NonZeroUsize,
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Only replay snapshots whose name, or kind and name such as `query/abc*`, match this pattern"
+    )]
+    filter: Vec<String>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Only replay the snapshot with this name, or kind and name such as `query/abc`"
+    )]
+    only: Vec<String>,
 }
 
 #[derive(Clone, Parser)]
 struct BenchCommand {
-    #[arg(long, value_name = "DIRECTORY", env = "HASURA_CONFIGURATION_DIRECTORY")]
-    configuration: PathBuf,
+    #[command(flatten)]
+    target: TestTarget,
     #[arg(
         long,
         value_name = "COUNT",
@@ -130,6 +826,64 @@ struct BenchCommand {
     tolerance: Option<f64>,
     #[arg(long, value_name = "DIRECTORY", env = "HASURA_SNAPSHOTS_DIR")]
     snapshots_dir: PathBuf,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "the number of samples to collect per test before measuring, which are not included in the statistics",
+        default_value = "0"
+    )]
+    warmup: u32,
+    #[arg(
+        long,
+        help = "Check serialized responses from a connector in this process against the expected responses byte for byte, or validate them if they differ, rather than deserializing them"
+    )]
+    pass_through: bool,
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = BenchOutput::Console)]
+    output: BenchOutput,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        env = "HASURA_LOG_LEVEL",
+        value_delimiter = ',',
+        help = "Log level or per-target directive, such as `debug` or `my_connector=debug`, overriding RUST_LOG"
+    )]
+    log_level: Vec<String>,
+}
+
+#[derive(Clone, Parser)]
+struct BenchCompareCommand {
+    #[arg(value_name = "OLD")]
+    old: PathBuf,
+    #[arg(value_name = "NEW")]
+    new: PathBuf,
+    #[arg(
+        long,
+        value_name = "TOLERANCE",
+        help = "tolerable increase in the mean, as a fraction of the old mean",
+        default_value = "0.1"
+    )]
+    tolerance: f64,
+}
+
+/// The format of benchmark results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum BenchOutput {
+    Console,
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Parser)]
+struct ValidateCommand {
+    #[arg(long, value_name = "PATH", env = "HASURA_CONFIGURATION_DIRECTORY")]
+    configuration: PathBuf,
+    #[arg(
+        long,
+        help = "Also initialize the connector state, to check that data sources are reachable"
+    )]
+    check_connectivity: bool,
+    #[arg(long, help = "Also print the effective settings as JSON")]
+    print_config: bool,
 }
 
 #[derive(Clone, Parser)]
@@ -143,10 +897,103 @@ struct CheckHealthCommand {
         default_value_t = 8080
     )]
     port: Port,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "The maximum time to wait for each attempt"
+    )]
+    timeout: Option<Duration>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 0,
+        help = "The number of times to retry after a failed attempt"
+    )]
+    retries: u32,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        default_value = "1",
+        help = "The time to wait between attempts"
+    )]
+    retry_interval: Duration,
+    #[arg(long, help = "Connect using HTTPS")]
+    tls: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "tls",
+        help = "A PEM-encoded CA certificate to trust"
+    )]
+    ca_cert: Option<PathBuf>,
+    #[arg(long, requires = "tls", help = "Accept invalid TLS certificates")]
+    insecure: bool,
+    #[arg(long, value_name = "TOKEN", env = "HASURA_SERVICE_TOKEN_SECRET")]
+    service_token_secret: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["host", "tls"],
+        help = "Connect to a Unix domain socket, instead of a host and port"
+    )]
+    unix_socket: Option<PathBuf>,
 }
 
 type Port = u16;
 
+/// Parse a number of seconds, which may be fractional, such as `0.5`.
+fn parse_seconds(value: &str) -> std::result::Result<Duration, String> {
+    let seconds = value
+        .parse::<f64>()
+        .map_err(|_| format!("expected a number of seconds, got {value:?}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+/// Parse a whole number of milliseconds.
+fn parse_milliseconds(value: &str) -> std::result::Result<Duration, String> {
+    let milliseconds = value
+        .parse::<u64>()
+        .map_err(|_| format!("expected a number of milliseconds, got {value:?}"))?;
+    Ok(Duration::from_millis(milliseconds))
+}
+
+/// Parse a number of seconds, like [`parse_seconds`], which must be greater than zero.
+fn parse_positive_seconds(value: &str) -> std::result::Result<Duration, String> {
+    let duration = parse_seconds(value)?;
+    if duration.is_zero() {
+        return Err("must be greater than zero".to_string());
+    }
+    Ok(duration)
+}
+
+/// Parse a histogram bucket boundary, which must be a finite number.
+fn parse_bucket(value: &str) -> std::result::Result<f64, String> {
+    let bucket = value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("expected a number, got {value:?}"))?;
+    if !bucket.is_finite() {
+        return Err("must be a finite number".to_string());
+    }
+    Ok(bucket)
+}
+
+/// Parse a header in the form `KEY=VALUE`.
+fn parse_header(value: &str) -> std::result::Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {value:?}"))?;
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+/// Parse a metrics prefix, which must be a valid metric name.
+fn parse_metrics_prefix(value: &str) -> std::result::Result<String, String> {
+    metrics_prefix::validate_metrics_prefix(value)?;
+    Ok(value.to_string())
+}
+
 /// A default main function for a connector.
 ///
 /// The intent is that this function can replace your `main` function
@@ -158,8 +1005,11 @@ type Port = u16;
 /// impl Connector for MyConnector { /* ... */ }
 ///
 /// #[tokio::main]
-/// async fn main() {
-///     default_main::<MyConnector>().await.unwrap()
+/// async fn main() -> std::process::ExitCode {
+///     match default_main::<MyConnector>().await {
+///         Ok(()) => std::process::ExitCode::SUCCESS,
+///         Err(err) => err.report(),
+///     }
 /// }
 /// ```
 ///
@@ -170,12 +1020,13 @@ type Port = u16;
 /// - It reads configuration as JSON from a file specified on the command line,
 /// - It reports traces to an OTLP collector specified on the command line,
 /// - Logs are written to stdout
-pub async fn default_main<Setup>() -> Result<()>
+///
+/// Failures are returned as a [`CommandError`], with an exit code describing the kind of
+/// failure, which `main` should exit with. See [`ExitCode`] for the full list.
+pub async fn default_main<Setup>() -> std::result::Result<(), CommandError>
 where
     Setup: ConnectorSetup + Default,
     Setup::Connector: Connector + 'static,
-    <Setup::Connector as Connector>::Configuration: Clone,
-    <Setup::Connector as Connector>::State: Clone,
 {
     default_main_with(Setup::default()).await
 }
@@ -183,92 +1034,759 @@ where
 /// A default main function for a connector, with a non-default setup.
 ///
 /// See [`default_main`] for further details.
-pub async fn default_main_with<Setup>(setup: Setup) -> Result<()>
+pub async fn default_main_with<Setup>(setup: Setup) -> std::result::Result<(), CommandError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    default_main_with_commands::<Setup, NoCustomCommands>(setup).await
+}
+
+/// A default main function for a connector, with additional connector-specific commands.
+///
+/// See [`default_main`] and [`CustomCommands`] for further details.
+pub async fn default_main_with_commands<Setup, Custom>(
+    setup: Setup,
+) -> std::result::Result<(), CommandError>
 where
     Setup: ConnectorSetup,
     Setup::Connector: Connector + 'static,
-    <Setup::Connector as Connector>::Configuration: Clone,
-    <Setup::Connector as Connector>::State: Clone,
+    Custom: CustomCommands<Setup>,
 {
-    let CliArgs { command } = CliArgs::parse();
+    default_main_with_options::<Setup, Custom>(setup, MainOptions::default()).await
+}
+
+/// Customizations of [`default_main`] which are made in code rather than on the command line.
+#[derive(Default)]
+#[non_exhaustive]
+pub struct MainOptions {
+    configure_tracing: Option<ConfigureTracing>,
+    skip_tracing_init: bool,
+    #[cfg(feature = "ndc-test")]
+    test_cases: Vec<Box<dyn TestCase>>,
+}
+
+type ConfigureTracing = Box<dyn FnOnce(TracingBuilder<'_>) -> TracingBuilder<'_> + Send>;
+
+impl MainOptions {
+    /// Customize how `serve` initializes tracing, for example to add layers. See
+    /// [`TracingBuilder`] for an example.
+    #[must_use]
+    pub fn configure_tracing(
+        mut self,
+        configure: impl FnOnce(TracingBuilder<'_>) -> TracingBuilder<'_> + Send + 'static,
+    ) -> Self {
+        self.configure_tracing = Some(Box::new(configure));
+        self
+    }
+
+    /// Do not initialize tracing in `serve`, because the application has already installed a
+    /// global subscriber. This is the same as `serve --skip-tracing-init`.
+    #[must_use]
+    pub fn skip_tracing_init(mut self) -> Self {
+        self.skip_tracing_init = true;
+        self
+    }
+
+    /// Add a connector-specific test, which the `test` command runs after the generated tests.
+    #[cfg(feature = "ndc-test")]
+    #[must_use]
+    pub fn test_case(mut self, test_case: impl TestCase + 'static) -> Self {
+        self.test_cases.push(Box::new(test_case));
+        self
+    }
+}
+
+/// A default main function for a connector, with additional connector-specific commands and
+/// customizations.
+///
+/// See [`default_main`], [`CustomCommands`] and [`MainOptions`] for further details. Use
+/// [`NoCustomCommands`] if there are no additional commands.
+pub async fn default_main_with_options<Setup, Custom>(
+    setup: Setup,
+    options: MainOptions,
+) -> std::result::Result<(), CommandError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+    Custom: CustomCommands<Setup>,
+{
+    // this must happen before parsing, so that the variables can provide defaults
+    load_env_file()?;
+
+    let version = VersionInfo::new(setup.connector_version());
+    let matches = CliArgs::<Custom>::command()
+        // flattening the custom commands would otherwise use their documentation here
+        .about(None::<&str>)
+        .long_about(None::<&str>)
+        .version(version.short())
+        .long_version(format!("{}\n\n{version}", version.short()))
+        .get_matches();
+    let CliArgs { global, command } =
+        CliArgs::<Custom>::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    run_command::<Setup, Custom>(setup, global, command, options, &version).await
+}
 
+async fn run_command<Setup, Custom>(
+    setup: Setup,
+    global: GlobalOptions,
+    command: Command<Custom>,
+    options: MainOptions,
+    version: &VersionInfo,
+) -> std::result::Result<(), CommandError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+    Custom: CustomCommands<Setup>,
+{
     match command {
-        Command::Serve(serve_command) => serve(setup, serve_command).await,
+        Command::Serve(serve_command) => serve(setup, *serve_command, options).await?,
         Command::PrintSchemaAndCapabilities(command) => {
             let mut stdout = io::stdout().lock();
-            print_schema_and_capabilities(setup, &command.configuration, &mut stdout).await
+            print_schema_and_capabilities(setup, &command.configuration, &mut stdout).await?;
+        }
+        Command::Capabilities => {
+            let stdout = io::stdout().lock();
+            print_capabilities::<Setup::Connector, _>(stdout).await?;
+        }
+        Command::CheckHealth(check_health_command) => check_health(check_health_command).await?,
+        Command::Validate(validate_command) => validate(setup, validate_command).await?,
+        Command::Version => println!("{version}"),
+        Command::Manifest => {
+            let stdout = io::stdout().lock();
+            let command = CliArgs::<Custom>::command();
+            print_manifest::<Setup::Connector, _>(version, &command, stdout).await?;
+        }
+        #[cfg(feature = "schemars")]
+        Command::PrintConfigurationSchema => print_configuration_schema(&setup)?,
+        #[cfg(feature = "ndc-test")]
+        Command::Test(test_command) => {
+            ndc_test_commands::test(setup, test_command, &options.test_cases).await?;
         }
-        Command::CheckHealth(check_health_command) => check_health(check_health_command).await,
         #[cfg(feature = "ndc-test")]
-        Command::Test(test_command) => Ok(ndc_test_commands::test(setup, test_command).await?),
+        Command::Bench(bench_command) => ndc_test_commands::bench(setup, bench_command).await?,
         #[cfg(feature = "ndc-test")]
-        Command::Bench(bench_command) => Ok(ndc_test_commands::bench(setup, bench_command).await?),
+        Command::BenchCompare(bench_compare_command) => {
+            ndc_test_commands::bench_compare(&bench_compare_command)?;
+        }
         #[cfg(feature = "ndc-test")]
         Command::Replay(replay_command) => {
-            Ok(ndc_test_commands::replay(setup, replay_command).await?)
+            ndc_test_commands::replay(setup, replay_command).await?;
+        }
+        Command::Custom(custom_command) => custom_command.run(setup, global).await?,
+    }
+    Ok(())
+}
+
+/// Load environment variables from the file given by `--env-file` or `HASURA_ENV_FILE`, if any.
+///
+/// No file is loaded otherwise, so that a stray `.env` in the working directory, or any of its
+/// parents, cannot change the configuration of a deployed connector.
+///
+/// We cannot wait for clap to parse `--env-file`, because clap reads environment variables while
+/// parsing, so we look for it ourselves.
+fn load_env_file() -> Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let mut env_file = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--env-file" {
+            env_file = args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--env-file=")) {
+            env_file = Some(PathBuf::from(path));
         }
     }
+    let env_file = env_file.or_else(|| std::env::var_os("HASURA_ENV_FILE").map(PathBuf::from));
+
+    match env_file {
+        Some(path) => dotenvy::from_path(&path).map_err(|err| {
+            ErrorResponse::from(format!(
+                "Unable to load environment file {}: {err}",
+                path.display()
+            ))
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The router options for `serve`. Request bodies are logged with the given redaction, which
+/// includes any fields declared by the connector.
+fn router_options(
+    serve_command: &ServeCommand,
+    body_redaction: FieldRedaction,
+    connector_version: Option<ConnectorVersion>,
+) -> RouterOptions {
+    RouterOptions {
+        service_token_secret: serve_command.service_token_secret.clone(),
+        max_request_size: serve_command.limits.max_request_size,
+        request_timeout: serve_command.limits.request_timeout,
+        max_concurrent_requests: serve_command
+            .limits
+            .max_concurrent_requests
+            .map(std::num::NonZeroUsize::get),
+        error_redaction: serve_command
+            .redact_error_details
+            .then_some(ErrorRedaction {
+                max_message_length: serve_command.max_error_message_length,
+            }),
+        body_logging: serve_command
+            .tracing
+            .log_request_bodies
+            .then_some(BodyLogging {
+                max_length: serve_command.tracing.max_logged_body_length,
+                redaction: body_redaction,
+            }),
+        histogram_buckets: serve_command.metrics.histogram_buckets(),
+        metrics_refresh_interval: serve_command.metrics.metrics_refresh_interval,
+        skip_probe_spans: serve_command.tracing.skip_probe_spans,
+        max_error_logs_per_minute: serve_command.tracing.max_error_logs_per_minute,
+        blocking_serialization_rows: Some(serve_command.blocking_serialization_rows),
+        record_snapshots_dir: serve_command.record_snapshots_dir.clone(),
+        query_cache: serve_command.query_cache.options(),
+        max_persisted_queries: serve_command
+            .max_persisted_queries
+            .map(std::num::NonZeroUsize::get),
+        connector_version,
+    }
+}
+
+/// The router options which `serve` would use with the given command-line arguments, which start
+/// with the name of the command.
+#[cfg(feature = "testing")]
+pub(crate) fn serve_router_options<I, T>(args: I) -> std::result::Result<RouterOptions, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let serve_command = ServeCommand::try_parse_from(args)?;
+    let body_redaction = match serve_command.tracing.redact_sensitive_fields {
+        Some(mode) => serve_command
+            .tracing
+            .sensitive_fields
+            .iter()
+            .fold(FieldRedaction::new(mode), FieldRedaction::with_field),
+        None => FieldRedaction::new(RedactionMode::Strip),
+    };
+    Ok(router_options(&serve_command, body_redaction, None))
 }
 
-async fn serve<Setup>(setup: Setup, serve_command: ServeCommand) -> Result<()>
+/// Combine the `--log-level` directives into a single filter, if any were provided.
+fn log_filter(log_level: &[String]) -> Option<String> {
+    (!log_level.is_empty()).then(|| log_level.join(","))
+}
+
+async fn serve<Setup>(
+    setup: Setup,
+    serve_command: ServeCommand,
+    options: MainOptions,
+) -> std::result::Result<(), CommandError>
 where
     Setup: ConnectorSetup,
     Setup::Connector: Connector + 'static,
-    <Setup::Connector as Connector>::Configuration: Clone,
-    <Setup::Connector as Connector>::State: Clone,
 {
-    init_tracing(
-        serve_command.service_name.as_deref(),
-        serve_command.otlp_endpoint.as_deref(),
-    )
-    .expect("Unable to initialize tracing");
+    let log_level = log_filter(&serve_command.tracing.log_level);
+    let connector_version = setup.connector_version();
+    let tracing_options = serve_tracing_options(
+        &serve_command,
+        connector_version.as_ref(),
+        log_level.as_deref(),
+    );
 
-    let server_state = init_server_state(setup, &serve_command.configuration).await?;
+    if serve_command.print_config {
+        return print_serve_settings(&setup, &serve_command, &tracing_options).await;
+    }
 
-    let router = create_router::<Setup::Connector>(
-        server_state,
-        serve_command.service_token_secret,
-        serve_command.max_request_size,
-    );
+    let (manage_tracing, body_redaction) =
+        init_serve_tracing(&serve_command, tracing_options, options)?;
+    // this must happen before any metrics are registered
+    if let Some(prefix) = &serve_command.metrics.metrics_prefix {
+        metrics_prefix::set_metrics_prefix(prefix.as_str()).map_err(ErrorResponse::from)?;
+    }
 
+    let router_options = router_options(&serve_command, body_redaction, connector_version.clone());
     let address = net::SocketAddr::new(serve_command.host, serve_command.port);
+    // install the signal handlers before doing anything slow, so that signals are not missed
+    let shutdown_signal = ShutdownSignals {
+        graceful: serve_command.graceful_shutdown_signals,
+        immediate: serve_command.immediate_shutdown_signals,
+    }
+    .install()
+    .map_err(ErrorResponse::from_error)?;
+
+    if serve_command.watch {
+        serve_watch(
+            setup,
+            &serve_command.configuration,
+            address,
+            router_options,
+            shutdown_signal,
+            serve_command.limits.shutdown_drain_timeout,
+        )
+        .await?;
+        if manage_tracing {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+        return Ok(());
+    }
+
+    let server_state = init_server_state(setup, &serve_command.configuration)
+        .await
+        .map_err(|err| ExitCode::ConfigurationError.with_error(err))?;
+
+    let publication = serve_command
+        .schema_registry
+        .publication()
+        .map(|publication| (publication, server_state.clone()));
+    let readiness = Readiness::default();
+    let router =
+        create_router_with_options::<Setup::Connector>(server_state, router_options).layer(
+            from_fn_with_state(readiness.clone(), fail_readiness_when_shutting_down),
+        );
+
     println!("Starting server on {address}");
-    axum::Server::bind(&address)
+    let (draining_sender, draining_receiver) = tokio::sync::oneshot::channel();
+    let server = axum::Server::bind(&address)
         .serve(router.into_make_service())
         .with_graceful_shutdown(async {
-            // wait for a SIGINT, i.e. a Ctrl+C from the keyboard
-            let sigint = async {
-                tokio::signal::ctrl_c()
-                    .await
-                    .expect("unable to install signal handler");
-            };
-            // wait for a SIGTERM, i.e. a normal `kill` command
-            #[cfg(unix)]
-            let sigterm = async {
-                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
-                    .expect("failed to install signal handler")
-                    .recv()
-                    .await
-            };
-            // block until either of the above happens
-            #[cfg(unix)]
-            tokio::select! {
-                () = sigint => (),
-                _ = sigterm => (),
+            readiness
+                .delay_shutdown(shutdown_signal, serve_command.limits.shutdown_delay)
+                .await;
+            let _ = draining_sender.send(());
+        });
+    // the server is bound, so the schema is published once it can be served
+    if let Some((publication, server_state)) = publication {
+        tokio::spawn(publication.publish(server_state, VersionInfo::new(connector_version)));
+    }
+    tokio::select! {
+        result = server => result.map_err(ErrorResponse::from_error)?,
+        () = drain_deadline(draining_receiver, serve_command.limits.shutdown_drain_timeout) => (),
+    }
+    if manage_tracing {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+
+    Ok(())
+}
+
+/// The tracing options for `serve`.
+fn serve_tracing_options<'a>(
+    serve_command: &'a ServeCommand,
+    connector_version: Option<&'a ConnectorVersion>,
+    log_level: Option<&'a str>,
+) -> TracingOptions<'a> {
+    TracingOptions {
+        service_name: serve_command.tracing.service_name.as_deref(),
+        deployment_environment: serve_command.tracing.deployment_environment.as_deref(),
+        connector_version,
+        otlp_endpoint: serve_command.tracing.otlp_endpoint.as_deref(),
+        log_format: serve_command.tracing.log_format,
+        log_level,
+        field_redaction: serve_command.tracing.redact_sensitive_fields.map(|mode| {
+            serve_command
+                .tracing
+                .sensitive_fields
+                .iter()
+                .fold(FieldRedaction::new(mode), FieldRedaction::with_field)
+        }),
+        otlp_exporter: serve_command.tracing.otlp.exporter_options(),
+        otlp_batch: serve_command.tracing.otlp_batch.batch_options(),
+        log_file: serve_command.tracing.log_file.log_file(),
+        propagators: serve_command.tracing.propagators.clone(),
+    }
+}
+
+/// Initialize tracing for `serve`, unless the application manages it.
+///
+/// This returns whether tracing was initialized, in which case it must be shut down too, and the
+/// redaction for logged request bodies, which includes any fields declared by the connector.
+fn init_serve_tracing(
+    serve_command: &ServeCommand,
+    tracing_options: TracingOptions<'_>,
+    options: MainOptions,
+) -> Result<(bool, FieldRedaction)> {
+    // if the application manages tracing, it is responsible for shutting it down, too
+    let manage_tracing = !(serve_command.tracing.skip_tracing_init || options.skip_tracing_init);
+    let mut body_redaction = tracing_options
+        .field_redaction
+        .clone()
+        .unwrap_or_else(|| FieldRedaction::new(RedactionMode::Strip));
+    if manage_tracing {
+        let mut tracing_builder = TracingBuilder::new(tracing_options);
+        if let Some(configure_tracing) = options.configure_tracing {
+            tracing_builder = configure_tracing(tracing_builder);
+        }
+        body_redaction = tracing_builder
+            .sensitive_fields()
+            .iter()
+            .fold(body_redaction, FieldRedaction::with_field);
+        tracing_builder
+            .init()
+            .map_err(|err| ErrorResponse::from(format!("Unable to initialize tracing: {err}")))?;
+    }
+    Ok((manage_tracing, body_redaction))
+}
+
+/// Serve the router on AWS Lambda, configured from the environment as `serve` would be, for
+/// [`lambda_main`](crate::lambda_main::lambda_main).
+///
+/// Only the configuration is read before the first request. The connector state is initialized
+/// by the first request which needs it, as with `serve`.
+#[cfg(feature = "lambda")]
+pub(crate) async fn serve_lambda<Setup>(
+    setup: Setup,
+    options: MainOptions,
+) -> std::result::Result<(), CommandError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    load_env_file()?;
+    // there are no command-line arguments on Lambda, so everything comes from the environment
+    let serve_command = ServeCommand::try_parse_from(["serve"])
+        .map_err(|err| ExitCode::UsageError.with_error(err))?;
+    let log_level = log_filter(&serve_command.tracing.log_level);
+    let connector_version = setup.connector_version();
+    let tracing_options = serve_tracing_options(
+        &serve_command,
+        connector_version.as_ref(),
+        log_level.as_deref(),
+    );
+    let (manage_tracing, body_redaction) =
+        init_serve_tracing(&serve_command, tracing_options, options)?;
+    if let Some(prefix) = &serve_command.metrics.metrics_prefix {
+        metrics_prefix::set_metrics_prefix(prefix.as_str()).map_err(ErrorResponse::from)?;
+    }
+
+    let server_state = init_server_state(setup, &serve_command.configuration)
+        .await
+        .map_err(|err| ExitCode::ConfigurationError.with_error(err))?;
+    let router = create_router_with_options::<Setup::Connector>(
+        server_state,
+        router_options(&serve_command, body_redaction, connector_version),
+    );
+    // the router expects Hyper's request bodies, rather than those of `lambda_http`
+    let handler = lambda_http::service_fn(move |request: lambda_http::Request| {
+        let mut router = router.clone();
+        let request = request.map(|body| match body {
+            lambda_http::Body::Empty => hyper::Body::empty(),
+            lambda_http::Body::Text(text) => hyper::Body::from(text),
+            lambda_http::Body::Binary(bytes) => hyper::Body::from(bytes),
+        });
+        async move { hyper::service::Service::call(&mut router, request).await }
+    });
+    let result = lambda_http::run(handler)
+        .await
+        .map_err(|err| ErrorResponse::from(format!("Lambda runtime error: {err}")));
+    if manage_tracing {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+    Ok(result?)
+}
+
+/// Print the settings which `serve` would use, after resolving command-line arguments,
+/// environment variables and defaults, for `serve --print-config`.
+async fn print_serve_settings<Setup: ConnectorSetup>(
+    setup: &Setup,
+    serve_command: &ServeCommand,
+    tracing_options: &TracingOptions<'_>,
+) -> std::result::Result<(), CommandError> {
+    let configuration = setup
+        .parse_configuration(&serve_command.configuration)
+        .await
+        .map_err(|err| ExitCode::ConfigurationError.with_error(err))?;
+    let histogram_buckets = serve_command.metrics.histogram_buckets().normalized();
+    let settings = json!({
+        "listenAddress": net::SocketAddr::new(serve_command.host, serve_command.port).to_string(),
+        "configuration": configuration_settings(setup, &serve_command.configuration, &configuration),
+        "limits": {
+            "maxRequestSize": serve_command.limits.max_request_size,
+            "requestTimeoutSeconds": serve_command.limits.request_timeout.map(|timeout| timeout.as_secs_f64()),
+            "maxConcurrentRequests": serve_command.limits.max_concurrent_requests,
+            "shutdownDrainTimeoutSeconds": serve_command.limits.shutdown_drain_timeout.as_secs_f64(),
+            "shutdownDelaySeconds": serve_command.limits.shutdown_delay.as_secs_f64(),
+        },
+        "tracing": {
+            "serviceName": tracing_options.resolved_service_name(),
+            "otlpEndpoint": tracing_options.resolved_otlp_endpoint(),
+            // header values are often secrets
+            "otlpHeaders": tracing_options.otlp_exporter.headers.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            "otlpCertificate": tracing_options.otlp_exporter.certificate,
+            "otlpClientCertificate": tracing_options.otlp_exporter.client_certificate,
+            "otlpInsecure": tracing_options.otlp_exporter.insecure,
+            "otlpBatch": json!({
+                "maxQueueSize": tracing_options.otlp_batch.max_queue_size,
+                "maxExportBatchSize": tracing_options.otlp_batch.max_export_batch_size,
+                "exportTimeoutMilliseconds": tracing_options.otlp_batch.export_timeout.map(|timeout| timeout.as_millis()),
+                "retries": tracing_options.otlp_batch.retries,
+            }),
+            "propagators": tracing_options.propagators().iter().filter_map(value_name).collect::<Vec<_>>(),
+            "logFormat": value_name(&tracing_options.log_format),
+            "logLevel": tracing_options.resolved_log_level(),
+            "logFile": tracing_options.log_file.as_ref().map(|log_file| json!({
+                "path": log_file.path,
+                "maxSize": log_file.max_size,
+                "rotationIntervalSeconds": log_file.rotation_interval.map(|interval| interval.as_secs_f64()),
+                "maxFiles": log_file.max_files,
+            })),
+            "logRequestBodies": serve_command.tracing.log_request_bodies,
+            "skipProbeSpans": serve_command.tracing.skip_probe_spans,
+            "maxErrorLogsPerMinute": serve_command.tracing.max_error_logs_per_minute,
+            "maxLoggedBodyLength": serve_command.tracing.max_logged_body_length,
+            "redaction": tracing_options.field_redaction.as_ref().map(|redaction| json!({
+                "mode": value_name(&redaction.mode()),
+                "fields": redaction.fields().collect::<Vec<_>>(),
+            })),
+            "resourceAttributes": tracing_options
+                .resource()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<std::collections::BTreeMap<_, _>>(),
+        },
+        "metrics": {
+            "prefix": serve_command.metrics.metrics_prefix,
+            "refreshIntervalSeconds": serve_command.metrics.metrics_refresh_interval.as_secs_f64(),
+            "requestDurationBuckets": histogram_buckets.request_duration_seconds,
+            "bodySizeBuckets": histogram_buckets.body_size_bytes,
+        },
+        "auth": {
+            "mode": if serve_command.service_token_secret.is_some() { "serviceToken" } else { "none" },
+        },
+        "errors": {
+            "redactDetails": serve_command.redact_error_details,
+            "maxErrorMessageLength": serve_command.max_error_message_length,
+        },
+        "shutdown": {
+            "gracefulSignals": serve_command.graceful_shutdown_signals.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "immediateSignals": serve_command.immediate_shutdown_signals.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        },
+        "watch": serve_command.watch,
+        "blockingSerializationRows": serve_command.blocking_serialization_rows,
+        "recordSnapshotsDir": serve_command.record_snapshots_dir,
+        "maxPersistedQueries": serve_command.max_persisted_queries,
+        "schemaRegistry": serve_command.schema_registry.url.as_ref().map(|url| json!({
+            "url": url.as_str(),
+            "auth": if serve_command.schema_registry.token.is_some() { "token" } else { "none" },
+            "retries": serve_command.schema_registry.publication_retries,
+        })),
+        "queryCache": serve_command.query_cache.options().map(|options| json!({
+            "ttlSeconds": options.ttl.as_secs_f64(),
+            "maxEntries": options.max_entries,
+            "excludedCollections": options.excluded_collections,
+        })),
+    });
+    Ok(print_settings(&settings)?)
+}
+
+/// Describe the configuration for `--print-config`, using the connector's summary if it provides
+/// one.
+fn configuration_settings<Setup: ConnectorSetup>(
+    setup: &Setup,
+    configuration_path: &std::path::Path,
+    configuration: &<Setup::Connector as Connector>::Configuration,
+) -> serde_json::Value {
+    json!({
+        "path": configuration_path,
+        "summary": setup.configuration_summary(configuration),
+    })
+}
+
+/// The name of a value as it would be written on the command line.
+fn value_name(value: &impl clap::ValueEnum) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+}
+
+fn print_settings(settings: &serde_json::Value) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, settings).map_err(ErrorResponse::from_error)?;
+    writeln!(stdout).map_err(ErrorResponse::from_error)?;
+    Ok(())
+}
+
+/// Resolve once `drain_timeout` has passed since the server started shutting down, which is
+/// signalled through `draining`, so that we stop waiting for requests in progress.
+async fn drain_deadline(draining: tokio::sync::oneshot::Receiver<()>, drain_timeout: Duration) {
+    match draining.await {
+        Ok(()) => {
+            tokio::time::sleep(drain_timeout).await;
+            println!(
+                "Requests were still in progress after {drain_timeout:?}, shutting down anyway"
+            );
+        }
+        // the server stopped without shutting down gracefully
+        Err(_) => std::future::pending().await,
+    }
+}
+
+/// Serve the connector, restarting the server whenever the configuration or the executable
+/// changes. This is intended for local development.
+///
+/// The configuration is parsed and the state is initialized eagerly on every restart, so that
+/// problems are reported immediately. If either fails, the previous server keeps running.
+async fn serve_watch<Setup>(
+    setup: Setup,
+    configuration_path: &std::path::Path,
+    address: net::SocketAddr,
+    router_options: RouterOptions,
+    shutdown_signal: impl std::future::Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> Result<()>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    let setup = Arc::new(setup);
+    let mut watcher = Watcher::new(configuration_path);
+
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal.await;
+        let _ = shutdown_sender.send(true);
+    });
+
+    let mut router = None;
+    loop {
+        let started = std::time::Instant::now();
+        let reloaded = async {
+            let server_state = init_server_state(setup.clone(), configuration_path).await?;
+            server_state.state().await?;
+            Ok::<_, ErrorResponse>(create_router_with_options(
+                server_state,
+                router_options.clone(),
+            ))
+        }
+        .await;
+        match reloaded {
+            Ok(reloaded) => {
+                println!(
+                    "Configuration loaded in {}ms, serving on {address}",
+                    started.elapsed().as_millis()
+                );
+                router = Some(reloaded);
+            }
+            Err(err) if router.is_some() => {
+                println!("Unable to reload, keeping the previous configuration: {err}");
+            }
+            Err(err) => {
+                println!("Unable to load the configuration, waiting for changes: {err}");
+            }
+        }
+
+        let mut shutdown = shutdown_receiver.clone();
+        let shutdown_requested = async move {
+            let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+        };
+        let change = match &router {
+            Some(router) => {
+                let (change_sender, change_receiver) = tokio::sync::oneshot::channel();
+                let (draining_sender, draining_receiver) = tokio::sync::oneshot::channel();
+                let server = axum::Server::bind(&address)
+                    .serve(router.clone().into_make_service())
+                    .with_graceful_shutdown(async {
+                        tokio::select! {
+                            () = shutdown_requested => (),
+                            change = watcher.changed() => {
+                                let _ = change_sender.send(change);
+                            },
+                        }
+                        let _ = draining_sender.send(());
+                    });
+                tokio::select! {
+                    result = server => result.map_err(ErrorResponse::from_error)?,
+                    () = drain_deadline(draining_receiver, drain_timeout) => (),
+                }
+                change_receiver.await.ok()
             }
-            #[cfg(windows)]
-            tokio::select! {
-                _ = sigint => (),
+            None => {
+                tokio::select! {
+                    () = shutdown_requested => None,
+                    change = watcher.changed() => Some(change),
+                }
             }
+        };
+
+        match change {
+            None => break,
+            Some(Change::Configuration) => println!("Configuration changed, reloading..."),
+            Some(Change::Executable) => restart_executable()?,
+        }
+    }
+
+    Ok(())
+}
 
-            opentelemetry::global::shutdown_tracer_provider();
-        })
-        .await
-        .map_err(ErrorResponse::from_error)?;
+/// Replace the running process with the new executable, using the same arguments.
+#[cfg(unix)]
+fn restart_executable() -> Result<()> {
+    use std::os::unix::process::CommandExt as _;
+
+    println!("Executable changed, restarting...");
+    opentelemetry::global::shutdown_tracer_provider();
+    let executable = std::env::current_exe().map_err(ErrorResponse::from_error)?;
+    // this only returns on failure
+    let err = std::process::Command::new(executable)
+        .args(std::env::args_os().skip(1))
+        .exec();
+    Err(ErrorResponse::from_error(err))
+}
 
+#[cfg(not(unix))]
+fn restart_executable() -> Result<()> {
+    println!("Executable changed, restart the server to use it. Reloading the configuration...");
     Ok(())
 }
 
+/// Options for the router created by [`create_router_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct RouterOptions {
+    /// If set, requests must provide this token in a bearer `Authorization` header.
+    pub service_token_secret: Option<String>,
+    /// The maximum request body size in bytes. Defaults to 100MB.
+    pub max_request_size: Option<usize>,
+    /// If set, requests which take longer than this are cancelled, and answered with
+    /// `504 Gateway Timeout`. Health checks are not subject to the timeout.
+    pub request_timeout: Option<Duration>,
+    /// If set, at most this many requests are handled at once, and any others wait for a slot.
+    /// Health checks are not subject to the limit.
+    pub max_concurrent_requests: Option<usize>,
+    /// If set, error responses are redacted before they are returned to clients.
+    pub error_redaction: Option<ErrorRedaction>,
+    /// If set, the bodies of query and mutation requests are logged at the debug level.
+    pub body_logging: Option<BodyLogging>,
+    /// The bucket boundaries of the built-in request duration and size histograms.
+    pub histogram_buckets: HistogramBuckets,
+    /// `/metrics` updates the connector's metrics at most once per this interval, so that
+    /// expensive [`Connector::fetch_metrics`] implementations are not run for every scrape.
+    /// Concurrent scrapes always share the same update.
+    pub metrics_refresh_interval: Duration,
+    /// If set, no spans are created for health checks and metrics scrapes.
+    pub skip_probe_spans: bool,
+    /// If set, at most this many request failures and authorization errors of each kind are
+    /// logged per minute.
+    pub max_error_logs_per_minute: Option<u32>,
+    /// If set, query responses with at least this many rows are serialized on the blocking
+    /// thread pool, so that serializing them does not stall other requests.
+    pub blocking_serialization_rows: Option<usize>,
+    /// If set, the requests and responses of successful queries and mutations are written to
+    /// this directory as `ndc-test` snapshots, which can be replayed with the `replay` command.
+    pub record_snapshots_dir: Option<PathBuf>,
+    /// If set, query responses are cached, and served from the cache until they expire.
+    pub query_cache: Option<QueryCacheOptions>,
+    /// If set, up to this many query requests can be registered at `/persisted-queries`, and
+    /// invoked at `/query` by their hash. If it is 0, every registration is rejected.
+    pub max_persisted_queries: Option<usize>,
+    /// The connector's name, version and build details, which are reported by `/version` along
+    /// with the SDK and specification versions.
+    pub connector_version: Option<ConnectorVersion>,
+}
+
+/// The `blocking_serialization_rows` router option, passed to the query handler.
+#[derive(Clone, Copy, Debug)]
+struct BlockingSerializationRows(Option<usize>);
+
 pub fn create_router<C>(
     state: ServerState<C>,
     service_token_secret: Option<String>,
@@ -276,9 +1794,59 @@ pub fn create_router<C>(
 ) -> axum::Router<()>
 where
     C: Connector + 'static,
-    C::Configuration: Clone,
-    C::State: Clone,
 {
+    create_router_with_options(
+        state,
+        RouterOptions {
+            service_token_secret,
+            max_request_size,
+            ..RouterOptions::default()
+        },
+    )
+}
+
+pub fn create_router_with_options<C>(
+    state: ServerState<C>,
+    options: RouterOptions,
+) -> axum::Router<()>
+where
+    C: Connector + 'static,
+{
+    let started = Instant::now();
+    let startup_state = state.clone();
+    let router = tracing::info_span!(CREATE_ROUTER).in_scope(|| build_router(state, options));
+    startup_state.record_startup_phase(CREATE_ROUTER, started.elapsed());
+    router
+}
+
+fn build_router<C>(state: ServerState<C>, options: RouterOptions) -> axum::Router<()>
+where
+    C: Connector + 'static,
+{
+    let RouterOptions {
+        service_token_secret,
+        max_request_size,
+        request_timeout,
+        max_concurrent_requests,
+        error_redaction,
+        body_logging,
+        histogram_buckets,
+        metrics_refresh_interval,
+        skip_probe_spans,
+        max_error_logs_per_minute,
+        blocking_serialization_rows,
+        record_snapshots_dir,
+        query_cache,
+        max_persisted_queries,
+        connector_version,
+    } = options;
+    let request_limits = RequestLimits::new(request_timeout, max_concurrent_requests);
+
+    let error_metrics = error_metrics(state.metrics());
+    let request_metrics = request_metrics(state.metrics(), &histogram_buckets);
+
+    let log_sampler = Arc::new(log_sampler(state.metrics(), max_error_logs_per_minute));
+
     axum::Router::new()
         .route("/capabilities", get(get_capabilities::<C>))
         .route("/metrics", get(get_metrics::<C>))
@@ -287,6 +1855,32 @@ where
         .route("/query/explain", post(post_query_explain::<C>))
         .route("/mutation", post(post_mutation::<C>))
         .route("/mutation/explain", post(post_mutation_explain::<C>))
+        .route(
+            "/persisted-queries",
+            get(get_persisted_queries).post(post_persisted_query),
+        )
+        .route("/persisted-queries/:hash", delete(delete_persisted_query))
+        .layer(Extension(
+            max_persisted_queries.map(|max| Arc::new(PersistedQueries::new(max))),
+        ))
+        .layer(Extension(body_logging))
+        .layer(Extension(
+            record_snapshots_dir.map(|dir| Arc::new(SnapshotRecorder::new(dir))),
+        ))
+        .layer(Extension(query_cache.map(|options| {
+            Arc::new(QueryCache::new(options, state.metrics()))
+        })))
+        .layer(Extension(BlockingSerializationRows(
+            blocking_serialization_rows,
+        )))
+        .layer(Extension(request_metrics.clone()))
+        .layer(Extension(Arc::new(metrics_throttle(
+            state.metrics(),
+            metrics_refresh_interval,
+        ))))
+        .layer(Extension(Arc::new(MetricsEncoder::default())))
+        .layer(from_fn(decode_precompressed))
+        .layer(from_fn_with_state(request_limits, limit_requests))
         // We want to limit the size of requests to 100MB to prevent various DDoS / SQL overflow
         // vulnerabilities. We use RequestBodyLimit instead of DefaultBodyLimit to include chunked
         // requests, too.
@@ -295,29 +1889,88 @@ where
         ))
         .layer(ValidateRequestHeaderLayer::custom(auth_handler(
             service_token_secret,
+            log_sampler.clone(),
         )))
         .layer(ValidateRequestHeaderLayer::custom(check_version_header))
-        .route("/health", get(get_health_readiness::<C>)) // health checks are not authenticated
+        // health checks are not authenticated
+        .route("/health", get(get_health_readiness::<C>))
+        .route("/health/live", get(get_health_liveness::<C>))
+        .route("/version", get(get_version))
+        .layer(Extension(Arc::new(VersionInfo::new(connector_version))))
+        .layer(map_response_with_state(error_redaction, redact_errors))
+        .route_layer(map_response_with_state(error_metrics, count_errors))
+        .route_layer(from_fn_with_state(request_metrics, observe_requests))
         .with_state(state)
+        .layer(map_response(add_trace_response))
+        .layer(from_fn(attach_baggage))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(make_span)
+                .make_span_with(if skip_probe_spans {
+                    make_span_except_probes
+                } else {
+                    make_span
+                })
                 .on_response(on_response)
-                .on_failure(|err, _dur, _span: &tracing::Span| {
-                    tracing::error!(
-                        meta.signal_type = "log",
-                        event.domain = "ndc",
-                        event.name = "Request failure",
-                        name = "Request failure",
-                        body = %err,
-                        error = true,
-                    );
+                .on_failure(move |err, _dur, _span: &tracing::Span| {
+                    if let Some(suppressed) = log_sampler.sample("request_failure") {
+                        tracing::error!(
+                            meta.signal_type = "log",
+                            event.domain = "ndc",
+                            event.name = "Request failure",
+                            name = "Request failure",
+                            body = %err,
+                            suppressed,
+                            error = true,
+                        );
+                    }
                 }),
         )
 }
 
+fn request_metrics(
+    metrics: &prometheus::Registry,
+    histogram_buckets: &HistogramBuckets,
+) -> Option<RequestMetrics> {
+    match RequestMetrics::register(metrics, histogram_buckets) {
+        Ok(request_metrics) => Some(request_metrics),
+        Err(err) => {
+            warn_unregistered("request", &err);
+            None
+        }
+    }
+}
+
+fn error_metrics(metrics: &prometheus::Registry) -> Option<ErrorMetrics> {
+    match ErrorMetrics::register(metrics) {
+        Ok(error_metrics) => Some(error_metrics),
+        Err(err) => {
+            warn_unregistered("error", &err);
+            None
+        }
+    }
+}
+
+fn log_sampler(metrics: &prometheus::Registry, max_per_minute: Option<u32>) -> LogSampler {
+    LogSampler::register(metrics, max_per_minute).unwrap_or_else(|err| {
+        warn_unregistered("log sampling", &err);
+        LogSampler::new(max_per_minute)
+    })
+}
+
+fn metrics_throttle(metrics: &prometheus::Registry, interval: Duration) -> Throttle<Result<()>> {
+    let throttle = Throttle::new(interval);
+    match ThrottleMetrics::register(metrics) {
+        Ok(throttle_metrics) => throttle.with_metrics("fetch_metrics", &throttle_metrics),
+        Err(err) => {
+            warn_unregistered("throttle", &err);
+            throttle.with_name("fetch_metrics")
+        }
+    }
+}
+
 fn auth_handler(
     service_token_secret: Option<String>,
+    log_sampler: Arc<LogSampler>,
 ) -> impl Fn(&mut Request<Body>) -> std::result::Result<(), axum::response::Response> + Clone {
     let expected_auth_header: Option<HeaderValue> =
         service_token_secret.and_then(|service_token_secret| {
@@ -336,14 +1989,17 @@ fn auth_handler(
 
         let message = "Bearer token does not match.".to_string();
 
-        tracing::error!(
-            meta.signal_type = "log",
-            event.domain = "ndc",
-            event.name = "Authorization error",
-            name = "Authorization error",
-            body = message,
-            error = true,
-        );
+        if let Some(suppressed) = log_sampler.sample("authorization") {
+            tracing::error!(
+                meta.signal_type = "log",
+                event.domain = "ndc",
+                event.name = "Authorization error",
+                name = "Authorization error",
+                body = message,
+                suppressed,
+                error = true,
+            );
+        }
         Err(ErrorResponse::new(
             StatusCode::UNAUTHORIZED,
             "Internal error".into(),
@@ -384,15 +2040,7 @@ fn check_version_header(
             .into_response());
         };
 
-        let comparator = semver::Comparator {
-            op: semver::Op::Caret,
-            major: wanted_version.major,
-            minor: Some(wanted_version.minor),
-            patch: Some(wanted_version.patch),
-            pre: wanted_version.pre,
-        };
-
-        if !comparator.matches(&semver::Version::parse(ndc_models::VERSION).unwrap()) {
+        if !supports_spec_version(&wanted_version) {
             return Err(ErrorResponse::new(
                 StatusCode::BAD_REQUEST,
                 "The connector does not support the requested NDC version".to_owned(),
@@ -408,64 +2056,355 @@ fn check_version_header(
     Ok(())
 }
 
-async fn get_metrics<C: Connector>(State(state): State<ServerState<C>>) -> Result<String> {
-    fetch_metrics::<C>(state.configuration(), state.state().await?, state.metrics())
+/// Whether a client which asks for the given ndc-spec version, in the `X-Hasura-NDC-Version`
+/// header, can be served.
+fn supports_spec_version(wanted_version: &semver::Version) -> bool {
+    let comparator = semver::Comparator {
+        op: semver::Op::Caret,
+        major: wanted_version.major,
+        minor: Some(wanted_version.minor),
+        patch: Some(wanted_version.patch),
+        pre: wanted_version.pre.clone(),
+    };
+    comparator.matches(&semver::Version::parse(ndc_models::VERSION).unwrap())
+}
+
+async fn get_metrics<C: Connector>(
+    State(state): State<ServerState<C>>,
+    Extension(request_metrics): Extension<Option<RequestMetrics>>,
+    Extension(metrics_throttle): Extension<Arc<Throttle<Result<()>>>>,
+    Extension(metrics_encoder): Extension<Arc<MetricsEncoder>>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response> {
+    refresh_metrics::<C>(
+        &metrics_throttle,
+        state.configuration(),
+        state.state().await?,
+    )
+    .await?;
+    let (format, content_type) = if openmetrics::accepts_openmetrics(&headers) {
+        (
+            MetricsFormat::OpenMetrics(request_metrics.as_ref()),
+            openmetrics::CONTENT_TYPE,
+        )
+    } else {
+        (MetricsFormat::Prometheus, "text/plain; charset=utf-8")
+    };
+    // Prometheus accepts gzip, which shrinks large registries considerably
+    let encoding =
+        accepts_encoding(&headers, ContentEncoding::Gzip).then_some(ContentEncoding::Gzip);
+    let body = metrics_encoder.encode(state.metrics(), format, encoding)?;
+    let mut response = ([(header::CONTENT_TYPE, content_type)], body).into_response();
+    if let Some(encoding) = encoding {
+        response.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+    }
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct HealthQuery {
+    /// Any value, including none as in `?verbose`, asks for the report, except `false` and `0`.
+    verbose: Option<String>,
+}
+
+impl HealthQuery {
+    fn verbose(&self) -> bool {
+        self.verbose
+            .as_deref()
+            .is_some_and(|verbose| !matches!(verbose, "false" | "0"))
+    }
+}
+
+async fn get_health_readiness<C: Connector>(
+    State(state): State<ServerState<C>>,
+    Query(query): Query<HealthQuery>,
+) -> Result<axum::response::Response> {
+    let report = C::get_health_report(state.configuration(), state.state().await?).await?;
+    let status = if report.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    if query.verbose() {
+        Ok((status, Json(report)).into_response())
+    } else {
+        Ok(status.into_response())
+    }
+}
+
+async fn get_health_liveness<C: Connector>(State(state): State<ServerState<C>>) -> Result<()> {
+    C::get_health_liveness(state.configuration(), state.initialized_state()).await
+}
+
+/// The versions of the connector, SDK and specification, so that fleet tooling can identify what
+/// is deployed. Like the health checks, this is not authenticated.
+async fn get_version(Extension(version): Extension<Arc<VersionInfo>>) -> Json<VersionInfo> {
+    Json(VersionInfo::clone(&version))
 }
 
-async fn get_health_readiness<C: Connector>(State(state): State<ServerState<C>>) -> Result<()> {
-    C::get_health_readiness(state.configuration(), state.state().await?).await
+/// With the `validate-serialized-responses` feature, check that a pre-serialized response
+/// represents the expected type before it is sent, so that connectors which emit malformed
+/// payloads are caught during development. Streamed responses are buffered to be checked.
+async fn validate_response<A: serde::de::DeserializeOwned>(
+    response: JsonResponse<A>,
+) -> Result<JsonResponse<A>> {
+    if !cfg!(feature = "validate-serialized-responses") {
+        return Ok(response);
+    }
+    let response = response.buffer().await?;
+    response.validate().map_err(|err| {
+        ErrorResponse::new_internal_with_details(json!({
+            "message": "The connector produced an invalid serialized response",
+            "error": err.to_string(),
+        }))
+    })?;
+    Ok(response)
 }
 
 async fn get_schema<C: Connector>(
     State(state): State<ServerState<C>>,
 ) -> Result<JsonResponse<SchemaResponse>> {
-    C::get_schema(state.configuration()).await
+    C::enrich_span(
+        state.configuration(),
+        ConnectorRequest::Schema,
+        &tracing::Span::current(),
+    );
+    validate_response(C::get_schema(state.configuration()).await?).await
 }
 
 async fn post_query_explain<C: Connector>(
     State(state): State<ServerState<C>>,
     WithRejection(Json(request), _): WithRejection<Json<QueryRequest>, JsonRejection>,
 ) -> Result<JsonResponse<ExplainResponse>> {
-    C::query_explain(state.configuration(), state.state().await?, request).await
+    record_query_request(&request);
+    C::enrich_span(
+        state.configuration(),
+        ConnectorRequest::QueryExplain(&request),
+        &tracing::Span::current(),
+    );
+    validate_response(C::query_explain(state.configuration(), state.state().await?, request).await?)
+        .await
 }
 
 async fn post_mutation_explain<C: Connector>(
     State(state): State<ServerState<C>>,
     WithRejection(Json(request), _): WithRejection<Json<MutationRequest>, JsonRejection>,
 ) -> Result<JsonResponse<ExplainResponse>> {
-    C::mutation_explain(state.configuration(), state.state().await?, request).await
+    record_mutation_request(&request);
+    C::enrich_span(
+        state.configuration(),
+        ConnectorRequest::MutationExplain(&request),
+        &tracing::Span::current(),
+    );
+    validate_response(
+        C::mutation_explain(state.configuration(), state.state().await?, request).await?,
+    )
+    .await
 }
 
 async fn post_mutation<C: Connector>(
     State(state): State<ServerState<C>>,
-    WithRejection(Json(request), _): WithRejection<Json<MutationRequest>, JsonRejection>,
+    Extension(body_logging): Extension<Option<BodyLogging>>,
+    Extension(snapshot_recorder): Extension<Option<Arc<SnapshotRecorder>>>,
+    JsonBody(request): JsonBody<MutationRequest>,
 ) -> Result<JsonResponse<MutationResponse>> {
-    C::mutation(state.configuration(), state.state().await?, request).await
+    // the request is only deserialized if something needs its value, so that connectors which
+    // forward requests send on the bytes without them being parsed in full
+    let needs_value = !tracing::Span::current().is_disabled()
+        || body_logging::enabled(body_logging.as_ref()).is_some()
+        || snapshot_recorder.is_some();
+    let mutation = needs_value
+        .then(|| request.value())
+        .transpose()
+        .map_err(|err| MutationError::new_invalid_request(&err))?;
+    if let Some(mutation) = mutation {
+        record_mutation_request(mutation);
+        log_mutation_request(body_logging.as_ref(), mutation);
+        C::enrich_span(
+            state.configuration(),
+            ConnectorRequest::Mutation(mutation),
+            &tracing::Span::current(),
+        );
+    }
+    let snapshot = snapshot_recorder
+        .zip(mutation)
+        .map(|(recorder, mutation)| (recorder, mutation.clone()));
+    let response = validate_response(
+        C::mutation_raw(state.configuration(), state.state().await?, request).await?,
+    )
+    .await?;
+    log_mutation_response(body_logging.as_ref(), &response);
+    match snapshot {
+        Some((recorder, request)) => recorder.record("mutation", &request, response).await,
+        None => Ok(response),
+    }
 }
 
 async fn post_query<C: Connector>(
     State(state): State<ServerState<C>>,
-    WithRejection(Json(request), _): WithRejection<Json<QueryRequest>, JsonRejection>,
+    Extension(body_logging): Extension<Option<BodyLogging>>,
+    Extension(BlockingSerializationRows(blocking_serialization_rows)): Extension<
+        BlockingSerializationRows,
+    >,
+    Extension(snapshot_recorder): Extension<Option<Arc<SnapshotRecorder>>>,
+    Extension(query_cache): Extension<Option<Arc<QueryCache>>>,
+    QueryBody(request): QueryBody,
 ) -> Result<JsonResponse<QueryResponse>> {
-    C::query(state.configuration(), state.state().await?, request).await
+    // the request is only deserialized if something needs its value, so that connectors which
+    // forward requests send on the bytes without them being parsed in full
+    let needs_value = !tracing::Span::current().is_disabled()
+        || body_logging::enabled(body_logging.as_ref()).is_some()
+        || snapshot_recorder.is_some()
+        || query_cache.is_some();
+    let query = needs_value
+        .then(|| request.value())
+        .transpose()
+        .map_err(|err| QueryError::new_invalid_request(&err))?;
+    if let Some(query) = query {
+        record_query_request(query);
+        log_query_request(body_logging.as_ref(), query);
+        C::enrich_span(
+            state.configuration(),
+            ConnectorRequest::Query(query),
+            &tracing::Span::current(),
+        );
+    }
+    let cache_key = query_cache
+        .as_ref()
+        .zip(query)
+        .and_then(|(cache, query)| Some((cache, cache.key(query)?)));
+    let snapshot = snapshot_recorder
+        .zip(query)
+        .map(|(recorder, query)| (recorder, query.clone()));
+    // hits are recorded, logged and snapshotted like any other response, but not cached again
+    let cached = cache_key.as_ref().and_then(|(cache, key)| cache.get(key));
+    let (response, cache_key) = match cached {
+        Some(response) => (response, None),
+        None => (
+            validate_response(
+                C::query_raw(state.configuration(), state.state().await?, request).await?,
+            )
+            .await?,
+            cache_key,
+        ),
+    };
+    record_query_response(&response);
+    log_query_response(body_logging.as_ref(), &response);
+    let response = match snapshot {
+        Some((recorder, request)) => recorder.record("query", &request, response).await?,
+        None => response,
+    };
+    let response = match response.body() {
+        JsonResponse::Value(value)
+            if blocking_serialization_rows.is_some_and(|rows| row_count(value) >= rows) =>
+        {
+            response.serialize_blocking().await?
+        }
+        _ => response,
+    };
+    match cache_key {
+        Some((cache, key)) => cache.insert(key, response).await,
+        None => Ok(response),
+    }
 }
 
 #[cfg(feature = "ndc-test")]
 mod ndc_test_commands {
     use async_trait::async_trait;
+    use axum::body::Bytes;
     use ndc_test::reporter::{ConsoleReporter, TestResults};
     use prometheus::Registry;
+    use serde::de::DeserializeOwned;
+    use std::collections::HashMap;
     use std::error::Error;
-    use std::path::PathBuf;
-    use std::process::exit;
+    use std::path::Path;
 
+    use crate::capability_coverage::CoverageConnector;
+    use crate::client::{NdcClient, NdcClientError};
+    use crate::exit_code::{CommandError, ExitCode};
+    use crate::fnv::canonical_request_bytes;
     use crate::json_response::JsonResponse;
+    use crate::snapshots::{self, SnapshotView};
+    use crate::test_cases::{run_test_cases, TestCase};
+    use crate::test_reporter::{JsonReporter, TestStatus};
+    use ndc_test::reporter::Reporter as _;
+
+    use std::io::Write as _;
 
-    use super::{BenchCommand, Connector, ConnectorSetup};
+    use super::{
+        log_filter, BenchCommand, BenchCompareCommand, BenchOutput, Connector, ConnectorSetup,
+        ErrorResponse, TestTarget,
+    };
+    use crate::bench_report;
+    use crate::tracing::{init_tracing_with_options, LogFormat, TracingOptions};
 
     struct ConnectorAdapter<C: Connector> {
         configuration: C::Configuration,
         state: C::State,
+        pass_through: Option<PassThrough>,
+    }
+
+    /// The expected responses of the snapshots being benchmarked, written compactly, by the
+    /// canonical JSON of their requests.
+    ///
+    /// With these, serialized responses are checked without deserializing them. A response which
+    /// is byte for byte the expected one is accepted, and any other is validated against the
+    /// response type. `ndc-test` discards the responses of benchmarked requests, so an empty
+    /// response is returned in place of a serialized one.
+    #[derive(Default)]
+    struct PassThrough {
+        queries: HashMap<Vec<u8>, Bytes>,
+        mutations: HashMap<Vec<u8>, Bytes>,
+    }
+
+    impl PassThrough {
+        /// Read the expected responses of the query and mutation snapshots in the directory.
+        fn load(snapshots_dir: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+            let mut pass_through = Self::default();
+            for snapshot in snapshots::list_snapshots(snapshots_dir)? {
+                let expected = match snapshot.kind.as_str() {
+                    "query" => &mut pass_through.queries,
+                    "mutation" => &mut pass_through.mutations,
+                    _ => continue,
+                };
+                let read = |name: &str| -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+                    Ok(serde_json::from_slice(&std::fs::read(
+                        snapshot.path.join(name),
+                    )?)?)
+                };
+                expected.insert(
+                    canonical_request_bytes(&read("request.json")?)?,
+                    Bytes::from(serde_json::to_vec(&read("expected.json")?)?),
+                );
+            }
+            Ok(pass_through)
+        }
+
+        /// The canonical JSON of a request, by which its expected response is found.
+        fn key(request: &impl serde::Serialize) -> Result<Vec<u8>, ndc_test::error::Error> {
+            canonical_request_bytes(request)
+                .map_err(|err| ndc_test::error::Error::OtherError(err.into()))
+        }
+
+        /// Check a serialized response against the expected response, returning whether it was
+        /// serialized.
+        fn check<A: DeserializeOwned>(
+            expected: Option<&Bytes>,
+            response: &JsonResponse<A>,
+        ) -> Result<bool, ndc_test::error::Error> {
+            let Some(bytes) = response.as_serialized() else {
+                return Ok(false);
+            };
+            if expected != Some(bytes) {
+                response
+                    .validate()
+                    .map_err(|err| ndc_test::error::Error::OtherError(err.into()))?;
+            }
+            Ok(true)
+        }
     }
 
     #[async_trait(?Send)]
@@ -481,6 +2420,8 @@ mod ndc_test_commands {
 
         async fn get_schema(&self) -> Result<ndc_models::SchemaResponse, ndc_test::error::Error> {
             Ok(C::get_schema(&self.configuration)
+                .await?
+                .buffer()
                 .await
                 .and_then(JsonResponse::into_value)?)
         }
@@ -489,25 +2430,147 @@ mod ndc_test_commands {
             &self,
             request: ndc_models::QueryRequest,
         ) -> Result<ndc_models::QueryResponse, ndc_test::error::Error> {
-            Ok(C::query(&self.configuration, &self.state, request)
-                .await
-                .and_then(JsonResponse::into_value)?)
+            let Some(pass_through) = &self.pass_through else {
+                let response = C::query(&self.configuration, &self.state, request).await?;
+                return Ok(response.buffer().await?.into_value::<ErrorResponse>()?);
+            };
+            let key = PassThrough::key(&request)?;
+            let response = C::query(&self.configuration, &self.state, request)
+                .await?
+                .buffer()
+                .await?;
+            if PassThrough::check(pass_through.queries.get(&key), &response)? {
+                return Ok(ndc_models::QueryResponse(vec![]));
+            }
+            Ok(response.into_value::<ErrorResponse>()?)
         }
 
         async fn mutation(
             &self,
             request: ndc_models::MutationRequest,
         ) -> Result<ndc_models::MutationResponse, ndc_test::error::Error> {
-            Ok(C::mutation(&self.configuration, &self.state, request)
-                .await
-                .and_then(JsonResponse::into_value)?)
+            let Some(pass_through) = &self.pass_through else {
+                let response = C::mutation(&self.configuration, &self.state, request).await?;
+                return Ok(response.buffer().await?.into_value::<ErrorResponse>()?);
+            };
+            let key = PassThrough::key(&request)?;
+            let response = C::mutation(&self.configuration, &self.state, request)
+                .await?
+                .buffer()
+                .await?;
+            if PassThrough::check(pass_through.mutations.get(&key), &response)? {
+                return Ok(ndc_models::MutationResponse {
+                    operation_results: vec![],
+                });
+            }
+            Ok(response.into_value::<ErrorResponse>()?)
+        }
+    }
+
+    /// A connector running elsewhere, which is tested over HTTP.
+    #[derive(Clone)]
+    struct RemoteConnector(NdcClient);
+
+    impl RemoteConnector {
+        fn with_spec_version(&self, spec_version: semver::Version) -> Self {
+            Self(self.0.clone().with_spec_version(spec_version))
+        }
+    }
+
+    fn remote_error(err: NdcClientError) -> ndc_test::error::Error {
+        ndc_test::error::Error::OtherError(err.into())
+    }
+
+    #[async_trait(?Send)]
+    impl ndc_test::connector::Connector for RemoteConnector {
+        async fn get_capabilities(
+            &self,
+        ) -> Result<ndc_models::CapabilitiesResponse, ndc_test::error::Error> {
+            self.0.capabilities().await.map_err(remote_error)
+        }
+
+        async fn get_schema(&self) -> Result<ndc_models::SchemaResponse, ndc_test::error::Error> {
+            self.0.schema().await.map_err(remote_error)
+        }
+
+        async fn query(
+            &self,
+            request: ndc_models::QueryRequest,
+        ) -> Result<ndc_models::QueryResponse, ndc_test::error::Error> {
+            self.0.query(&request).await.map_err(remote_error)
+        }
+
+        async fn mutation(
+            &self,
+            request: ndc_models::MutationRequest,
+        ) -> Result<ndc_models::MutationResponse, ndc_test::error::Error> {
+            self.0.mutation(&request).await.map_err(remote_error)
+        }
+    }
+
+    /// Either an in-process connector or a remote one.
+    enum TestConnector<C: Connector> {
+        InProcess(ConnectorAdapter<C>),
+        Remote(RemoteConnector),
+    }
+
+    impl<C: Connector> TestConnector<C> {
+        /// Check serialized responses without deserializing them, see [`PassThrough`]. Responses
+        /// from remote connectors are always deserialized.
+        fn with_pass_through(mut self, pass_through: PassThrough) -> Self {
+            if let Self::InProcess(connector) = &mut self {
+                connector.pass_through = Some(pass_through);
+            }
+            self
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl<C: Connector> ndc_test::connector::Connector for TestConnector<C> {
+        async fn get_capabilities(
+            &self,
+        ) -> Result<ndc_models::CapabilitiesResponse, ndc_test::error::Error> {
+            match self {
+                Self::InProcess(connector) => connector.get_capabilities().await,
+                Self::Remote(connector) => connector.get_capabilities().await,
+            }
+        }
+
+        async fn get_schema(&self) -> Result<ndc_models::SchemaResponse, ndc_test::error::Error> {
+            match self {
+                Self::InProcess(connector) => connector.get_schema().await,
+                Self::Remote(connector) => connector.get_schema().await,
+            }
+        }
+
+        async fn query(
+            &self,
+            request: ndc_models::QueryRequest,
+        ) -> Result<ndc_models::QueryResponse, ndc_test::error::Error> {
+            match self {
+                Self::InProcess(connector) => connector.query(request).await,
+                Self::Remote(connector) => connector.query(request).await,
+            }
+        }
+
+        async fn mutation(
+            &self,
+            request: ndc_models::MutationRequest,
+        ) -> Result<ndc_models::MutationResponse, ndc_test::error::Error> {
+            match self {
+                Self::InProcess(connector) => connector.mutation(request).await,
+                Self::Remote(connector) => connector.mutation(request).await,
+            }
         }
     }
 
     pub(super) async fn test<Setup: ConnectorSetup>(
         setup: Setup,
         command: super::TestCommand,
+        test_cases: &[Box<dyn TestCase>],
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        init_logging(&command.log_level)?;
+
         let test_configuration = ndc_test::configuration::TestConfiguration {
             seed: command.seed.map(|s| s.as_bytes().try_into()).transpose()?,
             snapshots_dir: command.snapshots_dir,
@@ -517,16 +2580,38 @@ mod ndc_test_commands {
             gen_config: ndc_test::configuration::TestGenerationConfiguration::default(),
         };
 
-        let connector = make_connector_adapter(setup, command.configuration).await?;
-        let mut reporter = (ConsoleReporter::new(), TestResults::default());
+        let connector = make_connector_adapter(setup, command.target).await?;
+        let mut reporter = (
+            ConsoleReporter::new(),
+            (TestResults::default(), JsonReporter::new()),
+        );
+
+        if command.spec_versions.is_empty() {
+            let connector = CoverageConnector::new(connector);
+            ndc_test::test_connector(&test_configuration, &connector, &mut reporter).await;
+            run_test_cases(test_cases, &connector, &mut reporter).await;
+            print_capability_coverage(&connector).await;
+        } else {
+            test_spec_versions(
+                &test_configuration,
+                &connector,
+                &command.spec_versions,
+                test_cases,
+                &mut reporter,
+            )
+            .await;
+        }
 
-        ndc_test::test_connector(&test_configuration, &connector, &mut reporter).await;
+        let (_, (results, json_reporter)) = reporter;
+        if let Some(report_json) = command.report_json {
+            write_json_report(&json_reporter, &report_json)?;
+        }
 
-        if !reporter.1.failures.is_empty() {
+        if !results.failures.is_empty() {
             println!();
-            println!("{}", reporter.1.report());
+            println!("{}", results.report());
 
-            exit(1)
+            return Err(CommandError::from(ExitCode::TestFailures).into());
         }
 
         Ok(())
@@ -536,77 +2621,377 @@ mod ndc_test_commands {
         setup: Setup,
         command: super::ReplayCommand,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let connector = make_connector_adapter(setup, command.configuration).await?;
+        init_logging(&command.log_level)?;
+
+        let connector =
+            CoverageConnector::new(make_connector_adapter(setup, command.target).await?);
         let options = ndc_test::configuration::TestOptions {
             validate_responses: !command.no_validate_responses,
         };
-        let mut reporter = (ConsoleReporter::new(), TestResults::default());
+        let selected = if command.filter.is_empty() && command.only.is_empty() {
+            None
+        } else {
+            let patterns = command
+                .filter
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern))
+                .collect::<Result<Vec<_>, _>>()?;
+            let selected = snapshots::list_snapshots(&command.snapshots_dir)?
+                .into_iter()
+                .filter(|snapshot| {
+                    let qualified_name = format!("{}/{}", snapshot.kind, snapshot.name);
+                    patterns.iter().any(|pattern| {
+                        pattern.matches(&snapshot.name) || pattern.matches(&qualified_name)
+                    }) || command
+                        .only
+                        .iter()
+                        .any(|name| *name == snapshot.name || *name == qualified_name)
+                })
+                .collect::<Vec<_>>();
+            if selected.is_empty() {
+                return Err("No snapshots match the given filters.".into());
+            }
+            Some(selected)
+        };
 
-        ndc_test::test_snapshots_in_directory(
-            &options,
-            &connector,
-            &mut reporter,
-            command.snapshots_dir,
-        )
-        .await;
+        let (results, json_reporter) = if command.jobs.get() > 1 {
+            let snapshots = match selected {
+                Some(selected) => selected,
+                None => snapshots::list_snapshots(&command.snapshots_dir)?,
+            };
+            replay_concurrently(&options, &connector, snapshots, command.jobs.get()).await?
+        } else {
+            let view = selected
+                .map(|selected| SnapshotView::create(&selected))
+                .transpose()?;
+            let snapshots_dir = match &view {
+                Some(view) => view.path().to_path_buf(),
+                None => command.snapshots_dir,
+            };
+            let mut reporter = (
+                ConsoleReporter::new(),
+                (TestResults::default(), JsonReporter::new()),
+            );
+            ndc_test::test_snapshots_in_directory(
+                &options,
+                &connector,
+                &mut reporter,
+                snapshots_dir,
+            )
+            .await;
+            reporter.1
+        };
+        print_capability_coverage(&connector).await;
+        if let Some(report_json) = command.report_json {
+            write_json_report(&json_reporter, &report_json)?;
+        }
 
-        if !reporter.1.failures.is_empty() {
+        if !results.failures.is_empty() {
             println!();
-            println!("{}", reporter.1.report());
+            println!("{}", results.report());
 
-            exit(1)
+            return Err(CommandError::from(ExitCode::TestFailures).into());
         }
 
         Ok(())
     }
 
+    /// Run the tests once for each ndc-spec version, in a group named after the version, and print
+    /// which versions passed.
+    ///
+    /// A running connector negotiates each version itself. An in-process connector supports the
+    /// versions which it would accept in the `X-Hasura-NDC-Version` header if it were served.
+    async fn test_spec_versions<C: Connector>(
+        test_configuration: &ndc_test::configuration::TestConfiguration,
+        connector: &TestConnector<C>,
+        spec_versions: &[semver::Version],
+        test_cases: &[Box<dyn TestCase>],
+        reporter: &mut (ConsoleReporter, (TestResults, JsonReporter)),
+    ) {
+        let mut matrix = vec![];
+        for spec_version in spec_versions {
+            let recorded = reporter.1 .1.tests().len();
+            reporter.enter(&format!("ndc-spec {spec_version}"));
+            match connector {
+                TestConnector::InProcess(_) if !super::supports_spec_version(spec_version) => {
+                    reporter.failure(&ndc_test::error::Error::OtherError(
+                        "The connector does not support the requested NDC version".into(),
+                    ));
+                }
+                TestConnector::InProcess(connector) => {
+                    ndc_test::test_connector(test_configuration, connector, reporter).await;
+                    run_test_cases(test_cases, connector, reporter).await;
+                }
+                TestConnector::Remote(connector) => {
+                    let connector = connector.with_spec_version(spec_version.clone());
+                    ndc_test::test_connector(test_configuration, &connector, reporter).await;
+                    run_test_cases(test_cases, &connector, reporter).await;
+                }
+            }
+            reporter.exit();
+            let tests = &reporter.1 .1.tests()[recorded..];
+            let failed = tests
+                .iter()
+                .filter(|test| test.status == TestStatus::Failed)
+                .count();
+            matrix.push((spec_version, failed, tests.len()));
+        }
+
+        println!();
+        println!("ndc-spec version matrix:");
+        for (spec_version, failed, total) in matrix {
+            if failed == 0 {
+                println!("  {spec_version}: passed ({total} tests)");
+            } else {
+                println!("  {spec_version}: failed ({failed} of {total} tests)");
+            }
+        }
+    }
+
+    async fn print_capability_coverage<C: ndc_test::connector::Connector>(
+        connector: &CoverageConnector<C>,
+    ) {
+        match connector.report().await {
+            Ok(report) => {
+                println!();
+                print!("{report}");
+            }
+            Err(err) => eprintln!("Unable to report capability coverage: {err}"),
+        }
+    }
+
+    /// Replay the snapshots in groups, one group per job.
+    ///
+    /// Console output from concurrent jobs would be interleaved, so only a summary is printed.
+    async fn replay_concurrently<C: ndc_test::connector::Connector>(
+        options: &ndc_test::configuration::TestOptions,
+        connector: &C,
+        snapshots: Vec<snapshots::Snapshot>,
+        jobs: usize,
+    ) -> Result<(TestResults, JsonReporter), Box<dyn Error + Send + Sync>> {
+        let views = snapshots::partition(snapshots, jobs)
+            .iter()
+            .map(|group| SnapshotView::create(group))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let reporters = futures::future::join_all(views.iter().map(|view| async move {
+            let mut reporter = (TestResults::default(), JsonReporter::new());
+            ndc_test::test_snapshots_in_directory(
+                options,
+                connector,
+                &mut reporter,
+                view.path().to_path_buf(),
+            )
+            .await;
+            reporter
+        }))
+        .await;
+
+        let mut results = TestResults::default();
+        let mut json_reporter = JsonReporter::new();
+        for (job_results, job_json_reporter) in reporters {
+            results.failures.extend(job_results.failures);
+            json_reporter.extend(job_json_reporter);
+        }
+        println!(
+            "Replayed {} snapshots with {jobs} jobs: {} failed.",
+            json_reporter.tests().len(),
+            results.failures.len()
+        );
+        Ok((results, json_reporter))
+    }
+
     pub(super) async fn bench<Setup: ConnectorSetup>(
         setup: Setup,
         command: BenchCommand,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        init_logging(&command.log_level)?;
+
+        let mut connector = make_connector_adapter(setup, command.target).await?;
+        if command.pass_through {
+            connector = connector.with_pass_through(PassThrough::load(&command.snapshots_dir)?);
+        }
+
+        if command.warmup > 0 {
+            let warmup_configuration = ndc_test::ReportConfiguration {
+                samples: command.warmup,
+                tolerance: None,
+            };
+            ndc_test::bench_snapshots_in_directory(
+                &warmup_configuration,
+                &connector,
+                &mut TestResults::default(),
+                command.snapshots_dir.clone(),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
         let configuration = ndc_test::ReportConfiguration {
             samples: command.samples,
             tolerance: command.tolerance,
         };
 
-        let connector = make_connector_adapter(setup, command.configuration).await?;
-        let mut reporter = (ConsoleReporter::new(), TestResults::default());
+        let (reports, results) = if command.output == BenchOutput::Console {
+            let mut reporter = (ConsoleReporter::new(), TestResults::default());
+            let reports = ndc_test::bench_snapshots_in_directory(
+                &configuration,
+                &connector,
+                &mut reporter,
+                command.snapshots_dir,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            (reports, reporter.1)
+        } else {
+            // progress output would make the results unreadable by other tools
+            let mut results = TestResults::default();
+            let reports = ndc_test::bench_snapshots_in_directory(
+                &configuration,
+                &connector,
+                &mut results,
+                command.snapshots_dir,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            (reports, results)
+        };
 
-        let reports = ndc_test::bench_snapshots_in_directory(
-            &configuration,
-            &connector,
-            &mut reporter,
-            command.snapshots_dir,
-        )
-        .await
-        .map_err(|e| e.to_string())?;
+        match command.output {
+            BenchOutput::Console => {
+                println!();
+                println!("{}", ndc_test::benchmark_report(&configuration, reports));
+            }
+            BenchOutput::Json => {
+                let mut stdout = std::io::stdout().lock();
+                serde_json::to_writer_pretty(
+                    &mut stdout,
+                    &bench_report::flatten_reports(&reports)?,
+                )?;
+                writeln!(stdout)?;
+            }
+            BenchOutput::Csv => {
+                let stdout = std::io::stdout().lock();
+                bench_report::write_csv(stdout, &bench_report::flatten_reports(&reports)?)?;
+            }
+        }
 
-        println!();
-        println!("{}", ndc_test::benchmark_report(&configuration, reports));
+        if !results.failures.is_empty() {
+            // with a tolerance, failures include benchmarks which deviate from the previous report
+            let exit_code = if command.tolerance.is_some() {
+                ExitCode::BenchRegression
+            } else {
+                ExitCode::TestFailures
+            };
+            return Err(CommandError::from(exit_code).into());
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn bench_compare(
+        command: &BenchCompareCommand,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let read =
+            |path: &Path| -> Result<bench_report::BenchReports, Box<dyn Error + Send + Sync>> {
+                let file = std::fs::File::open(path)?;
+                Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+            };
+        let old = read(&command.old)?;
+        let new = read(&command.new)?;
+
+        let mut regressions = 0;
+        for comparison in bench_report::compare(&old, &new) {
+            let change = comparison.change();
+            let regressed = change > command.tolerance;
+            if regressed {
+                regressions += 1;
+            }
+            println!(
+                "{}: {:.3} -> {:.3} ({:+.1}%){}",
+                comparison.name,
+                comparison.old_mean,
+                comparison.new_mean,
+                change * 100.0,
+                if regressed { " REGRESSION" } else { "" }
+            );
+        }
 
-        if !reporter.1.failures.is_empty() {
-            exit(1);
+        if regressions > 0 {
+            println!();
+            println!("{regressions} benchmarks regressed by more than the tolerance.");
+            return Err(CommandError::from(ExitCode::BenchRegression).into());
         }
 
         Ok(())
     }
 
+    fn write_json_report(
+        reporter: &JsonReporter,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file = std::fs::File::create(path)?;
+        reporter.write_to(std::io::BufWriter::new(file))?;
+        Ok(())
+    }
+
+    /// Logs would be interleaved with test reports, so they are only enabled on request.
+    fn init_logging(log_level: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(log_level) = log_filter(log_level) {
+            init_tracing_with_options(&TracingOptions {
+                log_format: LogFormat::Compact,
+                log_level: Some(&log_level),
+                ..TracingOptions::default()
+            })?;
+        }
+        Ok(())
+    }
+
     async fn make_connector_adapter<Setup: ConnectorSetup>(
         setup: Setup,
-        configuration_path: PathBuf,
-    ) -> Result<ConnectorAdapter<Setup::Connector>, Box<dyn Error + Send + Sync>> {
+        target: TestTarget,
+    ) -> Result<TestConnector<Setup::Connector>, Box<dyn Error + Send + Sync>> {
+        if let Some(base_url) = target.url {
+            let mut client = NdcClient::new(base_url);
+            if let Some(service_token_secret) = target.service_token_secret {
+                client = client.with_service_token(service_token_secret);
+            }
+            return Ok(TestConnector::Remote(RemoteConnector(client)));
+        }
+
+        let configuration_path = target
+            .configuration
+            .ok_or("either --configuration or --url must be provided")?;
         let mut metrics = Registry::new();
-        let configuration = setup.parse_configuration(&configuration_path).await?;
-        let state = setup.try_init_state(&configuration, &mut metrics).await?;
-        Ok(ConnectorAdapter {
+        let configuration = setup
+            .parse_configuration(&configuration_path)
+            .await
+            .map_err(|err| ExitCode::ConfigurationError.with_error(err))?;
+        let state = setup
+            .try_init_state(&configuration, &mut metrics)
+            .await
+            .map_err(|err| ExitCode::StateInitError.with_error(err))?;
+        Ok(TestConnector::InProcess(ConnectorAdapter {
             configuration,
             state,
-        })
+            pass_through: None,
+        }))
     }
 }
 
-async fn check_health(CheckHealthCommand { host, port }: CheckHealthCommand) -> Result<()> {
-    match check_health::check_health(host, port).await {
+async fn check_health(command: CheckHealthCommand) -> Result<()> {
+    let options = check_health::HealthCheckOptions {
+        timeout: command.timeout,
+        retries: command.retries,
+        retry_interval: command.retry_interval,
+        tls: command.tls,
+        ca_certificate: command.ca_cert,
+        insecure: command.insecure,
+        service_token_secret: command.service_token_secret,
+        unix_socket: command.unix_socket,
+        ..check_health::HealthCheckOptions::new(command.host, command.port)
+    };
+    match check_health::check_health_with_options(&options).await {
         Ok(()) => {
             println!("Health check succeeded.");
             Ok(())
@@ -617,3 +3002,63 @@ async fn check_health(CheckHealthCommand { host, port }: CheckHealthCommand) ->
         }
     }
 }
+
+async fn validate<Setup: ConnectorSetup>(
+    setup: Setup,
+    command: ValidateCommand,
+) -> std::result::Result<(), CommandError> {
+    let result = async {
+        let configuration = setup
+            .parse_configuration(&command.configuration)
+            .await
+            .map_err(|err| (ExitCode::ConfigurationError, err))?;
+        if command.check_connectivity {
+            let mut metrics = prometheus::Registry::new();
+            setup
+                .try_init_state(&configuration, &mut metrics)
+                .await
+                .map_err(|err| (ExitCode::StateInitError, err))?;
+        }
+        Ok(configuration)
+    }
+    .await;
+
+    match result {
+        Ok(configuration) => {
+            println!("Configuration is valid.");
+            if command.print_config {
+                print_settings(&json!({
+                    "configuration":
+                        configuration_settings(&setup, &command.configuration, &configuration),
+                }))?;
+            }
+            Ok(())
+        }
+        Err((exit_code, err)) => {
+            if exit_code == ExitCode::StateInitError {
+                eprintln!("Unable to initialize the connector: {}", err.message());
+            } else {
+                eprintln!("Configuration is invalid: {}", err.message());
+            }
+            if !err.details().is_null() {
+                let details = serde_json::to_string_pretty(err.details())
+                    .map_err(ErrorResponse::from_error)?;
+                eprintln!("{details}");
+            }
+            Err(exit_code.into())
+        }
+    }
+}
+
+#[cfg(feature = "schemars")]
+fn print_configuration_schema<Setup: ConnectorSetup>(setup: &Setup) -> Result<()> {
+    let Some(schema) = setup.configuration_schema() else {
+        return Err(ErrorResponse::from(
+            "This connector does not provide a configuration schema.".to_string(),
+        ));
+    };
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &schema).map_err(ErrorResponse::from_error)?;
+    writeln!(stdout).map_err(ErrorResponse::from_error)?;
+    Ok(())
+}
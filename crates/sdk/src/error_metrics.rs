@@ -0,0 +1,61 @@
+//! Built-in metrics for error responses, so that operators can alert on error rates without
+//! instrumenting each connector.
+
+use axum::extract::{MatchedPath, State};
+use axum::response::Response;
+use prometheus::{IntCounterVec, Registry};
+
+use crate::metrics_prefix::opts;
+
+/// Counters for error responses, labelled by route and status class.
+#[derive(Clone, Debug)]
+pub struct ErrorMetrics {
+    error_responses_total: IntCounterVec,
+}
+
+impl ErrorMetrics {
+    /// Create the error metrics, and register them with the given registry.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let error_responses_total = IntCounterVec::new(
+            opts(
+                "ndc_sdk_error_responses_total",
+                "Total number of error responses, by route and status class",
+            ),
+            &["route", "status_class"],
+        )?;
+        registry.register(Box::new(error_responses_total.clone()))?;
+        Ok(Self {
+            error_responses_total,
+        })
+    }
+
+    /// Record a response, if it is an error.
+    pub fn observe(&self, route: &str, response: &Response) {
+        let status_class = if response.status().is_server_error() {
+            "5xx"
+        } else if response.status().is_client_error() {
+            "4xx"
+        } else {
+            return;
+        };
+        self.error_responses_total
+            .with_label_values(&[route, status_class])
+            .inc();
+    }
+}
+
+/// Count error responses.
+///
+/// This is intended to be used with [`axum::middleware::map_response_with_state`], as a route
+/// layer, so that the matched route is available.
+pub async fn count_errors(
+    State(metrics): State<Option<ErrorMetrics>>,
+    matched_path: Option<MatchedPath>,
+    response: Response,
+) -> Response {
+    if let Some(metrics) = metrics {
+        let route = matched_path.as_ref().map_or("unknown", MatchedPath::as_str);
+        metrics.observe(route, &response);
+    }
+    response
+}
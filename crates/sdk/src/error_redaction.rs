@@ -0,0 +1,43 @@
+//! Operators may not want connector errors, which can contain connection strings or generated
+//! SQL, to reach clients. This middleware replaces outgoing errors with redacted copies, logging
+//! the original error instead.
+
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+
+use crate::connector::ErrorResponse;
+
+/// How errors should be redacted before they are returned to clients.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorRedaction {
+    /// Truncate error messages to this many characters.
+    pub max_message_length: Option<usize>,
+}
+
+/// Redact any [`ErrorResponse`] in the response, if redaction is enabled.
+///
+/// This is intended to be used with [`axum::middleware::map_response_with_state`].
+pub async fn redact_errors(
+    State(redaction): State<Option<ErrorRedaction>>,
+    mut response: Response,
+) -> Response {
+    let Some(redaction) = redaction else {
+        return response;
+    };
+    let Some(error) = response.extensions_mut().remove::<ErrorResponse>() else {
+        return response;
+    };
+
+    tracing::error!(
+        meta.signal_type = "log",
+        event.domain = "ndc",
+        event.name = "Redacted error",
+        name = "Redacted error",
+        status = error.status_code().as_u16(),
+        body = error.message(),
+        details = %error.details(),
+        error = true,
+    );
+
+    error.redacted(redaction.max_message_length).into_response()
+}
@@ -0,0 +1,135 @@
+//! The exit codes of the commands provided by [`default_main`](crate::default_main), so that
+//! scripts can tell different kinds of failure apart.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::connector::ErrorResponse;
+
+/// The reason a command failed, reported as the process exit code.
+///
+/// | Code | Meaning                                                                        |
+/// |------|--------------------------------------------------------------------------------|
+/// | 0    | Success.                                                                       |
+/// | 1    | Any other error, such as a failure to bind to a port or a failed health check. |
+/// | 2    | The command line is invalid.                                                   |
+/// | 3    | The configuration could not be read or parsed.                                 |
+/// | 4    | The connector state could not be initialized.                                  |
+/// | 5    | Tests or snapshot replays failed.                                              |
+/// | 6    | Benchmarks regressed beyond the tolerance.                                     |
+/// | 128+ | `serve` exited on one of the `--immediate-shutdown-signals`, plus its number.  |
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExitCode {
+    /// An error which is not covered by any other code.
+    RuntimeError = 1,
+    /// The command line is invalid. This is reported by `clap` before any command runs.
+    UsageError = 2,
+    /// The configuration could not be read or parsed, reported by `serve`, `validate`, `test`,
+    /// `replay` and `bench`.
+    ConfigurationError = 3,
+    /// The connector state could not be initialized, reported by `validate --check-connectivity`,
+    /// `test`, `replay` and `bench`.
+    StateInitError = 4,
+    /// Tests failed, reported by `test` and `replay`, and by `bench` when a benchmark could not
+    /// be run.
+    TestFailures = 5,
+    /// Benchmarks regressed, reported by `bench --tolerance` and `bench-compare`.
+    BenchRegression = 6,
+}
+
+impl ExitCode {
+    /// The numeric exit code.
+    pub fn code(self) -> i32 {
+        i32::from(self as u8)
+    }
+
+    /// Exit the process with this code.
+    pub fn exit(self) -> ! {
+        std::process::exit(self.code())
+    }
+
+    /// Print the error to stderr, and exit the process with this code.
+    pub fn exit_with_error(self, err: impl Display) -> ! {
+        eprintln!("{err}");
+        self.exit()
+    }
+
+    /// Fail a command with this code, printing the error before exiting.
+    pub(crate) fn with_error(self, err: impl Into<Box<dyn Error + Send + Sync>>) -> CommandError {
+        CommandError {
+            exit_code: self,
+            error: Some(err.into()),
+        }
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(exit_code: ExitCode) -> Self {
+        Self::from(exit_code as u8)
+    }
+}
+
+/// The failure of a command, with the [`ExitCode`] which describes it.
+///
+/// This is returned by [`default_main`](crate::default_main::default_main) and its variants, so
+/// that `main` can exit with the code, using [`CommandError::report`]. Failures which have
+/// already been reported, such as test failures, carry no error.
+#[derive(Debug)]
+pub struct CommandError {
+    exit_code: ExitCode,
+    error: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl CommandError {
+    /// The exit code which describes the failure.
+    pub fn exit_code(&self) -> ExitCode {
+        self.exit_code
+    }
+
+    /// Print the error to stderr if there is one, and return the exit code, so that `main` can
+    /// return it.
+    pub fn report(self) -> std::process::ExitCode {
+        if let Some(err) = self.error {
+            eprintln!("{err}");
+        }
+        self.exit_code.into()
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            Some(err) => err.fmt(f),
+            None => write!(f, "exited with code {}", self.exit_code.code()),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+impl From<ExitCode> for CommandError {
+    fn from(exit_code: ExitCode) -> Self {
+        Self {
+            exit_code,
+            error: None,
+        }
+    }
+}
+
+impl From<ErrorResponse> for CommandError {
+    fn from(err: ErrorResponse) -> Self {
+        ExitCode::RuntimeError.with_error(err)
+    }
+}
+
+/// Commands which report errors as trait objects can fail with a specific code by returning a
+/// [`CommandError`], which is recovered here. Any other error is a runtime error.
+impl From<Box<dyn Error + Send + Sync>> for CommandError {
+    fn from(err: Box<dyn Error + Send + Sync>) -> Self {
+        match err.downcast::<CommandError>() {
+            Ok(err) => *err,
+            Err(err) => ExitCode::RuntimeError.with_error(err),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Retrying span exports which fail, for example because the collector is briefly unavailable.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Retries failed exports, backing off exponentially between attempts.
+///
+/// The batch span processor's export timeout applies to all of the attempts together.
+#[derive(Debug)]
+pub(crate) struct RetryingExporter<E> {
+    inner: Arc<Mutex<E>>,
+    retries: u32,
+}
+
+impl<E> RetryingExporter<E> {
+    pub fn new(inner: E, retries: u32) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            retries,
+        }
+    }
+}
+
+impl<E: SpanExporter + 'static> SpanExporter for RetryingExporter<E> {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let inner = self.inner.clone();
+        let retries = self.retries;
+        Box::pin(async move {
+            let mut batch = batch;
+            let mut attempt = 0;
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                // the batch is only copied if it may be needed again
+                let spans = if attempt < retries {
+                    batch.clone()
+                } else {
+                    std::mem::take(&mut batch)
+                };
+                // the lock is only held to start the export, not while it runs
+                let export = inner
+                    .lock()
+                    .map_err(|_| TraceError::from("the span exporter lock is poisoned"))?
+                    .export(spans);
+                match export.await {
+                    Err(_) if attempt < retries => {
+                        attempt += 1;
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                    result => return result,
+                }
+            }
+        })
+    }
+
+    fn shutdown(&mut self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.shutdown();
+        }
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        match self.inner.lock() {
+            Ok(mut inner) => inner.force_flush(),
+            Err(_) => {
+                Box::pin(async { Err(TraceError::from("the span exporter lock is poisoned")) })
+            }
+        }
+    }
+}
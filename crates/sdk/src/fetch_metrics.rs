@@ -1,20 +1,137 @@
+use std::sync::Mutex;
+
+use axum::body::Bytes;
 use prometheus::{Registry, TextEncoder};
 
 use crate::connector::error::{ErrorResponse, Result};
 use crate::connector::Connector;
+use crate::json_response::ContentEncoding;
+use crate::openmetrics;
+use crate::request_metrics::RequestMetrics;
+use crate::throttle::Throttle;
 
 pub fn fetch_metrics<C: Connector>(
     configuration: &C::Configuration,
     state: &C::State,
     metrics: &Registry,
 ) -> Result<String> {
-    let encoder = TextEncoder::new();
-
     C::fetch_metrics(configuration, state)?;
 
+    encode_metrics(metrics)
+}
+
+/// Update the connector's metrics with [`Connector::fetch_metrics`], at most once per interval of
+/// the throttle. Concurrent scrapes share the same update.
+pub async fn refresh_metrics<C: Connector>(
+    throttle: &Throttle<Result<()>>,
+    configuration: &C::Configuration,
+    state: &C::State,
+) -> Result<()> {
+    throttle
+        .next(|| async { C::fetch_metrics(configuration, state) })
+        .await
+}
+
+/// Log that a set of metrics could not be registered, such as when another set has already
+/// registered the same names. The server carries on without recording them.
+pub(crate) fn warn_unregistered(name: &str, err: &prometheus::Error) {
+    let message = format!("Unable to register {name} metrics");
+    tracing::warn!(
+        meta.signal_type = "log",
+        event.domain = "ndc",
+        event.name = %message,
+        name = %message,
+        body = %err,
+    );
+}
+
+/// Encode the metrics in the Prometheus text format.
+pub fn encode_metrics(metrics: &Registry) -> Result<String> {
+    let encoder = TextEncoder::new();
+
     let metric_families = &metrics.gather();
 
     encoder
         .encode_to_string(metric_families)
         .map_err(ErrorResponse::from_error)
 }
+
+/// Encode the metrics in the OpenMetrics text format, with exemplars linking the built-in request
+/// duration histogram to traces.
+pub fn encode_openmetrics(metrics: &Registry, request_metrics: Option<&RequestMetrics>) -> String {
+    let mut output = String::new();
+    encode_openmetrics_into(&mut output, metrics, request_metrics);
+    output
+}
+
+fn encode_openmetrics_into(
+    output: &mut String,
+    metrics: &Registry,
+    request_metrics: Option<&RequestMetrics>,
+) {
+    let metric_families = &metrics.gather();
+
+    openmetrics::encode(output, metric_families, |family, labels, upper_bound| {
+        request_metrics?.exemplar(family, labels, upper_bound)
+    });
+}
+
+/// The format in which to encode metrics for a scrape.
+#[derive(Clone, Copy, Debug)]
+pub enum MetricsFormat<'a> {
+    /// The Prometheus text format.
+    Prometheus,
+    /// The OpenMetrics text format, with exemplars from the built-in request metrics.
+    OpenMetrics(Option<&'a RequestMetrics>),
+}
+
+/// Encodes metrics for scrapes into a buffer sized by the previous scrape, so that the output of a
+/// large registry is not reallocated as it grows on every scrape.
+///
+/// Compressed output is encoded from the buffer, which is kept for the next scrape. Uncompressed
+/// output is handed to the response without being copied, and is replaced with an empty buffer
+/// of the same capacity. A scrape which happens while another is being encoded uses a buffer of
+/// its own.
+#[derive(Debug, Default)]
+pub struct MetricsEncoder {
+    buffer: Mutex<String>,
+}
+
+impl MetricsEncoder {
+    /// Encode the metrics in the format, compressing them with the encoding if there is one.
+    pub fn encode(
+        &self,
+        metrics: &Registry,
+        format: MetricsFormat<'_>,
+        encoding: Option<ContentEncoding>,
+    ) -> Result<Bytes> {
+        let mut buffer = self.buffer.try_lock().ok();
+        let shared = buffer.is_some();
+        let mut unshared = String::new();
+        let output = match &mut buffer {
+            Some(buffer) => &mut **buffer,
+            None => &mut unshared,
+        };
+        output.clear();
+        match format {
+            MetricsFormat::Prometheus => TextEncoder::new()
+                .encode_utf8(&metrics.gather(), output)
+                .map_err(ErrorResponse::from_error)?,
+            MetricsFormat::OpenMetrics(request_metrics) => {
+                encode_openmetrics_into(output, metrics, request_metrics);
+            }
+        }
+        if let Some(encoding) = encoding {
+            return encoding
+                .encode(output.as_bytes())
+                .map(Bytes::from)
+                .map_err(ErrorResponse::from_error);
+        }
+        let next = if shared {
+            String::with_capacity(output.len())
+        } else {
+            String::new()
+        };
+        Ok(Bytes::from(std::mem::replace(output, next).into_bytes()))
+    }
+}
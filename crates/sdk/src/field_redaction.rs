@@ -0,0 +1,414 @@
+//! Regulated deployments may not allow sensitive values, such as SQL text, argument values or
+//! authorization headers, to be written to logs or exported with traces. When redaction is
+//! enabled, the values of sensitive fields are stripped or hashed before they leave the process.
+//!
+//! Connectors can declare their own sensitive fields with
+//! [`TracingBuilder::with_sensitive_field`](crate::tracing::TracingBuilder::with_sensitive_field).
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use tracing::field::{DisplayValue, Field, Value, Visit};
+use tracing_subscriber::field::{MakeVisitor, VisitFmt, VisitOutput};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::fnv::fnv1a;
+
+/// The fields which are always redacted when redaction is enabled.
+pub const DEFAULT_SENSITIVE_FIELDS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "http.request.header.authorization",
+    "http.request.header.cookie",
+    "db.statement",
+    "db.query.text",
+    "sql",
+    "arguments",
+    "variables",
+];
+
+/// How the values of sensitive fields are redacted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RedactionMode {
+    /// Replace values with `[redacted]`
+    #[default]
+    Strip,
+    /// Replace values with a hash, so that equal values can be correlated. The hash is not
+    /// cryptographic, so low-entropy values such as passwords should be stripped instead.
+    Hash,
+}
+
+/// Which fields are redacted from logs and traces, and how.
+#[derive(Clone, Debug)]
+pub struct FieldRedaction {
+    mode: RedactionMode,
+    fields: BTreeSet<String>,
+}
+
+impl FieldRedaction {
+    /// Redact the [`DEFAULT_SENSITIVE_FIELDS`].
+    pub fn new(mode: RedactionMode) -> Self {
+        Self {
+            mode,
+            fields: DEFAULT_SENSITIVE_FIELDS
+                .iter()
+                .map(|field| (*field).to_string())
+                .collect(),
+        }
+    }
+
+    /// Also redact this field. Field names are compared case-insensitively.
+    #[must_use]
+    pub fn with_field(mut self, field: impl AsRef<str>) -> Self {
+        self.fields.insert(field.as_ref().to_ascii_lowercase());
+        self
+    }
+
+    pub fn mode(&self) -> RedactionMode {
+        self.mode
+    }
+
+    /// The names of the redacted fields, in lowercase.
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(String::as_str)
+    }
+
+    pub fn is_sensitive(&self, field: &str) -> bool {
+        self.fields.contains(&field.to_ascii_lowercase())
+    }
+
+    /// The redacted form of a value.
+    pub fn redact(&self, value: &str) -> String {
+        match self.mode {
+            RedactionMode::Strip => "[redacted]".to_string(),
+            RedactionMode::Hash => format!("[redacted:{:016x}]", fnv1a(value.as_bytes())),
+        }
+    }
+
+    fn redact_attributes(&self, attributes: &mut [KeyValue]) {
+        for attribute in attributes {
+            if self.is_sensitive(attribute.key.as_str()) {
+                attribute.value = self.redact(&attribute.value.to_string()).into();
+            }
+        }
+    }
+
+    pub(crate) fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(object) => {
+                for (key, value) in object {
+                    if self.is_sensitive(key) {
+                        let text = match &*value {
+                            serde_json::Value::String(text) => text.clone(),
+                            other => other.to_string(),
+                        };
+                        *value = serde_json::Value::String(self.redact(&text));
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    self.redact_json(value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Redacts span attributes and span event attributes before they are exported.
+#[derive(Debug)]
+pub(crate) struct RedactingExporter<E> {
+    inner: E,
+    redaction: Arc<FieldRedaction>,
+}
+
+impl<E> RedactingExporter<E> {
+    pub fn new(inner: E, redaction: Arc<FieldRedaction>) -> Self {
+        Self { inner, redaction }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for RedactingExporter<E> {
+    fn export(
+        &mut self,
+        mut batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        for span in &mut batch {
+            self.redaction.redact_attributes(&mut span.attributes);
+            for event in &mut span.events.events {
+                self.redaction.redact_attributes(&mut event.attributes);
+            }
+        }
+        self.inner.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.inner.force_flush()
+    }
+}
+
+/// Redacts fields in human-readable log lines, wrapping the field formatter of the `pretty` or
+/// `compact` formats.
+pub(crate) struct RedactingFields<M> {
+    inner: M,
+    redaction: Arc<FieldRedaction>,
+}
+
+impl<M> RedactingFields<M> {
+    pub fn new(inner: M, redaction: Arc<FieldRedaction>) -> Self {
+        Self { inner, redaction }
+    }
+}
+
+impl<'a, M> MakeVisitor<Writer<'a>> for RedactingFields<M>
+where
+    M: MakeVisitor<Writer<'a>>,
+    M::Visitor: VisitFmt,
+{
+    type Visitor = RedactingVisitor<M::Visitor>;
+
+    fn make_visitor(&self, target: Writer<'a>) -> Self::Visitor {
+        RedactingVisitor {
+            inner: self.inner.make_visitor(target),
+            redaction: self.redaction.clone(),
+        }
+    }
+}
+
+pub(crate) struct RedactingVisitor<V> {
+    inner: V,
+    redaction: Arc<FieldRedaction>,
+}
+
+impl<V: Visit> Visit for RedactingVisitor<V> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.redaction.is_sensitive(field.name()) {
+            self.inner.record_str(field, &self.redaction.redact(value));
+        } else {
+            self.inner.record_str(field, value);
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        if self.redaction.is_sensitive(field.name()) {
+            self.inner
+                .record_str(field, &self.redaction.redact(&value.to_string()));
+        } else {
+            self.inner.record_error(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if self.redaction.is_sensitive(field.name()) {
+            self.inner
+                .record_str(field, &self.redaction.redact(&format!("{value:?}")));
+        } else {
+            self.inner.record_debug(field, value);
+        }
+    }
+}
+
+impl<V: VisitOutput<fmt::Result>> VisitOutput<fmt::Result> for RedactingVisitor<V> {
+    fn finish(self) -> fmt::Result {
+        self.inner.finish()
+    }
+}
+
+impl<V: VisitFmt> VisitFmt for RedactingVisitor<V> {
+    fn writer(&mut self) -> &mut dyn fmt::Write {
+        self.inner.writer()
+    }
+}
+
+/// Redacts the fields of events in the `pretty` format, which formats them itself rather than
+/// with its field formatter, by formatting a copy of each event with redacted values.
+pub(crate) struct RedactingEvents<E> {
+    inner: E,
+    redaction: Arc<FieldRedaction>,
+}
+
+impl<E> RedactingEvents<E> {
+    pub fn new(inner: E, redaction: Arc<FieldRedaction>) -> Self {
+        Self { inner, redaction }
+    }
+}
+
+/// The most fields a copied event can have. Any further fields are left out.
+const MAX_EVENT_FIELDS: usize = 64;
+
+impl<S, N, E> FormatEvent<S, N> for RedactingEvents<E>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    E: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        writer: Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> fmt::Result {
+        if !event
+            .fields()
+            .any(|field| self.redaction.is_sensitive(field.name()))
+        {
+            return self.inner.format_event(ctx, writer, event);
+        }
+
+        let mut copy = CopyFields {
+            redaction: &self.redaction,
+            fields: vec![],
+        };
+        event.record(&mut copy);
+        let Some((first, _)) = copy.fields.first() else {
+            return self.inner.format_event(ctx, writer, event);
+        };
+        let mut values: [(&Field, Option<&dyn Value>); MAX_EVENT_FIELDS] =
+            [(first, None); MAX_EVENT_FIELDS];
+        for (entry, (field, value)) in values.iter_mut().zip(&copy.fields) {
+            *entry = (field, Some(value.as_value()));
+        }
+        let metadata = event.metadata();
+        let values = metadata.fields().value_set(&values);
+        let redacted = if event.is_contextual() {
+            tracing::Event::new(metadata, &values)
+        } else {
+            tracing::Event::new_child_of(event.parent().cloned(), metadata, &values)
+        };
+        self.inner.format_event(ctx, writer, &redacted)
+    }
+}
+
+/// Copies the fields of an event, redacting sensitive values.
+struct CopyFields<'r> {
+    redaction: &'r FieldRedaction,
+    fields: Vec<(Field, CopiedValue)>,
+}
+
+impl CopyFields<'_> {
+    fn push(&mut self, field: &Field, value: CopiedValue) {
+        let value = if self.redaction.is_sensitive(field.name()) {
+            CopiedValue::Str(self.redaction.redact(&value.to_string()))
+        } else {
+            value
+        };
+        self.fields.push((field.clone(), value));
+    }
+}
+
+impl Visit for CopyFields<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, CopiedValue::F64(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, CopiedValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, CopiedValue::U64(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, CopiedValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, CopiedValue::Str(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(
+            field,
+            CopiedValue::Formatted(tracing::field::display(format!("{value:?}"))),
+        );
+    }
+}
+
+enum CopiedValue {
+    F64(f64),
+    I64(i64),
+    U64(u64),
+    Bool(bool),
+    Str(String),
+    Formatted(DisplayValue<String>),
+}
+
+impl CopiedValue {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            Self::F64(value) => value,
+            Self::I64(value) => value,
+            Self::U64(value) => value,
+            Self::Bool(value) => value,
+            Self::Str(value) => value,
+            Self::Formatted(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for CopiedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::F64(value) => value.fmt(f),
+            Self::I64(value) => value.fmt(f),
+            Self::U64(value) => value.fmt(f),
+            Self::Bool(value) => value.fmt(f),
+            Self::Str(value) => value.fmt(f),
+            Self::Formatted(value) => fmt::Display::fmt(value, f),
+        }
+    }
+}
+
+/// Redacts fields in JSON log lines, including the fields of the current spans, by formatting
+/// each line and redacting it before it is written.
+pub(crate) struct RedactingJson<E> {
+    inner: E,
+    redaction: Arc<FieldRedaction>,
+}
+
+impl<E> RedactingJson<E> {
+    pub fn new(inner: E, redaction: Arc<FieldRedaction>) -> Self {
+        Self { inner, redaction }
+    }
+}
+
+impl<S, N, E> FormatEvent<S, N> for RedactingJson<E>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    E: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> fmt::Result {
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(mut value) => {
+                self.redaction.redact_json(&mut value);
+                writeln!(writer, "{value}")
+            }
+            // write anything we do not understand unchanged, rather than dropping it
+            Err(_) => writer.write_str(&line),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! The 64-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash.
+//!
+//! Unlike the standard library's hasher, it is the same for every build, process and platform, so
+//! hashes can be written to files, or compared between replicas.
+
+use serde::Serialize;
+
+/// Hash the bytes with 64-bit FNV-1a.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The canonical JSON representation of a request, in which the keys of objects are sorted, so
+/// that equal requests have equal bytes, and so equal hashes.
+pub(crate) fn canonical_request_bytes(request: &impl Serialize) -> serde_json::Result<Vec<u8>> {
+    // converting to a value sorts the keys of objects
+    serde_json::to_value(request).and_then(|request| serde_json::to_vec(&request))
+}
@@ -1,9 +1,17 @@
 //! We want errors returned from failed json extractors to be formatted as json as well.
 
-use axum::extract;
-use axum::response::IntoResponse;
+use async_trait::async_trait;
+use axum::body::{Bytes, Full, HttpBody};
+use axum::extract::{self, FromRequest};
+use axum::http::request::Parts;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use axum_extra::extract::WithRejection;
 use ndc_models as models;
 
+use crate::json_request::JsonRequest;
+
 pub struct JsonRejection(extract::rejection::JsonRejection);
 
 impl From<extract::rejection::JsonRejection> for JsonRejection {
@@ -31,3 +39,48 @@ impl IntoResponse for JsonRejection {
         (rejection.status(), extract::Json(payload)).into_response()
     }
 }
+
+/// Extracts a JSON request body without deserializing it, so that the connector can forward the
+/// bytes as they were received. The value is deserialized when it is first needed.
+///
+/// The body is rejected as it would be by the `Json` extractor if the content type is not JSON or
+/// the body is not syntactically valid JSON.
+pub(crate) struct JsonBody<A>(pub JsonRequest<A>);
+
+#[async_trait]
+impl<S, B, A> FromRequest<S, B> for JsonBody<A>
+where
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    A: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Response> {
+        let WithRejection(request, _) =
+            WithRejection::<JsonRequest<A>, JsonRejection>::from_request(request, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+        Ok(Self(request))
+    }
+}
+
+/// Extract a request body which has already been read, without deserializing it.
+pub(crate) async fn json_request<A, S>(
+    parts: Parts,
+    bytes: Bytes,
+    state: &S,
+) -> Result<JsonRequest<A>, Response>
+where
+    A: Send,
+    S: Send + Sync,
+{
+    let JsonBody(request) = JsonBody::from_request(
+        Request::from_parts(parts, Full::<Bytes>::from(bytes)),
+        state,
+    )
+    .await?;
+    Ok(request)
+}
@@ -0,0 +1,50 @@
+//! Serving a connector on AWS Lambda, with the `lambda` feature.
+//!
+//! Lambda invokes the function through its runtime API, rather than by connecting to a port, so
+//! these entry points serve the same router as `serve` through `lambda_http`. The router is
+//! configured with the same environment variables as `serve`, such as
+//! `HASURA_CONFIGURATION_DIRECTORY` and `HASURA_SERVICE_TOKEN_SECRET`. There are no commands, as
+//! Lambda does not pass any arguments.
+//!
+//! To keep cold starts short, only the configuration is read when the function starts. The
+//! connector state is initialized by the first request which needs it.
+//!
+//! Lambda freezes the function between invocations, so traces exported in batches may be delayed
+//! until the next invocation.
+
+use crate::connector::{Connector, ConnectorSetup};
+use crate::default_main::{serve_lambda, MainOptions};
+use crate::exit_code::CommandError;
+
+/// A main function for a connector deployed on AWS Lambda.
+///
+/// See the [module documentation](self) for further details.
+pub async fn lambda_main<Setup>() -> Result<(), CommandError>
+where
+    Setup: ConnectorSetup + Default,
+    Setup::Connector: Connector + 'static,
+{
+    lambda_main_with(Setup::default()).await
+}
+
+/// A main function for a connector deployed on AWS Lambda, with a non-default setup.
+pub async fn lambda_main_with<Setup>(setup: Setup) -> Result<(), CommandError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    lambda_main_with_options(setup, MainOptions::default()).await
+}
+
+/// A main function for a connector deployed on AWS Lambda, with customizations such as
+/// additional tracing layers. Options which only apply to other commands are ignored.
+pub async fn lambda_main_with_options<Setup>(
+    setup: Setup,
+    options: MainOptions,
+) -> Result<(), CommandError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    serve_lambda(setup, options).await
+}
@@ -1,10 +1,59 @@
+pub mod baggage;
+#[cfg(feature = "ndc-test")]
+mod bench_report;
+pub mod body_logging;
+#[cfg(feature = "ndc-test")]
+mod capability_coverage;
 pub mod check_health;
+pub mod datadog;
 pub mod default_main;
+pub mod error_metrics;
+pub mod error_redaction;
+pub mod exit_code;
+mod export_retry;
 pub mod fetch_metrics;
+pub mod field_redaction;
+mod fnv;
 pub mod json_rejection;
+#[cfg(feature = "lambda")]
+pub mod lambda_main;
+pub mod log_file;
+pub mod log_sampling;
+mod manifest;
+pub mod multi_connector;
+mod openmetrics;
+mod persisted_queries;
+mod precompressed;
+pub mod proxy;
+pub mod query_cache;
+mod request_limits;
+pub mod request_metrics;
+mod schema_publication;
+mod shutdown;
+mod snapshot_recording;
+#[cfg(feature = "ndc-test")]
+mod snapshots;
+#[cfg(feature = "ndc-test")]
+pub mod test_cases;
+#[cfg(feature = "ndc-test")]
+pub mod test_reporter;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod tracing;
+mod watch;
 
 pub use ndc_models as models;
+pub use ndc_sdk_core::client;
+pub use ndc_sdk_core::clock;
+pub use ndc_sdk_core::configuration;
 pub use ndc_sdk_core::connector;
+pub use ndc_sdk_core::connector_version;
+pub use ndc_sdk_core::health;
+pub use ndc_sdk_core::json_request;
 pub use ndc_sdk_core::json_response;
+pub use ndc_sdk_core::metrics_prefix;
+pub use ndc_sdk_core::query_response_writer;
+pub use ndc_sdk_core::startup_metrics;
 pub use ndc_sdk_core::state;
+pub use ndc_sdk_core::throttle;
+pub use ndc_sdk_core::version;
@@ -0,0 +1,226 @@
+//! Writing logs to a file, for deployments without a log shipper.
+//!
+//! The file is rotated when it grows larger than a maximum size, or when it is older than the
+//! rotation interval. Rotated files are renamed with a numeric suffix, such as `connector.log.1`
+//! for the most recent, and the oldest are deleted to keep at most `max_files` of them.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Where and how to write logs to a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFile {
+    /// The path of the current log file.
+    pub path: PathBuf,
+    /// Rotate the file once it grows larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate the file once it has been written to for this long.
+    pub rotation_interval: Option<Duration>,
+    /// The number of rotated files to keep.
+    pub max_files: usize,
+}
+
+impl LogFile {
+    /// A log file which is never rotated.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: None,
+            rotation_interval: None,
+            max_files: 5,
+        }
+    }
+}
+
+/// A writer for the log file, which rotates it as required.
+pub(crate) struct RotatingFile {
+    config: LogFile,
+    current: Mutex<CurrentFile>,
+}
+
+struct CurrentFile {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    pub(crate) fn open(config: LogFile) -> io::Result<Self> {
+        let current = CurrentFile::open(&config.path)?;
+        Ok(Self {
+            config,
+            current: Mutex::new(current),
+        })
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self
+            .current
+            .lock()
+            .map_err(|_| io::Error::other("the log file lock is poisoned"))?;
+        let too_large = self
+            .config
+            .max_size
+            .is_some_and(|max_size| current.size > 0 && current.size + buf.len() as u64 > max_size);
+        let too_old = self
+            .config
+            .rotation_interval
+            .is_some_and(|interval| current.opened.elapsed() >= interval);
+        if too_large || too_old {
+            self.rotate()?;
+            *current = CurrentFile::open(&self.config.path)?;
+        }
+        let written = current.file.write(buf)?;
+        current.size += written as u64;
+        Ok(written)
+    }
+
+    /// Shift the rotated files along, dropping the oldest, and move the current file to `.1`.
+    fn rotate(&self) -> io::Result<()> {
+        let path = &self.config.path;
+        if self.config.max_files == 0 {
+            return remove_if_present(path);
+        }
+        remove_if_present(&rotated(path, self.config.max_files))?;
+        for index in (1..self.config.max_files).rev() {
+            rename_if_present(&rotated(path, index), &rotated(path, index + 1))?;
+        }
+        rename_if_present(path, &rotated(path, 1))
+    }
+}
+
+impl CurrentFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn rename_if_present(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// A handle used to write a single log line.
+pub(crate) struct RotatingFileWriter<'a>(&'a RotatingFile);
+
+impl Write for RotatingFileWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingFileWriter(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{rotated, LogFile, RotatingFile};
+
+    /// A log file in a new temporary directory, which is rotated once it exceeds 10 bytes.
+    fn log_file(name: &str, max_files: usize) -> LogFile {
+        let directory =
+            std::env::temp_dir().join(format!("ndc-sdk-log-file-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        LogFile {
+            max_size: Some(10),
+            max_files,
+            ..LogFile::new(directory.join("connector.log"))
+        }
+    }
+
+    fn contents(path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn rotates_when_the_file_would_exceed_the_maximum_size() {
+        let config = log_file("max-size", 5);
+        let path = config.path.clone();
+        let file = RotatingFile::open(config).unwrap();
+        // exactly the maximum size is allowed
+        file.write(b"first\n").unwrap();
+        file.write(b"two\n").unwrap();
+        assert_eq!(contents(&path).as_deref(), Some("first\ntwo\n"));
+        assert_eq!(contents(&rotated(&path, 1)), None);
+
+        file.write(b"three\n").unwrap();
+        assert_eq!(contents(&path).as_deref(), Some("three\n"));
+        assert_eq!(
+            contents(&rotated(&path, 1)).as_deref(),
+            Some("first\ntwo\n")
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keeps_at_most_the_maximum_number_of_files() {
+        let config = log_file("max-files", 2);
+        let path = config.path.clone();
+        let file = RotatingFile::open(config).unwrap();
+        for line in ["one  line\n", "two  line\n", "three line\n", "four line\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(contents(&path).as_deref(), Some("four line\n"));
+        assert_eq!(
+            contents(&rotated(&path, 1)).as_deref(),
+            Some("three line\n")
+        );
+        assert_eq!(contents(&rotated(&path, 2)).as_deref(), Some("two  line\n"));
+        assert_eq!(contents(&rotated(&path, 3)), None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn discards_the_file_when_no_rotated_files_are_kept() {
+        let config = log_file("no-files", 0);
+        let path = config.path.clone();
+        let file = RotatingFile::open(config).unwrap();
+        file.write(b"one  line\n").unwrap();
+        file.write(b"two  line\n").unwrap();
+        assert_eq!(contents(&path).as_deref(), Some("two  line\n"));
+        assert_eq!(contents(&rotated(&path, 1)), None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
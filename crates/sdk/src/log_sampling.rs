@@ -0,0 +1,95 @@
+//! Rate limiting for repetitive log events, so that a misconfigured client cannot flood the
+//! logging pipeline with the same error.
+//!
+//! At most a fixed number of events of each kind are logged per minute. The rest are counted in
+//! the `ndc_sdk_suppressed_log_events_total` metric, and the next event of that kind which is
+//! logged reports how many were suppressed before it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use prometheus::{IntCounterVec, Registry};
+
+use crate::metrics_prefix::opts;
+
+const WINDOW_SECONDS: u64 = 60;
+
+/// Decides which events of each kind are logged.
+#[derive(Debug)]
+pub struct LogSampler {
+    max_per_minute: Option<u32>,
+    windows: Mutex<HashMap<&'static str, Window>>,
+    suppressed_total: Option<IntCounterVec>,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    logged: u32,
+    /// Events suppressed since the last one which was logged.
+    suppressed: u64,
+}
+
+impl LogSampler {
+    /// A sampler which logs at most `max_per_minute` events of each kind per minute, or every
+    /// event if there is no maximum.
+    pub fn new(max_per_minute: Option<u32>) -> Self {
+        Self {
+            max_per_minute,
+            windows: Mutex::new(HashMap::new()),
+            suppressed_total: None,
+        }
+    }
+
+    /// Create a sampler, like [`LogSampler::new`], which counts the events it suppresses in a
+    /// metric registered with the given registry.
+    pub fn register(
+        registry: &Registry,
+        max_per_minute: Option<u32>,
+    ) -> Result<Self, prometheus::Error> {
+        let suppressed_total = IntCounterVec::new(
+            opts(
+                "ndc_sdk_suppressed_log_events_total",
+                "Total number of log events which were not logged because too many of the same kind were logged recently, by kind",
+            ),
+            &["kind"],
+        )?;
+        registry.register(Box::new(suppressed_total.clone()))?;
+        Ok(Self {
+            suppressed_total: Some(suppressed_total),
+            ..Self::new(max_per_minute)
+        })
+    }
+
+    /// Whether an event of this kind should be logged. If so, this returns the number of events
+    /// of the same kind which were suppressed since the last one which was logged.
+    pub fn sample(&self, kind: &'static str) -> Option<u64> {
+        let Some(max_per_minute) = self.max_per_minute else {
+            return Some(0);
+        };
+        // if the lock is poisoned, it is better to log too much than nothing at all
+        let Ok(mut windows) = self.windows.lock() else {
+            return Some(0);
+        };
+        let window = windows.entry(kind).or_insert_with(|| Window {
+            started: Instant::now(),
+            logged: 0,
+            suppressed: 0,
+        });
+        if window.started.elapsed() >= Duration::from_secs(WINDOW_SECONDS) {
+            window.started = Instant::now();
+            window.logged = 0;
+        }
+        if window.logged < max_per_minute {
+            window.logged += 1;
+            Some(std::mem::take(&mut window.suppressed))
+        } else {
+            window.suppressed += 1;
+            if let Some(suppressed_total) = &self.suppressed_total {
+                suppressed_total.with_label_values(&[kind]).inc();
+            }
+            None
+        }
+    }
+}
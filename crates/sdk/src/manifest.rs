@@ -0,0 +1,146 @@
+//! Packaging metadata for the connector hub and DDN, derived from the connector itself, so that it
+//! does not have to be kept in sync with the code by hand.
+//!
+//! The manifest describes the connector's name and version, the capabilities which it advertises,
+//! the environment variables which the server reads, and how to run it in a container.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::connector::{Connector, ErrorResponse, Result};
+use crate::version::VersionInfo;
+
+/// Where the configuration is conventionally mounted in a connector's container.
+const CONFIGURATION_DIRECTORY: &str = "/etc/connector";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'static str>,
+    version: &'static str,
+    sdk_version: &'static str,
+    ndc_spec_version: &'static str,
+    /// The advertised capabilities, by their paths in the capabilities object, such as
+    /// `query.aggregates`.
+    capabilities: Vec<String>,
+    environment_variables: Vec<EnvironmentVariable>,
+    docker: DockerHints,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvironmentVariable {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerHints {
+    entrypoint: Vec<String>,
+    healthcheck: Vec<String>,
+    configuration_directory: &'static str,
+    port: u16,
+    environment: BTreeMap<&'static str, String>,
+}
+
+/// Print the manifest as JSON, with the environment variables read by the global options and the
+/// `serve` subcommand of the command line.
+pub(crate) async fn print_manifest<C: Connector, W: Write>(
+    version: &VersionInfo,
+    command: &clap::Command,
+    mut writer: W,
+) -> Result<()> {
+    let capabilities =
+        serde_json::to_value(C::get_capabilities().await).map_err(ErrorResponse::from_error)?;
+    let mut capability_paths = vec![];
+    advertised(&capabilities, "", &mut capability_paths);
+
+    let executable = executable_name(version);
+    let manifest = Manifest {
+        name: version.connector.as_ref().map(|connector| connector.name),
+        version: version.short(),
+        sdk_version: version.sdk_version,
+        ndc_spec_version: version.ndc_spec_version,
+        capabilities: capability_paths,
+        environment_variables: environment_variables(command),
+        docker: DockerHints {
+            entrypoint: vec![executable.clone(), "serve".to_owned()],
+            healthcheck: vec![executable, "check-health".to_owned()],
+            configuration_directory: CONFIGURATION_DIRECTORY,
+            port: 8080,
+            environment: BTreeMap::from([(
+                "HASURA_CONFIGURATION_DIRECTORY",
+                CONFIGURATION_DIRECTORY.to_owned(),
+            )]),
+        },
+    };
+    serde_json::to_writer_pretty(&mut writer, &manifest).map_err(ErrorResponse::from_error)?;
+    writeln!(writer).map_err(ErrorResponse::from_error)?;
+    Ok(())
+}
+
+/// Collect the paths of the capabilities which are present, as nested capabilities are only
+/// present if their parents are.
+fn advertised(value: &serde_json::Value, prefix: &str, paths: &mut Vec<String>) {
+    let Some(object) = value.as_object() else {
+        return;
+    };
+    for (key, value) in object {
+        if value.is_null() {
+            continue;
+        }
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        paths.push(path.clone());
+        advertised(value, &path, paths);
+    }
+}
+
+fn environment_variables(command: &clap::Command) -> Vec<EnvironmentVariable> {
+    let serve_arguments = command
+        .find_subcommand("serve")
+        .into_iter()
+        .flat_map(clap::Command::get_arguments);
+    let mut variables = BTreeMap::new();
+    for argument in command.get_arguments().chain(serve_arguments) {
+        let Some(name) = argument.get_env() else {
+            continue;
+        };
+        let name = name.to_string_lossy().into_owned();
+        let default = argument
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy())
+            .collect::<Vec<_>>();
+        variables
+            .entry(name.clone())
+            .or_insert_with(|| EnvironmentVariable {
+                name,
+                description: argument.get_help().map(ToString::to_string),
+                required: argument.is_required_set(),
+                default: (!default.is_empty()).then(|| default.join(",")),
+            });
+    }
+    variables.into_values().collect()
+}
+
+/// The name of the executable in the container, which is assumed to be that of the running
+/// executable, or the connector's name if it cannot be determined.
+fn executable_name(version: &VersionInfo) -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .or_else(|| Some(version.connector.as_ref()?.name.to_owned()))
+        .unwrap_or_else(|| "connector".to_owned())
+}
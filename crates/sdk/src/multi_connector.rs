@@ -0,0 +1,237 @@
+//! Hosting several connectors in one server process, for platform operators who run many small
+//! connectors and want to consolidate them.
+//!
+//! Each connector is served under its own path prefix, with the usual routes, so a connector
+//! mounted at `/orders` serves `/orders/query`, `/orders/schema`, `/orders/health` and so on.
+//! Every connector is configured with the same [`RouterOptions`], so they share the service token,
+//! limits, error redaction and tracing settings. Metrics are collected separately for each
+//! connector, and served at its own `/metrics` route.
+//!
+//! The server also serves `/health`, which succeeds only if every connector is ready, and
+//! `/metrics`, which serves the metrics of every connector in the Prometheus text format, with a
+//! `connector` label holding the prefix of the connector which recorded them, so that one scrape
+//! covers the whole process.
+//!
+//! ```ignore
+//! let router = MultiConnectorRouter::new(RouterOptions::default())
+//!     .mount("/orders", init_server_state(OrdersSetup, "config/orders").await?)
+//!     .mount("/users", init_server_state(UsersSetup, "config/users").await?)
+//!     .into_router();
+//! axum::Server::bind(&address).serve(router.into_make_service()).await?;
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Extension;
+use prometheus::proto::{LabelPair, MetricFamily};
+use prometheus::TextEncoder;
+
+use crate::connector::{Connector, ErrorResponse, Result};
+use crate::default_main::{create_router_with_options, RouterOptions};
+use crate::fetch_metrics::refresh_metrics;
+use crate::health::HealthReport;
+use crate::state::ServerState;
+use crate::throttle::Throttle;
+
+/// Checks the readiness of a mounted connector, as its `/health` route would.
+type ReadinessCheck =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<HealthReport>> + Send>> + Send + Sync>;
+
+/// Refreshes and gathers the metrics of a mounted connector, as its `/metrics` route would.
+type MetricsSource =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<Vec<MetricFamily>>> + Send>> + Send + Sync>;
+
+/// A connector which has been mounted under a prefix.
+struct Mounted {
+    prefix: String,
+    readiness_check: ReadinessCheck,
+    metrics_source: MetricsSource,
+}
+
+/// Builds a router which serves several connectors, each under its own path prefix.
+pub struct MultiConnectorRouter {
+    options: RouterOptions,
+    router: axum::Router,
+    mounted: Vec<Mounted>,
+}
+
+impl MultiConnectorRouter {
+    /// Create a router with no connectors, which will configure every connector with the options.
+    pub fn new(options: RouterOptions) -> Self {
+        Self {
+            options,
+            router: axum::Router::new(),
+            mounted: vec![],
+        }
+    }
+
+    /// Serve a connector under the path prefix, such as `/orders`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix does not start with `/`, ends with `/`, or is already in use.
+    #[must_use]
+    pub fn mount<C>(mut self, prefix: &str, state: ServerState<C>) -> Self
+    where
+        C: Connector + 'static,
+    {
+        assert!(
+            prefix.starts_with('/') && !prefix.ends_with('/'),
+            "invalid connector prefix {prefix:?}: it must start with / and not end with /"
+        );
+        assert!(
+            self.prefixes().all(|mounted| mounted != prefix),
+            "a connector is already mounted at {prefix:?}"
+        );
+        let check_state = state.clone();
+        let readiness_check: ReadinessCheck = Arc::new(move || {
+            let state = check_state.clone();
+            Box::pin(async move {
+                C::get_health_report(state.configuration(), state.state().await?).await
+            })
+        });
+        let metrics_state = state.clone();
+        let throttle = Arc::new(Throttle::new(self.options.metrics_refresh_interval));
+        let metrics_source: MetricsSource = Arc::new(move || {
+            let state = metrics_state.clone();
+            let throttle = throttle.clone();
+            Box::pin(async move {
+                refresh_metrics::<C>(&throttle, state.configuration(), state.state().await?)
+                    .await?;
+                Ok(state.metrics().gather())
+            })
+        });
+        self.mounted.push(Mounted {
+            prefix: prefix.to_owned(),
+            readiness_check,
+            metrics_source,
+        });
+        self.router = self.router.nest(
+            prefix,
+            create_router_with_options(state, self.options.clone()),
+        );
+        self
+    }
+
+    /// The prefixes of the mounted connectors, in the order in which they were mounted.
+    pub fn prefixes(&self) -> impl Iterator<Item = &str> {
+        self.mounted.iter().map(|mounted| mounted.prefix.as_str())
+    }
+
+    /// Build the router, to be served by an axum server.
+    pub fn into_router(self) -> axum::Router {
+        self.router
+            .route("/health", get(get_health))
+            .route("/metrics", get(get_metrics))
+            .layer(Extension(Arc::new(self.mounted)))
+    }
+}
+
+/// Check the readiness of every connector, failing with the first connector which is not ready.
+async fn get_health(Extension(mounted): Extension<Arc<Vec<Mounted>>>) -> Result<StatusCode> {
+    for Mounted {
+        prefix,
+        readiness_check,
+        ..
+    } in mounted.iter()
+    {
+        let report = readiness_check().await.map_err(|err| {
+            ErrorResponse::new(
+                err.status_code(),
+                format!("the connector at {prefix} is not ready: {}", err.message()),
+                err.details().clone(),
+            )
+        })?;
+        if !report.is_healthy() {
+            return Err(ErrorResponse::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("the connector at {prefix} is not healthy"),
+                serde_json::to_value(report).unwrap_or_default(),
+            ));
+        }
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Serve the metrics of every connector, labelled with the prefix of the connector which recorded
+/// them. Metrics of the same name from different connectors are merged into one family.
+async fn get_metrics(
+    Extension(mounted): Extension<Arc<Vec<Mounted>>>,
+) -> Result<axum::response::Response> {
+    let mut families: Vec<MetricFamily> = vec![];
+    for Mounted {
+        prefix,
+        metrics_source,
+        ..
+    } in mounted.iter()
+    {
+        let gathered = metrics_source().await.map_err(|err| {
+            ErrorResponse::new(
+                err.status_code(),
+                format!(
+                    "the metrics of the connector at {prefix} could not be fetched: {}",
+                    err.message()
+                ),
+                err.details().clone(),
+            )
+        })?;
+        merge_metrics(&mut families, prefix, gathered);
+    }
+    let body = TextEncoder::new()
+        .encode_to_string(&families)
+        .map_err(ErrorResponse::from_error)?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response())
+}
+
+/// Label the metrics gathered from the connector at the prefix, and add them to the families.
+fn merge_metrics(families: &mut Vec<MetricFamily>, prefix: &str, gathered: Vec<MetricFamily>) {
+    for mut family in gathered {
+        for metric in family.mut_metric().iter_mut() {
+            let mut label = LabelPair::default();
+            label.set_name("connector".to_owned());
+            label.set_value(prefix.to_owned());
+            metric.mut_label().push(label);
+        }
+        match families
+            .iter_mut()
+            .find(|existing| existing.get_name() == family.get_name())
+        {
+            Some(existing) => existing.mut_metric().extend(family.take_metric()),
+            None => families.push(family),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{IntCounter, Registry};
+
+    use super::*;
+
+    #[test]
+    fn labels_and_merges_the_metrics_of_each_connector() {
+        let mut families = vec![];
+        for (prefix, count) in [("/orders", 2), ("/users", 3)] {
+            let registry = Registry::new();
+            let counter = IntCounter::new("queries_total", "Queries").unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter.inc_by(count);
+            merge_metrics(&mut families, prefix, registry.gather());
+        }
+
+        assert_eq!(
+            TextEncoder::new().encode_to_string(&families).unwrap(),
+            concat!(
+                "# HELP queries_total Queries\n",
+                "# TYPE queries_total counter\n",
+                "queries_total{connector=\"/orders\"} 2\n",
+                "queries_total{connector=\"/users\"} 3\n",
+            )
+        );
+    }
+}
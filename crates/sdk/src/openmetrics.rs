@@ -0,0 +1,322 @@
+//! An encoder for the [OpenMetrics text format](https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md).
+//!
+//! The `prometheus` crate only encodes the older Prometheus text format, which cannot carry
+//! exemplars, so this is used when a scraper asks for OpenMetrics.
+
+use std::fmt::Write as _;
+
+use axum::http::{header, HeaderMap};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+
+/// The content type of OpenMetrics responses.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// A sample linked to a trace, attached to a histogram bucket.
+#[derive(Clone, Debug)]
+pub struct Exemplar {
+    pub trace_id: String,
+    pub value: f64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: f64,
+}
+
+/// Whether the request asks for OpenMetrics rather than the Prometheus text format.
+pub fn accepts_openmetrics(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            media_range.split(';').next().is_some_and(|media_type| {
+                media_type
+                    .trim()
+                    .eq_ignore_ascii_case("application/openmetrics-text")
+            })
+        })
+}
+
+/// Encode the metric families into the output, attaching the exemplars returned by `exemplar` for each histogram
+/// bucket, given the family name, the labels and the upper bound of the bucket.
+pub fn encode(
+    output: &mut String,
+    families: &[MetricFamily],
+    exemplar: impl Fn(&str, &[LabelPair], f64) -> Option<Exemplar>,
+) {
+    for family in families {
+        let name = family.get_name();
+        let (family_name, type_name) = match family.get_field_type() {
+            // counter samples have a `_total` suffix, which the family name must not have
+            MetricType::COUNTER => (name.strip_suffix("_total").unwrap_or(name), "counter"),
+            MetricType::GAUGE => (name, "gauge"),
+            MetricType::HISTOGRAM => (name, "histogram"),
+            MetricType::SUMMARY => (name, "summary"),
+            MetricType::UNTYPED => (name, "unknown"),
+        };
+        let _ = writeln!(output, "# TYPE {family_name} {type_name}");
+        let _ = writeln!(output, "# HELP {family_name} {}", escape(family.get_help()));
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let sample_name = format!("{family_name}_total");
+                    write_sample(
+                        output,
+                        &sample_name,
+                        labels,
+                        None,
+                        metric.get_counter().get_value(),
+                        metric,
+                    );
+                }
+                MetricType::GAUGE => {
+                    write_sample(
+                        output,
+                        name,
+                        labels,
+                        None,
+                        metric.get_gauge().get_value(),
+                        metric,
+                    );
+                }
+                MetricType::UNTYPED => {
+                    write_sample(
+                        output,
+                        name,
+                        labels,
+                        None,
+                        metric.get_untyped().get_value(),
+                        metric,
+                    );
+                }
+                MetricType::HISTOGRAM => {
+                    write_histogram(output, name, metric, &exemplar);
+                }
+                MetricType::SUMMARY => {
+                    write_summary(output, name, metric);
+                }
+            }
+        }
+    }
+    output.push_str("# EOF\n");
+}
+
+fn write_histogram(
+    output: &mut String,
+    name: &str,
+    metric: &Metric,
+    exemplar: &impl Fn(&str, &[LabelPair], f64) -> Option<Exemplar>,
+) {
+    let labels = metric.get_label();
+    let histogram = metric.get_histogram();
+    let bucket_name = format!("{name}_bucket");
+    let buckets = histogram
+        .get_bucket()
+        .iter()
+        .map(|bucket| (bucket.get_upper_bound(), bucket.get_cumulative_count()))
+        // the `+Inf` bucket is implicit in the protobuf format, but required here
+        .chain(
+            (!histogram
+                .get_bucket()
+                .last()
+                .is_some_and(|bucket| bucket.get_upper_bound().is_infinite()))
+            .then_some((f64::INFINITY, histogram.get_sample_count())),
+        );
+    for (upper_bound, count) in buckets {
+        write_labels_and_value(
+            output,
+            &bucket_name,
+            labels,
+            Some(("le", &format_float(upper_bound))),
+            &count.to_string(),
+        );
+        if let Some(exemplar) = exemplar(name, labels, upper_bound) {
+            let _ = write!(
+                output,
+                " # {{trace_id=\"{}\"}} {} {}",
+                escape(&exemplar.trace_id),
+                format_float(exemplar.value),
+                format_float(exemplar.timestamp),
+            );
+        }
+        output.push('\n');
+    }
+    write_sample(
+        output,
+        &format!("{name}_sum"),
+        labels,
+        None,
+        histogram.get_sample_sum(),
+        metric,
+    );
+    write_labels_and_value(
+        output,
+        &format!("{name}_count"),
+        labels,
+        None,
+        &histogram.get_sample_count().to_string(),
+    );
+    output.push('\n');
+}
+
+fn write_summary(output: &mut String, name: &str, metric: &Metric) {
+    let labels = metric.get_label();
+    let summary = metric.get_summary();
+    for quantile in summary.get_quantile() {
+        write_sample(
+            output,
+            name,
+            labels,
+            Some(("quantile", &format_float(quantile.get_quantile()))),
+            quantile.get_value(),
+            metric,
+        );
+    }
+    write_sample(
+        output,
+        &format!("{name}_sum"),
+        labels,
+        None,
+        summary.get_sample_sum(),
+        metric,
+    );
+    write_labels_and_value(
+        output,
+        &format!("{name}_count"),
+        labels,
+        None,
+        &summary.get_sample_count().to_string(),
+    );
+    output.push('\n');
+}
+
+fn write_sample(
+    output: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    additional_label: Option<(&str, &str)>,
+    value: f64,
+    metric: &Metric,
+) {
+    write_labels_and_value(output, name, labels, additional_label, &format_float(value));
+    if metric.get_timestamp_ms() != 0 {
+        // OpenMetrics timestamps are in seconds
+        let _ = write!(output, " {}", metric.get_timestamp_ms() / 1000);
+    }
+    output.push('\n');
+}
+
+fn write_labels_and_value(
+    output: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    additional_label: Option<(&str, &str)>,
+    value: &str,
+) {
+    output.push_str(name);
+    let labels = labels
+        .iter()
+        .map(|label| (label.get_name(), label.get_value()))
+        .chain(additional_label)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>();
+    if !labels.is_empty() {
+        let _ = write!(output, "{{{}}}", labels.join(","));
+    }
+    let _ = write!(output, " {value}");
+}
+
+fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() && value > 0.0 {
+        "+Inf".to_string()
+    } else if value.is_infinite() {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escape help text and label values.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+
+    use super::{encode, Exemplar};
+
+    #[test]
+    fn encodes_counters_with_a_total_suffix_and_escaped_labels() {
+        let registry = Registry::new();
+        let counter = IntCounterVec::new(
+            Opts::new("requests_total", "Requests, by \"path\"\nand more"),
+            &["path"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter.with_label_values(&[r#"/a"b\c"#]).inc_by(3);
+
+        let mut output = String::new();
+        encode(&mut output, &registry.gather(), |_, _, _| None);
+        assert_eq!(
+            output,
+            concat!(
+                "# TYPE requests counter\n",
+                "# HELP requests Requests, by \\\"path\\\"\\nand more\n",
+                "requests_total{path=\"/a\\\"b\\\\c\"} 3\n",
+                "# EOF\n",
+            )
+        );
+    }
+
+    #[test]
+    fn encodes_histograms_with_an_infinite_bucket_and_exemplars() {
+        let registry = Registry::new();
+        let histogram = HistogramVec::new(
+            HistogramOpts::new("duration", "Duration").buckets(vec![0.1, 1.0]),
+            &["route"],
+        )
+        .unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        histogram.with_label_values(&["/query"]).observe(0.5);
+
+        let mut output = String::new();
+        encode(
+            &mut output,
+            &registry.gather(),
+            |name, labels, upper_bound| {
+                assert_eq!(name, "duration");
+                assert_eq!(labels[0].get_value(), "/query");
+                // only the `1` bucket, which is the first to contain the observation
+                (0.5..f64::INFINITY)
+                    .contains(&upper_bound)
+                    .then(|| Exemplar {
+                        trace_id: "0af7651916cd43dd8448eb211c80319c".to_string(),
+                        value: 0.5,
+                        timestamp: 1_700_000_000.25,
+                    })
+            },
+        );
+        assert_eq!(
+            output,
+            concat!(
+                "# TYPE duration histogram\n",
+                "# HELP duration Duration\n",
+                "duration_bucket{route=\"/query\",le=\"0.1\"} 0\n",
+                "duration_bucket{route=\"/query\",le=\"1\"} 1",
+                " # {trace_id=\"0af7651916cd43dd8448eb211c80319c\"} 0.5 1700000000.25\n",
+                "duration_bucket{route=\"/query\",le=\"+Inf\"} 1\n",
+                "duration_sum{route=\"/query\"} 0.5\n",
+                "duration_count{route=\"/query\"} 1\n",
+                "# EOF\n",
+            )
+        );
+    }
+}
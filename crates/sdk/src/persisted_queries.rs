@@ -0,0 +1,351 @@
+//! Persisted queries, so that large queries which recur can be sent once, and then invoked by
+//! their hash.
+//!
+//! A query request is registered by sending it to `POST /persisted-queries`, which responds with
+//! its hash, the hash of its canonical JSON representation. It can then be invoked by sending a
+//! compact body to `/query`, optionally replacing its variables:
+//!
+//! ```json
+//! { "persisted": "3f0c9a1e5b2d7c84", "variables": [{ "id": 1 }] }
+//! ```
+//!
+//! Persisted queries are listed by `GET /persisted-queries`, and evicted by
+//! `DELETE /persisted-queries/{hash}`. These endpoints require the service token, like `/query`.
+//! When the maximum number of persisted queries is reached, registering another evicts the one
+//! which was registered first.
+//!
+//! Hashes are short, so the canonical representation of each request is kept with it. A request
+//! whose hash is already taken by a different request is rejected, rather than replacing it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use async_trait::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{FromRequest, Path};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{BoxError, Extension, Json};
+use axum_extra::extract::WithRejection;
+use ndc_models::{QueryRequest, VariableName};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::connector::{ErrorResponse, Result};
+use crate::fnv::{canonical_request_bytes, fnv1a};
+use crate::json_rejection::{json_request, JsonBody, JsonRejection};
+use crate::json_request::JsonRequest;
+
+/// The registered query requests, by hash.
+#[derive(Debug)]
+pub(crate) struct PersistedQueries {
+    max_entries: usize,
+    entries: Mutex<PersistedEntries>,
+}
+
+#[derive(Debug, Default)]
+struct PersistedEntries {
+    requests: HashMap<String, PersistedEntry>,
+    /// The number of requests registered so far, to find the one which was registered first.
+    registered: u64,
+}
+
+#[derive(Debug)]
+struct PersistedEntry {
+    /// When the request was registered, counted in registrations.
+    registered: u64,
+    /// The canonical request, to rule out hash collisions.
+    canonical: Vec<u8>,
+    request: Arc<QueryRequest>,
+}
+
+/// The compact body which invokes a persisted query.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PersistedQueryInvocation {
+    persisted: String,
+    #[serde(default)]
+    variables: Option<Vec<BTreeMap<VariableName, serde_json::Value>>>,
+}
+
+impl PersistedQueries {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Register a query request, returning its hash.
+    fn register(&self, request: QueryRequest) -> Result<String> {
+        if self.max_entries == 0 {
+            return Err(ErrorResponse::new(
+                StatusCode::CONFLICT,
+                "No query requests can be persisted, as the maximum number is 0".to_owned(),
+                serde_json::Value::Null,
+            ));
+        }
+        let canonical = canonical_request_bytes(&request).map_err(ErrorResponse::from_error)?;
+        let hash = format!("{:016x}", fnv1a(&canonical));
+        let mut entries = self.lock();
+        match entries.requests.get(&hash) {
+            Some(entry) if entry.canonical == canonical => return Ok(hash),
+            Some(_) => {
+                return Err(ErrorResponse::new(
+                    StatusCode::CONFLICT,
+                    format!("The hash {hash} is already taken by a different persisted query"),
+                    serde_json::Value::Null,
+                ))
+            }
+            None => {}
+        }
+        if entries.requests.len() >= self.max_entries {
+            let first = entries
+                .requests
+                .iter()
+                .min_by_key(|(_, entry)| entry.registered)
+                .map(|(hash, _)| hash.clone());
+            if let Some(first) = first {
+                entries.requests.remove(&first);
+            }
+        }
+        entries.registered += 1;
+        let registered = entries.registered;
+        entries.requests.insert(
+            hash.clone(),
+            PersistedEntry {
+                registered,
+                canonical,
+                request: Arc::new(request),
+            },
+        );
+        Ok(hash)
+    }
+
+    /// The query request to execute for an invocation.
+    fn resolve(&self, invocation: PersistedQueryInvocation) -> Result<QueryRequest> {
+        let request = self
+            .lock()
+            .requests
+            .get(&invocation.persisted)
+            .map(|entry| entry.request.clone())
+            .ok_or_else(|| not_found(&invocation.persisted))?;
+        let mut request = QueryRequest::clone(&request);
+        if let Some(variables) = invocation.variables {
+            request.variables = Some(variables);
+        }
+        Ok(request)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PersistedEntries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn not_found(hash: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        StatusCode::NOT_FOUND,
+        format!("There is no persisted query with the hash {hash}"),
+        serde_json::Value::Null,
+    )
+}
+
+fn enabled(persisted_queries: Option<Arc<PersistedQueries>>) -> Result<Arc<PersistedQueries>> {
+    persisted_queries.ok_or_else(|| {
+        ErrorResponse::new(
+            StatusCode::NOT_FOUND,
+            "Persisted queries are not enabled".to_owned(),
+            serde_json::Value::Null,
+        )
+    })
+}
+
+/// The body of a query request, which may invoke a persisted query if they are enabled.
+///
+/// Other bodies are deserialized as a [`QueryRequest`] as they would be by [`JsonBody`], keeping
+/// their bytes.
+pub(crate) struct QueryBody(pub JsonRequest<QueryRequest>);
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for QueryBody
+where
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request<B>, state: &S) -> std::result::Result<Self, Response> {
+        let persisted_queries = request
+            .extensions()
+            .get::<Option<Arc<PersistedQueries>>>()
+            .cloned()
+            .flatten();
+        let Some(persisted_queries) = persisted_queries else {
+            let JsonBody(request) = JsonBody::from_request(request, state).await?;
+            return Ok(Self(request));
+        };
+
+        let (parts, body) = request.into_parts();
+        let bytes = Bytes::from_request(Request::new(body), state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        // a query request has other fields, so it fails to deserialize as an invocation quickly
+        if let Ok(invocation) = serde_json::from_slice::<PersistedQueryInvocation>(&bytes) {
+            return persisted_queries
+                .resolve(invocation)
+                .map(|request| Self(request.into()))
+                .map_err(IntoResponse::into_response);
+        }
+        json_request(parts, bytes, state).await.map(Self)
+    }
+}
+
+/// Register a query request, responding with its hash.
+pub(crate) async fn post_persisted_query(
+    Extension(persisted_queries): Extension<Option<Arc<PersistedQueries>>>,
+    WithRejection(Json(request), _): WithRejection<Json<QueryRequest>, JsonRejection>,
+) -> Result<Json<serde_json::Value>> {
+    let hash = enabled(persisted_queries)?.register(request)?;
+    Ok(Json(json!({ "hash": hash })))
+}
+
+/// List the persisted queries, with the collection which each one queries.
+pub(crate) async fn get_persisted_queries(
+    Extension(persisted_queries): Extension<Option<Arc<PersistedQueries>>>,
+) -> Result<Json<serde_json::Value>> {
+    let persisted_queries = enabled(persisted_queries)?;
+    let entries = persisted_queries.lock();
+    let mut listed = entries.requests.iter().collect::<Vec<_>>();
+    listed.sort_by_key(|(_, entry)| entry.registered);
+    let listed = listed
+        .into_iter()
+        .map(|(hash, entry)| json!({ "hash": hash, "collection": entry.request.collection }))
+        .collect();
+    Ok(Json(serde_json::Value::Array(listed)))
+}
+
+/// Evict a persisted query.
+pub(crate) async fn delete_persisted_query(
+    Extension(persisted_queries): Extension<Option<Arc<PersistedQueries>>>,
+    Path(hash): Path<String>,
+) -> Result<Response> {
+    let persisted_queries = enabled(persisted_queries)?;
+    let removed = persisted_queries.lock().requests.remove(&hash);
+    match removed {
+        Some(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+        None => Err(not_found(&hash)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_and_resolves_requests_by_hash() {
+        let persisted_queries = PersistedQueries::new(2);
+        let hash = persisted_queries.register(request("articles")).unwrap();
+        assert_eq!(hash.len(), 16);
+        // registering the same request again returns the same hash
+        assert_eq!(
+            persisted_queries.register(request("articles")).unwrap(),
+            hash
+        );
+
+        let resolved = persisted_queries.resolve(invocation(&hash)).unwrap();
+        assert_eq!(resolved, request("articles"));
+
+        let variables = vec![BTreeMap::from([("id".into(), json!(1))])];
+        let resolved = persisted_queries
+            .resolve(PersistedQueryInvocation {
+                persisted: hash,
+                variables: Some(variables.clone()),
+            })
+            .unwrap();
+        assert_eq!(resolved.variables, Some(variables));
+    }
+
+    #[test]
+    fn does_not_resolve_unknown_hashes() {
+        let persisted_queries = PersistedQueries::new(2);
+        let err = persisted_queries
+            .resolve(invocation("0123456789abcdef"))
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn evicts_the_request_registered_first_when_full() {
+        let persisted_queries = PersistedQueries::new(2);
+        let articles = persisted_queries.register(request("articles")).unwrap();
+        let authors = persisted_queries.register(request("authors")).unwrap();
+        let books = persisted_queries.register(request("books")).unwrap();
+
+        assert!(persisted_queries.resolve(invocation(&articles)).is_err());
+        assert!(persisted_queries.resolve(invocation(&authors)).is_ok());
+        assert!(persisted_queries.resolve(invocation(&books)).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_different_request_with_a_hash_which_is_taken() {
+        let persisted_queries = PersistedQueries::new(2);
+        let hash = persisted_queries.register(request("articles")).unwrap();
+        // simulate a collision, as finding a real one takes too long
+        persisted_queries
+            .lock()
+            .requests
+            .get_mut(&hash)
+            .unwrap()
+            .canonical = b"{}".to_vec();
+
+        let err = persisted_queries.register(request("articles")).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn rejects_requests_when_the_maximum_is_zero() {
+        let persisted_queries = PersistedQueries::new(0);
+        let err = persisted_queries.register(request("articles")).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+        assert!(persisted_queries.lock().requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn evicts_requests_by_hash() {
+        let persisted_queries = Arc::new(PersistedQueries::new(2));
+        let hash = persisted_queries.register(request("articles")).unwrap();
+
+        let response = delete_persisted_query(
+            Extension(Some(persisted_queries.clone())),
+            Path(hash.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(persisted_queries.resolve(invocation(&hash)).is_err());
+
+        let err = delete_persisted_query(Extension(Some(persisted_queries)), Path(hash))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    fn request(collection: &str) -> QueryRequest {
+        serde_json::from_value(json!({
+            "collection": collection,
+            "query": {},
+            "arguments": {},
+            "collection_relationships": {},
+        }))
+        .expect("invalid request")
+    }
+
+    fn invocation(hash: &str) -> PersistedQueryInvocation {
+        PersistedQueryInvocation {
+            persisted: hash.to_owned(),
+            variables: None,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Serving [`JsonResponse::Compressed`](crate::json_response::JsonResponse::Compressed) responses
+//! to clients which do not accept their encoding.
+//!
+//! Pre-compressed responses are sent as they are whenever the client's `Accept-Encoding` header
+//! allows it, so that they are not decompressed and compressed again. Otherwise, they are
+//! decompressed here.
+
+use axum::{
+    body::{boxed, Full},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+use serde_json::json;
+
+use crate::connector::ErrorResponse;
+use crate::json_response::ContentEncoding;
+
+/// Decompress pre-compressed responses if the request does not accept their encoding.
+pub async fn decode_precompressed<B>(request: Request<B>, next: Next<B>) -> Response {
+    let accept_encoding = accept_encoding(request.headers());
+    let response = next.run(request).await;
+    let Some(&encoding) = response.extensions().get::<ContentEncoding>() else {
+        return response;
+    };
+    if accepts(&accept_encoding, encoding) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let decoded = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => encoding.decode(&bytes),
+        Err(err) => Err(std::io::Error::other(err)),
+    };
+    match decoded {
+        Ok(decoded) => {
+            parts.headers.remove(header::CONTENT_ENCODING);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.extensions.remove::<ContentEncoding>();
+            Response::from_parts(parts, boxed(Full::from(decoded)))
+        }
+        Err(err) => ErrorResponse::new_internal_with_details(json!({
+            "message": format!("Unable to decompress a {} response", encoding.as_str()),
+            "error": err.to_string(),
+        }))
+        .into_response(),
+    }
+}
+
+/// Whether the request headers accept the encoding, for responses compressed by the handler.
+pub fn accepts_encoding(headers: &HeaderMap, encoding: ContentEncoding) -> bool {
+    accepts(&accept_encoding(headers), encoding)
+}
+
+/// All of the `Accept-Encoding` header values, joined.
+fn accept_encoding(headers: &HeaderMap) -> String {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Whether an `Accept-Encoding` header value allows the encoding, either by name or with `*`.
+fn accepts(accept_encoding: &str, encoding: ContentEncoding) -> bool {
+    let mut wildcard = false;
+    for entry in accept_encoding.split(',') {
+        let mut parameters = entry.split(';');
+        let coding = parameters.next().unwrap_or_default().trim();
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if coding.eq_ignore_ascii_case(encoding.as_str()) {
+            return quality > 0.0;
+        }
+        if coding == "*" {
+            wildcard = quality > 0.0;
+        }
+    }
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Request},
+        middleware::from_fn,
+        routing::get,
+        Router,
+    };
+
+    use super::{accepts, decode_precompressed};
+    use crate::json_response::{ContentEncoding, JsonResponse};
+
+    #[test]
+    fn accepts_encodings_by_name_regardless_of_case() {
+        assert!(accepts("gzip", ContentEncoding::Gzip));
+        assert!(accepts("deflate, GZip;q=0.5", ContentEncoding::Gzip));
+        assert!(!accepts("deflate", ContentEncoding::Gzip));
+        assert!(!accepts("", ContentEncoding::Gzip));
+    }
+
+    #[test]
+    fn rejects_encodings_with_zero_quality() {
+        assert!(!accepts("gzip;q=0", ContentEncoding::Gzip));
+        assert!(!accepts("gzip; q=0.0, deflate", ContentEncoding::Gzip));
+        // a named encoding takes precedence over the wildcard
+        assert!(!accepts("*, gzip;q=0", ContentEncoding::Gzip));
+    }
+
+    #[test]
+    fn accepts_encodings_with_a_wildcard() {
+        assert!(accepts("*", ContentEncoding::Deflate));
+        assert!(accepts("br, *;q=0.1", ContentEncoding::Deflate));
+        assert!(!accepts("*;q=0", ContentEncoding::Deflate));
+        assert!(accepts("deflate, *;q=0", ContentEncoding::Deflate));
+    }
+
+    async fn get_compressed(accept_encoding: Option<&str>) -> (Option<String>, Bytes) {
+        let mut router = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    JsonResponse::<serde_json::Value>::Compressed {
+                        bytes: ContentEncoding::Gzip.encode(br#"{"a":1}"#).unwrap().into(),
+                        encoding: ContentEncoding::Gzip,
+                    }
+                }),
+            )
+            .layer(from_fn(decode_precompressed));
+        let mut request = Request::get("/");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+        let response =
+            hyper::service::Service::call(&mut router, request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+        let content_encoding = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (content_encoding, body)
+    }
+
+    #[tokio::test]
+    async fn sends_compressed_responses_which_are_accepted_as_they_are() {
+        let (content_encoding, body) = get_compressed(Some("gzip, deflate")).await;
+        assert_eq!(content_encoding.as_deref(), Some("gzip"));
+        assert_eq!(ContentEncoding::Gzip.decode(&body).unwrap(), br#"{"a":1}"#);
+    }
+
+    #[tokio::test]
+    async fn decompresses_responses_which_are_not_accepted() {
+        let (content_encoding, body) = get_compressed(None).await;
+        assert_eq!(content_encoding, None);
+        assert_eq!(body, r#"{"a":1}"#);
+
+        let (content_encoding, body) = get_compressed(Some("gzip;q=0")).await;
+        assert_eq!(content_encoding, None);
+        assert_eq!(body, r#"{"a":1}"#);
+    }
+}
@@ -0,0 +1,299 @@
+//! A connector which forwards requests to another connector over HTTP, so that middleware-style
+//! connectors, which cache responses, translate authentication or rewrite requests, can be built
+//! by composition.
+//!
+//! The behavior of a [`ProxyConnector`] is customized by implementing [`Proxy`], whose methods
+//! forward each request unchanged by default. Queries and mutations are passed to the proxy as a
+//! [`JsonRequest`], so that requests which are not rewritten are forwarded as the bytes which
+//! were received, and their responses returned without being deserialized:
+//!
+//! ```ignore
+//! struct TenantProxy {
+//!     tenant: String,
+//! }
+//!
+//! #[async_trait]
+//! impl Proxy for TenantProxy {
+//!     fn capabilities() -> models::Capabilities {
+//!         MyCapabilities::capabilities()
+//!     }
+//!
+//!     async fn query(
+//!         &self,
+//!         client: &NdcClient,
+//!         request: JsonRequest<models::QueryRequest>,
+//!     ) -> Result<JsonResponse<models::QueryResponse>> {
+//!         let mut request = request
+//!             .into_value()
+//!             .map_err(|err| QueryError::new_invalid_request(&err))?;
+//!         request.arguments.insert("tenant".into(), tenant_argument(&self.tenant));
+//!         Ok(client.query(&request).await?.into())
+//!     }
+//! }
+//! ```
+//!
+//! The setup then parses the proxy and the URL of the remote connector into a
+//! [`ProxyConfiguration`].
+//!
+//! Capabilities are associated with the connector type rather than its configuration, so they
+//! cannot be fetched from the remote connector, and must be declared by the proxy instead.
+//!
+//! Like connectors, proxies are not given the headers of the incoming request. The client sends
+//! the trace context and baggage on, so values which the remote connector needs, such as a
+//! tenant, can be passed as baggage, and the client authenticates with the remote connector using
+//! its own service token.
+
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use ndc_models as models;
+
+use crate::client::NdcClient;
+use crate::connector::{Connector, Result};
+use crate::json_request::JsonRequest;
+use crate::json_response::JsonResponse;
+
+/// Customizes the requests which a [`ProxyConnector`] forwards, and their responses.
+#[async_trait]
+pub trait Proxy: Send + Sync + 'static {
+    /// The capabilities of the proxy, which should be no more than those of the remote connector,
+    /// unless the proxy implements the difference itself.
+    fn capabilities() -> models::Capabilities;
+
+    async fn schema(&self, client: &NdcClient) -> Result<JsonResponse<models::SchemaResponse>> {
+        Ok(client.schema().await?.into())
+    }
+
+    async fn query(
+        &self,
+        client: &NdcClient,
+        request: JsonRequest<models::QueryRequest>,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        Ok(client.query_raw(request).await?)
+    }
+
+    async fn query_explain(
+        &self,
+        client: &NdcClient,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        Ok(client.query_explain(&request).await?.into())
+    }
+
+    async fn mutation(
+        &self,
+        client: &NdcClient,
+        request: JsonRequest<models::MutationRequest>,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        Ok(client.mutation_raw(request).await?)
+    }
+
+    async fn mutation_explain(
+        &self,
+        client: &NdcClient,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        Ok(client.mutation_explain(&request).await?.into())
+    }
+}
+
+/// The configuration of a [`ProxyConnector`]: the proxy, and a client for the remote connector.
+#[derive(Clone, Debug)]
+pub struct ProxyConfiguration<P> {
+    pub proxy: P,
+    pub client: NdcClient,
+}
+
+/// A connector which forwards requests to a remote connector, customized by a [`Proxy`].
+///
+/// Errors from the remote connector are returned with their original status codes and details,
+/// and failures to reach it as `502 Bad Gateway`. The readiness check does not call the remote
+/// connector, as it should not depend on the availability of external services.
+pub struct ProxyConnector<P> {
+    proxy: PhantomData<fn() -> P>,
+}
+
+#[async_trait]
+impl<P: Proxy> Connector for ProxyConnector<P> {
+    type Configuration = ProxyConfiguration<P>;
+    type State = ();
+
+    fn fetch_metrics(_configuration: &Self::Configuration, _state: &Self::State) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_capabilities() -> models::Capabilities {
+        P::capabilities()
+    }
+
+    async fn get_schema(
+        configuration: &Self::Configuration,
+    ) -> Result<JsonResponse<models::SchemaResponse>> {
+        configuration.proxy.schema(&configuration.client).await
+    }
+
+    async fn query_explain(
+        configuration: &Self::Configuration,
+        _state: &Self::State,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        configuration
+            .proxy
+            .query_explain(&configuration.client, request)
+            .await
+    }
+
+    async fn mutation_explain(
+        configuration: &Self::Configuration,
+        _state: &Self::State,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::ExplainResponse>> {
+        configuration
+            .proxy
+            .mutation_explain(&configuration.client, request)
+            .await
+    }
+
+    async fn mutation(
+        configuration: &Self::Configuration,
+        _state: &Self::State,
+        request: models::MutationRequest,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        configuration
+            .proxy
+            .mutation(&configuration.client, request.into())
+            .await
+    }
+
+    async fn query(
+        configuration: &Self::Configuration,
+        _state: &Self::State,
+        request: models::QueryRequest,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        configuration
+            .proxy
+            .query(&configuration.client, request.into())
+            .await
+    }
+
+    async fn mutation_raw(
+        configuration: &Self::Configuration,
+        _state: &Self::State,
+        request: JsonRequest<models::MutationRequest>,
+    ) -> Result<JsonResponse<models::MutationResponse>> {
+        configuration
+            .proxy
+            .mutation(&configuration.client, request)
+            .await
+    }
+
+    async fn query_raw(
+        configuration: &Self::Configuration,
+        _state: &Self::State,
+        request: JsonRequest<models::QueryRequest>,
+    ) -> Result<JsonResponse<models::QueryResponse>> {
+        configuration
+            .proxy
+            .query(&configuration.client, request)
+            .await
+    }
+}
+
+// the tests serve the remote connector with the `testing` helpers
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::net::{Ipv6Addr, TcpListener};
+
+    use axum::body::Bytes;
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::connector::{ErrorCode, ErrorResponse};
+    use crate::testing::{
+        spawn_connector, CapabilitiesFixture, MinimalCapabilities, MockConnector, SpawnedConnector,
+        SERVICE_TOKEN,
+    };
+
+    struct ForwardingProxy;
+
+    impl Proxy for ForwardingProxy {
+        fn capabilities() -> models::Capabilities {
+            MinimalCapabilities::capabilities()
+        }
+    }
+
+    fn configuration(base_url: &str) -> ProxyConfiguration<ForwardingProxy> {
+        ProxyConfiguration {
+            proxy: ForwardingProxy,
+            client: NdcClient::new(base_url.parse().unwrap()).with_service_token(SERVICE_TOKEN),
+        }
+    }
+
+    async fn upstream(mock: MockConnector) -> SpawnedConnector {
+        spawn_connector(mock, ".").await.unwrap()
+    }
+
+    fn query_request(collection: &str) -> JsonRequest<models::QueryRequest> {
+        JsonRequest::from_bytes(Bytes::from(format!(
+            r#"{{"collection":"{collection}","query":{{}},"arguments":{{}},"collection_relationships":{{}}}}"#
+        )))
+    }
+
+    #[tokio::test]
+    async fn forwards_requests_and_their_responses() {
+        let upstream = upstream(MockConnector::new().with_query(|request| {
+            assert_eq!(request.collection.as_str(), "articles");
+            Ok(models::QueryResponse(vec![]))
+        }))
+        .await;
+
+        let response = ProxyConnector::<ForwardingProxy>::query_raw(
+            &configuration(&upstream.base_url()),
+            &(),
+            query_request("articles"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.as_serialized(), Some(&Bytes::from("[]")));
+    }
+
+    #[tokio::test]
+    async fn passes_on_remote_errors() {
+        let upstream = upstream(MockConnector::new().with_query(|_| {
+            Err(ErrorResponse::new_with_code(
+                ErrorCode::InvalidRequest,
+                "unknown collection".to_owned(),
+            ))
+        }))
+        .await;
+
+        let error = ProxyConnector::<ForwardingProxy>::query_raw(
+            &configuration(&upstream.base_url()),
+            &(),
+            query_request("authors"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.message(), "unknown collection");
+        assert_eq!(error.error_code(), Some(ErrorCode::InvalidRequest));
+    }
+
+    #[tokio::test]
+    async fn fails_with_bad_gateway_if_the_remote_connector_is_unreachable() {
+        // nothing listens on the port once the listener is dropped
+        let address = TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let error = ProxyConnector::<ForwardingProxy>::query_raw(
+            &configuration(&format!("http://{address}")),
+            &(),
+            query_request("articles"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+    }
+}
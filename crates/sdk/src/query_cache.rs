@@ -0,0 +1,371 @@
+//! Caching query responses, for connectors whose data sources are read heavily but change
+//! slowly.
+//!
+//! Responses are cached by a hash of the canonical JSON representation of the query request, in
+//! which object keys are sorted, and are served from the cache as serialized bytes until they
+//! expire. Queries against excluded collections are never cached. Responses with additional
+//! headers, such as `Cache-Control`, are not cached either, as the connector has chosen how they
+//! should be handled.
+//!
+//! Responses served from the cache are recorded on the request span, logged and recorded as
+//! snapshots in the same way as responses from the connector.
+//!
+//! The cache is shared by every caller, so it must not be enabled for connectors whose responses
+//! depend on anything other than the request, such as baggage identifying a tenant.
+//!
+//! Hits and misses are counted in `ndc_sdk_query_cache_hits_total` and
+//! `ndc_sdk_query_cache_misses_total`, and the number of cached responses is reported in
+//! `ndc_sdk_query_cache_entries`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use ndc_models::{QueryRequest, QueryResponse};
+use prometheus::{IntCounter, IntGauge, Registry};
+
+use crate::clock::{Clock, SystemClock};
+use crate::connector::{ErrorResponse, Result};
+use crate::fetch_metrics::warn_unregistered;
+use crate::fnv::{canonical_request_bytes, fnv1a};
+use crate::json_response::JsonResponse;
+use crate::metrics_prefix::opts;
+
+/// Options for the query response cache.
+#[derive(Clone, Debug)]
+pub struct QueryCacheOptions {
+    /// How long a response is served from the cache.
+    pub ttl: Duration,
+    /// The maximum number of cached responses. When the cache is full, the response which expires
+    /// soonest is evicted.
+    pub max_entries: usize,
+    /// Queries against these collections are never cached.
+    pub excluded_collections: BTreeSet<String>,
+}
+
+/// A cache of serialized query responses.
+#[derive(Debug)]
+pub(crate) struct QueryCache {
+    options: QueryCacheOptions,
+    entries: Mutex<HashMap<u64, CacheEntry>>,
+    metrics: Option<QueryCacheMetrics>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// The canonical request, to rule out hash collisions.
+    request: Vec<u8>,
+    response: Bytes,
+    expires_at: Instant,
+}
+
+/// The key under which the response to a request is cached.
+#[derive(Debug)]
+pub(crate) struct CacheKey {
+    hash: u64,
+    request: Vec<u8>,
+}
+
+impl QueryCache {
+    pub fn new(options: QueryCacheOptions, registry: &Registry) -> Self {
+        let metrics = QueryCacheMetrics::register(registry)
+            .map_err(|err| warn_unregistered("query cache", &err))
+            .ok();
+        Self {
+            options,
+            entries: Mutex::new(HashMap::new()),
+            metrics,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Measure time with the given clock, rather than the system clock, so that tests can control
+    /// when responses expire.
+    #[cfg(test)]
+    fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The key for the request, or `None` if it must not be cached.
+    pub fn key(&self, request: &QueryRequest) -> Option<CacheKey> {
+        if self
+            .options
+            .excluded_collections
+            .contains(request.collection.as_str())
+        {
+            return None;
+        }
+        let request = canonical_request_bytes(request).ok()?;
+        Some(CacheKey {
+            hash: fnv1a(&request),
+            request,
+        })
+    }
+
+    /// The cached response, if there is one which has not expired.
+    pub fn get(&self, key: &CacheKey) -> Option<JsonResponse<QueryResponse>> {
+        let now = self.clock.now();
+        let mut entries = self.lock();
+        let response = match entries.get(&key.hash) {
+            Some(entry) if entry.request == key.request && entry.expires_at > now => {
+                Some(entry.response.clone())
+            }
+            Some(entry) if entry.expires_at <= now => {
+                entries.remove(&key.hash);
+                None
+            }
+            _ => None,
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.entries.set(gauge_value(entries.len()));
+            match response {
+                Some(_) => metrics.hits_total.inc(),
+                None => metrics.misses_total.inc(),
+            }
+        }
+        response.map(JsonResponse::Serialized)
+    }
+
+    /// Cache the response, which is returned for sending as serialized bytes.
+    pub async fn insert(
+        &self,
+        key: CacheKey,
+        response: JsonResponse<QueryResponse>,
+    ) -> Result<JsonResponse<QueryResponse>> {
+        let response = response.buffer().await?;
+        if matches!(response, JsonResponse::WithHeaders { .. }) {
+            return Ok(response);
+        }
+        let bytes = response.into_bytes().map_err(ErrorResponse::from_error)?;
+        let now = self.clock.now();
+        let mut entries = self.lock();
+        if !entries.contains_key(&key.hash) && entries.len() >= self.options.max_entries {
+            entries.retain(|_, entry| entry.expires_at > now);
+            if entries.len() >= self.options.max_entries {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(hash, _)| *hash);
+                if let Some(soonest) = soonest {
+                    entries.remove(&soonest);
+                }
+            }
+        }
+        if self.options.max_entries > 0 {
+            entries.insert(
+                key.hash,
+                CacheEntry {
+                    request: key.request,
+                    response: bytes.clone(),
+                    expires_at: now + self.options.ttl,
+                },
+            );
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.entries.set(gauge_value(entries.len()));
+        }
+        Ok(JsonResponse::Serialized(bytes))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, CacheEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Debug)]
+struct QueryCacheMetrics {
+    hits_total: IntCounter,
+    misses_total: IntCounter,
+    entries: IntGauge,
+}
+
+impl QueryCacheMetrics {
+    fn register(registry: &Registry) -> std::result::Result<Self, prometheus::Error> {
+        let hits_total = IntCounter::with_opts(opts(
+            "ndc_sdk_query_cache_hits_total",
+            "Total number of queries answered from the query cache",
+        ))?;
+        let misses_total = IntCounter::with_opts(opts(
+            "ndc_sdk_query_cache_misses_total",
+            "Total number of cacheable queries which were not in the query cache",
+        ))?;
+        let entries = IntGauge::with_opts(opts(
+            "ndc_sdk_query_cache_entries",
+            "Number of responses in the query cache",
+        ))?;
+        registry.register(Box::new(hits_total.clone()))?;
+        registry.register(Box::new(misses_total.clone()))?;
+        registry.register(Box::new(entries.clone()))?;
+        Ok(Self {
+            hits_total,
+            misses_total,
+            entries,
+        })
+    }
+}
+
+fn gauge_value(len: usize) -> i64 {
+    i64::try_from(len).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderName, HeaderValue};
+    use ndc_models::{RowFieldValue, RowSet};
+    use ndc_sdk_core::testing::ManualClock;
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_responses_until_they_expire() {
+        let clock = ManualClock::new();
+        let cache = cache(2, []).with_clock(clock.clone());
+        let key = cache.key(&request("articles")).unwrap();
+        assert!(cache.get(&key).is_none());
+
+        let sent = insert(&cache, key, response()).await;
+        let key = cache.key(&request("articles")).unwrap();
+        assert_eq!(cached_bytes(&cache, &key), Some(sent));
+
+        clock.advance(Duration::from_secs(59));
+        assert!(cache.get(&key).is_some());
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get(&key).is_none());
+    }
+
+    #[tokio::test]
+    async fn evicts_the_response_which_expires_soonest_when_full() {
+        let clock = ManualClock::new();
+        let cache = cache(2, []).with_clock(clock.clone());
+        for collection in ["articles", "authors", "books"] {
+            insert(&cache, cache.key(&request(collection)).unwrap(), response()).await;
+            clock.advance(Duration::from_secs(1));
+        }
+
+        assert!(cache
+            .get(&cache.key(&request("articles")).unwrap())
+            .is_none());
+        assert!(cache
+            .get(&cache.key(&request("authors")).unwrap())
+            .is_some());
+        assert!(cache.get(&cache.key(&request("books")).unwrap()).is_some());
+    }
+
+    #[test]
+    fn does_not_cache_queries_against_excluded_collections() {
+        let cache = cache(2, ["authors"]);
+        assert!(cache.key(&request("authors")).is_none());
+        assert!(cache.key(&request("articles")).is_some());
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_responses_with_headers() {
+        let cache = cache(2, []);
+        let key = cache.key(&request("articles")).unwrap();
+        let response = response().with_header(
+            HeaderName::from_static("cache-control"),
+            HeaderValue::from_static("no-store"),
+        );
+        let sent = cache.insert(key, response).await.unwrap();
+        assert!(matches!(sent, JsonResponse::WithHeaders { .. }));
+        assert!(cache
+            .get(&cache.key(&request("articles")).unwrap())
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn counts_hits_and_misses() {
+        let registry = Registry::new();
+        let cache = QueryCache::new(options(2, []), &registry);
+        let key = cache.key(&request("articles")).unwrap();
+        assert!(cache.get(&key).is_none());
+        insert(&cache, key, response()).await;
+        let key = cache.key(&request("articles")).unwrap();
+        assert!(cache.get(&key).is_some());
+        assert!(cache.get(&key).is_some());
+
+        let families = registry.gather();
+        let metric = |name: &str| {
+            families
+                .iter()
+                .find(|family| family.get_name() == name)
+                .map(|family| family.get_metric()[0].clone())
+                .unwrap()
+        };
+        assert_eq!(
+            metric("ndc_sdk_query_cache_hits_total")
+                .get_counter()
+                .get_value()
+                .to_string(),
+            "2"
+        );
+        assert_eq!(
+            metric("ndc_sdk_query_cache_misses_total")
+                .get_counter()
+                .get_value()
+                .to_string(),
+            "1"
+        );
+        assert_eq!(
+            metric("ndc_sdk_query_cache_entries")
+                .get_gauge()
+                .get_value()
+                .to_string(),
+            "1"
+        );
+    }
+
+    fn options<const N: usize>(max_entries: usize, excluded: [&str; N]) -> QueryCacheOptions {
+        QueryCacheOptions {
+            ttl: Duration::from_secs(60),
+            max_entries,
+            excluded_collections: excluded.into_iter().map(str::to_owned).collect(),
+        }
+    }
+
+    fn cache<const N: usize>(max_entries: usize, excluded: [&str; N]) -> QueryCache {
+        QueryCache::new(options(max_entries, excluded), &Registry::new())
+    }
+
+    fn request(collection: &str) -> QueryRequest {
+        serde_json::from_value(json!({
+            "collection": collection,
+            "query": {},
+            "arguments": {},
+            "collection_relationships": {},
+        }))
+        .expect("invalid request")
+    }
+
+    fn response() -> JsonResponse<QueryResponse> {
+        JsonResponse::Value(QueryResponse(vec![RowSet {
+            aggregates: None,
+            rows: Some(vec![[("id".into(), RowFieldValue(1.into()))]
+                .into_iter()
+                .collect()]),
+            groups: None,
+        }]))
+    }
+
+    async fn insert(
+        cache: &QueryCache,
+        key: CacheKey,
+        response: JsonResponse<QueryResponse>,
+    ) -> Bytes {
+        match cache.insert(key, response).await.unwrap() {
+            JsonResponse::Serialized(bytes) => bytes,
+            response => panic!("expected a serialized response, got {response:?}"),
+        }
+    }
+
+    fn cached_bytes(cache: &QueryCache, key: &CacheKey) -> Option<Bytes> {
+        match cache.get(key)? {
+            JsonResponse::Serialized(bytes) => Some(bytes),
+            response => panic!("expected a serialized response, got {response:?}"),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Limits on how long requests may take, and how many may be handled at once, configured with
+//! [`RouterOptions`](crate::default_main::RouterOptions).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio::sync::Semaphore;
+
+use crate::connector::ErrorResponse;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RequestLimits {
+    timeout: Option<Duration>,
+    concurrency: Option<Arc<Semaphore>>,
+}
+
+impl RequestLimits {
+    pub fn new(timeout: Option<Duration>, max_concurrent_requests: Option<usize>) -> Self {
+        Self {
+            timeout,
+            concurrency: max_concurrent_requests.map(|permits| Arc::new(Semaphore::new(permits))),
+        }
+    }
+}
+
+/// Apply the limits to a request.
+///
+/// Requests over the concurrency limit wait for a slot rather than being rejected. The timeout
+/// includes this wait, so that requests cannot queue indefinitely. The limit applies until the
+/// response is ready, so it does not include streaming the response body.
+pub(crate) async fn limit_requests<B>(
+    State(limits): State<RequestLimits>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let handle = async {
+        let _permit = match &limits.concurrency {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        };
+        next.run(request).await
+    };
+    let Some(timeout) = limits.timeout else {
+        return handle.await;
+    };
+    match tokio::time::timeout(timeout, handle).await {
+        Ok(response) => response,
+        Err(_) => ErrorResponse::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!(
+                "The request did not complete within {} seconds.",
+                timeout.as_secs_f64()
+            ),
+            serde_json::Value::Null,
+        )
+        .into_response(),
+    }
+}
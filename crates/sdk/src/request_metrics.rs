@@ -0,0 +1,231 @@
+//! Built-in metrics for request durations and body sizes, labelled by route, so that operators can
+//! monitor latency without instrumenting each connector.
+//!
+//! When metrics are scraped in the OpenMetrics format, each bucket of the request duration
+//! histogram carries an exemplar with the trace ID of the latest sampled request which fell into
+//! it, so that operators can jump from a latency spike to the trace responsible.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use axum::body::HttpBody as _;
+use axum::extract::{MatchedPath, State};
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::trace::TraceContextExt as _;
+use prometheus::core::Collector as _;
+use prometheus::proto::LabelPair;
+use prometheus::{HistogramVec, Registry};
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+use crate::metrics_prefix::histogram_opts;
+use crate::openmetrics::Exemplar;
+
+/// The bucket boundaries of the built-in histograms.
+///
+/// The defaults suit most connectors. Connectors whose requests take minutes, or which return very
+/// large responses, should use wider buckets, as any observations above the largest bucket are
+/// only counted, which makes the tails of the histograms useless.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramBuckets {
+    /// The bucket boundaries for request durations, in seconds.
+    pub request_duration_seconds: Vec<f64>,
+    /// The bucket boundaries for request and response body sizes, in bytes.
+    pub body_size_bytes: Vec<f64>,
+}
+
+impl HistogramBuckets {
+    /// The buckets as they are used: sorted, with duplicate boundaries removed.
+    #[must_use]
+    pub fn normalized(&self) -> Self {
+        Self {
+            request_duration_seconds: normalize(&self.request_duration_seconds),
+            body_size_bytes: normalize(&self.body_size_bytes),
+        }
+    }
+}
+
+impl Default for HistogramBuckets {
+    fn default() -> Self {
+        Self {
+            request_duration_seconds: prometheus::DEFAULT_BUCKETS.to_vec(),
+            // 256B to 64MB
+            body_size_bytes: (0..10).map(|power| 256.0 * 4_f64.powi(power)).collect(),
+        }
+    }
+}
+
+/// Histograms of request durations and body sizes, labelled by route.
+#[derive(Clone, Debug)]
+pub struct RequestMetrics {
+    request_duration_seconds: HistogramVec,
+    request_size_bytes: HistogramVec,
+    response_size_bytes: HistogramVec,
+    duration_buckets: Arc<[f64]>,
+    /// The latest exemplar for each route and bucket, keyed by the bits of the upper bound.
+    duration_exemplars: Arc<Mutex<HashMap<(String, u64), Exemplar>>>,
+}
+
+impl RequestMetrics {
+    /// Create the request metrics, and register them with the given registry.
+    ///
+    /// The buckets are sorted, and duplicate boundaries are removed.
+    pub fn register(
+        registry: &Registry,
+        buckets: &HistogramBuckets,
+    ) -> Result<Self, prometheus::Error> {
+        let buckets = buckets.normalized();
+        let duration_buckets = Arc::from(buckets.request_duration_seconds.as_slice());
+        let request_duration_seconds = HistogramVec::new(
+            histogram_opts(
+                "ndc_sdk_request_duration_seconds",
+                "Time taken to respond to requests, by route",
+            )
+            .buckets(buckets.request_duration_seconds),
+            &["route"],
+        )?;
+        let request_size_bytes = HistogramVec::new(
+            histogram_opts(
+                "ndc_sdk_request_size_bytes",
+                "Size of request bodies with a known length, by route",
+            )
+            .buckets(buckets.body_size_bytes.clone()),
+            &["route"],
+        )?;
+        let response_size_bytes = HistogramVec::new(
+            histogram_opts(
+                "ndc_sdk_response_size_bytes",
+                "Size of response bodies with a known length, by route",
+            )
+            .buckets(buckets.body_size_bytes),
+            &["route"],
+        )?;
+        registry.register(Box::new(request_duration_seconds.clone()))?;
+        registry.register(Box::new(request_size_bytes.clone()))?;
+        registry.register(Box::new(response_size_bytes.clone()))?;
+        Ok(Self {
+            request_duration_seconds,
+            request_size_bytes,
+            response_size_bytes,
+            duration_buckets,
+            duration_exemplars: Arc::default(),
+        })
+    }
+
+    /// Record a request which has been responded to.
+    pub fn observe(
+        &self,
+        route: &str,
+        started: Instant,
+        request_size: Option<u64>,
+        response: &Response,
+    ) {
+        let duration = started.elapsed().as_secs_f64();
+        self.request_duration_seconds
+            .with_label_values(&[route])
+            .observe(duration);
+        self.record_exemplar(route, duration);
+        if let Some(request_size) = request_size {
+            self.request_size_bytes
+                .with_label_values(&[route])
+                .observe(bytes(request_size));
+        }
+        if let Some(response_size) = response.body().size_hint().exact() {
+            self.response_size_bytes
+                .with_label_values(&[route])
+                .observe(bytes(response_size));
+        }
+    }
+}
+
+impl RequestMetrics {
+    /// Record the current trace as the exemplar for the bucket the duration falls into.
+    fn record_exemplar(&self, route: &str, duration: f64) {
+        let context = tracing::Span::current().context();
+        let span = context.span();
+        let span_context = span.span_context();
+        // unsampled traces are not exported, so there would be nothing to jump to
+        if !span_context.is_valid() || !span_context.is_sampled() {
+            return;
+        }
+        let upper_bound = self
+            .duration_buckets
+            .iter()
+            .copied()
+            .find(|upper_bound| duration <= *upper_bound)
+            .unwrap_or(f64::INFINITY);
+        let exemplar = Exemplar {
+            trace_id: span_context.trace_id().to_string(),
+            value: duration,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        };
+        if let Ok(mut exemplars) = self.duration_exemplars.lock() {
+            exemplars.insert((route.to_string(), upper_bound.to_bits()), exemplar);
+        }
+    }
+
+    /// The exemplar for a bucket of a histogram, if it is a bucket of the request duration
+    /// histogram which has one.
+    pub(crate) fn exemplar(
+        &self,
+        family: &str,
+        labels: &[LabelPair],
+        upper_bound: f64,
+    ) -> Option<Exemplar> {
+        let duration_family = &self.request_duration_seconds.desc().first()?.fq_name;
+        if family != duration_family {
+            return None;
+        }
+        let route = labels
+            .iter()
+            .find(|label| label.get_name() == "route")?
+            .get_value();
+        let exemplars = self.duration_exemplars.lock().ok()?;
+        exemplars
+            .get(&(route.to_string(), upper_bound.to_bits()))
+            .cloned()
+    }
+}
+
+/// A size as a histogram observation. Sizes over 4GB are recorded as 4GB, which is far larger than
+/// any sensible bucket.
+fn bytes(size: u64) -> f64 {
+    f64::from(u32::try_from(size).unwrap_or(u32::MAX))
+}
+
+fn normalize(buckets: &[f64]) -> Vec<f64> {
+    let mut buckets = buckets.to_vec();
+    buckets.sort_by(f64::total_cmp);
+    buckets.dedup();
+    buckets
+}
+
+/// Record the duration and body sizes of requests.
+///
+/// This is intended to be used with [`axum::middleware::from_fn_with_state`], as a route layer, so
+/// that the matched route is available.
+pub async fn observe_requests<B>(
+    State(metrics): State<Option<RequestMetrics>>,
+    matched_path: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(metrics) = metrics else {
+        return next.run(request).await;
+    };
+    let started = Instant::now();
+    // chunked requests are not counted, because their size is not known up front
+    let request_size = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    let response = next.run(request).await;
+    let route = matched_path.as_ref().map_or("unknown", MatchedPath::as_str);
+    metrics.observe(route, started, request_size, &response);
+    response
+}
@@ -0,0 +1,118 @@
+//! Publishing the connector's schema and capabilities to a registry on startup, so that platform
+//! teams can track schema drift across a fleet of connectors without introspecting each one.
+//!
+//! Once the server has started, the schema and capabilities are sent to the registry URL in a
+//! `POST` request, along with the connector's version information:
+//!
+//! ```json
+//! { "connector": { ... }, "schema": { ... }, "capabilities": { ... } }
+//! ```
+//!
+//! Failed attempts are retried with exponential backoff. Publication never prevents the connector
+//! from serving requests, so a failure is only logged.
+
+use std::time::Duration;
+
+use ndc_sdk_core::schema::get_capabilities;
+use serde::Serialize;
+use serde_json::value::RawValue;
+
+use crate::connector::{Connector, ErrorResponse, Result};
+use crate::json_response::JsonResponse;
+use crate::state::ServerState;
+use crate::version::VersionInfo;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where to publish the schema, and how.
+#[derive(Clone, Debug)]
+pub(crate) struct SchemaPublication {
+    pub url: url::Url,
+    /// Sent as a bearer token, if set.
+    pub token: Option<String>,
+    /// The number of times to retry after a failed attempt.
+    pub retries: u32,
+}
+
+impl SchemaPublication {
+    /// Publish the schema and capabilities, logging the outcome.
+    pub async fn publish<C: Connector>(self, state: ServerState<C>, version: VersionInfo) {
+        let result = match publication_body(&state, &version).await {
+            Ok(body) => self.send(body).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => tracing::info!(
+                meta.signal_type = "log",
+                event.domain = "ndc",
+                event.name = "Published the schema",
+                name = "Published the schema",
+                body = %self.url,
+            ),
+            Err(err) => tracing::warn!(
+                meta.signal_type = "log",
+                event.domain = "ndc",
+                event.name = "Unable to publish the schema",
+                name = "Unable to publish the schema",
+                body = %err,
+            ),
+        }
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<()> {
+        let client = reqwest::Client::new();
+        let mut attempt = 0;
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let mut request = client
+                .post(self.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            let result = match request.send().await {
+                Ok(response) => response.error_for_status().map(|_| ()),
+                Err(err) => Err(err),
+            };
+            match result {
+                Err(_) if attempt < self.retries => {
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                result => return result.map_err(ErrorResponse::from_error),
+            }
+        }
+    }
+}
+
+/// The body of the publication request, which embeds the schema as it was serialized by the
+/// connector.
+#[derive(Serialize)]
+struct PublicationBody<'a> {
+    connector: &'a VersionInfo,
+    schema: Box<RawValue>,
+    capabilities: Box<RawValue>,
+}
+
+async fn publication_body<C: Connector>(
+    state: &ServerState<C>,
+    version: &VersionInfo,
+) -> Result<Vec<u8>> {
+    let schema = C::get_schema(state.configuration()).await?.buffer().await?;
+    let capabilities = get_capabilities::<C>().await;
+    let body = PublicationBody {
+        connector: version,
+        schema: raw_value(schema)?,
+        capabilities: raw_value(capabilities)?,
+    };
+    serde_json::to_vec(&body).map_err(ErrorResponse::from_error)
+}
+
+fn raw_value<A: serde::Serialize>(response: JsonResponse<A>) -> Result<Box<RawValue>> {
+    let bytes = response.into_bytes().map_err(ErrorResponse::from_error)?;
+    let json = String::from_utf8(bytes.to_vec()).map_err(ErrorResponse::from_error)?;
+    RawValue::from_string(json).map_err(ErrorResponse::from_error)
+}
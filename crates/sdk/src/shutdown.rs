@@ -0,0 +1,210 @@
+//! Signal handling for `serve`.
+//!
+//! Operators can choose which signals shut the server down gracefully, waiting for requests in
+//! progress to complete, and which exit the process immediately.
+//!
+//! Behind a load balancer, as in Kubernetes, a graceful shutdown can first be delayed: readiness
+//! checks fail, but requests are still served, until the load balancer has stopped routing
+//! requests to the server. Only then does the server stop accepting connections and drain the
+//! requests in progress.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::connector::ErrorResponse;
+
+/// A signal which can shut down the server.
+///
+/// Not every signal is available on every platform. On Windows, `SIGINT` is CTRL+C.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum ShutdownSignal {
+    #[value(name = "SIGINT")]
+    Sigint,
+    #[value(name = "SIGTERM")]
+    Sigterm,
+    #[value(name = "SIGQUIT")]
+    Sigquit,
+    #[value(name = "SIGHUP")]
+    Sighup,
+    /// CTRL+BREAK, on Windows only
+    #[value(name = "CTRL_BREAK")]
+    CtrlBreak,
+}
+
+impl ShutdownSignal {
+    /// The signals which shut down the server gracefully by default.
+    pub fn graceful_defaults() -> Vec<Self> {
+        if cfg!(windows) {
+            vec![Self::Sigint, Self::CtrlBreak]
+        } else {
+            vec![Self::Sigint, Self::Sigterm]
+        }
+    }
+
+    /// The exit code used when this signal exits the process immediately, which is 128 plus the
+    /// signal number, as reported by shells. CTRL+BREAK uses `SIGBREAK` from the Windows C
+    /// runtime.
+    fn exit_code(self) -> i32 {
+        let number = match self {
+            Self::Sighup => 1,
+            Self::Sigint => 2,
+            Self::Sigquit => 3,
+            Self::Sigterm => 15,
+            Self::CtrlBreak => 21,
+        };
+        128 + number
+    }
+
+    /// Install a handler for the signal, returning a future which resolves when it is received.
+    #[cfg(unix)]
+    fn listen(self) -> io::Result<Received> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let kind = match self {
+            Self::Sigint => SignalKind::interrupt(),
+            Self::Sigterm => SignalKind::terminate(),
+            Self::Sigquit => SignalKind::quit(),
+            Self::Sighup => SignalKind::hangup(),
+            Self::CtrlBreak => return Err(self.unsupported()),
+        };
+        let mut signal = signal(kind)?;
+        Ok(Box::pin(async move {
+            signal.recv().await;
+        }))
+    }
+
+    /// Install a handler for the signal, returning a future which resolves when it is received.
+    #[cfg(windows)]
+    fn listen(self) -> io::Result<Received> {
+        use tokio::signal::windows::{ctrl_break, ctrl_c};
+
+        match self {
+            Self::Sigint => {
+                let mut signal = ctrl_c()?;
+                Ok(Box::pin(async move {
+                    signal.recv().await;
+                }))
+            }
+            Self::CtrlBreak => {
+                let mut signal = ctrl_break()?;
+                Ok(Box::pin(async move {
+                    signal.recv().await;
+                }))
+            }
+            Self::Sigterm | Self::Sigquit | Self::Sighup => Err(self.unsupported()),
+        }
+    }
+
+    fn unsupported(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{self} is not supported on this platform"),
+        )
+    }
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = clap::ValueEnum::to_possible_value(self).expect("no signals are skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+type Received = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Which signals shut down the server, and how.
+#[derive(Clone, Debug)]
+pub(crate) struct ShutdownSignals {
+    pub graceful: Vec<ShutdownSignal>,
+    pub immediate: Vec<ShutdownSignal>,
+}
+
+impl ShutdownSignals {
+    /// Install handlers for all the signals.
+    ///
+    /// Signals which shut down immediately exit the process as soon as they are received, and take
+    /// precedence if they are also listed as graceful. The returned future resolves when any of
+    /// the graceful shutdown signals is received.
+    pub fn install(&self) -> io::Result<impl Future<Output = ()> + Send + 'static> {
+        for &signal in &self.immediate {
+            let listener = signal.listen()?;
+            tokio::spawn(async move {
+                listener.await;
+                eprintln!("Received {signal}, exiting immediately");
+                std::process::exit(signal.exit_code());
+            });
+        }
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        for &signal in &self.graceful {
+            if self.immediate.contains(&signal) {
+                continue;
+            }
+            let listener = signal.listen()?;
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                listener.await;
+                let _ = sender.send(signal).await;
+            });
+        }
+
+        Ok(async move {
+            if let Some(signal) = receiver.recv().await {
+                println!("Received {signal}, shutting down");
+            }
+            // the channel is kept open until here, so that we wait forever if there are no
+            // graceful signals
+            drop(sender);
+        })
+    }
+}
+
+/// Whether the server has started shutting down, so that readiness checks fail while it keeps
+/// serving other requests during the shutdown delay.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    fn shutting_down(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the shutdown signal, then fail readiness checks for the given delay, so that load
+    /// balancers stop routing requests here before the server stops accepting connections.
+    pub async fn delay_shutdown(&self, shutdown_signal: impl Future<Output = ()>, delay: Duration) {
+        shutdown_signal.await;
+        self.0.store(true, Ordering::Relaxed);
+        if !delay.is_zero() {
+            println!("Failing readiness checks for {delay:?} before draining requests");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Respond to readiness checks with `503 Service Unavailable` once the server is shutting down.
+/// Liveness checks are unaffected, so that the process is not killed while it drains.
+pub(crate) async fn fail_readiness_when_shutting_down<B>(
+    State(readiness): State<Readiness>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if readiness.shutting_down() && request.uri().path() == "/health" {
+        return ErrorResponse::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The connector is shutting down".to_owned(),
+            serde_json::Value::Null,
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
@@ -0,0 +1,100 @@
+//! Recording live traffic as `ndc-test` snapshots, so that it can be replayed against later
+//! versions of the connector with the `replay` command.
+//!
+//! When enabled, the request and response of every successful `/query` and `/mutation` request are
+//! written to the snapshots directory, in the layout which `ndc-test` uses: a directory per kind
+//! of request, containing a directory per snapshot with `request.json` and `expected.json`.
+//! Snapshots are named after a hash of the request, so identical requests share a snapshot, which
+//! holds the latest response.
+//!
+//! Responses must be complete to be recorded, so streamed responses are buffered while recording.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::connector::{ErrorResponse, Result};
+use crate::fnv::fnv1a;
+use crate::json_response::JsonResponse;
+
+/// Records requests and responses in a snapshots directory.
+#[derive(Clone, Debug)]
+pub(crate) struct SnapshotRecorder {
+    snapshots_dir: PathBuf,
+}
+
+impl SnapshotRecorder {
+    pub fn new(snapshots_dir: PathBuf) -> Self {
+        Self { snapshots_dir }
+    }
+
+    /// Record the request with its response, which is returned for sending.
+    ///
+    /// Failing to write the snapshot is logged, rather than failing the request.
+    pub async fn record<A: Serialize>(
+        &self,
+        kind: &str,
+        request: &impl Serialize,
+        response: JsonResponse<A>,
+    ) -> Result<JsonResponse<A>> {
+        let response = response.buffer().await?;
+        let written = match snapshot_files(request, &response) {
+            Ok((request, expected)) => self.write(kind, request, expected).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            tracing::warn!(
+                meta.signal_type = "log",
+                event.domain = "ndc",
+                event.name = "Unable to record a snapshot",
+                name = "Unable to record a snapshot",
+                body = %err,
+            );
+        }
+        Ok(response)
+    }
+
+    async fn write(&self, kind: &str, request: Vec<u8>, expected: Vec<u8>) -> Result<()> {
+        let snapshot_dir = self.snapshot_dir(kind, &request);
+        tokio::fs::create_dir_all(&snapshot_dir)
+            .await
+            .map_err(ErrorResponse::from_error)?;
+        tokio::fs::write(snapshot_dir.join("request.json"), request)
+            .await
+            .map_err(ErrorResponse::from_error)?;
+        tokio::fs::write(snapshot_dir.join("expected.json"), expected)
+            .await
+            .map_err(ErrorResponse::from_error)?;
+        Ok(())
+    }
+
+    fn snapshot_dir(&self, kind: &str, request: &[u8]) -> PathBuf {
+        self.snapshots_dir
+            .join(kind)
+            .join(format!("{:016x}", fnv1a(request)))
+    }
+}
+
+/// The contents of `request.json` and `expected.json`, for a buffered response.
+fn snapshot_files<A: Serialize>(
+    request: &impl Serialize,
+    response: &JsonResponse<A>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let request = serde_json::to_vec_pretty(request).map_err(ErrorResponse::from_error)?;
+    let expected = match response.body() {
+        JsonResponse::Value(value) => serde_json::to_value(value),
+        JsonResponse::Serialized(bytes) => serde_json::from_slice(bytes),
+        JsonResponse::Compressed { bytes, encoding } => encoding
+            .decode(bytes)
+            .map_err(serde_json::Error::io)
+            .and_then(|bytes| serde_json::from_slice(&bytes)),
+        _ => {
+            return Err(ErrorResponse::from(
+                "the response was not buffered".to_owned(),
+            ))
+        }
+    }
+    .and_then(|expected: serde_json::Value| serde_json::to_vec_pretty(&expected))
+    .map_err(ErrorResponse::from_error)?;
+    Ok((request, expected))
+}
@@ -0,0 +1,104 @@
+//! Helpers for replaying a subset of a snapshots directory.
+//!
+//! `ndc-test` replays every snapshot in a directory. A snapshots directory contains a directory
+//! per kind of request (`query`, `mutation`), each containing a directory per snapshot. To replay
+//! some of the snapshots, we create a temporary directory with the same layout, containing links
+//! to the selected snapshots only.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A single snapshot in a snapshots directory.
+#[derive(Clone, Debug)]
+pub(crate) struct Snapshot {
+    /// The kind of request, such as `query`, which is the name of the enclosing directory.
+    pub kind: String,
+    /// The name of the snapshot directory.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// List the snapshots in a snapshots directory, in a stable order.
+pub(crate) fn list_snapshots(snapshots_dir: &Path) -> io::Result<Vec<Snapshot>> {
+    let mut snapshots = vec![];
+    for kind_entry in fs::read_dir(snapshots_dir)? {
+        let kind_entry = kind_entry?;
+        if !kind_entry.file_type()?.is_dir() {
+            continue;
+        }
+        let kind = kind_entry.file_name().to_string_lossy().into_owned();
+        for entry in fs::read_dir(kind_entry.path())? {
+            let entry = entry?;
+            snapshots.push(Snapshot {
+                kind: kind.clone(),
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path: entry.path(),
+            });
+        }
+    }
+    snapshots.sort_by(|a, b| (&a.kind, &a.name).cmp(&(&b.kind, &b.name)));
+    Ok(snapshots)
+}
+
+/// A temporary snapshots directory containing links to a selection of snapshots.
+///
+/// The directory is removed when this value is dropped. Because the snapshots are linked rather
+/// than copied, any snapshots written by `ndc-test` are written to the original directory.
+pub(crate) struct SnapshotView {
+    root: PathBuf,
+}
+
+impl SnapshotView {
+    pub fn create(snapshots: &[Snapshot]) -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "ndc-sdk-snapshots-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let view = Self { root };
+        for snapshot in snapshots {
+            let kind_dir = view.root.join(&snapshot.kind);
+            fs::create_dir_all(&kind_dir)?;
+            link_dir(
+                &fs::canonicalize(&snapshot.path)?,
+                &kind_dir.join(&snapshot.name),
+            )?;
+        }
+        fs::create_dir_all(&view.root)?;
+        Ok(view)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Drop for SnapshotView {
+    fn drop(&mut self) {
+        // removing the directory removes the links, not the snapshots they point to
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Split snapshots into at most `count` groups of similar size.
+pub(crate) fn partition(snapshots: Vec<Snapshot>, count: usize) -> Vec<Vec<Snapshot>> {
+    let count = count.clamp(1, snapshots.len().max(1));
+    let mut groups = vec![vec![]; count];
+    for (index, snapshot) in snapshots.into_iter().enumerate() {
+        groups[index % count].push(snapshot);
+    }
+    groups
+}
+
+#[cfg(unix)]
+fn link_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn link_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
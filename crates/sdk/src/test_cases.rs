@@ -0,0 +1,63 @@
+//! Connector-specific tests, which the `test` command runs after the tests generated by
+//! `ndc-test`, and reports in the same way.
+
+use async_trait::async_trait;
+use ndc_test::connector::Connector;
+use ndc_test::error::Error;
+use ndc_test::reporter::Reporter;
+
+/// The group in which connector-specific tests are reported.
+const GROUP: &str = "Connector-specific tests";
+
+/// A connector-specific test, added with
+/// [`MainOptions::test_case`](crate::default_main::MainOptions::test_case).
+///
+/// The test is given the connector under test, which may be running in-process or, with
+/// `test --endpoint`, elsewhere. It fails by returning an error.
+///
+/// ```ignore
+/// struct ArticlesAreNotEmpty;
+///
+/// #[async_trait(?Send)]
+/// impl TestCase for ArticlesAreNotEmpty {
+///     fn name(&self) -> &str {
+///         "articles are not empty"
+///     }
+///
+///     async fn run(&self, connector: &dyn Connector) -> Result<(), Error> {
+///         let response = connector.query(articles_request()).await?;
+///         if response.0[0].rows.as_ref().map_or(true, Vec::is_empty) {
+///             return Err(Error::OtherError("no articles".into()));
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait(?Send)]
+pub trait TestCase: Send {
+    /// The name which the result is reported with.
+    fn name(&self) -> &str;
+
+    async fn run(&self, connector: &dyn Connector) -> Result<(), Error>;
+}
+
+/// Run the tests one at a time, in the order they were added.
+pub(crate) async fn run_test_cases(
+    test_cases: &[Box<dyn TestCase>],
+    connector: &dyn Connector,
+    reporter: &mut impl Reporter,
+) {
+    if test_cases.is_empty() {
+        return;
+    }
+    reporter.enter(GROUP);
+    for test_case in test_cases {
+        reporter.enter(test_case.name());
+        match test_case.run(connector).await {
+            Ok(()) => reporter.success(),
+            Err(err) => reporter.failure(&err),
+        }
+        reporter.exit();
+    }
+    reporter.exit();
+}
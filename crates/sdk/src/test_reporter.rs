@@ -0,0 +1,141 @@
+//! A machine-readable reporter for `ndc-test`, used by the `--report-json` option.
+
+use std::time::Instant;
+
+use ndc_test::error::Error;
+use ndc_test::reporter::Reporter;
+use serde::Serialize;
+
+/// Records the outcome and duration of every test, so that the results can be written as JSON.
+///
+/// Groups of tests which do not report an outcome of their own are not recorded, but their names
+/// are included in the paths of the tests they contain.
+#[derive(Debug, Default)]
+pub struct JsonReporter {
+    stack: Vec<Frame>,
+    tests: Vec<TestReport>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    started: Instant,
+    outcome: Option<(TestStatus, Option<String>)>,
+}
+
+/// The result of a single test.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestReport {
+    /// The names of the enclosing groups of tests, outermost first.
+    pub path: Vec<String>,
+    pub name: String,
+    pub status: TestStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub duration_seconds: f64,
+}
+
+/// Test statuses, ordered from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TestStatus {
+    Passed,
+    Warning,
+    Failed,
+}
+
+/// The JSON document written by [`JsonReporter::write_to`].
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report<'a> {
+    passed: usize,
+    warnings: usize,
+    failed: usize,
+    tests: &'a [TestReport],
+}
+
+impl JsonReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The results recorded so far.
+    pub fn tests(&self) -> &[TestReport] {
+        &self.tests
+    }
+
+    /// Add the results recorded by another reporter, for example one used by a concurrent job.
+    pub fn extend(&mut self, other: JsonReporter) {
+        self.tests.extend(other.tests);
+    }
+
+    /// Write the results, and a summary of them, as JSON.
+    pub fn write_to<W: std::io::Write>(&self, writer: W) -> serde_json::Result<()> {
+        let count = |status| {
+            self.tests
+                .iter()
+                .filter(|test| test.status == status)
+                .count()
+        };
+        let report = Report {
+            passed: count(TestStatus::Passed),
+            warnings: count(TestStatus::Warning),
+            failed: count(TestStatus::Failed),
+            tests: &self.tests,
+        };
+        serde_json::to_writer_pretty(writer, &report)
+    }
+
+    fn record(&mut self, status: TestStatus, message: Option<String>) {
+        let Some(frame) = self.stack.last_mut() else {
+            return;
+        };
+        // a failure takes precedence over a warning, which takes precedence over a success
+        if frame
+            .outcome
+            .as_ref()
+            .is_some_and(|(existing, _)| *existing > status)
+        {
+            return;
+        }
+        frame.outcome = Some((status, message));
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn enter(&mut self, name: &str) {
+        self.stack.push(Frame {
+            name: name.to_string(),
+            started: Instant::now(),
+            outcome: None,
+        });
+    }
+
+    fn exit(&mut self) {
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+        if let Some((status, message)) = frame.outcome {
+            self.tests.push(TestReport {
+                path: self.stack.iter().map(|frame| frame.name.clone()).collect(),
+                name: frame.name,
+                status,
+                message,
+                duration_seconds: frame.started.elapsed().as_secs_f64(),
+            });
+        }
+    }
+
+    fn success(&mut self) {
+        self.record(TestStatus::Passed, None);
+    }
+
+    fn warning(&mut self, err: &Error) {
+        self.record(TestStatus::Warning, Some(err.to_string()));
+    }
+
+    fn failure(&mut self, err: &Error) {
+        self.record(TestStatus::Failed, Some(err.to_string()));
+    }
+}
@@ -0,0 +1,135 @@
+//! Testing connectors over HTTP, against the same router as the `serve` command.
+//!
+//! ```ignore
+//! let client = ndc_sdk::testing::client(MyConnectorSetup::default(), "tests/configuration").await?;
+//! let schema = client.schema().await?;
+//! ```
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener};
+use std::path::Path;
+
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::connector::error::Result;
+use crate::connector::{Connector, ConnectorSetup, ErrorResponse};
+use crate::default_main::{create_router_with_options, serve_router_options, RouterOptions};
+use crate::state::init_server_state;
+
+#[cfg(feature = "proptest")]
+pub use ndc_sdk_core::testing::query_requests;
+pub use ndc_sdk_core::testing::{
+    assert_golden, assert_golden_capabilities, assert_golden_schema, assert_query_response_eq,
+    canonical_json, CapabilitiesFixture, ManualClock, MinimalCapabilities, MockConnector,
+    QueryResponseComparison, TestClient, TestClientError, UPDATE_GOLDEN_FILES,
+};
+pub use ndc_sdk_core::testing::{
+    capabilities_matrix, for_each_capabilities, AggregatesOnlyCapabilities, FullCapabilities,
+    QueryOnlyCapabilities, RelationalCapabilities,
+};
+
+/// Build the router for the configuration in the given directory.
+pub async fn router<Setup>(
+    setup: Setup,
+    configuration_dir: impl AsRef<Path>,
+    options: RouterOptions,
+) -> Result<axum::Router>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    let state = init_server_state(setup, configuration_dir.as_ref()).await?;
+    Ok(create_router_with_options(state, options))
+}
+
+/// Serve the router for the configuration in the given directory, with the default options, and
+/// create a client for it.
+pub async fn client<Setup>(
+    setup: Setup,
+    configuration_dir: impl AsRef<Path>,
+) -> std::result::Result<TestClient, TestClientError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    TestClient::new(router(setup, configuration_dir, RouterOptions::default()).await?)
+}
+
+/// The service token which [`spawn_connector`] requires.
+pub const SERVICE_TOKEN: &str = "ndc-sdk-test-service-token";
+
+/// A connector served by [`spawn_connector`]. Dropping it shuts the server down.
+#[derive(Debug)]
+pub struct SpawnedConnector {
+    address: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<std::result::Result<(), hyper::Error>>,
+}
+
+impl SpawnedConnector {
+    /// The address on which the connector is served.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The URL of the connector, such as `http://[::1]:12345`.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// A client which authenticates with the service token.
+    pub fn client(&self) -> std::result::Result<TestClient, TestClientError> {
+        Ok(TestClient::connect(self.address)?.with_service_token(SERVICE_TOKEN))
+    }
+
+    /// Stop accepting connections, and wait for requests in flight to finish.
+    pub async fn shutdown(self) -> std::result::Result<(), TestClientError> {
+        // if this fails, the server has already stopped
+        let _ = self.shutdown.send(());
+        self.server
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(std::io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// Serve the connector as the `serve` command would, on an ephemeral port, for black-box tests.
+///
+/// The router is configured from the environment just like `serve`, except that it requires
+/// [`SERVICE_TOKEN`]. Tracing is not initialized, so tests can install their own subscriber.
+pub async fn spawn_connector<Setup>(
+    setup: Setup,
+    configuration_dir: impl AsRef<Path>,
+) -> std::result::Result<SpawnedConnector, TestClientError>
+where
+    Setup: ConnectorSetup,
+    Setup::Connector: Connector + 'static,
+{
+    let configuration_dir = configuration_dir.as_ref();
+    let options = serve_router_options([
+        "serve".as_ref(),
+        "--configuration".as_ref(),
+        configuration_dir.as_os_str(),
+        "--service-token-secret".as_ref(),
+        SERVICE_TOKEN.as_ref(),
+    ])
+    .map_err(ErrorResponse::from_error)?;
+    let router = router(setup, configuration_dir, options).await?;
+
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 0))?;
+    let address = listener.local_addr()?;
+    let (shutdown, shutdown_requested) = oneshot::channel();
+    let server = axum::Server::from_tcp(listener)
+        .map_err(std::io::Error::other)?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(async {
+            // this also fails when the handle is dropped, which shuts down the server, too
+            let _ = shutdown_requested.await;
+        });
+    Ok(SpawnedConnector {
+        address,
+        shutdown,
+        server: tokio::spawn(server),
+    })
+}
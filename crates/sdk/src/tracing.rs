@@ -1,118 +1,615 @@
 use std::borrow::ToOwned;
 use std::env;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use axum::body::{Body, BoxBody};
 use http::{Request, Response};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
 use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
-use tracing::{Level, Span};
+use opentelemetry_sdk::resource::{EnvResourceDetector, Resource, ResourceDetector as _};
+use opentelemetry_sdk::trace::{BatchConfig, BatchConfigBuilder, BatchSpanProcessor};
+use opentelemetry_semantic_conventions::resource as semconv;
+use tracing::{Instrument as _, Level, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
-use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::fmt::format::{DefaultFields, PrettyFields};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layer as _, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Registry;
+
+use crate::datadog::DatadogPropagator;
+use crate::export_retry::RetryingExporter;
+use crate::field_redaction::{
+    FieldRedaction, RedactingEvents, RedactingExporter, RedactingFields, RedactingJson,
+};
+use crate::log_file::{LogFile, RotatingFile};
+use crate::version::ConnectorVersion;
+
+/// The format of log lines written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One JSON object per line, for log collectors
+    #[default]
+    Json,
+    /// Multi-line, human-readable output, for local development
+    Pretty,
+    /// Single-line, human-readable output
+    Compact,
+}
+
+/// A format for propagating trace context to and from other services.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Propagator {
+    /// W3C Trace Context, with the `traceparent` and `tracestate` headers
+    #[value(name = "tracecontext")]
+    TraceContext,
+    /// W3C Baggage, with the `baggage` header
+    Baggage,
+    /// Zipkin's B3 headers, such as `X-B3-TraceId`
+    #[value(name = "b3multi")]
+    B3Multi,
+    /// Datadog's headers, such as `x-datadog-trace-id`
+    Datadog,
+}
+
+/// The propagators used when none are configured.
+pub const DEFAULT_PROPAGATORS: &[Propagator] = &[
+    Propagator::TraceContext,
+    Propagator::B3Multi,
+    Propagator::Baggage,
+];
+
+impl Propagator {
+    fn text_map_propagator(self) -> Box<dyn TextMapPropagator + Send + Sync> {
+        match self {
+            Self::TraceContext => {
+                Box::new(opentelemetry_sdk::propagation::TraceContextPropagator::new())
+            }
+            Self::Baggage => Box::new(opentelemetry_sdk::propagation::BaggagePropagator::new()),
+            Self::B3Multi => Box::new(opentelemetry_zipkin::Propagator::new()),
+            Self::Datadog => Box::new(DatadogPropagator::new()),
+        }
+    }
+}
+
+/// Options for [`init_tracing_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct TracingOptions<'a> {
+    /// The service name reported with traces. Defaults to `service.name` in the
+    /// `OTEL_RESOURCE_ATTRIBUTES` environment variable, and then to the SDK package name.
+    pub service_name: Option<&'a str>,
+    /// The environment the connector is deployed to, such as `production`, reported with traces
+    /// as `deployment.environment`.
+    pub deployment_environment: Option<&'a str>,
+    /// The connector name and version, reported with traces.
+    pub connector_version: Option<&'a ConnectorVersion>,
+    /// The OTLP endpoint to send traces to. If not set, traces are not exported.
+    pub otlp_endpoint: Option<&'a str>,
+    /// The format of log lines.
+    pub log_format: LogFormat,
+    /// Log filter directives, such as `debug` or `info,my_connector=debug`. This overrides the
+    /// `RUST_LOG` environment variable.
+    pub log_level: Option<&'a str>,
+    /// Redact sensitive fields from logs and exported traces. If not set, nothing is redacted.
+    pub field_redaction: Option<FieldRedaction>,
+    /// How the OTLP exporter connects to the endpoint.
+    pub otlp_exporter: OtlpExporterOptions,
+    /// How spans are queued and exported.
+    pub otlp_batch: OtlpBatchOptions,
+    /// If set, logs are also written to this file, as JSON.
+    pub log_file: Option<LogFile>,
+    /// The formats used to propagate trace context. If empty, [`DEFAULT_PROPAGATORS`] are used.
+    pub propagators: Vec<Propagator>,
+}
+
+/// How spans are queued and exported, for connectors which produce more spans than the defaults
+/// can export, or which must bound the memory used by the queue.
+///
+/// Settings which are not set are read from the `OTEL_BSP_*` environment variables, and then
+/// default to the OpenTelemetry SDK's defaults.
+#[derive(Clone, Debug, Default)]
+pub struct OtlpBatchOptions {
+    /// The maximum number of spans waiting to be exported. Spans are dropped when it is full.
+    pub max_queue_size: Option<usize>,
+    /// The maximum number of spans exported at once.
+    pub max_export_batch_size: Option<usize>,
+    /// The maximum time to export a batch, including any retries.
+    pub export_timeout: Option<Duration>,
+    /// The number of times to retry a failed export.
+    pub retries: u32,
+}
+
+impl OtlpBatchOptions {
+    fn batch_config(&self) -> BatchConfig {
+        let mut builder = BatchConfigBuilder::default();
+        if let Some(max_queue_size) = self.max_queue_size {
+            builder = builder.with_max_queue_size(max_queue_size);
+        }
+        if let Some(max_export_batch_size) = self.max_export_batch_size {
+            builder = builder.with_max_export_batch_size(max_export_batch_size);
+        }
+        if let Some(export_timeout) = self.export_timeout {
+            builder = builder.with_max_export_timeout(export_timeout);
+        }
+        builder.build()
+    }
+}
+
+/// How the OTLP exporter connects to the endpoint, for collectors which require API keys or
+/// private certificates.
+#[derive(Clone, Debug, Default)]
+pub struct OtlpExporterOptions {
+    /// Headers sent with every export, such as API keys. Headers in the
+    /// `OTEL_EXPORTER_OTLP_HEADERS` environment variable are sent too, and take precedence.
+    pub headers: Vec<(String, String)>,
+    /// A PEM file of CA certificates to trust, in addition to the system's.
+    pub certificate: Option<PathBuf>,
+    /// A PEM client certificate for mutual TLS, which requires `client_key`. This is only
+    /// supported with the gRPC protocol.
+    pub client_certificate: Option<PathBuf>,
+    /// The PEM private key of `client_certificate`.
+    pub client_key: Option<PathBuf>,
+    /// Connect to gRPC endpoints without TLS. Otherwise, TLS is used for `https` endpoints, and
+    /// whenever certificates are configured.
+    pub insecure: bool,
+}
+
+impl OtlpExporterOptions {
+    /// The exporter for the endpoint, using the protocol in the `OTEL_EXPORTER_OTLP_PROTOCOL`
+    /// environment variable, which defaults to gRPC.
+    fn span_exporter(
+        &self,
+        endpoint: &str,
+    ) -> Result<SpanExporterBuilder, Box<dyn Error + Send + Sync>> {
+        match env::var(opentelemetry_otlp::OTEL_EXPORTER_OTLP_PROTOCOL) {
+            Ok(protocol) => match protocol.as_str() {
+                "grpc" => self.grpc_exporter(endpoint),
+                "http/protobuf" => self.http_exporter(endpoint),
+                invalid => Err(format!("invalid protocol: {invalid:?}").into()),
+            },
+            // the default exporter protocol is grpc
+            Err(env::VarError::NotPresent) => self.grpc_exporter(endpoint),
+            Err(env::VarError::NotUnicode(os_str)) => {
+                Err(format!("invalid protocol: {os_str:?}").into())
+            }
+        }
+    }
+
+    fn grpc_exporter(
+        &self,
+        endpoint: &str,
+    ) -> Result<SpanExporterBuilder, Box<dyn Error + Send + Sync>> {
+        let mut exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint);
+        if !self.headers.is_empty() {
+            let mut metadata = tonic::metadata::MetadataMap::new();
+            for (key, value) in &self.headers {
+                metadata.insert(
+                    tonic::metadata::MetadataKey::from_bytes(key.to_lowercase().as_bytes())?,
+                    value.parse()?,
+                );
+            }
+            exporter = exporter.with_metadata(metadata);
+        }
+        let use_tls = endpoint.starts_with("https:")
+            || self.certificate.is_some()
+            || self.client_certificate.is_some();
+        if use_tls && !self.insecure {
+            let mut tls_config = tonic::transport::ClientTlsConfig::new();
+            if let Some(certificate) = &self.certificate {
+                tls_config = tls_config
+                    .ca_certificate(tonic::transport::Certificate::from_pem(read(certificate)?));
+            }
+            if let Some(client_certificate) = &self.client_certificate {
+                let client_key = self
+                    .client_key
+                    .as_ref()
+                    .ok_or("a client certificate requires a client key")?;
+                tls_config = tls_config.identity(tonic::transport::Identity::from_pem(
+                    read(client_certificate)?,
+                    read(client_key)?,
+                ));
+            }
+            exporter = exporter.with_tls_config(tls_config);
+        }
+        Ok(exporter.into())
+    }
+
+    fn http_exporter(
+        &self,
+        endpoint: &str,
+    ) -> Result<SpanExporterBuilder, Box<dyn Error + Send + Sync>> {
+        if self.client_certificate.is_some() {
+            return Err("client certificates are only supported with the grpc protocol".into());
+        }
+        let mut exporter = opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint)
+            .with_headers(self.headers.iter().cloned().collect());
+        if let Some(certificate) = &self.certificate {
+            let client = reqwest::Client::builder()
+                .add_root_certificate(reqwest::Certificate::from_pem(&read(certificate)?)?)
+                .build()?;
+            exporter = exporter.with_http_client(client);
+        }
+        Ok(exporter.into())
+    }
+}
+
+/// Read a certificate or key file, mentioning the path in any error.
+fn read(path: &Path) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    std::fs::read(path).map_err(|err| format!("unable to read {}: {err}", path.display()).into())
+}
+
+impl TracingOptions<'_> {
+    /// The OTLP endpoint traces will be sent to, falling back to the
+    /// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` environment variable.
+    pub fn resolved_otlp_endpoint(&self) -> Option<String> {
+        self.otlp_endpoint
+            .map(ToOwned::to_owned)
+            .or_else(|| env::var(opentelemetry_otlp::OTEL_EXPORTER_OTLP_TRACES_ENDPOINT).ok())
+    }
+
+    /// The service name reported with traces.
+    pub fn resolved_service_name(&self) -> String {
+        self.resource()
+            .get(semconv::SERVICE_NAME.into())
+            .map_or_else(
+                || env!("CARGO_PKG_NAME").to_string(),
+                |name| name.to_string(),
+            )
+    }
+
+    /// The OpenTelemetry resource which describes this service in traces.
+    ///
+    /// Attributes from the `OTEL_RESOURCE_ATTRIBUTES` environment variable override the defaults,
+    /// such as the host name, and are overridden by these options.
+    pub fn resource(&self) -> Resource {
+        let sdk_version = env!("CARGO_PKG_VERSION");
+        let mut defaults = vec![
+            KeyValue::new(semconv::SERVICE_NAME, env!("CARGO_PKG_NAME")),
+            KeyValue::new(
+                semconv::SERVICE_VERSION,
+                self.connector_version
+                    .map_or(sdk_version, |connector| connector.version),
+            ),
+        ];
+        if let Some(host_name) = host_name() {
+            defaults.push(KeyValue::new(semconv::HOST_NAME, host_name));
+        }
+
+        let mut overrides = vec![KeyValue::new("ndc.sdk.version", sdk_version)];
+        if let Some(service_name) = self.service_name {
+            overrides.push(KeyValue::new(
+                semconv::SERVICE_NAME,
+                service_name.to_string(),
+            ));
+        }
+        if let Some(deployment_environment) = self.deployment_environment {
+            overrides.push(KeyValue::new(
+                semconv::DEPLOYMENT_ENVIRONMENT,
+                deployment_environment.to_string(),
+            ));
+        }
+        if let Some(connector) = self.connector_version {
+            overrides.push(KeyValue::new("ndc.connector.name", connector.name));
+            overrides.push(KeyValue::new("ndc.connector.version", connector.version));
+        }
+
+        Resource::new(defaults)
+            .merge(&EnvResourceDetector::new().detect(Duration::from_secs(0)))
+            .merge(&Resource::new(overrides))
+    }
+
+    /// The log filter directives, falling back to the `RUST_LOG` environment variable, and then
+    /// to `info`.
+    pub fn resolved_log_level(&self) -> String {
+        match self.log_level {
+            Some(log_level) => log_level.to_owned(),
+            None => env::var("RUST_LOG").unwrap_or(Level::INFO.to_string()),
+        }
+    }
+    /// The propagators to use, falling back to [`DEFAULT_PROPAGATORS`].
+    pub fn propagators(&self) -> &[Propagator] {
+        if self.propagators.is_empty() {
+            DEFAULT_PROPAGATORS
+        } else {
+            &self.propagators
+        }
+    }
+}
+
+/// The name of this host, as set by container runtimes and most shells.
+fn host_name() -> Option<String> {
+    let variable = if cfg!(windows) {
+        "COMPUTERNAME"
+    } else {
+        "HOSTNAME"
+    };
+    env::var(variable)
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+}
 
 pub fn init_tracing(
     service_name: Option<&str>,
     otlp_endpoint: Option<&str>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let trace_endpoint = otlp_endpoint
-        .map(ToOwned::to_owned)
-        .or_else(|| env::var(opentelemetry_otlp::OTEL_EXPORTER_OTLP_TRACES_ENDPOINT).ok());
-
-    let log_level = env::var("RUST_LOG").unwrap_or(Level::INFO.to_string());
-    let subscriber = tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::builder()
-                .parse(format!("{log_level},otel::tracing=trace,otel=debug"))?,
-        )
-        .with(
-            tracing_subscriber::fmt::layer()
-                .json()
-                .with_timer(tracing_subscriber::fmt::time::time()),
+    init_tracing_with_options(&TracingOptions {
+        service_name,
+        otlp_endpoint,
+        ..TracingOptions::default()
+    })
+}
+
+pub fn init_tracing_with_options(
+    options: &TracingOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    TracingBuilder::new(options.clone()).init()
+}
+
+/// A layer which can be added with [`TracingBuilder::with_layer`].
+pub type BoxedLayer = Box<dyn tracing_subscriber::Layer<Registry> + Send + Sync>;
+
+/// Initializes tracing from [`TracingOptions`], with additional layers and filter directives.
+///
+/// Connectors can customize how `serve` initializes tracing with
+/// [`MainOptions::configure_tracing`](crate::default_main::MainOptions::configure_tracing), for
+/// example to report errors to another service:
+///
+/// ```ignore
+/// let options = MainOptions::default().configure_tracing(|builder| {
+///     builder
+///         .with_layer(sentry_tracing::layer())
+///         .with_directive("hyper=warn")
+/// });
+/// ```
+pub struct TracingBuilder<'a> {
+    options: TracingOptions<'a>,
+    layers: Vec<BoxedLayer>,
+    directives: Vec<String>,
+    sensitive_fields: Vec<String>,
+}
+
+impl<'a> TracingBuilder<'a> {
+    pub fn new(options: TracingOptions<'a>) -> Self {
+        Self {
+            options,
+            layers: vec![],
+            directives: vec![],
+            sensitive_fields: vec![],
+        }
+    }
+
+    /// The options tracing will be initialized with.
+    pub fn options(&self) -> &TracingOptions<'a> {
+        &self.options
+    }
+
+    /// The fields declared with [`TracingBuilder::with_sensitive_field`].
+    pub fn sensitive_fields(&self) -> &[String] {
+        &self.sensitive_fields
+    }
+
+    /// Add a layer to the subscriber. Events and spans are filtered by the log level before they
+    /// reach the layer.
+    #[must_use]
+    pub fn with_layer<L>(mut self, layer: L) -> Self
+    where
+        L: tracing_subscriber::Layer<Registry> + Send + Sync + 'static,
+    {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Add a filter directive, such as `hyper=warn`, which takes precedence over the log level.
+    #[must_use]
+    pub fn with_directive(mut self, directive: impl Into<String>) -> Self {
+        self.directives.push(directive.into());
+        self
+    }
+
+    /// Declare a field, such as `connection_string`, whose values are sensitive. Its values are
+    /// redacted along with the default sensitive fields when
+    /// [`TracingOptions::field_redaction`] is set, and are left unchanged otherwise.
+    #[must_use]
+    pub fn with_sensitive_field(mut self, field: impl Into<String>) -> Self {
+        self.sensitive_fields.push(field.into());
+        self
+    }
+
+    /// Install the global subscriber.
+    ///
+    /// This fails if a global subscriber has already been installed, in which case the
+    /// OpenTelemetry propagator and tracer provider are left unchanged too.
+    pub fn init(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let options = &self.options;
+        let trace_endpoint = options.resolved_otlp_endpoint();
+        let redaction = options.field_redaction.clone().map(|redaction| {
+            Arc::new(
+                self.sensitive_fields
+                    .iter()
+                    .fold(redaction, FieldRedaction::with_field),
+            )
+        });
+        let mut filter = format!(
+            "{},otel::tracing=trace,otel=debug",
+            options.resolved_log_level()
         );
+        for directive in &self.directives {
+            filter.push(',');
+            filter.push_str(directive);
+        }
+        let subscriber = tracing_subscriber::registry()
+            // an empty list of layers is not interested in anything, which would disable logging
+            .with((!self.layers.is_empty()).then_some(self.layers))
+            .with(tracing_subscriber::EnvFilter::builder().parse(filter)?)
+            .with(fmt_layer(
+                options.log_format,
+                redaction.clone(),
+                std::io::stdout,
+            ))
+            .with(log_file_layer(
+                options.log_file.as_ref(),
+                redaction.clone(),
+            )?);
 
-    match trace_endpoint {
         // disable traces exporter if the endpoint is empty
-        None => subscriber.init(),
-        Some(endpoint) => {
-            opentelemetry::global::set_text_map_propagator(
-                opentelemetry::propagation::composite::TextMapCompositePropagator::new(vec![
-                    Box::new(opentelemetry_sdk::propagation::TraceContextPropagator::new()),
-                    Box::new(opentelemetry_zipkin::Propagator::new()),
-                ]),
-            );
-
-            let service_name = service_name.unwrap_or(env!("CARGO_PKG_NAME"));
-
-            let exporter: SpanExporterBuilder =
-                match env::var(opentelemetry_otlp::OTEL_EXPORTER_OTLP_PROTOCOL) {
-                    Ok(protocol) => match protocol.as_str() {
-                        "grpc" => Ok(opentelemetry_otlp::new_exporter()
-                            .tonic()
-                            .with_endpoint(endpoint)
-                            .into()),
-                        "http/protobuf" => Ok(opentelemetry_otlp::new_exporter()
-                            .http()
-                            .with_endpoint(endpoint)
-                            .into()),
-                        invalid => Err(format!("invalid protocol: {invalid:?}")),
-                    },
-                    // the default exporter protocol is grpc
-                    Err(env::VarError::NotPresent) => Ok(opentelemetry_otlp::new_exporter()
-                        .tonic()
-                        .with_endpoint(endpoint)
-                        .into()),
-                    Err(env::VarError::NotUnicode(os_str)) => {
-                        Err(format!("invalid protocol: {os_str:?}"))
-                    }
-                }?;
-
-            let tracer = opentelemetry_otlp::new_pipeline()
-                .tracing()
-                .with_exporter(exporter)
-                .with_trace_config(
+        let provider = match trace_endpoint {
+            None => None,
+            Some(endpoint) => {
+                let exporter = options.otlp_exporter.span_exporter(&endpoint)?;
+                let exporter = exporter.build_span_exporter()?;
+                let provider = opentelemetry_sdk::trace::TracerProvider::builder().with_config(
                     opentelemetry_sdk::trace::config()
-                        .with_resource(opentelemetry_sdk::Resource::new(vec![
-                            opentelemetry::KeyValue::new(
-                                opentelemetry_semantic_conventions::resource::SERVICE_NAME,
-                                service_name.to_string(),
-                            ),
-                            opentelemetry::KeyValue::new(
-                                opentelemetry_semantic_conventions::resource::SERVICE_VERSION,
-                                env!("CARGO_PKG_VERSION"),
-                            ),
-                        ]))
+                        .with_resource(options.resource())
                         .with_sampler(opentelemetry_sdk::trace::Sampler::ParentBased(Box::new(
                             opentelemetry_sdk::trace::Sampler::AlwaysOn,
                         ))),
-                )
-                .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+                );
+                let batch = &options.otlp_batch;
+                // the exporter is only wrapped when redacting, as this copies attribute values
+                let processor = match &redaction {
+                    None => BatchSpanProcessor::builder(
+                        RetryingExporter::new(exporter, batch.retries),
+                        opentelemetry_sdk::runtime::Tokio,
+                    )
+                    .with_batch_config(batch.batch_config())
+                    .build(),
+                    Some(redaction) => BatchSpanProcessor::builder(
+                        RetryingExporter::new(
+                            RedactingExporter::new(exporter, redaction.clone()),
+                            batch.retries,
+                        ),
+                        opentelemetry_sdk::runtime::Tokio,
+                    )
+                    .with_batch_config(batch.batch_config())
+                    .build(),
+                };
+                Some(provider.with_span_processor(processor).build())
+            }
+        };
+
+        // the OpenTelemetry globals are only touched once the subscriber is installed, so that a
+        // failure leaves an application's existing tracing setup as it was
+        subscriber
+            .with(provider.as_ref().map(|provider| {
+                tracing_opentelemetry::layer()
+                    .with_error_records_to_exceptions(true)
+                    .with_tracer(provider.tracer("opentelemetry-otlp"))
+            }))
+            .try_init()?;
 
-            subscriber
-                .with(
-                    tracing_opentelemetry::layer()
-                        .with_error_records_to_exceptions(true)
-                        .with_tracer(tracer),
-                )
-                .init();
+        // propagate context even if traces are not exported, so that baggage is passed on
+        opentelemetry::global::set_text_map_propagator(
+            opentelemetry::propagation::composite::TextMapCompositePropagator::new(
+                options
+                    .propagators()
+                    .iter()
+                    .map(|propagator| propagator.text_map_propagator())
+                    .collect(),
+            ),
+        );
+        crate::client::set_header_injector(crate::baggage::inject);
+        if let Some(provider) = provider {
+            opentelemetry::global::set_tracer_provider(provider);
         }
+
+        Ok(())
+    }
+}
+
+type FmtLayer<S> = Box<dyn tracing_subscriber::Layer<S> + Send + Sync>;
+
+/// The layer which writes JSON log lines to the log file, if there is one.
+fn log_file_layer<S>(
+    log_file: Option<&LogFile>,
+    redaction: Option<Arc<FieldRedaction>>,
+) -> Result<Option<FmtLayer<S>>, Box<dyn Error + Send + Sync>>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(log_file) = log_file else {
+        return Ok(None);
     };
+    let file = RotatingFile::open(log_file.clone()).map_err(|err| {
+        format!(
+            "unable to open the log file {}: {err}",
+            log_file.path.display()
+        )
+    })?;
+    Ok(Some(fmt_layer(LogFormat::Json, redaction, file)))
+}
 
-    Ok(())
+/// The layer which writes log lines, redacting sensitive fields if required.
+fn fmt_layer<S, W>(
+    log_format: LogFormat,
+    redaction: Option<Arc<FieldRedaction>>,
+    writer: W,
+) -> FmtLayer<S>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_timer(tracing_subscriber::fmt::time::time())
+        .with_writer(writer);
+    match (log_format, redaction) {
+        (LogFormat::Json, None) => layer.json().boxed(),
+        (LogFormat::Json, Some(redaction)) => layer
+            .json()
+            .map_event_format(|format| RedactingJson::new(format, redaction))
+            .boxed(),
+        (LogFormat::Pretty, None) => layer.pretty().boxed(),
+        (LogFormat::Pretty, Some(redaction)) => layer
+            .pretty()
+            .fmt_fields(RedactingFields::new(PrettyFields::new(), redaction.clone()))
+            .map_event_format(|format| RedactingEvents::new(format, redaction))
+            .boxed(),
+        (LogFormat::Compact, None) => layer.compact().boxed(),
+        (LogFormat::Compact, Some(redaction)) => layer
+            .compact()
+            .fmt_fields(RedactingFields::new(DefaultFields::new(), redaction))
+            .boxed(),
+    }
 }
+
 // Custom function for creating request-level spans
 // tracing crate requires all fields to be defined at creation time, so any fields that will be set
 // later should be defined as Empty
 pub fn make_span(request: &Request<Body>) -> Span {
+    use opentelemetry::baggage::BaggageExt;
     use opentelemetry::trace::TraceContextExt;
 
+    // routers nested by `MultiConnectorRouter` see the URI without the connector's prefix
+    let uri = request
+        .extensions()
+        .get::<axum::extract::OriginalUri>()
+        .map_or(request.uri(), |original| &original.0);
     let span = tracing::info_span!(
         "request",
         method = %request.method(),
-        uri = %request.uri(),
+        uri = %uri,
         version = ?request.version(),
         status = tracing::field::Empty,
         latency = tracing::field::Empty,
+        ndc.collection = tracing::field::Empty,
+        ndc.procedures = tracing::field::Empty,
+        ndc.operation_count = tracing::field::Empty,
+        ndc.variable_sets = tracing::field::Empty,
+        ndc.row_count = tracing::field::Empty,
     );
 
     // Get parent trace id from headers, if available
@@ -127,13 +624,153 @@ pub fn make_span(request: &Request<Body>) -> Span {
     let parent_context_span_context = parent_context_span.span_context();
     if parent_context_span_context.is_valid() {
         span.set_parent(parent_context);
+    } else if !parent_context.baggage().is_empty() {
+        // keep the baggage, so that it is propagated from this span, without the invalid parent
+        span.set_parent(
+            opentelemetry::Context::new()
+                .with_baggage(crate::baggage::entries(parent_context.baggage())),
+        );
     }
 
     span
 }
 
+/// The paths of the health check and metrics endpoints, which are called frequently by probes and
+/// scrapers.
+const PROBE_PATHS: [&str; 3] = ["/health", "/health/live", "/metrics"];
+
+/// Like [`make_span`], but without spans for health checks and metrics scrapes, which would
+/// otherwise flood tracing backends in busy clusters.
+pub fn make_span_except_probes(request: &Request<Body>) -> Span {
+    if PROBE_PATHS.contains(&request.uri().path()) {
+        Span::none()
+    } else {
+        make_span(request)
+    }
+}
+
 // Custom function for adding information to request-level span that is only available at response time.
 pub fn on_response(response: &Response<BoxBody>, latency: Duration, span: &Span) {
     span.record("status", tracing::field::display(response.status()));
     span.record("latency", tracing::field::display(latency.as_nanos()));
 }
+
+/// Add a [`traceresponse`](https://w3c.github.io/trace-context/#traceresponse-header) header with
+/// the trace ID of the request span, so that callers can find the connector's trace for a failed
+/// request without searching the logs.
+///
+/// This is intended to be used with [`axum::middleware::map_response`], inside the `TraceLayer`,
+/// so that the request span is current. Nothing is added if traces are not being recorded.
+pub async fn add_trace_response(mut response: Response<BoxBody>) -> Response<BoxBody> {
+    use opentelemetry::trace::TraceContextExt;
+
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if span_context.is_valid() {
+        let trace_response = format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            span_context.trace_flags().to_u8()
+        );
+        if let Ok(value) = http::HeaderValue::from_str(&trace_response) {
+            response.headers_mut().insert("traceresponse", value);
+        }
+    }
+    response
+}
+
+/// Record the target collection of a query, and the number of sets of variables if there are
+/// any, on the request span.
+pub(crate) fn record_query_request(request: &ndc_models::QueryRequest) {
+    let span = Span::current();
+    span.record(
+        "ndc.collection",
+        tracing::field::display(&request.collection),
+    );
+    if let Some(variables) = &request.variables {
+        span.record("ndc.variable_sets", variables.len());
+    }
+}
+
+/// Record the number of rows in a query response on the request span.
+///
+/// Responses which have already been serialized are not inspected.
+pub(crate) fn record_query_response(
+    response: &crate::json_response::JsonResponse<ndc_models::QueryResponse>,
+) {
+    if let crate::json_response::JsonResponse::Value(response) = response.body() {
+        Span::current().record("ndc.row_count", row_count(response));
+    }
+}
+
+/// The number of rows in a query response, over all of its row sets.
+pub(crate) fn row_count(response: &ndc_models::QueryResponse) -> usize {
+    response
+        .0
+        .iter()
+        .filter_map(|row_set| row_set.rows.as_ref())
+        .map(Vec::len)
+        .sum()
+}
+
+/// Run one operation of a mutation in a child span of the current span, recording the procedure
+/// name, the index of the operation in the request, and whether it succeeded.
+///
+/// Connectors which execute the operations of a [`ndc_models::MutationRequest`] one at a time can
+/// wrap each of them in this, so that the latency of multi-operation mutations can be broken down
+/// in traces.
+///
+/// ```ignore
+/// for (index, operation) in request.operations.iter().enumerate() {
+///     let result = trace_mutation_operation(index, operation, execute(operation)).await?;
+///     operation_results.push(result);
+/// }
+/// ```
+pub async fn trace_mutation_operation<F, T, E>(
+    index: usize,
+    operation: &ndc_models::MutationOperation,
+    execute: F,
+) -> Result<T, E>
+where
+    F: std::future::Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let span = match operation {
+        ndc_models::MutationOperation::Procedure { name, .. } => tracing::info_span!(
+            "mutation operation",
+            ndc.operation_index = index,
+            ndc.procedure = %name,
+            ndc.outcome = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+            otel.status_message = tracing::field::Empty,
+        ),
+    };
+    let result = execute.instrument(span.clone()).await;
+    match &result {
+        Ok(_) => {
+            span.record("ndc.outcome", "success");
+        }
+        Err(err) => {
+            span.record("ndc.outcome", "error");
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", tracing::field::display(err));
+        }
+    }
+    result
+}
+
+/// Record the procedures called by a mutation, and the number of operations, on the request span.
+pub(crate) fn record_mutation_request(request: &ndc_models::MutationRequest) {
+    let procedures = request
+        .operations
+        .iter()
+        .map(|operation| match operation {
+            ndc_models::MutationOperation::Procedure { name, .. } => name.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let span = Span::current();
+    span.record("ndc.procedures", procedures.join(","));
+    span.record("ndc.operation_count", procedures.len());
+}
@@ -0,0 +1,109 @@
+//! Change detection for `serve --watch`.
+//!
+//! We poll for changes rather than subscribing to file system events, because configuration is
+//! often mounted from volumes which do not report events reliably.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often to check for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What changed since the server was last started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Change {
+    Configuration,
+    Executable,
+}
+
+/// Watches the configuration path, and the running executable.
+pub(crate) struct Watcher {
+    configuration_path: PathBuf,
+    executable: Option<PathBuf>,
+    configuration_fingerprint: u64,
+    executable_fingerprint: u64,
+}
+
+impl Watcher {
+    pub fn new(configuration_path: &Path) -> Self {
+        let executable = std::env::current_exe().ok();
+        Self {
+            configuration_fingerprint: fingerprint(configuration_path),
+            executable_fingerprint: executable.as_deref().map_or(0, fingerprint),
+            configuration_path: configuration_path.to_path_buf(),
+            executable,
+        }
+    }
+
+    /// Wait until something changes.
+    ///
+    /// Changes are reported once the files have stopped changing, so that a configuration which is
+    /// being written is not read half-way through.
+    pub async fn changed(&mut self) -> Change {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let (configuration_fingerprint, executable_fingerprint) = self.fingerprints();
+            let change = if executable_fingerprint != self.executable_fingerprint {
+                Change::Executable
+            } else if configuration_fingerprint != self.configuration_fingerprint {
+                Change::Configuration
+            } else {
+                continue;
+            };
+            (self.configuration_fingerprint, self.executable_fingerprint) = self.settle().await;
+            return change;
+        }
+    }
+
+    /// Wait until the files stop changing, and return their fingerprints.
+    async fn settle(&self) -> (u64, u64) {
+        let mut previous = self.fingerprints();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let current = self.fingerprints();
+            if current == previous {
+                return current;
+            }
+            previous = current;
+        }
+    }
+
+    fn fingerprints(&self) -> (u64, u64) {
+        (
+            fingerprint(&self.configuration_path),
+            self.executable.as_deref().map_or(0, fingerprint),
+        )
+    }
+}
+
+/// Summarize a file or directory tree by the paths, sizes, and modification times of its files.
+fn fingerprint(path: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    fingerprint_into(path, &mut hasher);
+    hasher.finish()
+}
+
+fn fingerprint_into(path: &Path, hasher: &mut DefaultHasher) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    path.hash(hasher);
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            fingerprint_into(&path, hasher);
+        }
+    } else {
+        metadata.len().hash(hasher);
+        metadata.modified().ok().hash(hasher);
+    }
+}